@@ -0,0 +1,105 @@
+//! A chat room, where every message is streamed to all receivers
+//!
+//! `Receive` is a long running `more` call, which holds a worker thread of the
+//! server, until the client disconnects or the server shuts down. A receiver,
+//! which falls more than `MAX_LAG` messages behind, is disconnected.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use varlink::broadcast::Broadcaster;
+use varlink::{ErrorKind, VarlinkService};
+
+use crate::org_example_chat::*;
+
+// Dynamically build the varlink rust code.
+pub mod org_example_chat;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// The number of messages kept for `Receive`
+pub const HISTORY_SIZE: usize = 100;
+
+/// The number of messages queued for a receiver, before it is disconnected
+pub const MAX_LAG: usize = 1000;
+
+// Server
+
+pub struct MyOrgExampleChat {
+    history: Mutex<VecDeque<Message>>,
+    receivers: Broadcaster<Message>,
+}
+
+impl Default for MyOrgExampleChat {
+    fn default() -> Self {
+        MyOrgExampleChat {
+            history: Mutex::new(VecDeque::new()),
+            receivers: Broadcaster::new().max_lag_messages(MAX_LAG),
+        }
+    }
+}
+
+impl VarlinkInterface for MyOrgExampleChat {
+    fn send(&self, call: &mut dyn Call_Send, message: Message) -> varlink::Result<()> {
+        if message.text.is_empty() {
+            return call.reply_empty_message();
+        }
+
+        {
+            let mut history = self.history.lock().unwrap();
+            self.receivers.publish(message.clone());
+            if history.len() == HISTORY_SIZE {
+                history.pop_front();
+            }
+            history.push_back(message);
+        }
+        call.reply()
+    }
+
+    fn receive(&self, call: &mut dyn Call_Receive, history: i64) -> varlink::Result<()> {
+        if !call.wants_more() {
+            return call.reply_more_required();
+        }
+
+        // subscribe with the history locked, so no message is missed or repeated
+        let (receiver, history): (_, Vec<Message>) = {
+            let messages = self.history.lock().unwrap();
+            let skip = messages.len().saturating_sub(history.max(0) as usize);
+            (
+                self.receivers.subscribe(),
+                messages.iter().skip(skip).cloned().collect(),
+            )
+        };
+
+        call.set_continues(true);
+        for message in history {
+            call.reply(message)?;
+        }
+
+        loop {
+            call.yield_point()?;
+            match receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(Some(message)) => call.reply(message)?,
+                Ok(None) => {}
+                Err(ref e) if *e.kind() == ErrorKind::SlowConsumer => {
+                    call.set_continues(false);
+                    return call.reply_slow_consumer();
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub fn run_server(address: &str, config: &varlink::ListenConfig) -> varlink::Result<()> {
+    let myinterface = org_example_chat::new(Box::new(MyOrgExampleChat::default()));
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test chat service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(myinterface)],
+    );
+    varlink::listen(service, &address, config)
+}
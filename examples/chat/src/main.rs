@@ -0,0 +1,128 @@
+use std::env;
+use std::io::{self, BufRead};
+use std::process::exit;
+use std::thread;
+
+use varlink::Connection;
+
+use chat::org_example_chat::{Message, VarlinkClient, VarlinkClientInterface};
+use chat::Result;
+
+// Main
+
+fn print_usage(program: &str, opts: &getopts::Options) {
+    let brief = format!(
+        "Usage: {0} --varlink=<address> [--timeout=<seconds>]\n       \
+         {0} --varlink=<address> --client --name=<name>",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "varlink", "varlink address URL", "<address>");
+    opts.optflag("", "client", "run in client mode");
+    opts.optopt("", "name", "name shown with the messages", "<name>");
+    opts.optopt("", "timeout", "server timeout", "<seconds>");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", f);
+            print_usage(&program, &opts);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    let address = match matches.opt_str("varlink") {
+        Some(address) => address,
+        None => {
+            print_usage(&program, &opts);
+            eprintln!("Need varlink address.");
+            exit(1);
+        }
+    };
+
+    let ret: Result<()> = if matches.opt_present("client") {
+        let name = matches
+            .opt_str("name")
+            .or_else(|| env::var("USER").ok())
+            .unwrap_or_else(|| "anonymous".into());
+        run_client(&address, name)
+    } else {
+        let timeout = matches
+            .opt_str("timeout")
+            .unwrap_or_default()
+            .parse::<u64>()
+            .unwrap_or(0);
+        chat::run_server(
+            &address,
+            &varlink::ListenConfig {
+                idle_timeout: timeout,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| e.into())
+    };
+
+    exit(match ret {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("error: {:?}", err);
+            1
+        }
+    });
+}
+
+// Client
+
+/// Prints the messages of the room, and sends every line of stdin.
+fn run_client(address: &str, name: String) -> Result<()> {
+    // `Receive` blocks its connection, so the messages are sent on a second one
+    let mut receiver = VarlinkClient::new(Connection::with_address(address)?);
+    let mut sender = VarlinkClient::new(Connection::with_address(address)?);
+
+    thread::spawn(move || {
+        let mut call = receiver.receive(10);
+        let replies = match call.more() {
+            Ok(replies) => replies,
+            Err(e) => {
+                eprintln!("error: {:?}", e);
+                exit(1);
+            }
+        };
+        for reply in replies {
+            match reply {
+                Ok(reply) => println!("<{}> {}", reply.message.from, reply.message.text),
+                Err(e) => {
+                    eprintln!("error: {:?}", e);
+                    exit(1);
+                }
+            }
+        }
+    });
+
+    for line in io::stdin().lock().lines() {
+        let text = line?;
+        if text.is_empty() {
+            continue;
+        }
+        sender
+            .send(Message {
+                from: name.clone(),
+                text,
+            })
+            .call()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,316 @@
+#![doc = "This file was automatically generated by the varlink rust generator"]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use std::io::BufRead;
+use std::sync::{Arc, RwLock};
+use varlink::{self, CallTrait};
+#[allow(dead_code)]
+pub const INTERFACE_NAME: &str = "org.example.chat";
+#[allow(dead_code)]
+pub mod method_names {
+    pub const RECEIVE: &str = "org.example.chat.Receive";
+    pub const SEND: &str = "org.example.chat.Send";
+}
+#[allow(dead_code)]
+pub mod error_names {
+    pub const EMPTY_MESSAGE: &str = "org.example.chat.EmptyMessage";
+    pub const MORE_REQUIRED: &str = "org.example.chat.MoreRequired";
+}
+#[allow(dead_code)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum ErrorKind {
+    Varlink_Error,
+    VarlinkReply_Error,
+    EmptyMessage,
+    MoreRequired,
+}
+impl ::std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            ErrorKind::Varlink_Error => write!(f, "Varlink Error"),
+            ErrorKind::VarlinkReply_Error => write!(f, "Varlink error reply"),
+            ErrorKind::EmptyMessage => write!(f, "org.example.chat.EmptyMessage"),
+            ErrorKind::MoreRequired => write!(f, "org.example.chat.MoreRequired"),
+        }
+    }
+}
+pub struct Error(
+    pub ErrorKind,
+    pub Option<Box<dyn std::error::Error + 'static + Send + Sync>>,
+    pub Option<&'static str>,
+);
+impl Error {
+    #[allow(dead_code)]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0
+    }
+}
+impl From<ErrorKind> for Error {
+    fn from(e: ErrorKind) -> Self {
+        Error(e, None, None)
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.1
+            .as_ref()
+            .map(|e| e.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use std::error::Error as StdError;
+        if let Some(ref o) = self.2 {
+            std::fmt::Display::fmt(o, f)?;
+        }
+        std::fmt::Debug::fmt(&self.0, f)?;
+        if let Some(e) = self.source() {
+            std::fmt::Display::fmt("\nCaused by:\n", f)?;
+            std::fmt::Debug::fmt(&e, f)?;
+        }
+        Ok(())
+    }
+}
+#[allow(dead_code)]
+pub type Result<T> = std::result::Result<T, Error>;
+impl From<varlink::Error> for Error {
+    fn from(e: varlink::Error) -> Self {
+        match e.kind() {
+            varlink::ErrorKind::VarlinkErrorReply(r) => Error(
+                ErrorKind::from(r),
+                Some(Box::from(e)),
+                Some(concat!(file!(), ":", line!(), ": ")),
+            ),
+            _ => Error(
+                ErrorKind::Varlink_Error,
+                Some(Box::from(e)),
+                Some(concat!(file!(), ":", line!(), ": ")),
+            ),
+        }
+    }
+}
+#[allow(dead_code)]
+impl Error {
+    pub fn source_varlink_kind(&self) -> Option<&varlink::ErrorKind> {
+        use std::error::Error as StdError;
+        let mut s: &dyn StdError = self;
+        while let Some(c) = s.source() {
+            let k = self
+                .source()
+                .and_then(|e| e.downcast_ref::<varlink::Error>())
+                .and_then(|e| Some(e.kind()));
+            if k.is_some() {
+                return k;
+            }
+            s = c;
+        }
+        None
+    }
+}
+impl From<&varlink::Reply> for ErrorKind {
+    #[allow(unused_variables)]
+    fn from(e: &varlink::Reply) -> Self {
+        match e {
+            varlink::Reply {
+                error: Some(ref t), ..
+            } if t == "org.example.chat.EmptyMessage" => ErrorKind::EmptyMessage,
+            varlink::Reply {
+                error: Some(ref t), ..
+            } if t == "org.example.chat.MoreRequired" => ErrorKind::MoreRequired,
+            _ => ErrorKind::VarlinkReply_Error,
+        }
+    }
+}
+pub trait VarlinkCallError: varlink::CallTrait {
+    fn reply_empty_message(&mut self) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::error("org.example.chat.EmptyMessage", None))
+    }
+    fn reply_more_required(&mut self) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::error("org.example.chat.MoreRequired", None))
+    }
+}
+impl<'a> VarlinkCallError for varlink::Call<'a> {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct r#Message {
+    pub r#from: String,
+    pub r#text: String,
+}
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct EmptyMessage_Args {}
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct MoreRequired_Args {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Receive_Reply {
+    pub r#message: Message,
+}
+impl varlink::VarlinkReply for Receive_Reply {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Receive_Args {
+    pub r#history: i64,
+}
+pub trait Call_Receive: VarlinkCallError {
+    fn reply(&mut self, r#message: Message) -> varlink::Result<()> {
+        self.reply_struct(Receive_Reply { r#message }.into())
+    }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self, r#message: Message) -> varlink::Result<()> {
+        self.reply_struct_continues(Receive_Reply { r#message }.into())
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self, r#message: Message) -> varlink::Result<()> {
+        self.reply_struct_final(Receive_Reply { r#message }.into())
+    }
+}
+impl<'a> Call_Receive for varlink::Call<'a> {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Send_Reply {}
+impl varlink::VarlinkReply for Send_Reply {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Send_Args {
+    pub r#message: Message,
+}
+pub trait Call_Send: VarlinkCallError {
+    fn reply(&mut self) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::parameters(None))
+    }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self) -> varlink::Result<()> {
+        self.reply_struct_continues(varlink::Reply::parameters(None))
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self) -> varlink::Result<()> {
+        self.reply_struct_final(varlink::Reply::parameters(None))
+    }
+}
+impl<'a> Call_Send for varlink::Call<'a> {}
+pub trait VarlinkInterface {
+    #[doc = "Replies with the last `history` messages, then with every new message.\nNeeds to be called with `more`."]
+    fn receive(&self, call: &mut dyn Call_Receive, r#history: i64) -> varlink::Result<()>;
+    #[doc = "Sends a message to all receivers"]
+    fn send(&self, call: &mut dyn Call_Send, r#message: Message) -> varlink::Result<()>;
+    fn call_upgraded(
+        &self,
+        _call: &mut varlink::Call,
+        _bufreader: &mut dyn BufRead,
+    ) -> varlink::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+pub trait VarlinkClientInterface {
+    #[doc = "Replies with the last `history` messages, then with every new message.\nNeeds to be called with `more`."]
+    fn receive(
+        &mut self,
+        r#history: i64,
+    ) -> varlink::MethodCall<Receive_Args, Receive_Reply, Error>;
+    #[doc = "Sends a message to all receivers"]
+    fn send(&mut self, r#message: Message) -> varlink::MethodCall<Send_Args, Send_Reply, Error>;
+}
+#[allow(dead_code)]
+pub struct VarlinkClient {
+    connection: Arc<RwLock<varlink::Connection>>,
+}
+impl VarlinkClient {
+    #[allow(dead_code)]
+    pub fn new(connection: Arc<RwLock<varlink::Connection>>) -> Self {
+        VarlinkClient { connection }
+    }
+}
+impl VarlinkClientInterface for VarlinkClient {
+    fn receive(
+        &mut self,
+        r#history: i64,
+    ) -> varlink::MethodCall<Receive_Args, Receive_Reply, Error> {
+        varlink::MethodCall::<Receive_Args, Receive_Reply, Error>::new(
+            self.connection.clone(),
+            "org.example.chat.Receive",
+            Receive_Args { r#history },
+        )
+    }
+    fn send(&mut self, r#message: Message) -> varlink::MethodCall<Send_Args, Send_Reply, Error> {
+        varlink::MethodCall::<Send_Args, Send_Reply, Error>::new(
+            self.connection.clone(),
+            "org.example.chat.Send",
+            Send_Args { r#message },
+        )
+    }
+}
+#[allow(dead_code)]
+pub struct VarlinkInterfaceProxy {
+    inner: Box<dyn VarlinkInterface + Send + Sync>,
+}
+#[allow(dead_code)]
+pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfaceProxy {
+    VarlinkInterfaceProxy { inner }
+}
+impl varlink::Interface for VarlinkInterfaceProxy {
+    fn get_description(&self) -> &'static str {
+        "# A chat room\ninterface org.example.chat\n\ntype Message (from: string, text: string)\n\n# Sends a message to all receivers\nmethod Send(message: Message) -> ()\n\n# Replies with the last `history` messages, then with every new message.\n# Needs to be called with `more`.\nmethod Receive(history: int) -> (message: Message)\n\nerror EmptyMessage ()\n\nerror MoreRequired ()\n"
+    }
+    fn get_name(&self) -> &'static str {
+        "org.example.chat"
+    }
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        Some(&["Receive", "Send"])
+    }
+    fn call_upgraded(
+        &self,
+        call: &mut varlink::Call,
+        bufreader: &mut dyn BufRead,
+    ) -> varlink::Result<Vec<u8>> {
+        self.inner.call_upgraded(call, bufreader)
+    }
+    fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
+        let req = call.require_request()?;
+        match req.method.as_ref() {
+            "org.example.chat.Receive" => {
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: Receive_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner
+                    .receive(call as &mut dyn Call_Receive, args.r#history)
+            }
+            "org.example.chat.Send" => {
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: Send_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner.send(call as &mut dyn Call_Send, args.r#message)
+            }
+            m => call.reply_method_not_found(String::from(m)),
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::thread;
+
+use varlink::{Connection, ListenConfig, ServerHandle};
+
+use chat::org_example_chat::{ErrorKind, Message, VarlinkClient, VarlinkClientInterface};
+use chat::Result;
+
+fn message(from: &str, text: &str) -> Message {
+    Message {
+        from: from.into(),
+        text: text.into(),
+    }
+}
+
+fn with_server<F: FnOnce(&str) -> Result<()>>(address: &'static str, f: F) -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || chat::run_server(address, &config));
+
+    // give server time to start
+    thread::sleep(std::time::Duration::from_millis(500));
+
+    let ret = f(address);
+    // stops the pending `Receive` calls
+    handle.shutdown();
+    child.join().map_err(|_| "Error joining thread")??;
+    ret
+}
+
+#[test]
+fn test_chat() -> Result<()> {
+    with_server("tcp:127.0.0.1:12350", |address| {
+        let mut alice = VarlinkClient::new(Connection::with_address(address)?);
+        let mut bob = VarlinkClient::new(Connection::with_address(address)?);
+
+        alice.send(message("alice", "first")).call()?;
+
+        // the history is sent, after bob is registered as a receiver
+        let mut call = bob.receive(1);
+        let received = call.more()?;
+        assert_eq!(received.next().unwrap()?.message, message("alice", "first"));
+
+        alice.send(message("alice", "hi bob")).call()?;
+        alice.send(message("alice", "are you there?")).call()?;
+        assert_eq!(
+            received.next().unwrap()?.message,
+            message("alice", "hi bob")
+        );
+        assert_eq!(
+            received.next().unwrap()?.message,
+            message("alice", "are you there?")
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_errors() -> Result<()> {
+    with_server("tcp:127.0.0.1:12351", |address| {
+        let mut iface = VarlinkClient::new(Connection::with_address(address)?);
+
+        match iface.send(message("alice", "")).call() {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::EmptyMessage),
+            Ok(_) => panic!("sent an empty message"),
+        }
+
+        match iface.receive(0).call() {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::MoreRequired),
+            Ok(_) => panic!("received without more"),
+        }
+        Ok(())
+    })
+}
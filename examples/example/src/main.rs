@@ -151,9 +151,9 @@ fn run_client(connection: Arc<RwLock<varlink::Connection>>) -> Result<()> {
     }
 
     match iface.info(4).call().err().unwrap().kind() {
-        org_example_network::ErrorKind::UnknownNetworkIfIndex(Some(
+        org_example_network::ErrorKind::UnknownNetworkIfIndex(
             org_example_network::UnknownNetworkIfIndex_Args { ifindex: 4 },
-        )) => {}
+        ) => {}
         res => panic!("Unknown result {:?}", res),
     }
 
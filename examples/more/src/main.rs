@@ -1,19 +1,9 @@
 use std::env;
 use std::process::exit;
-use std::sync::{Arc, RwLock};
-use std::{thread, time};
 
-use varlink::{Connection, VarlinkService};
+use varlink::Connection;
 
-use crate::org_example_more::*;
-
-// Dynamically build the varlink rust code.
-mod org_example_more;
-
-#[cfg(test)]
-mod test;
-
-pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+use more::{run_client, run_server, Result};
 
 // Main
 
@@ -92,137 +82,3 @@ fn main() {
         }
     });
 }
-
-// Client
-
-fn run_client(connection: Arc<RwLock<varlink::Connection>>) -> Result<()> {
-    /*
-    let new_addr = {
-        let conn = connection.read().unwrap();
-        conn.address()
-    };
-    */
-    let mut iface = org_example_more::VarlinkClient::new(connection);
-
-    //let con2 = varlink::Connection::with_address(&new_addr)?;
-    //let mut pingiface = org_example_more::VarlinkClient::new(con2);
-
-    for reply in iface.test_more(10).more()? {
-        let reply = reply?;
-        //assert!(reply.state.is_some());
-        let state = reply.state;
-        match state {
-            State {
-                start: Some(true),
-                end: None,
-                progress: None,
-                ..
-            } => {
-                eprintln!("--- Start ---");
-            }
-            State {
-                start: None,
-                end: Some(true),
-                progress: None,
-                ..
-            } => {
-                eprintln!("--- End ---");
-            }
-            State {
-                start: None,
-                end: None,
-                progress: Some(progress),
-                ..
-            } => {
-                eprintln!("Progress: {}", progress);
-                /*
-                if progress > 50 {
-                    let reply = pingiface.ping("Test".into()).call()?;
-                    eprintln!("Pong: '{}'", reply.pong);
-                }
-                */
-            }
-            _ => eprintln!("Got unknown state: {:?}", state),
-        }
-    }
-
-    Ok(())
-}
-
-// Server
-
-struct MyOrgExampleMore {
-    sleep_duration: u64,
-}
-
-impl VarlinkInterface for MyOrgExampleMore {
-    fn ping(&self, call: &mut dyn Call_Ping, ping: String) -> varlink::Result<()> {
-        call.reply(ping)
-    }
-
-    fn stop_serving(&self, call: &mut dyn Call_StopServing) -> varlink::Result<()> {
-        call.reply()?;
-        Err(varlink::ErrorKind::ConnectionClosed.into())
-    }
-    fn test_more(&self, call: &mut dyn Call_TestMore, n: i64) -> varlink::Result<()> {
-        if !call.wants_more() {
-            return call.reply_test_more_error("called without more".into());
-        }
-
-        if n == 0 {
-            return call.reply_test_more_error("n == 0".into());
-        }
-
-        call.set_continues(true);
-
-        call.reply(State {
-            start: Some(true),
-            end: None,
-            progress: None,
-        })?;
-
-        for i in 0..n {
-            thread::sleep(time::Duration::from_millis(self.sleep_duration));
-            call.reply(State {
-                progress: Some(i * 100 / n),
-                start: None,
-                end: None,
-            })?;
-        }
-
-        call.reply(State {
-            progress: Some(100),
-            start: None,
-            end: None,
-        })?;
-
-        call.set_continues(false);
-
-        call.reply(State {
-            end: Some(true),
-            progress: None,
-            start: None,
-        })
-    }
-}
-
-fn run_server(address: &str, timeout: u64, sleep_duration: u64) -> varlink::Result<()> {
-    let myexamplemore = MyOrgExampleMore { sleep_duration };
-    let myinterface = org_example_more::new(Box::new(myexamplemore));
-    let service = VarlinkService::new(
-        "org.varlink",
-        "test service",
-        "0.1",
-        "http://varlink.org",
-        vec![Box::new(myinterface)],
-    );
-    varlink::listen(
-        service,
-        &address,
-        &varlink::ListenConfig {
-            idle_timeout: timeout,
-            ..Default::default()
-        },
-    )?;
-    Ok(())
-}
@@ -7,11 +7,23 @@ use std::io::BufRead;
 use std::sync::{Arc, RwLock};
 use varlink::{self, CallTrait};
 #[allow(dead_code)]
+pub const INTERFACE_NAME: &str = "org.example.more";
+#[allow(dead_code)]
+pub mod method_names {
+    pub const PING: &str = "org.example.more.Ping";
+    pub const STOP_SERVING: &str = "org.example.more.StopServing";
+    pub const TEST_MORE: &str = "org.example.more.TestMore";
+}
+#[allow(dead_code)]
+pub mod error_names {
+    pub const TEST_MORE_ERROR: &str = "org.example.more.TestMoreError";
+}
+#[allow(dead_code)]
 #[derive(Clone, PartialEq, Debug)]
 pub enum ErrorKind {
     Varlink_Error,
     VarlinkReply_Error,
-    TestMoreError(Option<TestMoreError_Args>),
+    TestMoreError(TestMoreError_Args),
 }
 impl ::std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
@@ -112,14 +124,24 @@ impl From<&varlink::Reply> for ErrorKind {
                     ..
                 } => match serde_json::from_value(p.clone()) {
                     Ok(v) => ErrorKind::TestMoreError(v),
-                    Err(_) => ErrorKind::TestMoreError(None),
+                    Err(_) => ErrorKind::VarlinkReply_Error,
                 },
-                _ => ErrorKind::TestMoreError(None),
+                _ => ErrorKind::VarlinkReply_Error,
             },
             _ => ErrorKind::VarlinkReply_Error,
         }
     }
 }
+impl From<TestMoreError_Args> for ErrorKind {
+    fn from(v: TestMoreError_Args) -> Self {
+        ErrorKind::TestMoreError(v)
+    }
+}
+impl From<TestMoreError_Args> for Error {
+    fn from(v: TestMoreError_Args) -> Self {
+        Error(ErrorKind::TestMoreError(v), None, None)
+    }
+}
 pub trait VarlinkCallError: varlink::CallTrait {
     fn reply_test_more_error(&mut self, r#reason: String) -> varlink::Result<()> {
         self.reply_struct(varlink::Reply::error(
@@ -138,6 +160,7 @@ pub struct r#State {
     pub r#progress: Option<i64>,
     pub r#end: Option<bool>,
 }
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct TestMoreError_Args {
     pub r#reason: String,
@@ -155,6 +178,16 @@ pub trait Call_Ping: VarlinkCallError {
     fn reply(&mut self, r#pong: String) -> varlink::Result<()> {
         self.reply_struct(Ping_Reply { r#pong }.into())
     }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self, r#pong: String) -> varlink::Result<()> {
+        self.reply_struct_continues(Ping_Reply { r#pong }.into())
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self, r#pong: String) -> varlink::Result<()> {
+        self.reply_struct_final(Ping_Reply { r#pong }.into())
+    }
 }
 impl<'a> Call_Ping for varlink::Call<'a> {}
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -166,6 +199,16 @@ pub trait Call_StopServing: VarlinkCallError {
     fn reply(&mut self) -> varlink::Result<()> {
         self.reply_struct(varlink::Reply::parameters(None))
     }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self) -> varlink::Result<()> {
+        self.reply_struct_continues(varlink::Reply::parameters(None))
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self) -> varlink::Result<()> {
+        self.reply_struct_final(varlink::Reply::parameters(None))
+    }
 }
 impl<'a> Call_StopServing for varlink::Call<'a> {}
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -181,11 +224,24 @@ pub trait Call_TestMore: VarlinkCallError {
     fn reply(&mut self, r#state: State) -> varlink::Result<()> {
         self.reply_struct(TestMore_Reply { r#state }.into())
     }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self, r#state: State) -> varlink::Result<()> {
+        self.reply_struct_continues(TestMore_Reply { r#state }.into())
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self, r#state: State) -> varlink::Result<()> {
+        self.reply_struct_final(TestMore_Reply { r#state }.into())
+    }
 }
 impl<'a> Call_TestMore for varlink::Call<'a> {}
 pub trait VarlinkInterface {
+    #[doc = "Returns the same string"]
     fn ping(&self, call: &mut dyn Call_Ping, r#ping: String) -> varlink::Result<()>;
+    #[doc = "Stop serving"]
     fn stop_serving(&self, call: &mut dyn Call_StopServing) -> varlink::Result<()>;
+    #[doc = "Dummy progress method\nn: number of progress steps"]
     fn test_more(&self, call: &mut dyn Call_TestMore, r#n: i64) -> varlink::Result<()>;
     fn call_upgraded(
         &self,
@@ -196,8 +252,11 @@ pub trait VarlinkInterface {
     }
 }
 pub trait VarlinkClientInterface {
+    #[doc = "Returns the same string"]
     fn ping(&mut self, r#ping: String) -> varlink::MethodCall<Ping_Args, Ping_Reply, Error>;
+    #[doc = "Stop serving"]
     fn stop_serving(&mut self) -> varlink::MethodCall<StopServing_Args, StopServing_Reply, Error>;
+    #[doc = "Dummy progress method\nn: number of progress steps"]
     fn test_more(&mut self, r#n: i64) -> varlink::MethodCall<TestMore_Args, TestMore_Reply, Error>;
 }
 #[allow(dead_code)]
@@ -248,6 +307,9 @@ impl varlink::Interface for VarlinkInterfaceProxy {
     fn get_name(&self) -> &'static str {
         "org.example.more"
     }
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        Some(&["Ping", "StopServing", "TestMore"])
+    }
     fn call_upgraded(
         &self,
         call: &mut varlink::Call,
@@ -256,45 +318,45 @@ impl varlink::Interface for VarlinkInterfaceProxy {
         self.inner.call_upgraded(call, bufreader)
     }
     fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
-        let req = call.request.unwrap();
+        let req = call.require_request()?;
         match req.method.as_ref() {
             "org.example.more.Ping" => {
-                if let Some(args) = req.parameters.clone() {
-                    let args: Ping_Args = match serde_json::from_value(args) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let es = format!("{}", e);
-                            let _ = call.reply_invalid_parameter(es.clone());
-                            return Err(
-                                varlink::context!(varlink::ErrorKind::SerdeJsonDe(es)).into()
-                            );
-                        }
-                    };
-                    self.inner.ping(call as &mut dyn Call_Ping, args.r#ping)
-                } else {
-                    call.reply_invalid_parameter("parameters".into())
-                }
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: Ping_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner.ping(call as &mut dyn Call_Ping, args.r#ping)
             }
             "org.example.more.StopServing" => {
                 self.inner.stop_serving(call as &mut dyn Call_StopServing)
             }
             "org.example.more.TestMore" => {
-                if let Some(args) = req.parameters.clone() {
-                    let args: TestMore_Args = match serde_json::from_value(args) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let es = format!("{}", e);
-                            let _ = call.reply_invalid_parameter(es.clone());
-                            return Err(
-                                varlink::context!(varlink::ErrorKind::SerdeJsonDe(es)).into()
-                            );
-                        }
-                    };
-                    self.inner
-                        .test_more(call as &mut dyn Call_TestMore, args.r#n)
-                } else {
-                    call.reply_invalid_parameter("parameters".into())
-                }
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: TestMore_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner
+                    .test_more(call as &mut dyn Call_TestMore, args.r#n)
             }
             m => call.reply_method_not_found(String::from(m)),
         }
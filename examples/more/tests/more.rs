@@ -0,0 +1,81 @@
+use std::{thread, time};
+
+use varlink::Connection;
+
+use more::org_example_more::{
+    ErrorKind, TestMoreError_Args, VarlinkClient, VarlinkClientInterface,
+};
+use more::Result;
+
+fn with_server<F: FnOnce(&mut VarlinkClient) -> Result<()>>(address: &str, f: F) -> Result<()> {
+    let server_address = address.to_string();
+    let child = thread::spawn(move || {
+        if let Err(e) = more::run_server(&server_address, 1, 10) {
+            match e.kind() {
+                varlink::ErrorKind::Timeout => {}
+                _ => panic!("error: {}", e),
+            }
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    {
+        let mut iface = VarlinkClient::new(Connection::with_address(address)?);
+        f(&mut iface)?;
+    }
+
+    child.join().map_err(|_| "Error joining thread")?;
+    Ok(())
+}
+
+#[test]
+fn test_streaming() -> Result<()> {
+    with_server("unix:org.example.more.streaming", |iface| {
+        let states = iface
+            .test_more(4)
+            .more()?
+            .map(|reply| reply.map(|r| r.state))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        assert_eq!(states.len(), 4 + 3);
+        assert_eq!(states[0].start, Some(true));
+        let progress: Vec<_> = states[1..6].iter().map(|s| s.progress).collect();
+        assert_eq!(
+            progress,
+            vec![Some(0), Some(25), Some(50), Some(75), Some(100)]
+        );
+        assert_eq!(states[6].end, Some(true));
+
+        // the connection is usable after the last reply
+        assert_eq!(iface.ping("Test".into()).call()?.pong, "Test");
+        Ok(())
+    })
+}
+
+#[test]
+fn test_errors() -> Result<()> {
+    with_server("unix:org.example.more.errors", |iface| {
+        match iface.test_more(0).more()?.next() {
+            Some(Err(e)) => match e.kind() {
+                ErrorKind::TestMoreError(TestMoreError_Args { reason }) => {
+                    assert_eq!(reason, "n == 0")
+                }
+                k => panic!("unexpected error: {}", k),
+            },
+            r => panic!("unexpected reply: {:?}", r.map(|r| r.map(|r| r.state))),
+        }
+
+        match iface.test_more(1).call() {
+            Err(e) => match e.kind() {
+                ErrorKind::TestMoreError(TestMoreError_Args { reason }) => {
+                    assert_eq!(reason, "called without more")
+                }
+                k => panic!("unexpected error: {}", k),
+            },
+            Ok(r) => panic!("unexpected reply: {:?}", r.state),
+        }
+        Ok(())
+    })
+}
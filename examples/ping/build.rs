@@ -1,5 +1,14 @@
 extern crate varlink_generator;
 
+use varlink_generator::GeneratorOptions;
+
 fn main() {
-    varlink_generator::cargo_build_tosource("src/org.example.ping.varlink", true);
+    varlink_generator::cargo_build_tosource_options(
+        "src/org.example.ping.varlink",
+        true,
+        &GeneratorOptions {
+            example_tests: true,
+            ..Default::default()
+        },
+    );
 }
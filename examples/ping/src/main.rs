@@ -1,17 +1,9 @@
 use std::env;
-use std::io::{BufRead, Read, Write};
 use std::process::exit;
-use std::sync::{Arc, RwLock};
 
-use varlink::{Call, Connection, VarlinkService};
+use varlink::Connection;
 
-use crate::org_example_ping::*;
-
-// Dynamically build the varlink rust code.
-mod org_example_ping;
-
-#[cfg(test)]
-mod test;
+use ping::{run_client, run_server};
 
 // Main
 
@@ -88,471 +80,3 @@ fn main() {
         }
     });
 }
-
-// Client
-
-fn run_client(connection: &Arc<RwLock<varlink::Connection>>) -> Result<()> {
-    {
-        let mut iface = VarlinkClient::new(connection.clone());
-        let ping = String::from("Test");
-
-        let reply = iface.ping(ping.clone()).call()?;
-        assert_eq!(ping, reply.pong);
-        eprintln!("Pong: '{}'", reply.pong);
-
-        let reply = iface.ping(ping.clone()).call()?;
-        assert_eq!(ping, reply.pong);
-        eprintln!("Pong: '{}'", reply.pong);
-
-        let reply = iface.ping(ping.clone()).call()?;
-        assert_eq!(ping, reply.pong);
-        eprintln!("Pong: '{}'", reply.pong);
-
-        let _reply = iface.upgrade().upgrade()?;
-        eprintln!("Client: upgrade()");
-    }
-
-    // Extended upgrade protocol test
-    {
-        // talk our own protocol on an upgraded connection
-        let mut conn = connection.write().unwrap();
-        let mut writer = conn.writer.take().unwrap();
-        eprintln!("Client: send \"test test\\nline 2\\n\"");
-
-        writer
-            .write_all(b"test test\nline 2\n")
-            .map_err(varlink::map_context!())?;
-        conn.writer = Some(writer);
-        let mut buf = Vec::new();
-        let mut reader = conn.reader.take().unwrap();
-        if reader
-            .read_until(b'\n', &mut buf)
-            .map_err(varlink::map_context!())?
-            == 0
-        {
-            // incomplete data, in real life, store all bytes for the next call
-            // for now just read the rest
-            reader
-                .read_to_end(&mut buf)
-                .map_err(varlink::map_context!())?;
-        };
-        eprintln!("Client: upgraded got: {}", String::from_utf8_lossy(&buf));
-        let mut buf = Vec::new();
-        if reader
-            .read_until(b'\n', &mut buf)
-            .map_err(varlink::map_context!())?
-            == 0
-        {
-            // incomplete data, in real life, store all bytes for the next call
-            // for now just read the rest
-            reader
-                .read_to_end(&mut buf)
-                .map_err(varlink::map_context!())?;
-        };
-        eprintln!("Client: upgraded got: {}", String::from_utf8_lossy(&buf));
-        let mut writer = conn.writer.take().unwrap();
-        eprintln!("Client: send \"End\\n\"");
-        writer
-            .write_all(b"End\n")
-            .map_err(varlink::map_context!())?;
-        writer.flush().map_err(varlink::map_context!())?;
-        let mut buf = Vec::new();
-        if reader
-            .read_until(b'\n', &mut buf)
-            .map_err(varlink::map_context!())?
-            == 0
-        {
-            // incomplete data, in real life, store all bytes for the next call
-            // for now just read the rest
-            reader
-                .read_to_end(&mut buf)
-                .map_err(varlink::map_context!())?;
-        };
-        eprintln!("Client: upgraded got: {}", String::from_utf8_lossy(&buf));
-        conn.writer = Some(writer);
-        conn.reader = Some(reader);
-    }
-    Ok(())
-}
-
-// Server
-
-struct MyOrgExamplePing;
-
-impl org_example_ping::VarlinkInterface for MyOrgExamplePing {
-    fn ping(&self, call: &mut dyn Call_Ping, ping: String) -> varlink::Result<()> {
-        call.reply(ping)
-    }
-
-    fn upgrade(&self, call: &mut dyn Call_Upgrade) -> varlink::Result<()> {
-        eprintln!("Server: called upgrade");
-        call.to_upgraded();
-        call.reply()
-    }
-
-    // An upgraded connection has its own application specific protocol.
-    // Normally, there is no way back to the varlink protocol with this connection.
-    fn call_upgraded(
-        &self,
-        call: &mut Call,
-        bufreader: &mut dyn BufRead,
-    ) -> varlink::Result<Vec<u8>> {
-        loop {
-            let mut buf = String::new();
-            let len = bufreader
-                .read_line(&mut buf)
-                .map_err(varlink::map_context!())?;
-            if len == 0 {
-                eprintln!("Server: upgraded got: none");
-                // incomplete data, in real life, store all bytes for the next call
-                // return Ok(buf.as_bytes().to_vec());
-                return Err(varlink::context!(varlink::ErrorKind::ConnectionClosed).into());
-            }
-            eprintln!("Server: upgraded got: {}", buf);
-
-            call.writer
-                .write_all(b"server reply: ")
-                .map_err(varlink::map_context!())?;
-            call.writer
-                .write_all(buf.as_bytes())
-                .map_err(varlink::map_context!())?;
-            call.writer.flush().map_err(varlink::map_context!())?;
-
-            if buf.eq("End\n") {
-                break;
-            }
-        }
-        eprintln!("Server: upgraded ending");
-        Ok(Vec::new())
-    }
-}
-
-#[cfg(unix)]
-mod multiplex {
-    use std::collections::HashMap;
-    use std::io::{self, BufRead, BufReader, Error, Read, Write};
-    use std::sync::{Arc, RwLock};
-    use std::thread;
-
-    use varlink::{ConnectionHandler, Listener, ServerStream};
-
-    struct FdTracker {
-        stream: Option<ServerStream>,
-        buffer: Option<Vec<u8>>,
-    }
-
-    impl FdTracker {
-        fn shutdown(&mut self) -> varlink::Result<()> {
-            self.stream.as_mut().unwrap().shutdown()
-        }
-        fn chain_buffer(&mut self, buf: &mut Vec<u8>) {
-            self.buffer.as_mut().unwrap().append(buf);
-        }
-        #[allow(clippy::ptr_arg)]
-        fn fill_buffer(&mut self, buf: &Vec<u8>) {
-            self.buffer.as_mut().unwrap().clone_from(buf);
-        }
-        fn buf_as_slice(&mut self) -> &[u8] {
-            self.buffer.as_mut().unwrap().as_slice()
-        }
-        fn write(&mut self, out: &[u8]) -> io::Result<usize> {
-            self.stream.as_mut().unwrap().write(out)
-        }
-        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-            self.stream.as_mut().unwrap().read(buf)
-        }
-    }
-
-    // listen_multiplex is just an example, if you don't want to use varlink::listen()
-    // and how to build your own main-loop and use the low-level varlink::handle() instead
-    pub fn listen_multiplex<
-        S: ?Sized + AsRef<str>,
-        H: ConnectionHandler + Send + Sync + 'static,
-    >(
-        handler: H,
-        address: &S,
-        idle_timeout: u64,
-    ) -> varlink::Result<()> {
-        let timeout: i32 = match idle_timeout {
-            0 => -1,
-            n => (n * 1000) as i32,
-        };
-
-        let handler = Arc::new(handler);
-        let mut fdmap: HashMap<i32, FdTracker> = HashMap::new();
-        let mut fds = Vec::new();
-        let mut threads = Vec::new();
-        let listener = Listener::new(address)?;
-        let upgraded_in_use = Arc::new(RwLock::new(0));
-
-        listener.set_nonblocking(true)?;
-
-        fds.push(libc::pollfd {
-            fd: listener
-                .as_raw_fd()
-                .ok_or_else(|| varlink::context!(varlink::error::ErrorKind::ConnectionClosed))?,
-            revents: 0,
-            events: libc::POLLIN,
-        });
-
-        loop {
-            // Read activity on listening socket
-            if fds[0].revents != 0 {
-                let mut client = listener.accept(0)?;
-
-                client.set_nonblocking(true)?;
-
-                let fd = client.as_raw_fd();
-                fds.push(libc::pollfd {
-                    fd,
-                    revents: 0,
-                    events: libc::POLLIN,
-                });
-
-                fdmap.insert(
-                    fd,
-                    FdTracker {
-                        stream: Some(client),
-                        buffer: Some(Vec::new()),
-                    },
-                );
-            }
-
-            // Store which indices to remove
-            let mut indices_to_remove = vec![];
-
-            // Check client connections ...
-            for (i, fds_item) in fds.iter().enumerate().skip(1) {
-                if fds_item.revents != 0 {
-                    let mut upgraded_iface: Option<String> = None;
-                    let tracker = fdmap.get_mut(&fds_item.fd).unwrap();
-                    loop {
-                        let mut readbuf: [u8; 8192] = [0; 8192];
-
-                        match tracker.read(&mut readbuf) {
-                            Ok(0) => {
-                                let _ = tracker.shutdown();
-                                indices_to_remove.push(i);
-                                break;
-                            }
-                            Ok(len) => {
-                                let mut out: Vec<u8> = Vec::new();
-                                tracker.chain_buffer(&mut readbuf[0..len].to_vec());
-                                eprintln!(
-                                    "Handling: {}",
-                                    String::from_utf8_lossy(&tracker.buf_as_slice())
-                                );
-
-                                match handler.handle(&mut tracker.buf_as_slice(), &mut out, None) {
-                                    // TODO: buffer output and write only on POLLOUT
-                                    Ok((unprocessed_bytes, last_iface)) => {
-                                        upgraded_iface = last_iface;
-                                        if !unprocessed_bytes.is_empty() {
-                                            eprintln!(
-                                                "Unprocessed bytes: {}",
-                                                String::from_utf8_lossy(&unprocessed_bytes)
-                                            );
-                                        }
-                                        tracker.fill_buffer(&unprocessed_bytes);
-
-                                        if let Err(err) = tracker.write(out.as_ref()) {
-                                            eprintln!("write error: {}", err);
-                                            let _ = tracker.shutdown();
-                                            indices_to_remove.push(i);
-                                            break;
-                                        }
-                                    }
-                                    Err(e) => match e.kind() {
-                                        err => {
-                                            eprintln!("handler error: {}", err);
-                                            let _ = tracker.shutdown();
-                                            indices_to_remove.push(i);
-                                            break;
-                                        }
-                                    },
-                                }
-                            }
-                            Err(e) => match e.kind() {
-                                io::ErrorKind::WouldBlock => {
-                                    break;
-                                }
-                                _ => {
-                                    let _ = tracker.shutdown();
-                                    indices_to_remove.push(i);
-                                    eprintln!("IO error: {}", e);
-                                    break;
-                                }
-                            },
-                        }
-                    }
-                    if upgraded_iface.is_some() {
-                        eprintln!("Upgraded MODE");
-                        // upgraded mode... thread away the server
-                        // feed it directly with the client stream
-                        // If you have a better idea, open an Issue or PR on github
-                        indices_to_remove.push(i);
-
-                        let j = thread::spawn({
-                            eprintln!("upgraded thread");
-                            let handler = handler.clone();
-                            let mut stream = tracker.stream.take().unwrap();
-                            let buffer = tracker.buffer.take().unwrap();
-                            let upgraded_in_use = upgraded_in_use.clone();
-                            move || {
-                                let _r = stream.set_nonblocking(false);
-                                let (reader, mut writer) = stream.split().unwrap();
-                                let br = BufReader::new(reader);
-                                let mut bufreader = Box::new(buffer.chain(br));
-                                let mut upgraded_iface = upgraded_iface.take();
-
-                                {
-                                    let mut ctr = upgraded_in_use.write().unwrap();
-                                    *ctr += 1;
-                                }
-                                loop {
-                                    match handler.handle(
-                                        &mut bufreader,
-                                        &mut writer,
-                                        upgraded_iface,
-                                    ) {
-                                        Ok((unread, iface)) => {
-                                            upgraded_iface = iface;
-                                            match bufreader.fill_buf() {
-                                                Err(_) => {
-                                                    eprintln!("Upgraded end");
-                                                    break;
-                                                }
-                                                Ok(buf) => {
-                                                    if buf.is_empty() & &unread.is_empty() {
-                                                        eprintln!("Upgraded end");
-                                                        break;
-                                                    }
-
-                                                    if !unread.is_empty() {
-                                                        eprintln!(
-                                                            "Not handled bytes: {}",
-                                                            String::from_utf8_lossy(&unread)
-                                                        );
-                                                        break;
-                                                    }
-
-                                                    if !buf.is_empty() {
-                                                        eprintln!(
-                                                            "fill_buf(): {}",
-                                                            String::from_utf8_lossy(&buf)
-                                                        );
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        Err(err) => match err.kind() {
-                                            varlink::ErrorKind::ConnectionClosed => {
-                                                eprintln!("Upgraded end");
-                                                break;
-                                            }
-                                            _ => {
-                                                eprintln!("Upgraded end: {}", err);
-                                                break;
-                                            }
-                                        },
-                                    }
-                                }
-                                {
-                                    let mut ctr = upgraded_in_use.write().unwrap();
-                                    *ctr -= 1;
-                                }
-                            }
-                        });
-                        threads.push(j);
-                    }
-                }
-            }
-
-            // We can't modify the vector while we are traversing it, so update now.
-            for i in indices_to_remove {
-                fdmap.remove(&fds[i].fd);
-                fds.remove(i);
-            }
-
-            let r = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
-
-            if r < 0 {
-                for t in threads {
-                    let _r = t.join();
-                }
-                return Err(Error::last_os_error())
-                    .map_err(varlink::map_context!())
-                    .map_err(|e| e.into());
-            }
-
-            if r == 0 && fds.len() == 1 && *upgraded_in_use.read().unwrap() == 0 {
-                eprintln!("listen_multiplex: Waiting for threads to end.");
-                for t in threads {
-                    let _r = t.join();
-                }
-
-                return Err(varlink::context!(varlink::ErrorKind::Timeout).into());
-            }
-        }
-    }
-}
-
-fn run_server(address: &str, timeout: u64, multiplex: bool) -> varlink::Result<()> {
-    let myorgexampleping = MyOrgExamplePing;
-    let myinterface = org_example_ping::new(Box::new(myorgexampleping));
-    let service = VarlinkService::new(
-        "org.varlink",
-        "test ping service",
-        "0.1",
-        "http://varlink.org",
-        vec![Box::new(myinterface)],
-    );
-
-    #[cfg(windows)]
-    {
-        let _ = multiplex;
-        varlink::listen(
-            service,
-            &address,
-            &varlink::ListenConfig {
-                idle_timeout: timeout,
-                // stop_listening: Some(stop_listening),
-                ..Default::default()
-            },
-        )?;
-    }
-
-    #[cfg(unix)]
-    {
-        if multiplex {
-            // Demonstrate a single process, single-threaded service
-            multiplex::listen_multiplex(service, &address, timeout)?;
-        } else {
-            /*
-            use std::sync::atomic::Ordering;
-            use std::{thread, time};
-            let stop_listening = Arc::new(std::sync::atomic::AtomicBool::new(false));
-
-            let child = {
-                let stop_running = stop_listening.clone();
-                thread::spawn(move || {
-                    thread::sleep(time::Duration::from_secs(10));
-                    stop_running.store(true, Ordering::Relaxed);
-                })
-            };
-            */
-            varlink::listen(
-                service,
-                &address,
-                &varlink::ListenConfig {
-                    idle_timeout: timeout,
-                    // stop_listening: Some(stop_listening),
-                    ..Default::default()
-                },
-            )?;
-
-            //child.join().expect("Error joining thread");
-        }
-    }
-    Ok(())
-}
@@ -7,11 +7,22 @@ use std::io::BufRead;
 use std::sync::{Arc, RwLock};
 use varlink::{self, CallTrait};
 #[allow(dead_code)]
+pub const INTERFACE_NAME: &str = "org.example.ping";
+#[allow(dead_code)]
+pub mod method_names {
+    pub const PING: &str = "org.example.ping.Ping";
+    pub const UPGRADE: &str = "org.example.ping.Upgrade";
+}
+#[allow(dead_code)]
+pub mod error_names {
+    pub const PING_ERROR: &str = "org.example.ping.PingError";
+}
+#[allow(dead_code)]
 #[derive(Clone, PartialEq, Debug)]
 pub enum ErrorKind {
     Varlink_Error,
     VarlinkReply_Error,
-    PingError(Option<PingError_Args>),
+    PingError(PingError_Args),
 }
 impl ::std::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
@@ -112,14 +123,24 @@ impl From<&varlink::Reply> for ErrorKind {
                     ..
                 } => match serde_json::from_value(p.clone()) {
                     Ok(v) => ErrorKind::PingError(v),
-                    Err(_) => ErrorKind::PingError(None),
+                    Err(_) => ErrorKind::VarlinkReply_Error,
                 },
-                _ => ErrorKind::PingError(None),
+                _ => ErrorKind::VarlinkReply_Error,
             },
             _ => ErrorKind::VarlinkReply_Error,
         }
     }
 }
+impl From<PingError_Args> for ErrorKind {
+    fn from(v: PingError_Args) -> Self {
+        ErrorKind::PingError(v)
+    }
+}
+impl From<PingError_Args> for Error {
+    fn from(v: PingError_Args) -> Self {
+        Error(ErrorKind::PingError(v), None, None)
+    }
+}
 pub trait VarlinkCallError: varlink::CallTrait {
     fn reply_ping_error(&mut self, r#parameter: i64) -> varlink::Result<()> {
         self.reply_struct(varlink::Reply::error(
@@ -132,6 +153,7 @@ pub trait VarlinkCallError: varlink::CallTrait {
     }
 }
 impl<'a> VarlinkCallError for varlink::Call<'a> {}
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct PingError_Args {
     pub r#parameter: i64,
@@ -149,6 +171,16 @@ pub trait Call_Ping: VarlinkCallError {
     fn reply(&mut self, r#pong: String) -> varlink::Result<()> {
         self.reply_struct(Ping_Reply { r#pong }.into())
     }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self, r#pong: String) -> varlink::Result<()> {
+        self.reply_struct_continues(Ping_Reply { r#pong }.into())
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self, r#pong: String) -> varlink::Result<()> {
+        self.reply_struct_final(Ping_Reply { r#pong }.into())
+    }
 }
 impl<'a> Call_Ping for varlink::Call<'a> {}
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -160,9 +192,20 @@ pub trait Call_Upgrade: VarlinkCallError {
     fn reply(&mut self) -> varlink::Result<()> {
         self.reply_struct(varlink::Reply::parameters(None))
     }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self) -> varlink::Result<()> {
+        self.reply_struct_continues(varlink::Reply::parameters(None))
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self) -> varlink::Result<()> {
+        self.reply_struct_final(varlink::Reply::parameters(None))
+    }
 }
 impl<'a> Call_Upgrade for varlink::Call<'a> {}
 pub trait VarlinkInterface {
+    #[doc = "Returns the same string\n\n# Examples\n\n```text\n-> {\"ping\":\"Test\"}\n<- {\"pong\":\"Test\"}\n```"]
     fn ping(&self, call: &mut dyn Call_Ping, r#ping: String) -> varlink::Result<()>;
     fn upgrade(&self, call: &mut dyn Call_Upgrade) -> varlink::Result<()>;
     fn call_upgraded(
@@ -174,6 +217,7 @@ pub trait VarlinkInterface {
     }
 }
 pub trait VarlinkClientInterface {
+    #[doc = "Returns the same string\n\n# Examples\n\n```text\n-> {\"ping\":\"Test\"}\n<- {\"pong\":\"Test\"}\n```"]
     fn ping(&mut self, r#ping: String) -> varlink::MethodCall<Ping_Args, Ping_Reply, Error>;
     fn upgrade(&mut self) -> varlink::MethodCall<Upgrade_Args, Upgrade_Reply, Error>;
 }
@@ -213,11 +257,14 @@ pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfacePr
 }
 impl varlink::Interface for VarlinkInterfaceProxy {
     fn get_description(&self) -> &'static str {
-        "# Example service\ninterface org.example.ping\n\n# Returns the same string\nmethod Ping(ping: string) -> (pong: string)\n\nmethod Upgrade() -> ()\n\nerror PingError(parameter: int)"
+        "# Example service\ninterface org.example.ping\n\n# Returns the same string\n# Example: {\"ping\": \"Test\"} -> {\"pong\": \"Test\"}\nmethod Ping(ping: string) -> (pong: string)\n\nmethod Upgrade() -> ()\n\nerror PingError(parameter: int)"
     }
     fn get_name(&self) -> &'static str {
         "org.example.ping"
     }
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        Some(&["Ping", "Upgrade"])
+    }
     fn call_upgraded(
         &self,
         call: &mut varlink::Call,
@@ -226,27 +273,44 @@ impl varlink::Interface for VarlinkInterfaceProxy {
         self.inner.call_upgraded(call, bufreader)
     }
     fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
-        let req = call.request.unwrap();
+        let req = call.require_request()?;
         match req.method.as_ref() {
             "org.example.ping.Ping" => {
-                if let Some(args) = req.parameters.clone() {
-                    let args: Ping_Args = match serde_json::from_value(args) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let es = format!("{}", e);
-                            let _ = call.reply_invalid_parameter(es.clone());
-                            return Err(
-                                varlink::context!(varlink::ErrorKind::SerdeJsonDe(es)).into()
-                            );
-                        }
-                    };
-                    self.inner.ping(call as &mut dyn Call_Ping, args.r#ping)
-                } else {
-                    call.reply_invalid_parameter("parameters".into())
-                }
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: Ping_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner.ping(call as &mut dyn Call_Ping, args.r#ping)
             }
             "org.example.ping.Upgrade" => self.inner.upgrade(call as &mut dyn Call_Upgrade),
             m => call.reply_method_not_found(String::from(m)),
         }
     }
 }
+#[cfg(test)]
+mod example_tests {
+    use super::*;
+    #[test]
+    fn ping_example_0() {
+        let args: Ping_Args = serde_json::from_str("{\"ping\":\"Test\"}").unwrap();
+        assert_eq!(
+            serde_json::to_value(&args).unwrap(),
+            serde_json::from_str::<serde_json::Value>("{\"ping\":\"Test\"}").unwrap()
+        );
+        let reply: Ping_Reply = serde_json::from_str("{\"pong\":\"Test\"}").unwrap();
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            serde_json::from_str::<serde_json::Value>("{\"pong\":\"Test\"}").unwrap()
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{BufRead, Write};
+use std::{thread, time};
+
+use varlink::{Connection, OrgVarlinkServiceClient, OrgVarlinkServiceInterface};
+
+use ping::org_example_ping::{VarlinkClient, VarlinkClientInterface};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[test]
+fn test_ping() -> Result<()> {
+    let address = "unix:org.example.ping.integration";
+    let child = thread::spawn(move || {
+        if let Err(e) = ping::run_server(address, 1, false) {
+            match e.kind() {
+                varlink::ErrorKind::Timeout => {}
+                _ => panic!("error: {}", e),
+            }
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    {
+        let connection = Connection::with_address(address)?;
+
+        let mut iface = VarlinkClient::new(connection.clone());
+        assert_eq!(iface.ping("Test".into()).call()?.pong, "Test");
+
+        let mut service = OrgVarlinkServiceClient::new(connection.clone());
+        let description = service.get_interface_description("org.example.ping")?;
+        assert!(description
+            .description
+            .unwrap()
+            .contains("method Upgrade()"));
+
+        match service.get_interface_description("org.example.missing") {
+            Err(e) => assert_eq!(
+                *e.kind(),
+                varlink::ErrorKind::InvalidParameter("interface".into())
+            ),
+            Ok(_) => panic!("found org.example.missing"),
+        }
+
+        // a missing required field is named in the error reply, which ends the connection
+        let reply =
+            varlink::MethodCall::<serde_json::Value, serde_json::Value, varlink::Error>::new(
+                Connection::with_address(address)?,
+                "org.example.ping.Ping",
+                serde_json::json!({}),
+            )
+            .call();
+        match reply {
+            Err(e) => assert_eq!(
+                *e.kind(),
+                varlink::ErrorKind::InvalidParameter("ping".into())
+            ),
+            Ok(_) => panic!("called Ping without ping"),
+        }
+
+        // switch to the line based protocol, which echoes every line until `End`
+        iface.upgrade().upgrade()?;
+        let mut conn = connection.write().unwrap();
+        let writer = conn.writer.as_mut().unwrap();
+        writer.write_all(b"Hello\nEnd\n")?;
+        writer.flush()?;
+
+        let reader = conn.reader.as_mut().unwrap();
+        for expected in &["server reply: Hello\n", "server reply: End\n"] {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            assert_eq!(line, *expected);
+        }
+    }
+
+    child.join().map_err(|_| "Error joining thread")?;
+    Ok(())
+}
@@ -0,0 +1,5 @@
+extern crate varlink_generator;
+
+fn main() {
+    varlink_generator::cargo_build_tosource("src/org.example.transfer.varlink", true);
+}
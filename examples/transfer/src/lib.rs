@@ -0,0 +1,172 @@
+//! A file store, which transfers the file contents over an upgraded connection
+//!
+//! `List` and `Remove` are plain varlink methods. After `Transfer`, the connection
+//! speaks a simple line based protocol with the raw file data in between, so the
+//! data does not have to be encoded in JSON.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use std::sync::{Arc, RwLock};
+
+use varlink::framing::read_line;
+use varlink::{Call, Connection, Upgraded, VarlinkService};
+
+use crate::org_example_transfer::*;
+
+// Dynamically build the varlink rust code.
+pub mod org_example_transfer;
+
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Files larger than this are refused
+pub const MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The maximum length of a request or answer line
+const MAX_LINE: usize = 4096;
+
+// Client
+
+/// Switches `connection` to the transfer protocol and returns the stream for `put`
+/// and `get`.
+///
+/// The connection cannot be used for varlink calls afterwards.
+pub fn start_transfer(connection: &Arc<RwLock<Connection>>) -> Result<Upgraded> {
+    let (_, stream) = VarlinkClient::new(connection.clone())
+        .transfer()
+        .upgrade_stream()?;
+    Ok(stream)
+}
+
+fn read_answer(stream: &mut Upgraded) -> Result<usize> {
+    let line = read_line(stream, MAX_LINE)?.ok_or("connection closed")?;
+    if let Some(size) = line.strip_prefix("OK ") {
+        Ok(size.parse()?)
+    } else if let Some(error) = line.strip_prefix("ERROR ") {
+        Err(error.into())
+    } else {
+        Err(format!("invalid answer: '{}'", line).into())
+    }
+}
+
+/// Stores `data` as `name` on a stream returned by `start_transfer`.
+pub fn put(stream: &mut Upgraded, name: &str, data: &[u8]) -> Result<()> {
+    stream.write_all(format!("PUT {} {}\n", name, data.len()).as_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()?;
+    read_answer(stream)?;
+    Ok(())
+}
+
+/// Fetches the file `name` on a stream returned by `start_transfer`.
+pub fn get(stream: &mut Upgraded, name: &str) -> Result<Vec<u8>> {
+    varlink::framing::write_line(stream, &format!("GET {}", name))?;
+    let size = read_answer(stream)?;
+    let mut data = vec![0; size];
+    stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+// Server
+
+#[derive(Default)]
+pub struct MyOrgExampleTransfer {
+    files: RwLock<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MyOrgExampleTransfer {
+    /// Handles a single request of the transfer protocol and returns the answer.
+    fn handle_request(&self, line: &str, bufreader: &mut dyn BufRead) -> varlink::Result<Vec<u8>> {
+        if let Some(name) = line.strip_prefix("GET ") {
+            return Ok(match self.files.read().unwrap().get(name) {
+                Some(data) => {
+                    let mut answer = format!("OK {}\n", data.len()).into_bytes();
+                    answer.extend_from_slice(data);
+                    answer
+                }
+                None => b"ERROR file not found\n".to_vec(),
+            });
+        }
+
+        if let Some(args) = line.strip_prefix("PUT ") {
+            // the name may contain spaces, the size is the last word
+            let mut args = args.rsplitn(2, ' ');
+            let size = args.next().and_then(|s| s.parse::<usize>().ok());
+            return Ok(match (args.next(), size) {
+                (Some(name), Some(size)) if size <= MAX_FILE_SIZE => {
+                    let mut data = vec![0; size];
+                    bufreader
+                        .read_exact(&mut data)
+                        .map_err(varlink::map_context!())?;
+                    self.files.write().unwrap().insert(name.into(), data);
+                    format!("OK {}\n", size).into_bytes()
+                }
+                // the data of the request cannot be skipped reliably
+                (Some(_), Some(_)) => {
+                    return Err(varlink::context!(varlink::ErrorKind::ConnectionClosed))
+                }
+                _ => b"ERROR invalid request\n".to_vec(),
+            });
+        }
+
+        Ok(b"ERROR invalid request\n".to_vec())
+    }
+}
+
+impl VarlinkInterface for MyOrgExampleTransfer {
+    fn list(&self, call: &mut dyn Call_List) -> varlink::Result<()> {
+        let files = self
+            .files
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, data)| File {
+                name: name.clone(),
+                size: data.len() as i64,
+            })
+            .collect();
+        call.reply(files)
+    }
+
+    fn remove(&self, call: &mut dyn Call_Remove, name: String) -> varlink::Result<()> {
+        if self.files.write().unwrap().remove(&name).is_none() {
+            return call.reply_file_not_found(name);
+        }
+        call.reply()
+    }
+
+    fn transfer(&self, call: &mut dyn Call_Transfer) -> varlink::Result<()> {
+        call.to_upgraded();
+        call.reply()
+    }
+
+    // Called for every request on an upgraded connection.
+    fn call_upgraded(
+        &self,
+        call: &mut Call,
+        bufreader: &mut dyn BufRead,
+    ) -> varlink::Result<Vec<u8>> {
+        let line = match read_line(bufreader, MAX_LINE)? {
+            Some(line) => line,
+            None => return Err(varlink::context!(varlink::ErrorKind::ConnectionClosed)),
+        };
+
+        let answer = self.handle_request(&line, bufreader)?;
+        call.writer
+            .write_all(&answer)
+            .map_err(varlink::map_context!())?;
+        call.writer.flush().map_err(varlink::map_context!())?;
+        Ok(Vec::new())
+    }
+}
+
+pub fn run_server(address: &str, config: &varlink::ListenConfig) -> varlink::Result<()> {
+    let myinterface = org_example_transfer::new(Box::new(MyOrgExampleTransfer::default()));
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test transfer service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(myinterface)],
+    );
+    varlink::listen(service, &address, config)
+}
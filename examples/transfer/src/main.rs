@@ -0,0 +1,116 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process::exit;
+
+use varlink::Connection;
+
+use transfer::org_example_transfer::{VarlinkClient, VarlinkClientInterface};
+use transfer::Result;
+
+// Main
+
+fn print_usage(program: &str, opts: &getopts::Options) {
+    let brief = format!(
+        "Usage: {0} --varlink=<address> [--timeout=<seconds>]\n       \
+         {0} --varlink=<address> --client (list | rm <name> | put <file> | get <name>)",
+        program
+    );
+    print!("{}", opts.usage(&brief));
+}
+
+fn main() {
+    let args: Vec<_> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = getopts::Options::new();
+    opts.optopt("", "varlink", "varlink address URL", "<address>");
+    opts.optflag("", "client", "run in client mode");
+    opts.optopt("", "timeout", "server timeout", "<seconds>");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{}", f);
+            print_usage(&program, &opts);
+            return;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&program, &opts);
+        return;
+    }
+
+    let address = match matches.opt_str("varlink") {
+        Some(address) => address,
+        None => {
+            print_usage(&program, &opts);
+            eprintln!("Need varlink address.");
+            exit(1);
+        }
+    };
+
+    let ret: Result<()> = if matches.opt_present("client") {
+        run_client(&address, &matches.free)
+    } else {
+        let timeout = matches
+            .opt_str("timeout")
+            .unwrap_or_default()
+            .parse::<u64>()
+            .unwrap_or(0);
+        transfer::run_server(
+            &address,
+            &varlink::ListenConfig {
+                idle_timeout: timeout,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| e.into())
+    };
+
+    exit(match ret {
+        Ok(_) => 0,
+        Err(err) => {
+            eprintln!("error: {:?}", err);
+            1
+        }
+    });
+}
+
+// Client
+
+fn run_client(address: &str, args: &[String]) -> Result<()> {
+    let connection = Connection::with_address(address)?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["list"] => {
+            for file in VarlinkClient::new(connection).list().call()?.files {
+                println!("{:>10} {}", file.size, file.name);
+            }
+        }
+        ["rm", name] => {
+            VarlinkClient::new(connection)
+                .remove(name.to_string())
+                .call()?;
+        }
+        ["put", path] => {
+            let data = fs::read(path)?;
+            let name = std::path::Path::new(path)
+                .file_name()
+                .and_then(|n| n.to_str())
+                .ok_or("invalid file name")?;
+            let mut stream = transfer::start_transfer(&connection)?;
+            transfer::put(&mut stream, name, &data)?;
+        }
+        ["get", name] => {
+            let mut stream = transfer::start_transfer(&connection)?;
+            let data = transfer::get(&mut stream, name)?;
+            io::stdout().write_all(&data)?;
+        }
+        _ => return Err("unknown command, see --help".into()),
+    }
+    Ok(())
+}
@@ -0,0 +1,340 @@
+#![doc = "This file was automatically generated by the varlink rust generator"]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+use serde_derive::{Deserialize, Serialize};
+use serde_json;
+use std::io::BufRead;
+use std::sync::{Arc, RwLock};
+use varlink::{self, CallTrait};
+#[allow(dead_code)]
+pub const INTERFACE_NAME: &str = "org.example.transfer";
+#[allow(dead_code)]
+pub mod method_names {
+    pub const LIST: &str = "org.example.transfer.List";
+    pub const REMOVE: &str = "org.example.transfer.Remove";
+    pub const TRANSFER: &str = "org.example.transfer.Transfer";
+}
+#[allow(dead_code)]
+pub mod error_names {
+    pub const FILE_NOT_FOUND: &str = "org.example.transfer.FileNotFound";
+}
+#[allow(dead_code)]
+#[derive(Clone, PartialEq, Debug)]
+pub enum ErrorKind {
+    Varlink_Error,
+    VarlinkReply_Error,
+    FileNotFound(FileNotFound_Args),
+}
+impl ::std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match self {
+            ErrorKind::Varlink_Error => write!(f, "Varlink Error"),
+            ErrorKind::VarlinkReply_Error => write!(f, "Varlink error reply"),
+            ErrorKind::FileNotFound(v) => write!(f, "org.example.transfer.FileNotFound: {:#?}", v),
+        }
+    }
+}
+pub struct Error(
+    pub ErrorKind,
+    pub Option<Box<dyn std::error::Error + 'static + Send + Sync>>,
+    pub Option<&'static str>,
+);
+impl Error {
+    #[allow(dead_code)]
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0
+    }
+}
+impl From<ErrorKind> for Error {
+    fn from(e: ErrorKind) -> Self {
+        Error(e, None, None)
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.1
+            .as_ref()
+            .map(|e| e.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        std::fmt::Display::fmt(&self.0, f)
+    }
+}
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use std::error::Error as StdError;
+        if let Some(ref o) = self.2 {
+            std::fmt::Display::fmt(o, f)?;
+        }
+        std::fmt::Debug::fmt(&self.0, f)?;
+        if let Some(e) = self.source() {
+            std::fmt::Display::fmt("\nCaused by:\n", f)?;
+            std::fmt::Debug::fmt(&e, f)?;
+        }
+        Ok(())
+    }
+}
+#[allow(dead_code)]
+pub type Result<T> = std::result::Result<T, Error>;
+impl From<varlink::Error> for Error {
+    fn from(e: varlink::Error) -> Self {
+        match e.kind() {
+            varlink::ErrorKind::VarlinkErrorReply(r) => Error(
+                ErrorKind::from(r),
+                Some(Box::from(e)),
+                Some(concat!(file!(), ":", line!(), ": ")),
+            ),
+            _ => Error(
+                ErrorKind::Varlink_Error,
+                Some(Box::from(e)),
+                Some(concat!(file!(), ":", line!(), ": ")),
+            ),
+        }
+    }
+}
+#[allow(dead_code)]
+impl Error {
+    pub fn source_varlink_kind(&self) -> Option<&varlink::ErrorKind> {
+        use std::error::Error as StdError;
+        let mut s: &dyn StdError = self;
+        while let Some(c) = s.source() {
+            let k = self
+                .source()
+                .and_then(|e| e.downcast_ref::<varlink::Error>())
+                .and_then(|e| Some(e.kind()));
+            if k.is_some() {
+                return k;
+            }
+            s = c;
+        }
+        None
+    }
+}
+impl From<&varlink::Reply> for ErrorKind {
+    #[allow(unused_variables)]
+    fn from(e: &varlink::Reply) -> Self {
+        match e {
+            varlink::Reply {
+                error: Some(ref t), ..
+            } if t == "org.example.transfer.FileNotFound" => match e {
+                varlink::Reply {
+                    parameters: Some(p),
+                    ..
+                } => match serde_json::from_value(p.clone()) {
+                    Ok(v) => ErrorKind::FileNotFound(v),
+                    Err(_) => ErrorKind::VarlinkReply_Error,
+                },
+                _ => ErrorKind::VarlinkReply_Error,
+            },
+            _ => ErrorKind::VarlinkReply_Error,
+        }
+    }
+}
+impl From<FileNotFound_Args> for ErrorKind {
+    fn from(v: FileNotFound_Args) -> Self {
+        ErrorKind::FileNotFound(v)
+    }
+}
+impl From<FileNotFound_Args> for Error {
+    fn from(v: FileNotFound_Args) -> Self {
+        Error(ErrorKind::FileNotFound(v), None, None)
+    }
+}
+pub trait VarlinkCallError: varlink::CallTrait {
+    fn reply_file_not_found(&mut self, r#name: String) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::error(
+            "org.example.transfer.FileNotFound",
+            Some(
+                serde_json::to_value(FileNotFound_Args { r#name })
+                    .map_err(varlink::map_context!())?,
+            ),
+        ))
+    }
+}
+impl<'a> VarlinkCallError for varlink::Call<'a> {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct r#File {
+    pub r#name: String,
+    pub r#size: i64,
+}
+#[allow(dead_code)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FileNotFound_Args {
+    pub r#name: String,
+}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct List_Reply {
+    pub r#files: Vec<File>,
+}
+impl varlink::VarlinkReply for List_Reply {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct List_Args {}
+pub trait Call_List: VarlinkCallError {
+    fn reply(&mut self, r#files: Vec<File>) -> varlink::Result<()> {
+        self.reply_struct(List_Reply { r#files }.into())
+    }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self, r#files: Vec<File>) -> varlink::Result<()> {
+        self.reply_struct_continues(List_Reply { r#files }.into())
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self, r#files: Vec<File>) -> varlink::Result<()> {
+        self.reply_struct_final(List_Reply { r#files }.into())
+    }
+}
+impl<'a> Call_List for varlink::Call<'a> {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Remove_Reply {}
+impl varlink::VarlinkReply for Remove_Reply {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Remove_Args {
+    pub r#name: String,
+}
+pub trait Call_Remove: VarlinkCallError {
+    fn reply(&mut self) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::parameters(None))
+    }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self) -> varlink::Result<()> {
+        self.reply_struct_continues(varlink::Reply::parameters(None))
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self) -> varlink::Result<()> {
+        self.reply_struct_final(varlink::Reply::parameters(None))
+    }
+}
+impl<'a> Call_Remove for varlink::Call<'a> {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Transfer_Reply {}
+impl varlink::VarlinkReply for Transfer_Reply {}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Transfer_Args {}
+pub trait Call_Transfer: VarlinkCallError {
+    fn reply(&mut self) -> varlink::Result<()> {
+        self.reply_struct(varlink::Reply::parameters(None))
+    }
+    #[doc = "Reply of a `more` call, which is followed by more replies"]
+    #[allow(dead_code)]
+    fn reply_continues(&mut self) -> varlink::Result<()> {
+        self.reply_struct_continues(varlink::Reply::parameters(None))
+    }
+    #[doc = "The last reply of the call"]
+    #[allow(dead_code)]
+    fn reply_final(&mut self) -> varlink::Result<()> {
+        self.reply_struct_final(varlink::Reply::parameters(None))
+    }
+}
+impl<'a> Call_Transfer for varlink::Call<'a> {}
+pub trait VarlinkInterface {
+    #[doc = "Lists the stored files"]
+    fn list(&self, call: &mut dyn Call_List) -> varlink::Result<()>;
+    #[doc = "Removes a stored file"]
+    fn remove(&self, call: &mut dyn Call_Remove, r#name: String) -> varlink::Result<()>;
+    #[doc = "Switches the connection to the transfer protocol.\nA request is a line `PUT <name> <size>` followed by <size> bytes,\nor a line `GET <name>`. The server answers with a line `OK <size>`,\nfollowed by the file for `GET`, or with a line `ERROR <reason>`."]
+    fn transfer(&self, call: &mut dyn Call_Transfer) -> varlink::Result<()>;
+    fn call_upgraded(
+        &self,
+        _call: &mut varlink::Call,
+        _bufreader: &mut dyn BufRead,
+    ) -> varlink::Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+}
+pub trait VarlinkClientInterface {
+    #[doc = "Lists the stored files"]
+    fn list(&mut self) -> varlink::MethodCall<List_Args, List_Reply, Error>;
+    #[doc = "Removes a stored file"]
+    fn remove(&mut self, r#name: String) -> varlink::MethodCall<Remove_Args, Remove_Reply, Error>;
+    #[doc = "Switches the connection to the transfer protocol.\nA request is a line `PUT <name> <size>` followed by <size> bytes,\nor a line `GET <name>`. The server answers with a line `OK <size>`,\nfollowed by the file for `GET`, or with a line `ERROR <reason>`."]
+    fn transfer(&mut self) -> varlink::MethodCall<Transfer_Args, Transfer_Reply, Error>;
+}
+#[allow(dead_code)]
+pub struct VarlinkClient {
+    connection: Arc<RwLock<varlink::Connection>>,
+}
+impl VarlinkClient {
+    #[allow(dead_code)]
+    pub fn new(connection: Arc<RwLock<varlink::Connection>>) -> Self {
+        VarlinkClient { connection }
+    }
+}
+impl VarlinkClientInterface for VarlinkClient {
+    fn list(&mut self) -> varlink::MethodCall<List_Args, List_Reply, Error> {
+        varlink::MethodCall::<List_Args, List_Reply, Error>::new(
+            self.connection.clone(),
+            "org.example.transfer.List",
+            List_Args {},
+        )
+    }
+    fn remove(&mut self, r#name: String) -> varlink::MethodCall<Remove_Args, Remove_Reply, Error> {
+        varlink::MethodCall::<Remove_Args, Remove_Reply, Error>::new(
+            self.connection.clone(),
+            "org.example.transfer.Remove",
+            Remove_Args { r#name },
+        )
+    }
+    fn transfer(&mut self) -> varlink::MethodCall<Transfer_Args, Transfer_Reply, Error> {
+        varlink::MethodCall::<Transfer_Args, Transfer_Reply, Error>::new(
+            self.connection.clone(),
+            "org.example.transfer.Transfer",
+            Transfer_Args {},
+        )
+    }
+}
+#[allow(dead_code)]
+pub struct VarlinkInterfaceProxy {
+    inner: Box<dyn VarlinkInterface + Send + Sync>,
+}
+#[allow(dead_code)]
+pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfaceProxy {
+    VarlinkInterfaceProxy { inner }
+}
+impl varlink::Interface for VarlinkInterfaceProxy {
+    fn get_description(&self) -> &'static str {
+        "# Stores files, which are transferred over an upgraded connection\ninterface org.example.transfer\n\ntype File (name: string, size: int)\n\n# Lists the stored files\nmethod List() -> (files: []File)\n\n# Removes a stored file\nmethod Remove(name: string) -> ()\n\n# Switches the connection to the transfer protocol.\n# A request is a line `PUT <name> <size>` followed by <size> bytes,\n# or a line `GET <name>`. The server answers with a line `OK <size>`,\n# followed by the file for `GET`, or with a line `ERROR <reason>`.\nmethod Transfer() -> ()\n\nerror FileNotFound (name: string)\n"
+    }
+    fn get_name(&self) -> &'static str {
+        "org.example.transfer"
+    }
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        Some(&["List", "Remove", "Transfer"])
+    }
+    fn call_upgraded(
+        &self,
+        call: &mut varlink::Call,
+        bufreader: &mut dyn BufRead,
+    ) -> varlink::Result<Vec<u8>> {
+        self.inner.call_upgraded(call, bufreader)
+    }
+    fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
+        let req = call.require_request()?;
+        match req.method.as_ref() {
+            "org.example.transfer.List" => self.inner.list(call as &mut dyn Call_List),
+            "org.example.transfer.Remove" => {
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: Remove_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner.remove(call as &mut dyn Call_Remove, args.r#name)
+            }
+            "org.example.transfer.Transfer" => self.inner.transfer(call as &mut dyn Call_Transfer),
+            m => call.reply_method_not_found(String::from(m)),
+        }
+    }
+}
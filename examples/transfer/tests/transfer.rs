@@ -0,0 +1,79 @@
+use std::thread;
+
+use varlink::{Connection, ListenConfig, ServerHandle};
+
+use transfer::org_example_transfer::{ErrorKind, VarlinkClient, VarlinkClientInterface};
+use transfer::Result;
+
+fn with_server<F: FnOnce(&str) -> Result<()>>(address: &'static str, f: F) -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || transfer::run_server(address, &config));
+
+    // give server time to start
+    thread::sleep(std::time::Duration::from_millis(500));
+
+    let ret = f(address);
+    handle.shutdown();
+    child.join().map_err(|_| "Error joining thread")??;
+    ret
+}
+
+#[test]
+fn test_put_get() -> Result<()> {
+    with_server("unix:org.example.transfer.put_get", |address| {
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        {
+            let connection = Connection::with_address(address)?;
+            let mut stream = transfer::start_transfer(&connection)?;
+            transfer::put(&mut stream, "a file", &data)?;
+            transfer::put(&mut stream, "empty", b"")?;
+            assert_eq!(transfer::get(&mut stream, "a file")?, data);
+            assert_eq!(transfer::get(&mut stream, "empty")?, b"");
+        }
+
+        let mut iface = VarlinkClient::new(Connection::with_address(address)?);
+        let files: Vec<_> = iface
+            .list()
+            .call()?
+            .files
+            .into_iter()
+            .map(|f| (f.name, f.size))
+            .collect();
+        assert_eq!(
+            files,
+            vec![("a file".to_string(), 100_000), ("empty".to_string(), 0)]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn test_errors() -> Result<()> {
+    with_server("unix:org.example.transfer.errors", |address| {
+        {
+            let connection = Connection::with_address(address)?;
+            let mut stream = transfer::start_transfer(&connection)?;
+            match transfer::get(&mut stream, "missing") {
+                Err(e) => assert_eq!(e.to_string(), "file not found"),
+                Ok(_) => panic!("got a missing file"),
+            }
+            // the connection is still usable after an error
+            transfer::put(&mut stream, "file", b"data")?;
+        }
+
+        let mut iface = VarlinkClient::new(Connection::with_address(address)?);
+        iface.remove("file".into()).call()?;
+        match iface.remove("file".into()).call() {
+            Err(e) => match e.kind() {
+                ErrorKind::FileNotFound(args) => assert_eq!(args.name, "file"),
+                k => panic!("unexpected error: {}", k),
+            },
+            Ok(_) => panic!("removed a missing file"),
+        }
+        Ok(())
+    })
+}
@@ -66,6 +66,21 @@ fn test_exec() -> Result<()> {
     ))?)
 }
 
+#[cfg(unix)]
+#[test]
+fn test_exec_address() -> Result<()> {
+    use escargot::CargoBuild;
+    let runner = CargoBuild::new()
+        .current_release()
+        .run()
+        .map_err(mstrerr!("Error running CargoBuild"))?;
+
+    crate::run_client(Connection::with_address(&format!(
+        "exec:{} --varlink=$VARLINK_ADDRESS --timeout 4",
+        runner.path().display()
+    ))?)
+}
+
 #[test]
 fn test_wrong_address_1() {
     assert!(crate::run_server("tcpd:0.0.0.0:12345".into(), 1).is_err());
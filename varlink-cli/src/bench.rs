@@ -0,0 +1,197 @@
+//! `varlink bench`, calls a method over several connections and measures the latency
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chainerror::*;
+use varlink::{Connection, MethodCall};
+
+use crate::Result;
+
+/// The parameters of a benchmark
+pub struct Bench {
+    pub method: String,
+    pub args: serde_json::Value,
+    pub connections: usize,
+    /// calls per second of all connections together, or as fast as possible
+    pub rate: Option<f64>,
+    /// stop after this time
+    pub duration: Option<Duration>,
+    /// stop after this number of calls of all connections together
+    pub calls: Option<usize>,
+}
+
+/// The measurements of one connection
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+/// The summary of a benchmark
+pub struct Report {
+    pub calls: usize,
+    /// the calls, which were answered with an error reply
+    pub errors: usize,
+    pub elapsed: Duration,
+    /// the latencies of all calls, sorted
+    pub latencies: Vec<Duration>,
+}
+
+impl Report {
+    pub fn throughput(&self) -> f64 {
+        self.calls as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Returns the latency, which `percent` of the calls did not exceed.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::default();
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.max(1).min(self.latencies.len()) - 1]
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::default();
+        }
+        self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32
+    }
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.3}ms", d.as_secs_f64() * 1000.0)
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Calls: {} ({} errors)", self.calls, self.errors)?;
+        writeln!(f, "Duration: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "Throughput: {:.1} calls/s", self.throughput())?;
+        write!(
+            f,
+            "Latency: min {}, mean {}, p50 {}, p90 {}, p99 {}, max {}",
+            ms(self.percentile(0.0)),
+            ms(self.mean()),
+            ms(self.percentile(50.0)),
+            ms(self.percentile(90.0)),
+            ms(self.percentile(99.0)),
+            ms(self.percentile(100.0)),
+        )
+    }
+}
+
+/// Calls the method on `connection` every `interval` until `deadline` or `calls` calls.
+fn run_connection(
+    bench: &Bench,
+    connection: Arc<RwLock<Connection>>,
+    interval: Option<Duration>,
+    deadline: Option<Instant>,
+    calls: Option<usize>,
+) -> std::result::Result<Stats, String> {
+    let mut stats = Stats::default();
+    let start = Instant::now();
+
+    loop {
+        if let Some(calls) = calls {
+            if stats.latencies.len() >= calls {
+                break;
+            }
+        }
+        let next = match interval {
+            Some(interval) => start + interval * stats.latencies.len() as u32,
+            None => Instant::now(),
+        };
+        if let Some(deadline) = deadline {
+            if next >= deadline {
+                break;
+            }
+        }
+        let now = Instant::now();
+        if next > now {
+            thread::sleep(next - now);
+        }
+
+        let call_start = Instant::now();
+        let ret = MethodCall::<serde_json::Value, serde_json::Value, varlink::Error>::new(
+            connection.clone(),
+            bench.method.clone(),
+            bench.args.clone(),
+        )
+        .call();
+        stats.latencies.push(call_start.elapsed());
+
+        // the connection can be used further after an error reply
+        if let Err(e) = ret {
+            match e.kind() {
+                varlink::ErrorKind::InterfaceNotFound(_)
+                | varlink::ErrorKind::MethodNotFound(_)
+                | varlink::ErrorKind::MethodNotImplemented(_)
+                | varlink::ErrorKind::InvalidParameter(_)
+                | varlink::ErrorKind::VarlinkErrorReply(_) => stats.errors += 1,
+                _ => return Err(format!("Failed to call method '{}': {}", bench.method, e)),
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Runs `bench` with a connection returned by `connect` for each thread.
+///
+/// With a rate, the calls of a connection are sent at fixed times, a call, which is late,
+/// because the reply of the one before took too long, is sent right away.
+pub fn run<F>(bench: Bench, connect: F) -> Result<Report>
+where
+    F: Fn() -> Result<Arc<RwLock<Connection>>>,
+{
+    if bench.connections == 0 {
+        return Err(strerr!("At least one connection is needed").into());
+    }
+
+    let mut connections = Vec::new();
+    for _ in 0..bench.connections {
+        connections.push(connect()?);
+    }
+
+    let interval = match bench.rate {
+        Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(bench.connections as f64 / rate)),
+        Some(_) => return Err(strerr!("The rate must be greater than 0").into()),
+        None => None,
+    };
+
+    let bench = Arc::new(bench);
+    let start = Instant::now();
+    let deadline = bench.duration.map(|d| start + d);
+    let mut threads = Vec::new();
+
+    for (i, connection) in connections.into_iter().enumerate() {
+        // the calls are distributed over the connections as evenly as possible
+        let calls = bench.calls.map(|calls| {
+            calls / bench.connections + if i < calls % bench.connections { 1 } else { 0 }
+        });
+        let bench = bench.clone();
+        threads.push(thread::spawn(move || {
+            run_connection(&bench, connection, interval, deadline, calls)
+        }));
+    }
+
+    let mut report = Report {
+        calls: 0,
+        errors: 0,
+        elapsed: Duration::default(),
+        latencies: Vec::new(),
+    };
+    for thread in threads {
+        let stats = thread
+            .join()
+            .map_err(|_| strerr!("A benchmark thread panicked"))??;
+        report.calls += stats.latencies.len();
+        report.errors += stats.errors;
+        report.latencies.extend(stats.latencies);
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.sort();
+    Ok(report)
+}
@@ -0,0 +1,292 @@
+//! Live completion of interfaces, methods and parameters, which asks the services
+//!
+//! The completion scripts of `varlink completions` call the hidden `varlink complete`
+//! for the arguments of `call`, `bench` and `help`.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use chainerror::*;
+use varlink::{Connection, OrgVarlinkServiceClient, OrgVarlinkServiceInterface};
+use varlink_parser::{Limits, IDL};
+use varlink_stdinterfaces::org_varlink_resolver::{VarlinkClient, VarlinkClientInterface};
+
+use crate::Result;
+
+/// A completion must not hang on an unreachable address.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Calls `__varlink_complete` of `varlink complete` for the positional arguments of the
+/// subcommands and falls back to the completion of clap for everything else.
+///
+/// The words are split from `COMP_LINE`, because `COMP_WORDS` is also split at the `:`
+/// of the addresses.
+const BASH: &str = r#"
+__varlink_complete() {
+    local line=${COMP_LINE:0:COMP_POINT} words cur i cmd="" cmd_index=0 positional=()
+    read -ra words <<< "$line"
+    [[ -z $line || $line == *[[:space:]] ]] && words+=("")
+    cur=${words[${#words[@]} - 1]}
+
+    for ((i = 1; i < ${#words[@]} - 1; i++)); do
+        case ${words[i]} in
+            call|bench|help) cmd=${words[i]}; cmd_index=$i; break ;;
+        esac
+    done
+    if [[ -z $cmd || $cur == -* ]]; then
+        _varlink "$@"
+        return
+    fi
+
+    for ((i = cmd_index + 1; i < ${#words[@]} - 1; i++)); do
+        case ${words[i]} in
+            -c|-r|-d|-n|--cols|--connections|--rate|--duration|--calls) ((i++)) ;;
+            -*) ;;
+            *) positional+=("${words[i]}") ;;
+        esac
+    done
+
+    local global=("${words[@]:1:cmd_index-1}") IFS=$'\n'
+    case $cmd:${#positional[@]} in
+        help:0) COMPREPLY=($(varlink "${global[@]}" complete --interface -- "$cur" 2>/dev/null)) ;;
+        call:0|bench:0) COMPREPLY=($(varlink "${global[@]}" complete -- "$cur" 2>/dev/null)) ;;
+        call:1|bench:1)
+            COMPREPLY=($(varlink "${global[@]}" complete --arguments "${positional[0]}" -- "$cur" 2>/dev/null)) ;;
+        *) _varlink "$@"; return ;;
+    esac
+    [[ $cmd == help ]] || compopt -o nospace
+
+    # readline only replaces the part of the word after the last word break
+    local breaks=${cur%"${cur##*[$COMP_WORDBREAKS]}"}
+    COMPREPLY=("${COMPREPLY[@]#"$breaks"}")
+}
+
+complete -F __varlink_complete -o bashdefault -o default varlink
+"#;
+
+/// Replaces the last line of the zsh script of clap, which calls `_varlink`.
+const ZSH: &str = r#"
+__varlink_complete() {
+    local i cmd="" cmd_index=0
+    local -a positional global candidates
+
+    for ((i = 2; i < CURRENT; i++)); do
+        case ${words[i]} in
+            call|bench|help) cmd=${words[i]}; cmd_index=$i; break ;;
+        esac
+    done
+    if [[ -z $cmd || ${words[CURRENT]} == -* ]]; then
+        _varlink "$@"
+        return
+    fi
+
+    for ((i = cmd_index + 1; i < CURRENT; i++)); do
+        case ${words[i]} in
+            -c|-r|-d|-n|--cols|--connections|--rate|--duration|--calls) ((i++)) ;;
+            -*) ;;
+            *) positional+=(${words[i]}) ;;
+        esac
+    done
+
+    global=(${words[2,cmd_index-1]})
+    case $cmd:${#positional} in
+        help:0) candidates=(${(f)"$(varlink $global complete --interface -- ${words[CURRENT]} 2>/dev/null)"}) ;;
+        call:0|bench:0) candidates=(${(f)"$(varlink $global complete -- ${words[CURRENT]} 2>/dev/null)"}) ;;
+        call:1|bench:1)
+            candidates=(${(f)"$(varlink $global complete --arguments ${positional[1]} -- ${words[CURRENT]} 2>/dev/null)"}) ;;
+        *) _varlink "$@"; return ;;
+    esac
+
+    if [[ $cmd == help ]]; then
+        compadd -U -Q -- $candidates
+    else
+        compadd -U -Q -S '' -- $candidates
+    fi
+}
+
+compdef __varlink_complete varlink
+__varlink_complete "$@"
+"#;
+
+/// Returns `script` of clap with the live completion of `shell`.
+pub fn script(shell: &str, script: String) -> String {
+    match shell {
+        "bash" => script + BASH,
+        "zsh" => {
+            let script = script.trim_end();
+            let script = script.trim_end_matches("_varlink \"$@\"");
+            script.to_string() + ZSH
+        }
+        _ => script,
+    }
+}
+
+fn connect(address: &str) -> Result<Arc<RwLock<Connection>>> {
+    let connection = Connection::connect_with_timeout(address, TIMEOUT)
+        .map_err(mstrerr!("Failed to connect to '{}'", address))?;
+    Ok(connection)
+}
+
+/// Returns the connection to the service of `activate` or `bridge`.
+fn connect_service(
+    activate: Option<&str>,
+    bridge: Option<&str>,
+) -> Result<Option<Arc<RwLock<Connection>>>> {
+    let connection = match (activate, bridge) {
+        (Some(activate), _) => Connection::with_activate(activate)
+            .map_err(mstrerr!("Failed to connect with activate '{}'", activate))?,
+        (None, Some(bridge)) => Connection::with_bridge(bridge)
+            .map_err(mstrerr!("Failed to connect with bridge '{}'", bridge))?,
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(connection))
+}
+
+fn describe(connection: Arc<RwLock<Connection>>, interface: &str) -> Result<String> {
+    let reply = OrgVarlinkServiceClient::new(connection)
+        .get_interface_description(interface.to_string())
+        .map_err(mstrerr!(
+            "Can't get interface description for '{}'",
+            interface
+        ))?;
+    match reply.description {
+        Some(description) => Ok(description),
+        None => Err(strerr!("No description for '{}'", interface).into()),
+    }
+}
+
+/// Returns the completions of `word`, which is `[ADDRESS/]INTERFACE.METHOD`, or
+/// `[ADDRESS/]INTERFACE`, if not `methods`.
+///
+/// Without an address, the interfaces are listed by the resolver.
+pub fn complete_method(
+    word: &str,
+    methods: bool,
+    resolver: &str,
+    activate: Option<&str>,
+    bridge: Option<&str>,
+) -> Result<Vec<String>> {
+    let (prefix, rest, service) = match word.rfind('/') {
+        Some(del) => (
+            &word[..=del],
+            &word[del + 1..],
+            Some(connect(&word[..del])?),
+        ),
+        None => ("", word, connect_service(activate, bridge)?),
+    };
+
+    let interfaces: Vec<String> = match service {
+        Some(ref connection) => OrgVarlinkServiceClient::new(connection.clone())
+            .get_info()
+            .map_err(mstrerr!("Cannot call GetInfo()"))?
+            .interfaces
+            .into_iter()
+            .map(|i| i.to_string())
+            .collect(),
+        None => {
+            VarlinkClient::new(connect(resolver)?)
+                .get_info()
+                .call()
+                .map_err(mstrerr!("Cannot call GetInfo() of the resolver"))?
+                .interfaces
+        }
+    };
+
+    // the interface of the method, which is being completed
+    let interface = interfaces
+        .iter()
+        .filter(|i| rest.starts_with(&format!("{}.", i)))
+        .max_by_key(|i| i.len());
+
+    let mut candidates = Vec::new();
+    match interface {
+        Some(interface) if methods => {
+            let connection = match service {
+                Some(connection) => connection,
+                None => {
+                    let address = VarlinkClient::new(connect(resolver)?)
+                        .resolve(interface.to_string())
+                        .call()
+                        .map_err(mstrerr!("Interface '{}' not found", interface))?
+                        .address;
+                    connect(&address)?
+                }
+            };
+            let description = describe(connection, interface)?;
+            let idl = IDL::from_string_with_limits(&description, &Limits::default())
+                .map_err(mstrerr!("Can't parse '{}'", description))?;
+            for method in &idl.method_keys {
+                candidates.push(format!("{}{}.{}", prefix, interface, method));
+            }
+        }
+        _ => {
+            for interface in &interfaces {
+                if methods {
+                    candidates.push(format!("{}{}.", prefix, interface));
+                } else {
+                    candidates.push(format!("{}{}", prefix, interface));
+                }
+            }
+        }
+    }
+
+    candidates.retain(|c| c.starts_with(word));
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// Returns the completions of the key at the end of `word`, which are the JSON
+/// `ARGUMENTS` of `method`, e.g. `{"interface":` for `{"int`.
+///
+/// Only the keys of the top level are completed, which are not in `word` already.
+pub fn complete_arguments(
+    method: &str,
+    word: &str,
+    resolver: &str,
+    activate: Option<&str>,
+    bridge: Option<&str>,
+) -> Result<Vec<String>> {
+    // the key starts after the last `{` or `,`
+    let (head, tail) = match word.rfind(&['{', ','][..]) {
+        Some(n) => word.split_at(n + 1),
+        None if word.is_empty() => ("{", ""),
+        None => ("", word),
+    };
+    let key = tail.trim_start();
+    let space = &tail[..tail.len() - key.len()];
+    let partial = key.trim_start_matches('"');
+    if !partial
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Ok(Vec::new());
+    }
+
+    let (connection, method) = match connect_service(activate, bridge)? {
+        Some(connection) => (connection, method.to_string()),
+        None => {
+            let (address, method) = crate::resolve_method(method, resolver)?;
+            (connect(&address)?, method)
+        }
+    };
+    let (interface, name) = match method.rfind('.') {
+        Some(del) => (&method[..del], &method[del + 1..]),
+        None => return Err(strerr!("Invalid method '{}'", method).into()),
+    };
+
+    let description = describe(connection, interface)?;
+    let idl = IDL::from_string_with_limits(&description, &Limits::default())
+        .map_err(mstrerr!("Can't parse '{}'", description))?;
+    let method = match idl.methods.get(name) {
+        Some(method) => method,
+        None => return Err(strerr!("Method '{}' not found", method).into()),
+    };
+
+    let mut candidates = Vec::new();
+    for field in &method.input.elts {
+        if field.name.starts_with(partial) && !head.contains(&format!("\"{}\"", field.name)) {
+            candidates.push(format!("{}{}\"{}\":", head, space, field.name));
+        }
+    }
+    Ok(candidates)
+}
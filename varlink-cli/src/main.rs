@@ -3,18 +3,23 @@ use std::io;
 use std::io::prelude::*;
 use std::path::Path;
 use std::str;
+use std::time::Duration;
 
 use chainerror::*;
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, SubCommand};
 use colored_json::{ColorMode, ColoredFormatter, Colour, Output, PrettyFormatter, Style, Styler};
 
 use varlink::{
     Connection, GetInterfaceDescriptionReply, MethodCall, OrgVarlinkServiceClient,
     OrgVarlinkServiceInterface,
 };
-use varlink_parser::{Format, FormatColored, IDL};
+use varlink_parser::{lint_with, Format, FormatColored, Limits, LintRule, IDL};
 use varlink_stdinterfaces::org_varlink_resolver::{VarlinkClient, VarlinkClientInterface};
 
+use crate::bench::Bench;
+
+mod bench;
+mod complete;
 #[cfg(test)]
 mod test;
 
@@ -25,32 +30,119 @@ mod watchclose_epoll;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
-fn varlink_format(filename: &str, line_len: Option<&str>, should_colorize: bool) -> Result<()> {
+fn read_file(filename: &str) -> Result<String> {
     let mut buffer = String::new();
     File::open(Path::new(filename))
         .map_err(mstrerr!("Failed to open '{}'", filename))?
         .read_to_string(&mut buffer)
         .map_err(mstrerr!("Failed to read '{}'", filename))?;
+    Ok(buffer)
+}
+
+fn parse_error_message(e: &ChainError<varlink_parser::Error>) -> String {
+    let mut s = String::new();
+    for i in e.iter() {
+        s += &i.to_string();
+        s += "\n";
+    }
+    s
+}
 
-    let idl = IDL::from_string(&buffer).map_err(|e| {
-        let mut s = String::new();
-        for i in e.iter() {
-            s += &i.to_string();
-            s += "\n";
+fn varlink_format(
+    filenames: Vec<&str>,
+    line_len: Option<&str>,
+    check: bool,
+    in_place: bool,
+    should_colorize: bool,
+) -> Result<()> {
+    let line_len = line_len.unwrap_or("80").parse::<usize>().unwrap_or(80);
+    let mut unformatted = 0;
+
+    for filename in filenames.iter() {
+        let buffer = read_file(filename)?;
+        let idl = IDL::from_string(&buffer).map_err(|e| {
+            let s = format!("{}: {}", filename, parse_error_message(&e));
+            cherr!(e, s)
+        })?;
+        let formatted = idl.get_multiline(0, line_len);
+
+        if check {
+            if formatted != buffer {
+                println!("{}", filename);
+                unformatted += 1;
+            }
+        } else if in_place {
+            if formatted != buffer {
+                ::std::fs::write(filename, formatted)
+                    .map_err(mstrerr!("Failed to write '{}'", filename))?;
+            }
+        } else if should_colorize {
+            print!("{}", idl.get_multiline_colored(0, line_len));
+        } else {
+            print!("{}", formatted);
         }
-        cherr!(e, s)
-    })?;
-    if should_colorize {
-        println!(
-            "{}",
-            idl.get_multiline_colored(0, line_len.unwrap_or("80").parse::<usize>().unwrap_or(80))
-        );
+    }
+
+    if unformatted > 0 {
+        Err(strerr!(
+            "{} of {} files are not formatted",
+            unformatted,
+            filenames.len()
+        ))?
+    }
+    Ok(())
+}
+
+fn varlink_validate(filenames: Vec<&str>, allowed: Vec<&str>, should_colorize: bool) -> Result<()> {
+    let red: fn(w: &str) -> String = if should_colorize {
+        |w| Colour::Red.bold().paint(w).to_string()
     } else {
-        println!(
-            "{}",
-            idl.get_multiline(0, line_len.unwrap_or("80").parse::<usize>().unwrap_or(80))
-        );
+        |w| w.to_string()
+    };
+    let yellow: fn(w: &str) -> String = if should_colorize {
+        |w| Colour::Yellow.bold().paint(w).to_string()
+    } else {
+        |w| w.to_string()
     };
+
+    let rules: Vec<LintRule> = LintRule::all()
+        .iter()
+        .cloned()
+        .filter(|r| !allowed.contains(&r.name()))
+        .collect();
+    let mut invalid = 0;
+
+    for filename in filenames.iter() {
+        let buffer = read_file(filename)?;
+        let idl = match IDL::from_string(&buffer) {
+            Ok(idl) => idl,
+            Err(e) => {
+                print!(
+                    "{}: {} {}",
+                    filename,
+                    red("error:"),
+                    parse_error_message(&e)
+                );
+                invalid += 1;
+                continue;
+            }
+        };
+        let warnings = lint_with(&idl, &rules);
+        for warning in warnings.iter() {
+            println!("{}: {} {}", filename, yellow("warning:"), warning);
+        }
+        if !warnings.is_empty() {
+            invalid += 1;
+        }
+    }
+
+    if invalid > 0 {
+        Err(strerr!(
+            "{} of {} files have issues",
+            invalid,
+            filenames.len()
+        ))?
+    }
     Ok(())
 }
 
@@ -164,7 +256,7 @@ fn varlink_help(
             if should_colorize {
                 println!(
                     "{}",
-                    IDL::from_string(&desc)
+                    IDL::from_string_with_limits(&desc, &Limits::default())
                         .map_err(mstrerr!("Can't parse '{}'", desc))?
                         .get_multiline_colored(
                             0,
@@ -174,7 +266,7 @@ fn varlink_help(
             } else {
                 println!(
                     "{}",
-                    IDL::from_string(&desc)
+                    IDL::from_string_with_limits(&desc, &Limits::default())
                         .map_err(mstrerr!("Can't parse '{}'", desc))?
                         .get_multiline(0, columns.unwrap_or("80").parse::<usize>().unwrap_or(80))
                 );
@@ -186,6 +278,30 @@ fn varlink_help(
     Ok(())
 }
 
+/// Splits `url` into the address and the method, or resolves the address of the
+/// interface of the method with the resolver.
+fn resolve_method(url: &str, resolver: &str) -> Result<(String, String)> {
+    if let Some(del) = url.rfind('/') {
+        let method = &url[(del + 1)..];
+        if method.find('.').is_none() {
+            return Err(strerr!("Invalid address {}", url).into());
+        }
+        return Ok((url[0..del].into(), method.into()));
+    }
+
+    let interface = match url.rfind('.') {
+        Some(del) => &url[0..del],
+        None => return Err(strerr!("Invalid address {}", url).into()),
+    };
+    let conn = Connection::new(resolver)
+        .map_err(mstrerr!("Failed to connect with resolver '{}'", resolver))?;
+    let mut resolver = VarlinkClient::new(conn);
+    match resolver.resolve(interface.into()).call() {
+        Ok(r) => Ok((r.address, url.into())),
+        _ => Err(strerr!("Interface '{}' not found", interface).into()),
+    }
+}
+
 fn varlink_call(
     url: &str,
     args: Option<&str>,
@@ -195,56 +311,29 @@ fn varlink_call(
     bridge: Option<&str>,
     should_colorize: bool,
 ) -> Result<()> {
-    let resolved_address: String;
-    let address: &str;
-    let interface: &str;
-    let method: &str;
+    let method: String;
 
     let connection = match activate {
         Some(activate) => {
-            method = url;
+            method = url.into();
             Connection::with_activate(activate)
                 .map_err(mstrerr!("Failed to connect with activate '{}'", activate))?
         }
         None => match bridge {
             Some(bridge) => {
-                method = url;
+                method = url.into();
                 Connection::with_bridge(bridge)
                     .map_err(mstrerr!("Failed to connect with bridge '{}'", bridge))?
             }
             None => {
-                if let Some(del) = url.rfind('/') {
-                    address = &url[0..del];
-                    method = &url[(del + 1)..];
-                    if method.find('.') == None {
-                        return Err(strerr!("Invalid address {}", url).into());
-                    }
-                } else {
-                    if let Some(del) = url.rfind('.') {
-                        interface = &url[0..del];
-                        method = url;
-                        if method.find('.') == None {
-                            return Err(strerr!("Invalid address {}", url).into());
-                        }
-                    } else {
-                        return Err(strerr!("Invalid address {}", url).into());
-                    }
-                    let conn = Connection::new(resolver)
-                        .map_err(mstrerr!("Failed to connect with resolver '{}'", resolver))?;
-                    let mut resolver = VarlinkClient::new(conn);
-                    address = match resolver.resolve(interface.into()).call() {
-                        Ok(r) => {
-                            resolved_address = r.address.clone();
-                            resolved_address.as_ref()
-                        }
-                        _ => Err(strerr!("Interface '{}' not found", interface))?,
-                    };
-                }
-                Connection::with_address(address)
+                let (address, m) = resolve_method(url, resolver)?;
+                method = m;
+                Connection::with_address(&address)
                     .map_err(mstrerr!("Failed to connect to '{}'", address))?
             }
         },
     };
+    let method = method.as_str();
 
     let args = match args {
         Some(args) => serde_json::from_str(args)
@@ -294,6 +383,66 @@ fn varlink_call(
     Ok(())
 }
 
+fn varlink_bench(
+    url: &str,
+    args: Option<&str>,
+    bench: &clap::ArgMatches,
+    resolver: &str,
+    activate: Option<&str>,
+    bridge: Option<&str>,
+) -> Result<()> {
+    fn number<T: str::FromStr>(bench: &clap::ArgMatches, name: &str) -> Result<Option<T>> {
+        match bench.value_of(name) {
+            Some(v) => match v.parse() {
+                Ok(v) => Ok(Some(v)),
+                Err(_) => Err(strerr!("Invalid value '{}' of --{}", v, name).into()),
+            },
+            None => Ok(None),
+        }
+    }
+
+    let (address, method) = match (activate, bridge) {
+        (None, None) => resolve_method(url, resolver)?,
+        _ => (String::new(), url.into()),
+    };
+    let args = match args {
+        Some(args) => serde_json::from_str(args)
+            .map_err(mstrerr!("Failed to parse JSON for '{}'", args.to_string()))?,
+        None => serde_json::Value::Null,
+    };
+    let calls = number(bench, "calls")?;
+    // without a number of calls, the benchmark runs for 10 seconds
+    let duration = match number::<f64>(bench, "duration")? {
+        Some(d) if d > 0.0 => Some(Duration::from_secs_f64(d)),
+        Some(_) => return Err(strerr!("The duration must be greater than 0").into()),
+        None if calls.is_none() => Some(Duration::from_secs(10)),
+        None => None,
+    };
+
+    let bench = Bench {
+        method,
+        args,
+        connections: number(bench, "connections")?.unwrap_or(1),
+        rate: number(bench, "rate")?,
+        duration,
+        calls,
+    };
+
+    let report = bench::run(bench, || match (activate, bridge) {
+        (Some(activate), _) => Connection::with_activate(activate)
+            .map_err(mstrerr!("Failed to connect with activate '{}'", activate))
+            .map_err(|e| e.into()),
+        (None, Some(bridge)) => Connection::with_bridge(bridge)
+            .map_err(mstrerr!("Failed to connect with bridge '{}'", bridge))
+            .map_err(|e| e.into()),
+        (None, None) => Connection::with_address(&address)
+            .map_err(mstrerr!("Failed to connect to '{}'", address))
+            .map_err(|e| e.into()),
+    })?;
+    println!("{}", report);
+    Ok(())
+}
+
 fn print_call_ret(
     color_mode: ColorMode,
     cf: ColoredFormatter<PrettyFormatter>,
@@ -363,6 +512,62 @@ fn print_call_ret(
     Ok(())
 }
 
+fn varlink_monitor(
+    listen: &str,
+    address: &str,
+    filters: Vec<String>,
+    resolver: &str,
+    should_colorize: bool,
+) -> Result<()> {
+    use varlink::monitor::{monitor, Direction, Message};
+
+    let address = if address.rfind(':').is_none() {
+        let conn = Connection::new(resolver)
+            .map_err(mstrerr!("Failed to connect with resolver '{}'", resolver))?;
+        let mut resolver = VarlinkClient::new(conn);
+        match resolver.resolve(address.into()).call() {
+            Ok(r) => r.address,
+            _ => Err(strerr!("Interface '{}' not found", address))?,
+        }
+    } else {
+        address.to_string()
+    };
+
+    let color_mode = if should_colorize {
+        ColorMode::On
+    } else {
+        ColorMode::Off
+    };
+
+    let print = move |m: &Message| {
+        if !filters.is_empty() && !filters.iter().any(|f| m.matches(f)) {
+            return;
+        }
+        let (arrow, colour) = match m.direction {
+            Direction::Request => ("->", Colour::Cyan),
+            Direction::Reply => ("<-", Colour::Green),
+        };
+        let header = format!("[{}] {} {}", m.connection, arrow, m.method);
+        let json = colored_json::to_colored_json(&m.message, color_mode)
+            .unwrap_or_else(|_| m.message.to_string());
+        // a single write, so the messages of concurrent connections do not mix
+        let out = io::stdout();
+        let mut out = out.lock();
+        let _ = if should_colorize {
+            writeln!(out, "{}\n{}", colour.bold().paint(header), json)
+        } else {
+            writeln!(out, "{}\n{}", header, json)
+        };
+    };
+
+    monitor(listen, &address, &varlink::ListenConfig::default(), print).map_err(mstrerr!(
+        "Failed to monitor '{}' on '{}'",
+        address,
+        listen
+    ))?;
+    Ok(())
+}
+
 #[cfg(target_os = "linux")]
 fn varlink_bridge(
     address: Option<&str>,
@@ -528,10 +733,64 @@ fn main() {
                 )
                 .arg(Arg::with_name("ARGUMENTS").required(false)),
         )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .version(VERSION)
+                .about("Measure the throughput and latency of a method")
+                .long_about(
+                    "Calls METHOD on INTERFACE at ADDRESS over CONNECTIONS connections in \
+                     parallel and prints the throughput and the percentiles of the latency. \
+                     ARGUMENTS must be valid JSON. The benchmark stops after --calls calls or \
+                     after --duration, which defaults to 10 seconds.",
+                )
+                .arg(
+                    Arg::with_name("connections")
+                        .short("c")
+                        .long("connections")
+                        .value_name("CONNECTIONS")
+                        .help("number of connections, defaults to 1")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .short("r")
+                        .long("rate")
+                        .value_name("CALLS")
+                        .help("calls per second of all connections, defaults to unlimited")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .short("d")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .help("stop after SECONDS")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("calls")
+                        .short("n")
+                        .long("calls")
+                        .value_name("CALLS")
+                        .help("stop after CALLS calls of all connections")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("METHOD")
+                        .value_name("[ADDRESS/]INTERFACE.METHOD")
+                        .required(true),
+                )
+                .arg(Arg::with_name("ARGUMENTS").required(false)),
+        )
         .subcommand(
             SubCommand::with_name("format")
                 .version(VERSION)
                 .about("Format a varlink service file")
+                .long_about(
+                    "Prints the varlink interface definition FILEs in their canonical format. \
+                     With --check, lists the files which are not formatted and fails, if \
+                     there are any.",
+                )
                 .arg(
                     Arg::with_name("COLUMNS")
                         .short("c")
@@ -540,12 +799,57 @@ fn main() {
                         .required(false)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("check")
+                        .long("check")
+                        .help("only check, if the files are formatted")
+                        .conflicts_with("in-place"),
+                )
+                .arg(
+                    Arg::with_name("in-place")
+                        .short("i")
+                        .long("in-place")
+                        .help("rewrite the files instead of printing them"),
+                )
                 .arg(
                     Arg::with_name("FILE")
                         .required(true)
+                        .multiple(true)
                         .help("The varlink interface definition file to format"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .version(VERSION)
+                .about("Check varlink service files for errors and style issues")
+                .long_about(
+                    "Parses the varlink interface definition FILEs and checks them with all \
+                     lint rules. Fails, if a file has an error or a warning.",
+                )
+                .arg(
+                    Arg::with_name("allow")
+                        .short("a")
+                        .long("allow")
+                        .value_name("RULE")
+                        .help("do not check RULE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .possible_values(&[
+                            "naming",
+                            "unused-type",
+                            "empty-struct",
+                            "duplicate-enum-value",
+                            "rust-keyword",
+                        ]),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .required(true)
+                        .multiple(true)
+                        .help("The varlink interface definition file to check"),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("info")
                 .version(VERSION)
@@ -572,6 +876,32 @@ fn main() {
                         .takes_value(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("monitor")
+                .version(VERSION)
+                .about("Print the messages between clients and a service")
+                .long_about(
+                    "Listens on the address given with --listen and forwards all connections \
+                     to the service at ADDRESS, printing every request and reply. Clients have \
+                     to connect to the --listen address. FILTER is an interface or a method; \
+                     if given, only matching messages are printed.",
+                )
+                .arg(
+                    Arg::with_name("listen")
+                        .short("l")
+                        .long("listen")
+                        .value_name("ADDRESS")
+                        .help("address to listen on for clients")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ADDRESS")
+                        .value_name("ADDRESS|INTERFACE")
+                        .required(true),
+                )
+                .arg(Arg::with_name("FILTER").multiple(true).required(false)),
+        )
         .subcommand(
             SubCommand::with_name("resolve")
                 .version(VERSION)
@@ -579,10 +909,34 @@ fn main() {
                 .long_about("Resolve INTERFACE to the varlink address that implements it.")
                 .arg(Arg::with_name("INTERFACE").required(true)),
         )
+        .subcommand(
+            SubCommand::with_name("complete")
+                .setting(AppSettings::Hidden)
+                .about("Print the completions of a word, used by the completion scripts")
+                .arg(
+                    Arg::with_name("interface")
+                        .long("interface")
+                        .help("complete an interface instead of a method"),
+                )
+                .arg(
+                    Arg::with_name("arguments")
+                        .long("arguments")
+                        .value_name("[ADDRESS/]INTERFACE.METHOD")
+                        .help("complete the keys of the arguments of the method")
+                        .takes_value(true)
+                        .conflicts_with("interface"),
+                )
+                .arg(Arg::with_name("WORD").required(false)),
+        )
         .subcommand(
             SubCommand::with_name("completions")
                 .version(VERSION)
                 .about("Generates completion scripts for your shell")
+                .long_about(
+                    "Generates completion scripts for your shell. The scripts for bash and \
+                     zsh complete the interfaces, methods and parameters of `call`, `bench` \
+                     and `help` by asking the services or the resolver.",
+                )
                 .arg(
                     Arg::with_name("SHELL")
                         .required(true)
@@ -630,13 +984,45 @@ fn do_main(app: &mut App) -> Result<()> {
     match matches.subcommand() {
         ("completions", Some(sub_matches)) => {
             let shell = sub_matches.value_of("SHELL").unwrap();
-            app.gen_completions_to("varlink", shell.parse().unwrap(), &mut io::stdout());
+            let mut script = Vec::new();
+            app.gen_completions_to("varlink", shell.parse().unwrap(), &mut script);
+            let script = String::from_utf8(script).map_err(mstrerr!("Invalid script"))?;
+            print!("{}", complete::script(shell, script));
+        }
+        ("complete", Some(sub_matches)) => {
+            let word = sub_matches.value_of("WORD").unwrap_or("");
+            let candidates = match sub_matches.value_of("arguments") {
+                Some(method) => {
+                    complete::complete_arguments(method, word, resolver, activate, bridge)?
+                }
+                None => complete::complete_method(
+                    word,
+                    !sub_matches.is_present("interface"),
+                    resolver,
+                    activate,
+                    bridge,
+                )?,
+            };
+            for candidate in candidates {
+                println!("{}", candidate);
+            }
         }
         ("format", Some(sub_matches)) => {
-            let filename = sub_matches.value_of("FILE").unwrap();
+            let filenames = sub_matches.values_of("FILE").unwrap().collect();
             let cols = sub_matches.value_of("COLUMNS");
+            let check = sub_matches.is_present("check");
+            let in_place = sub_matches.is_present("in-place");
+
+            varlink_format(filenames, cols, check, in_place, should_colorize)?
+        }
+        ("validate", Some(sub_matches)) => {
+            let filenames = sub_matches.values_of("FILE").unwrap().collect();
+            let allowed = sub_matches
+                .values_of("allow")
+                .map(|v| v.collect())
+                .unwrap_or_default();
 
-            varlink_format(filename, cols, should_colorize)?
+            varlink_validate(filenames, allowed, should_colorize)?
         }
         ("info", Some(sub_matches)) => {
             let address = sub_matches.value_of("ADDRESS");
@@ -652,6 +1038,15 @@ fn do_main(app: &mut App) -> Result<()> {
             let address = sub_matches.value_of("connect");
             varlink_bridge(address, resolver, activate, bridge)?
         }
+        ("monitor", Some(sub_matches)) => {
+            let listen = sub_matches.value_of("listen").unwrap();
+            let address = sub_matches.value_of("ADDRESS").unwrap();
+            let filters = sub_matches
+                .values_of("FILTER")
+                .map(|v| v.map(String::from).collect())
+                .unwrap_or_default();
+            varlink_monitor(listen, address, filters, resolver, should_colorize)?
+        }
         ("help", Some(sub_matches)) => {
             let interface = sub_matches.value_of("INTERFACE").unwrap();
             let cols = sub_matches.value_of("COLUMNS");
@@ -672,6 +1067,12 @@ fn do_main(app: &mut App) -> Result<()> {
                 should_colorize,
             )?
         }
+        ("bench", Some(sub_matches)) => {
+            let method = sub_matches.value_of("METHOD").unwrap();
+            let args = sub_matches.value_of("ARGUMENTS");
+
+            varlink_bench(method, args, sub_matches, resolver, activate, bridge)?
+        }
         (_, _) => {
             app.print_help().map_err(mstrerr!("Couldn't print help"))?;
             println!();
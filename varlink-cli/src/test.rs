@@ -1,7 +1,10 @@
 use escargot::CargoBuild;
 use std::error::Error;
+use std::process::Output;
 use std::result::Result;
 
+use varlink::VarlinkService;
+
 #[test]
 fn test_exec() -> Result<(), Box<dyn Error>> {
     let runner = CargoBuild::new().current_release().run()?;
@@ -10,3 +13,253 @@ fn test_exec() -> Result<(), Box<dyn Error>> {
     assert!(cmd.output().is_ok());
     Ok(())
 }
+
+fn run_varlink(args: &[&str]) -> Result<Output, Box<dyn Error>> {
+    let runner = CargoBuild::new().current_release().run()?;
+    let output = runner
+        .command()
+        .arg("--color")
+        .arg("off")
+        .args(args)
+        .output()?;
+    Ok(output)
+}
+
+fn test_service() -> VarlinkService {
+    VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    )
+}
+
+#[test]
+fn test_info() -> Result<(), Box<dyn Error>> {
+    let server = varlink::test::run_service(test_service())?;
+
+    let output = run_varlink(&["info", server.address()])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("Vendor: org.varlink\n"));
+    assert!(stdout.contains("Product: test service\n"));
+    assert!(stdout.contains("Interfaces:\n  org.varlink.service\n"));
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_help() -> Result<(), Box<dyn Error>> {
+    let server = varlink::test::run_service(test_service())?;
+
+    let output = run_varlink(&["help", &format!("{}/org.varlink.service", server.address())])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.contains("interface org.varlink.service\n"));
+    assert!(stdout.contains("method GetInfo()"));
+
+    let output = run_varlink(&["help", &format!("{}/org.example.unknown", server.address())])?;
+    assert!(!output.status.success());
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_call() -> Result<(), Box<dyn Error>> {
+    let server = varlink::test::run_service(test_service())?;
+    let method = format!(
+        "{}/org.varlink.service.GetInterfaceDescription",
+        server.address()
+    );
+
+    for more in &[false, true] {
+        let mut args = vec!["call"];
+        if *more {
+            args.push("--more");
+        }
+        args.push(&method);
+        args.push(r#"{"interface": "org.varlink.service"}"#);
+
+        let output = run_varlink(&args)?;
+        assert!(output.status.success());
+        let reply: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+        assert!(reply["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("# The Varlink Service Interface"));
+    }
+
+    let output = run_varlink(&[
+        "call",
+        &format!("{}/org.varlink.service.Unknown", server.address()),
+    ])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("MethodNotFound"));
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_bench() -> Result<(), Box<dyn Error>> {
+    let server = varlink::test::run_service(test_service())?;
+    let method = format!("{}/org.varlink.service.GetInfo", server.address());
+
+    let output = run_varlink(&["bench", "--connections", "3", "--calls", "20", &method])?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with("Calls: 20 (0 errors)\n"));
+    assert!(stdout.contains("\nThroughput: "));
+    assert!(stdout.contains("\nLatency: min "));
+
+    // 2 connections at 40 calls per second in 0.5 seconds
+    let output = run_varlink(&["bench", "-c", "2", "-r", "40", "-d", "0.5", &method])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.starts_with("Calls: 20 (0 errors)\n"));
+
+    let output = run_varlink(&[
+        "bench",
+        "-n",
+        "5",
+        &format!("{}/org.varlink.service.Unknown", server.address()),
+    ])?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)?.starts_with("Calls: 5 (5 errors)\n"));
+
+    let output = run_varlink(&["bench", "--connections", "none", &method])?;
+    assert!(!output.status.success());
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_complete() -> Result<(), Box<dyn Error>> {
+    let server = varlink::test::run_service(test_service())?;
+    let address = server.address();
+
+    let complete = |args: &[&str]| -> Result<String, Box<dyn Error>> {
+        let mut args = args.to_vec();
+        args.insert(0, "complete");
+        let output = run_varlink(&args)?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    assert_eq!(
+        complete(&["--", &format!("{}/org", address)])?,
+        format!("{}/org.varlink.service.\n", address)
+    );
+    assert_eq!(
+        complete(&["--", &format!("{}/org.varlink.service.GetI", address)])?,
+        format!(
+            "{a}/org.varlink.service.GetInfo\n{a}/org.varlink.service.GetInterfaceDescription\n",
+            a = address
+        )
+    );
+    assert_eq!(
+        complete(&["--interface", "--", &format!("{}/", address)])?,
+        format!("{}/org.varlink.service\n", address)
+    );
+
+    let method = format!("{}/org.varlink.service.GetInterfaceDescription", address);
+    assert_eq!(
+        complete(&["--arguments", &method, "--", ""])?,
+        "{\"interface\":\n"
+    );
+    assert_eq!(
+        complete(&["--arguments", &method, "--", "'{\"int"])?,
+        "'{\"interface\":\n"
+    );
+    assert_eq!(
+        complete(&["--arguments", &method, "--", "{\"interface\": \"a\", "])?,
+        ""
+    );
+
+    let output = run_varlink(&["complete", "--", "unix:/nonexistent/org"])?;
+    assert!(!output.status.success());
+
+    let output = run_varlink(&["completions", "bash"])?;
+    let script = String::from_utf8(output.stdout)?;
+    assert!(script.starts_with("_varlink() {"));
+    assert!(script.ends_with("complete -F __varlink_complete -o bashdefault -o default varlink\n"));
+
+    let output = run_varlink(&["completions", "zsh"])?;
+    let script = String::from_utf8(output.stdout)?;
+    assert!(script.starts_with("#compdef varlink\n"));
+    assert!(script.ends_with("compdef __varlink_complete varlink\n__varlink_complete \"$@\"\n"));
+    assert!(!script.contains("\n_varlink \"$@\""));
+
+    server.stop()?;
+    Ok(())
+}
+
+fn write_temp(name: &str, content: &str) -> Result<String, Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("varlink-cli-{}-{}", std::process::id(), name));
+    std::fs::write(&path, content)?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+#[test]
+fn test_format() -> Result<(), Box<dyn Error>> {
+    let file = write_temp(
+        "format.varlink",
+        "interface org.example.format\nmethod Ping(ping:string)->(pong:string)\n",
+    )?;
+
+    let output = run_varlink(&["format", &file])?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "interface org.example.format\n\nmethod Ping(ping: string) -> (pong: string)\n"
+    );
+
+    let output = run_varlink(&["format", "--check", &file])?;
+    assert!(!output.status.success());
+    assert_eq!(String::from_utf8(output.stdout)?, format!("{}\n", file));
+
+    assert!(run_varlink(&["format", "--in-place", &file])?
+        .status
+        .success());
+    assert!(run_varlink(&["format", "--check", &file])?.status.success());
+
+    std::fs::remove_file(&file)?;
+    Ok(())
+}
+
+#[test]
+fn test_validate() -> Result<(), Box<dyn Error>> {
+    let good = write_temp(
+        "good.varlink",
+        "interface org.example.good\n\nmethod Ping(ping: string) -> (pong: string)\n",
+    )?;
+    let lint = write_temp(
+        "lint.varlink",
+        "interface org.example.lint\n\nmethod Ping(Ping: string) -> ()\n",
+    )?;
+    let broken = write_temp("broken.varlink", "interface org.example.broken\nmethod (\n")?;
+
+    assert!(run_varlink(&["validate", &good])?.status.success());
+
+    let output = run_varlink(&["validate", &good, &lint])?;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    assert!(stdout.starts_with(&format!("{}: warning: ", lint)));
+    assert!(stdout.contains("[naming]"));
+
+    assert!(run_varlink(&["validate", "--allow", "naming", &lint])?
+        .status
+        .success());
+
+    let output = run_varlink(&["validate", &broken])?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stdout)?.starts_with(&format!("{}: error: ", broken)));
+
+    for file in &[good, lint, broken] {
+        std::fs::remove_file(file)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+//! Soak test of the server and client, which fails on leaked objects
+//!
+//! Runs a service and lets client threads connect, call and disconnect in rounds,
+//! some of them abandoning their calls. After every round all calls, connections and
+//! served streams must be gone, and after the server stopped, all worker threads.
+//!
+//! ```text
+//! cargo run --release -p varlink --features leak-tracking --example soak -- [SECONDS] [CLIENTS]
+//! ```
+
+use std::env;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use varlink::{
+    Connection, Error, GetInfoArgs, ListenConfig, LiveObjects, MethodCall, OrgVarlinkServiceClient,
+    OrgVarlinkServiceInterface, ServerHandle, ServiceInfo, VarlinkService,
+};
+
+const WORKER_IDLE_TIMEOUT: u64 = 1;
+
+fn arg(n: usize, default: u64) -> u64 {
+    env::args()
+        .nth(n)
+        .map(|a| a.parse().expect("invalid number"))
+        .unwrap_or(default)
+}
+
+/// Connects, calls and disconnects `calls` times, in different ways.
+fn client(address: &str, calls: usize) -> varlink::Result<()> {
+    for i in 0..calls {
+        let connection = Connection::with_address(address)?;
+        match i % 4 {
+            0 => {
+                let mut client = OrgVarlinkServiceClient::new(connection);
+                client.get_info()?;
+                client.get_interface_description("org.varlink.service")?;
+            }
+            1 => {
+                MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+                    connection,
+                    "org.varlink.service.GetInfo",
+                    GetInfoArgs {},
+                )
+                .oneway()?;
+            }
+            2 => {
+                // abandon the call before its reply
+                let mut call = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+                    connection,
+                    "org.varlink.service.GetInfo",
+                    GetInfoArgs {},
+                );
+                call.more()?;
+            }
+            _ => {
+                let mut client = OrgVarlinkServiceClient::new(connection);
+                if client
+                    .get_interface_description("org.example.unknown")
+                    .is_ok()
+                {
+                    panic!("unknown interface found");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check(name: &str, baseline: LiveObjects, timeout: Duration) {
+    match varlink::wait_for_baseline(baseline, timeout) {
+        Ok(()) => eprintln!("{}: ok", name),
+        Err(live) => {
+            eprintln!(
+                "{}: leaked objects {:?}, baseline {:?}",
+                name, live, baseline
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn main() {
+    let seconds = arg(1, 10);
+    let clients = arg(2, 8);
+    let baseline = varlink::live_objects();
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        worker_idle_timeout: WORKER_IDLE_TIMEOUT,
+        ..Default::default()
+    };
+    let server = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "soak test",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        varlink::listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+
+    let end = Instant::now() + Duration::from_secs(seconds);
+    let mut round = 0;
+    while Instant::now() < end {
+        round += 1;
+        let threads: Vec<_> = (0..clients)
+            .map(|_| {
+                let address = address.clone();
+                thread::spawn(move || client(&address, 100))
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap().unwrap();
+        }
+
+        // the workers of the running server stay alive
+        let round_baseline = LiveObjects {
+            workers: usize::MAX,
+            ..baseline
+        };
+        check(
+            &format!("round {}", round),
+            round_baseline,
+            Duration::from_secs(5),
+        );
+    }
+
+    handle.shutdown();
+    server.join().unwrap().unwrap();
+    check(
+        "shutdown",
+        baseline,
+        Duration::from_secs(WORKER_IDLE_TIMEOUT + 5),
+    );
+}
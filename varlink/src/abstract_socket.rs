@@ -0,0 +1,90 @@
+//! Unix sockets in the abstract namespace of Linux, see `unix(7)`
+//!
+//! The name of an abstract socket starts with a zero byte and has no file, which std
+//! does not support before Rust 1.70. The sockets are returned as std sockets, so they
+//! take the same options as the sockets bound to a path.
+
+use std::io;
+use std::mem;
+use std::os::unix::io::{FromRawFd, RawFd};
+#[cfg(feature = "server")]
+use std::os::unix::net::UnixListener;
+#[cfg(feature = "client")]
+use std::os::unix::net::UnixStream;
+
+use crate::error::*;
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Returns the address of the abstract `name` and its length. An empty name is the
+/// address, which lets the kernel choose a free name on bind, see autobind in `unix(7)`.
+fn address(name: &str) -> Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // the name follows the zero byte of sun_path
+    if name.len() >= addr.sun_path.len() {
+        return Err(context!(ErrorKind::InvalidAddress));
+    }
+    for (dst, src) in addr.sun_path[1..].iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let mut len = mem::size_of::<libc::sa_family_t>();
+    if !name.is_empty() {
+        len += 1 + name.len();
+    }
+    Ok((addr, len as libc::socklen_t))
+}
+
+fn socket() -> io::Result<RawFd> {
+    check(unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) })
+}
+
+/// Binds a listener to the abstract `name`, without the leading `@`.
+#[cfg(feature = "server")]
+pub(crate) fn bind(name: &str) -> Result<UnixListener> {
+    let (addr, len) = address(name)?;
+    let fd = socket().map_err(map_context!())?;
+    // closes the socket on error
+    let listener = unsafe { UnixListener::from_raw_fd(fd) };
+    unsafe {
+        check(libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            len,
+        ))
+        .map_err(map_context!())?;
+        check(libc::listen(fd, 128)).map_err(map_context!())?;
+    }
+    Ok(listener)
+}
+
+/// Connects to the abstract `name`, without the leading `@`.
+#[cfg(feature = "client")]
+pub(crate) fn connect(name: &str) -> Result<UnixStream> {
+    if name.is_empty() {
+        return Err(context!(ErrorKind::InvalidAddress));
+    }
+    let (addr, len) = address(name)?;
+    let fd = socket().map_err(map_context!())?;
+    let stream = unsafe { UnixStream::from_raw_fd(fd) };
+    loop {
+        let ret = unsafe {
+            libc::connect(
+                fd,
+                &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+                len,
+            )
+        };
+        match check(ret) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(context!(e, ErrorKind::from(&e))),
+            Ok(_) => return Ok(stream),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! Socket activation and readiness notification for a service manager, e.g. systemd
+//!
+//! A service manager passes the listening sockets of a service with the environment
+//! variables `LISTEN_FDS`, `LISTEN_PID` and `LISTEN_FDNAMES`, see `sd_listen_fds(3)`.
+//! [`varlink::listen`] serves the socket named `varlink` (or
+//! `ListenConfig::activation_fd_name`), if several are passed, and
+//! [`listen_activated`] serves several of them at once.
+//!
+//! [`notify_ready`] tells the service manager, that the service is up, for
+//! services of `Type=notify`.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! let service = varlink::VarlinkService::new(
+//!     "org.varlink",
+//!     "test service",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     vec![/* Your varlink interfaces go here */],
+//! );
+//!
+//! // serve the sockets `public` and `admin` of the socket unit
+//! varlink::activation::notify_ready().unwrap();
+//! varlink::activation::listen_activated(service, &["public", "admin"], &Default::default())
+//!     .unwrap();
+//! ```
+//!
+//! [`varlink::listen`]: ../fn.listen.html
+//! [`listen_activated`]: fn.listen_activated.html
+//! [`notify_ready`]: fn.notify_ready.html
+
+use std::env;
+use std::process;
+#[cfg(unix)]
+use std::sync::Arc;
+#[cfg(unix)]
+use std::{io, mem, thread};
+
+#[cfg(unix)]
+use crate::error::*;
+#[cfg(unix)]
+use crate::server::{listen_on, ListenConfig, Listener};
+
+/// The first file descriptor passed by socket activation
+const LISTEN_FDS_START: usize = 3;
+
+/// Returns the activated file descriptors of the process `pid` with their names.
+///
+/// An empty value stands for an unset variable.
+fn parse_fds(
+    listen_fds: &str,
+    listen_pid: &str,
+    listen_fdnames: &str,
+    pid: u32,
+) -> Vec<(usize, String)> {
+    let nfds = match listen_fds.parse::<usize>() {
+        Ok(n) => n,
+        _ => return Vec::new(),
+    };
+    match listen_pid.parse::<u32>() {
+        Ok(p) if p == pid => {}
+        _ => return Vec::new(),
+    }
+    let mut names = listen_fdnames.split(':');
+    (0..nfds)
+        .map(|i| {
+            let name = names.next().unwrap_or("").to_string();
+            (LISTEN_FDS_START + i, name)
+        })
+        .collect()
+}
+
+/// The file descriptors passed to this process by socket activation, with their names
+fn activated_fds() -> Vec<(usize, String)> {
+    let var = |name| env::var(name).unwrap_or_default();
+    parse_fds(
+        &var("LISTEN_FDS"),
+        &var("LISTEN_PID"),
+        &var("LISTEN_FDNAMES"),
+        process::id(),
+    )
+}
+
+/// Returns the activated socket to serve with `varlink::listen`.
+///
+/// This is the only passed socket, or the one named `name`.
+pub(crate) fn activation_fd(name: &str) -> Option<usize> {
+    let fds = activated_fds();
+    if fds.len() == 1 {
+        return Some(fds[0].0);
+    }
+    fds.into_iter().find(|(_, n)| n == name).map(|(fd, _)| fd)
+}
+
+/// Takes ownership of the activated sockets, for which `filter` returns `true`.
+#[cfg(unix)]
+fn take_listeners<F: Fn(&str) -> bool>(filter: F) -> Result<Vec<(String, Listener)>> {
+    let mut listeners = Vec::new();
+    for (fd, name) in activated_fds() {
+        if !filter(&name) {
+            continue;
+        }
+        let mut listener = unsafe { Listener::from_raw_fd(fd as libc::c_int)? };
+        listener.mark_activated();
+        listeners.push((name, listener));
+    }
+    Ok(listeners)
+}
+
+/// Returns all sockets passed by socket activation, with their names from `LISTEN_FDNAMES`.
+///
+/// The names of the sockets are empty, if `LISTEN_FDNAMES` is not set. The listeners
+/// own the sockets, so call this only once.
+#[cfg(unix)]
+pub fn activation_listeners() -> Result<Vec<(String, Listener)>> {
+    take_listeners(|_| true)
+}
+
+/// Serves the activated sockets named `names` concurrently, or all of them, if `names`
+/// is empty.
+///
+/// Every socket is served like with [`varlink::listen_on`] and `listen_config`, all
+/// by the same `handler`. Returns, when all of them returned, with the first error.
+/// Returns an error with `ErrorKind::InvalidAddress`, if no socket matches.
+///
+/// [`varlink::listen_on`]: ../fn.listen_on.html
+#[cfg(unix)]
+pub fn listen_activated<H: crate::ConnectionHandler + Send + Sync + 'static>(
+    handler: H,
+    names: &[&str],
+    listen_config: &ListenConfig,
+) -> Result<()> {
+    let mut listeners = take_listeners(|n| names.is_empty() || names.contains(&n))?;
+    let last = match listeners.pop() {
+        Some((_, l)) => l,
+        None => return Err(context!(ErrorKind::InvalidAddress)),
+    };
+
+    let handler = Arc::new(handler);
+    let threads: Vec<_> = listeners
+        .into_iter()
+        .map(|(_, listener)| {
+            let handler = handler.clone();
+            let listen_config = listen_config.clone();
+            thread::spawn(move || listen_on(handler, listener, &listen_config))
+        })
+        .collect();
+
+    let mut ret = listen_on(handler, last, listen_config);
+    for t in threads {
+        let r = t
+            .join()
+            .unwrap_or_else(|_| Err(context!(ErrorKind::Server)));
+        ret = ret.and(r);
+    }
+    ret
+}
+
+/// Sends the state `state`, e.g. `"STATUS=idle"`, to the service manager.
+///
+/// Returns `false`, if the service manager does not expect notifications, because
+/// `NOTIFY_SOCKET` is not set. See `sd_notify(3)` for the known states.
+#[cfg(unix)]
+pub fn notify(state: &str) -> Result<bool> {
+    use std::os::unix::ffi::OsStrExt;
+
+    match env::var_os("NOTIFY_SOCKET") {
+        None => Ok(false),
+        Some(path) => {
+            notify_socket(path.as_bytes(), state)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Tells the service manager, that the service is ready to accept connections.
+///
+/// The same as `notify("READY=1")`.
+#[cfg(unix)]
+pub fn notify_ready() -> Result<bool> {
+    notify("READY=1")
+}
+
+/// Sends `state` to the datagram socket `path`, a leading `@` marks an abstract address.
+#[cfg(unix)]
+fn notify_socket(path: &[u8], state: &str) -> Result<()> {
+    let check = |ret: isize| {
+        if ret < 0 {
+            Err(context!(ErrorKind::from(&io::Error::last_os_error())))
+        } else {
+            Ok(ret)
+        }
+    };
+    unsafe {
+        let mut addr: libc::sockaddr_un = mem::zeroed();
+        if path.is_empty() || path.len() >= addr.sun_path.len() {
+            return Err(context!(ErrorKind::InvalidAddress));
+        }
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (dst, src) in addr.sun_path.iter_mut().zip(path.iter()) {
+            *dst = *src as libc::c_char;
+        }
+        if path[0] == b'@' {
+            addr.sun_path[0] = 0;
+        }
+        let offset = addr.sun_path.as_ptr() as usize - &addr as *const _ as usize;
+
+        let fd = check(libc::socket(libc::AF_UNIX, libc::SOCK_DGRAM, 0) as isize)?;
+        let ret = check(libc::sendto(
+            fd as libc::c_int,
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            0,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            (offset + path.len()) as libc::socklen_t,
+        ));
+        libc::close(fd as libc::c_int);
+        ret.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fds() {
+        assert_eq!(parse_fds("", "1", "", 1), vec![]);
+        assert_eq!(parse_fds("1", "2", "", 1), vec![]);
+        assert_eq!(parse_fds("1", "1", "", 1), vec![(3, String::new())]);
+        assert_eq!(
+            parse_fds("3", "1", "varlink:admin", 1),
+            vec![
+                (3, "varlink".to_string()),
+                (4, "admin".to_string()),
+                (5, String::new())
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_socket() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        notify_socket(path.as_os_str().as_bytes(), "READY=1").unwrap();
+        let mut buf = [0u8; 16];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+
+        assert!(notify_socket(b"", "READY=1").is_err());
+    }
+}
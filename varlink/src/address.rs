@@ -0,0 +1,300 @@
+//! Parsing varlink address URIs
+//!
+//! An address is a transport, followed by parameters in the form `;key=value`:
+//!
+//! - `tcp:host:port`, an IPv6 address is written in brackets, e.g. `tcp:[::1]:12345`
+//! - `tcp+tls:host:port`
+//! - `unix:path` and `unix:@name` in the abstract namespace of Linux
+//! - `vsock:cid:port`, the CID is a number or `any`
+//! - `pipe:name`
+//!
+//! The parameters are:
+//!
+//! - `mode=0660` and `group=varlink`, the access to the socket file of a `unix:` path
+//!   created by a server
+//! - `timeout=1000`, the timeout of connecting to a `tcp:` or `tcp+tls:` address in
+//!   milliseconds, which is shared by the addresses of a host name
+//! - `ca=`, `cert=` and `key=`, the paths of the trusted CAs, the certificate chain and
+//!   the private key of a `tcp+tls:` address, see `TlsConfig`
+//!
+//! Malformed addresses, unknown parameters and parameters, which the transport does
+//! not take, fail with `ErrorKind::InvalidAddress`, whose cause says what is wrong.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::address::{Address, Transport};
+//!
+//! let address = Address::parse("unix:/run/org.example.ftl;mode=0660")?;
+//! assert_eq!(address.transport, Transport::Unix("/run/org.example.ftl".into()));
+//! assert_eq!(address.mode, Some(0o660));
+//! assert_eq!(address.to_string(), "unix:/run/org.example.ftl;mode=0660");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error::*;
+
+/// The transport of an [`Address`](struct.Address.html) with its location
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    /// `tcp:host:port`
+    Tcp { host: String, port: u16 },
+    /// `tcp+tls:host:port`
+    TcpTls { host: String, port: u16 },
+    /// `unix:path`
+    Unix(String),
+    /// `unix:@name`, an empty name lets a server bind to a free name
+    UnixAbstract(String),
+    /// `vsock:cid:port`, a CID of `None` is `any`
+    Vsock { cid: Option<u32>, port: u32 },
+    /// `pipe:name`
+    Pipe(String),
+}
+
+/// A parsed varlink address URI, see the [module](index.html)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Address {
+    pub transport: Transport,
+    /// `mode=`, the permissions of the socket file in octal
+    pub mode: Option<u32>,
+    /// `group=`, the group name or id of the socket file
+    pub group: Option<String>,
+    /// `timeout=`, the timeout of connecting
+    pub timeout: Option<Duration>,
+    /// `ca=`, the certificates of the trusted CAs
+    pub ca_certs: Option<PathBuf>,
+    /// `cert=`, the certificate, followed by the intermediate certificates
+    pub cert_chain: Option<PathBuf>,
+    /// `key=`, the private key of the certificate
+    pub private_key: Option<PathBuf>,
+}
+
+fn invalid<T>(address: &str, msg: String) -> Result<T> {
+    let e = format!("{} in '{}'", msg, address);
+    Err(context!(e, ErrorKind::InvalidAddress))
+}
+
+/// Splits `host:port`, where an IPv6 host is in brackets.
+fn parse_host_port(address: &str, location: &str) -> Result<(String, u16)> {
+    let (host, port) = if location.starts_with('[') {
+        match location.find(']') {
+            Some(n) if location[n + 1..].starts_with(':') => (&location[1..n], &location[n + 2..]),
+            _ => return invalid(address, "expected '[host]:port'".into()),
+        }
+    } else {
+        match location.rfind(':') {
+            Some(n) if location[..n].contains(':') => {
+                return invalid(address, "an IPv6 host must be written as '[host]'".into())
+            }
+            Some(n) => (&location[..n], &location[n + 1..]),
+            None => return invalid(address, "missing port".into()),
+        }
+    };
+    if host.is_empty() {
+        return invalid(address, "missing host".into());
+    }
+    match port.parse() {
+        Ok(port) => Ok((host.into(), port)),
+        Err(_) => invalid(address, format!("invalid port '{}'", port)),
+    }
+}
+
+fn parse_transport(address: &str, uri: &str) -> Result<Transport> {
+    let (scheme, location) = match uri.find(':') {
+        Some(n) => (&uri[..n], &uri[n + 1..]),
+        None => return invalid(address, "missing transport".into()),
+    };
+    match scheme {
+        "tcp" => {
+            let (host, port) = parse_host_port(address, location)?;
+            Ok(Transport::Tcp { host, port })
+        }
+        "tcp+tls" => {
+            let (host, port) = parse_host_port(address, location)?;
+            Ok(Transport::TcpTls { host, port })
+        }
+        "unix" if location.starts_with('@') => Ok(Transport::UnixAbstract(location[1..].into())),
+        "unix" if location.is_empty() => invalid(address, "missing path".into()),
+        "unix" => Ok(Transport::Unix(location.into())),
+        "vsock" => {
+            let n = match location.find(':') {
+                Some(n) => n,
+                None => return invalid(address, "expected 'cid:port'".into()),
+            };
+            let cid = match &location[..n] {
+                "any" => None,
+                cid => match cid.parse() {
+                    Ok(cid) => Some(cid),
+                    Err(_) => return invalid(address, format!("invalid CID '{}'", cid)),
+                },
+            };
+            match location[n + 1..].parse() {
+                Ok(port) => Ok(Transport::Vsock { cid, port }),
+                Err(_) => invalid(address, format!("invalid port '{}'", &location[n + 1..])),
+            }
+        }
+        "pipe" if location.is_empty() => invalid(address, "missing pipe name".into()),
+        "pipe" => Ok(Transport::Pipe(location.into())),
+        _ => invalid(address, format!("unknown transport '{}'", scheme)),
+    }
+}
+
+impl Address {
+    /// Parses `address`, see the [module](index.html) for the syntax.
+    pub fn parse(address: &str) -> Result<Address> {
+        let mut parts = address.split(';');
+        let transport = parse_transport(address, parts.next().unwrap())?;
+        let mut parsed = Address {
+            transport,
+            mode: None,
+            group: None,
+            timeout: None,
+            ca_certs: None,
+            cert_chain: None,
+            private_key: None,
+        };
+
+        let mut keys: Vec<&str> = Vec::new();
+        for param in parts {
+            let (key, value) = match param.find('=') {
+                Some(n) if n > 0 => (&param[..n], &param[n + 1..]),
+                _ => return invalid(address, format!("expected 'key=value', not '{}'", param)),
+            };
+            if keys.contains(&key) {
+                return invalid(address, format!("duplicate parameter '{}'", key));
+            }
+            keys.push(key);
+            if value.is_empty() {
+                return invalid(address, format!("empty parameter '{}'", key));
+            }
+            parsed.set_parameter(address, key, value)?;
+        }
+        Ok(parsed)
+    }
+
+    fn set_parameter(&mut self, address: &str, key: &str, value: &str) -> Result<()> {
+        let (unix, tcp, tls) = match self.transport {
+            Transport::Unix(_) => (true, false, false),
+            Transport::Tcp { .. } => (false, true, false),
+            Transport::TcpTls { .. } => (false, true, true),
+            _ => (false, false, false),
+        };
+
+        match key {
+            "mode" if unix => match u32::from_str_radix(value, 8) {
+                Ok(mode) if mode <= 0o7777 => self.mode = Some(mode),
+                _ => return invalid(address, format!("invalid octal mode '{}'", value)),
+            },
+            "group" if unix => self.group = Some(value.into()),
+            "timeout" if tcp => match value.parse() {
+                Ok(ms) => self.timeout = Some(Duration::from_millis(ms)),
+                Err(_) => return invalid(address, format!("invalid timeout '{}'", value)),
+            },
+            "ca" if tls => self.ca_certs = Some(value.into()),
+            "cert" if tls => self.cert_chain = Some(value.into()),
+            "key" if tls => self.private_key = Some(value.into()),
+            "mode" | "group" | "timeout" | "ca" | "cert" | "key" => {
+                return invalid(
+                    address,
+                    format!("parameter '{}' does not apply to the transport", key),
+                )
+            }
+            _ => return invalid(address, format!("unknown parameter '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Returns `config` with the paths of the `ca=`, `cert=` and `key=` parameters.
+    #[cfg(feature = "tls")]
+    pub fn tls_config(&self, config: &crate::TlsConfig) -> crate::TlsConfig {
+        crate::TlsConfig {
+            ca_certs: self.ca_certs.clone().or_else(|| config.ca_certs.clone()),
+            cert_chain: self
+                .cert_chain
+                .clone()
+                .or_else(|| config.cert_chain.clone()),
+            private_key: self
+                .private_key
+                .clone()
+                .or_else(|| config.private_key.clone()),
+            pinned_certs: config.pinned_certs.clone(),
+        }
+    }
+
+    /// Returns `true`, if the address has any of the `ca=`, `cert=` and `key=` parameters.
+    #[cfg(feature = "tls")]
+    pub(crate) fn has_tls_parameters(&self) -> bool {
+        self.ca_certs.is_some() || self.cert_chain.is_some() || self.private_key.is_some()
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Address> {
+        Address::parse(s)
+    }
+}
+
+fn fmt_host(f: &mut fmt::Formatter, host: &str, port: u16) -> fmt::Result {
+    if host.contains(':') {
+        write!(f, "[{}]:{}", host, port)
+    } else {
+        write!(f, "{}:{}", host, port)
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Tcp { host, port } => {
+                f.write_str("tcp:")?;
+                fmt_host(f, host, *port)
+            }
+            Transport::TcpTls { host, port } => {
+                f.write_str("tcp+tls:")?;
+                fmt_host(f, host, *port)
+            }
+            Transport::Unix(path) => write!(f, "unix:{}", path),
+            Transport::UnixAbstract(name) => write!(f, "unix:@{}", name),
+            Transport::Vsock { cid: None, port } => write!(f, "vsock:any:{}", port),
+            Transport::Vsock {
+                cid: Some(cid),
+                port,
+            } => write!(f, "vsock:{}:{}", cid, port),
+            Transport::Pipe(name) => write!(f, "pipe:{}", name),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.transport, f)?;
+        if let Some(mode) = self.mode {
+            write!(f, ";mode={:04o}", mode)?;
+        }
+        if let Some(ref group) = self.group {
+            write!(f, ";group={}", group)?;
+        }
+        if let Some(timeout) = self.timeout {
+            write!(f, ";timeout={}", timeout.as_millis())?;
+        }
+        if let Some(ref path) = self.ca_certs {
+            write!(f, ";ca={}", path.display())?;
+        }
+        if let Some(ref path) = self.cert_chain {
+            write!(f, ";cert={}", path.display())?;
+        }
+        if let Some(ref path) = self.private_key {
+            write!(f, ";key={}", path.display())?;
+        }
+        Ok(())
+    }
+}
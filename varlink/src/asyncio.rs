@@ -0,0 +1,360 @@
+//! Adapter to use a blocking varlink client from async code
+//!
+//! The generated clients block the calling thread until the reply arrives.
+//! [`wrap_blocking`] hands out futures for the calls and runs the blocking calls on a
+//! pool of threads shared by all wrapped clients, so an async runtime is never blocked
+//! by a varlink call.
+//!
+//! A connection serves one call at a time, so the calls of one `AsyncClient` are
+//! serialized: they run one after the other, in the order they are issued, and a
+//! `more` call occupies the client until its last reply. Wrap a client per
+//! connection to run calls in parallel.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let client = varlink::asyncio::wrap_blocking(VarlinkClient::new(connection));
+//! let reply = client.call(|c| c.ping("Test".into()).call()).await??;
+//!
+//! // the replies of a `more` call, as soon as they arrive;
+//! // further calls on `client` wait, until the stream has ended
+//! let mut replies = client.stream(|c| c.test_more(10));
+//! while let Some(reply) = replies.next().await {
+//!     println!("{:?}", reply?);
+//! }
+//!
+//! // a second connection runs its calls in parallel to the first one
+//! let other = varlink::asyncio::wrap_blocking(VarlinkClient::new(Connection::new(address)?));
+//! ```
+//!
+//! [`wrap_blocking`]: fn.wrap_blocking.html
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, Once};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use crate::{ErrorKind, Result};
+
+/// The maximum number of threads running blocking calls at once, for all clients
+const MAX_THREADS: usize = 512;
+
+/// Threads of the pool exit, when they are idle for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Job<C> = Box<dyn FnOnce(&mut C) + Send>;
+type Task = Box<dyn FnOnce() + Send>;
+
+/// The blocking threads shared by all `AsyncClient`s
+struct Pool {
+    state: Mutex<PoolState>,
+    work: Condvar,
+}
+
+struct PoolState {
+    tasks: VecDeque<Task>,
+    num_threads: usize,
+    num_idle: usize,
+}
+
+fn pool() -> &'static Pool {
+    static INIT: Once = Once::new();
+    static mut POOL: *const Pool = ptr::null();
+
+    unsafe {
+        INIT.call_once(|| {
+            POOL = Box::into_raw(Box::new(Pool {
+                state: Mutex::new(PoolState {
+                    tasks: VecDeque::new(),
+                    num_threads: 0,
+                    num_idle: 0,
+                }),
+                work: Condvar::new(),
+            }))
+        });
+        &*POOL
+    }
+}
+
+impl Pool {
+    /// Queues `task` and spawns a thread for it, if no thread is idle.
+    fn execute(&'static self, task: Task) {
+        let mut state = self.state.lock().unwrap();
+        state.tasks.push_back(task);
+        if state.num_idle < state.tasks.len() && state.num_threads < MAX_THREADS {
+            state.num_threads += 1;
+            thread::spawn(move || self.run());
+        }
+        self.work.notify_one();
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(task) = state.tasks.pop_front() {
+                drop(state);
+                task();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state.num_idle += 1;
+            let (s, timeout) = self.work.wait_timeout(state, IDLE_TIMEOUT).unwrap();
+            state = s;
+            state.num_idle -= 1;
+
+            if timeout.timed_out() && state.tasks.is_empty() {
+                state.num_threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+/// The client and the calls waiting for it
+struct Queue<C> {
+    client: Mutex<C>,
+    jobs: Mutex<Jobs<C>>,
+}
+
+struct Jobs<C> {
+    pending: VecDeque<Job<C>>,
+    // a task of the pool is about to run the next pending job
+    running: bool,
+}
+
+impl<C: Send + 'static> Queue<C> {
+    fn push(self: &Arc<Self>, job: Job<C>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.pending.push_back(job);
+        if !jobs.running {
+            jobs.running = true;
+            let queue = self.clone();
+            pool().execute(Box::new(move || queue.run_next()));
+        }
+    }
+
+    /// Runs one job and queues the next one behind the jobs of the other clients.
+    fn run_next(self: Arc<Self>) {
+        let job = self.jobs.lock().unwrap().pending.pop_front();
+
+        if let Some(job) = job {
+            // After a panic the client is poisoned and the following jobs are dropped,
+            // which resolves their futures to errors.
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                if let Ok(mut client) = self.client.lock() {
+                    job(&mut client);
+                }
+            }));
+        }
+
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.pending.is_empty() {
+            jobs.running = false;
+        } else {
+            let queue = self.clone();
+            pool().execute(Box::new(move || queue.run_next()));
+        }
+    }
+}
+
+/// Wraps a blocking client, see [`wrap_blocking`](fn.wrap_blocking.html)
+pub struct AsyncClient<C> {
+    queue: Arc<Queue<C>>,
+}
+
+/// Returns an [`AsyncClient`] to call `client` from async code.
+///
+/// The calls run on a pool of blocking threads shared by all wrapped clients. The calls
+/// of this client are serialized and executed in the order they are issued. The client
+/// is dropped, when the `AsyncClient` is dropped and the pending calls are done.
+///
+/// [`AsyncClient`]: struct.AsyncClient.html
+pub fn wrap_blocking<C: Send + 'static>(client: C) -> AsyncClient<C> {
+    AsyncClient {
+        queue: Arc::new(Queue {
+            client: Mutex::new(client),
+            jobs: Mutex::new(Jobs {
+                pending: VecDeque::new(),
+                running: false,
+            }),
+        }),
+    }
+}
+
+impl<C: Send + 'static> AsyncClient<C> {
+    /// Runs `f` with the wrapped client on the blocking pool, after the calls issued before.
+    ///
+    /// The returned future resolves to the return value of `f`, or to an error
+    /// with `ErrorKind::ConnectionClosed`, if an earlier call panicked.
+    pub fn call<F, T>(&self, f: F) -> CallFuture<T>
+    where
+        F: FnOnce(&mut C) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            value: None,
+            closed: false,
+            waker: None,
+        }));
+
+        let sender = Sender(shared.clone());
+        let job: Job<C> = Box::new(move |client: &mut C| sender.send(f(client)));
+
+        self.queue.push(job);
+
+        CallFuture(shared)
+    }
+
+    /// Iterates the iterator returned by `f` on the blocking pool and streams its items,
+    /// e.g. the replies of a `more` call, see `MethodCall::next`.
+    ///
+    /// Later calls on this client wait, until the iterator is exhausted. The stream ends
+    /// after the last item, or right away, if an earlier call panicked.
+    pub fn stream<F, I>(&self, f: F) -> ReplyStream<I::Item>
+    where
+        F: FnOnce(&mut C) -> I + Send + 'static,
+        I: IntoIterator,
+        I::Item: Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(StreamShared {
+            items: VecDeque::new(),
+            closed: false,
+            waker: None,
+        }));
+
+        let sender = StreamSender(shared.clone());
+        let job: Job<C> = Box::new(move |client: &mut C| {
+            for item in f(client) {
+                sender.send(item);
+            }
+        });
+
+        self.queue.push(job);
+
+        ReplyStream(shared)
+    }
+}
+
+struct Shared<T> {
+    value: Option<T>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Sends the result to the future and wakes it up.
+/// If dropped without sending, e.g. on a panic, the future resolves to an error.
+struct Sender<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Sender<T> {
+    fn send(self, value: T) {
+        self.0.lock().unwrap().value = Some(value);
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut shared = match self.0.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        };
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by [`AsyncClient::call`](struct.AsyncClient.html#method.call)
+pub struct CallFuture<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Future for CallFuture<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(value) = shared.value.take() {
+            return Poll::Ready(Ok(value));
+        }
+        if shared.closed {
+            return Poll::Ready(Err(context!(ErrorKind::ConnectionClosed)));
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+struct StreamShared<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// Queues the items for the stream and wakes it up, closes the stream on drop.
+struct StreamSender<T>(Arc<Mutex<StreamShared<T>>>);
+
+impl<T> StreamSender<T> {
+    fn send(&self, item: T) {
+        let mut shared = self.0.lock().unwrap();
+        shared.items.push_back(item);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for StreamSender<T> {
+    fn drop(&mut self) {
+        let mut shared = match self.0.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        };
+        shared.closed = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Stream returned by [`AsyncClient::stream`](struct.AsyncClient.html#method.stream)
+///
+/// `poll_next` has the signature of `futures::Stream::poll_next`, so the stream can be
+/// adapted to the trait of the runtime.
+pub struct ReplyStream<T>(Arc<Mutex<StreamShared<T>>>);
+
+impl<T> ReplyStream<T> {
+    /// Returns the next item, or `None` after the last one, once it is available.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut shared = self.0.lock().unwrap();
+        if let Some(item) = shared.items.pop_front() {
+            return Poll::Ready(Some(item));
+        }
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Returns a future resolving to the next item, or `None` after the last one.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, T> {
+        Next(self)
+    }
+}
+
+/// Future returned by [`ReplyStream::next`](struct.ReplyStream.html#method.next)
+pub struct Next<'a, T>(&'a mut ReplyStream<T>);
+
+impl<'a, T> Future for Next<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut *self.0).poll_next(cx)
+    }
+}
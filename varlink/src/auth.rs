@@ -0,0 +1,121 @@
+//! Access control for the methods of a [`VarlinkService`](../struct.VarlinkService.html)
+
+use std::cell::RefCell;
+use std::net::SocketAddr;
+
+use crate::stream::Stream;
+
+/// The peer of a connection, as far as it is known
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The address of a TCP peer
+    pub address: Option<SocketAddr>,
+    /// The process id of a unix socket peer, on Linux
+    pub pid: Option<u32>,
+    /// The user id of a unix socket peer, on Linux
+    pub uid: Option<u32>,
+    /// The group id of a unix socket peer, on Linux
+    pub gid: Option<u32>,
+}
+
+impl PeerInfo {
+    /// Collects the address and the credentials of the peer of `stream`.
+    pub(crate) fn new(stream: &dyn Stream, address: Option<SocketAddr>) -> Self {
+        let mut peer = PeerInfo {
+            address,
+            ..Default::default()
+        };
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            use std::mem;
+
+            let mut cred: libc::ucred = unsafe { mem::zeroed() };
+            let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+            let ret = unsafe {
+                libc::getsockopt(
+                    stream.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    libc::SO_PEERCRED,
+                    &mut cred as *mut _ as *mut libc::c_void,
+                    &mut len,
+                )
+            };
+            // SO_PEERCRED fails for TCP sockets
+            if address.is_none() && ret == 0 && cred.pid > 0 {
+                peer.pid = Some(cred.pid as u32);
+                peer.uid = Some(cred.uid);
+                peer.gid = Some(cred.gid);
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let _ = stream;
+        peer
+    }
+}
+
+/// The result of [`Authorizer::authorize`](trait.Authorizer.html#tymethod.authorize)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allow,
+    /// The call is answered with `org.varlink.service.PermissionDenied`
+    Deny,
+}
+
+/// Decides, which peer may call which method of a service
+///
+/// Set with [`VarlinkService::with_authorizer`]. Every call is checked before it
+/// is dispatched to its interface, including the calls of `org.varlink.service`.
+/// The calls on an upgraded connection are not checked again.
+///
+/// # Examples
+///
+/// ```rust
+/// use varlink::{Decision, PeerInfo};
+///
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![/* Your varlink interfaces go here */],
+/// )
+/// // only root may call org.example.admin
+/// .with_authorizer(|peer: &PeerInfo, interface: &str, _method: &str| {
+///     if interface != "org.example.admin" || peer.uid == Some(0) {
+///         Decision::Allow
+///     } else {
+///         Decision::Deny
+///     }
+/// });
+/// ```
+///
+/// [`VarlinkService::with_authorizer`]: ../struct.VarlinkService.html#method.with_authorizer
+pub trait Authorizer: Send + Sync {
+    /// Decides, if `peer` may call `method` of `interface`.
+    ///
+    /// `method` is the full method name including the interface.
+    fn authorize(&self, peer: &PeerInfo, interface: &str, method: &str) -> Decision;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&PeerInfo, &str, &str) -> Decision + Send + Sync,
+{
+    fn authorize(&self, peer: &PeerInfo, interface: &str, method: &str) -> Decision {
+        self(peer, interface, method)
+    }
+}
+
+thread_local! {
+    /// The peer of the connection handled by the current worker thread
+    static CURRENT_PEER: RefCell<Option<PeerInfo>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn set_current_peer(peer: Option<PeerInfo>) {
+    CURRENT_PEER.with(|p| *p.borrow_mut() = peer);
+}
+
+/// The peer of the current worker thread, or an unknown peer outside of `listen`.
+pub(crate) fn current_peer() -> PeerInfo {
+    CURRENT_PEER.with(|p| p.borrow().clone().unwrap_or_default())
+}
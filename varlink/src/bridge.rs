@@ -0,0 +1,292 @@
+//! Bridging a connection to the services of the system
+//!
+//! A bridge reads the requests of one client, e.g. from stdin, forwards every request
+//! to the service implementing its interface, and writes the replies back. The address
+//! of a service is looked up in the routes of the bridge and then asked from a resolver.
+//! This is the `varlink bridge` of other implementations, which exposes the services
+//! over `ssh` or into a container, where a client connects with
+//! `Connection::with_bridge("ssh host -- mybridge")`.
+//!
+//! `org.varlink.service.GetInfo` is answered by the bridge itself with all interfaces it
+//! can route to. A connection upgraded by a service is passed through unchanged, until
+//! the client closes its side.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::bridge::Bridge;
+//!
+//! Bridge::new()
+//!     .route("org.example.ping", "unix:/run/org.example.ping")
+//!     .handle_stdio()
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::thread;
+
+use serde_json::Value;
+
+use crate::client::varlink_connect;
+use crate::error::*;
+use crate::resolver::{self, RESOLVER_ADDRESS};
+use crate::{Call, CallTrait, GetInterfaceDescriptionArgs, Reply, Request, ServiceInfo, Stream};
+
+/// Forwards the requests of a client to the services, see the [module](index.html)
+pub struct Bridge {
+    routes: BTreeMap<String, String>,
+    resolver: Option<String>,
+    info: ServiceInfo,
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The connection to the service of the last request
+struct Service {
+    address: String,
+    stream: Box<dyn Stream>,
+    reader: BufReader<Box<dyn Read + Send + Sync>>,
+    writer: Box<dyn Write + Send + Sync>,
+}
+
+impl Service {
+    fn connect(address: &str) -> Result<Self> {
+        let (mut stream, address) = varlink_connect(address)?;
+        let (reader, writer) = stream.split()?;
+        Ok(Service {
+            address,
+            stream,
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+}
+
+impl Bridge {
+    /// Creates a bridge, which asks the resolver at
+    /// [`RESOLVER_ADDRESS`](../resolver/constant.RESOLVER_ADDRESS.html) for the services.
+    pub fn new() -> Self {
+        Bridge {
+            routes: BTreeMap::new(),
+            resolver: Some(RESOLVER_ADDRESS.into()),
+            info: ServiceInfo {
+                vendor: "varlink".into(),
+                product: "bridge".into(),
+                version: env!("CARGO_PKG_VERSION").into(),
+                url: "https://varlink.org".into(),
+                interfaces: Vec::new(),
+            },
+        }
+    }
+
+    /// Sets the address of the resolver, or `None` to only use the routes.
+    pub fn with_resolver(mut self, resolver: Option<&str>) -> Self {
+        self.resolver = resolver.map(String::from);
+        self
+    }
+
+    /// Sets the identity of the bridge returned by `GetInfo`.
+    pub fn with_info<S: Into<Cow<'static, str>>>(
+        mut self,
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+    ) -> Self {
+        self.info.vendor = vendor.into();
+        self.info.product = product.into();
+        self.info.version = version.into();
+        self.info.url = url.into();
+        self
+    }
+
+    /// Routes the calls of `interface` to the service at `address`, without asking the
+    /// resolver.
+    pub fn route(mut self, interface: &str, address: &str) -> Self {
+        self.routes.insert(interface.into(), address.into());
+        self
+    }
+
+    /// Bridges the requests read from stdin, and writes the replies to stdout.
+    ///
+    /// Returns, when stdin is closed.
+    pub fn handle_stdio(&self) -> Result<()> {
+        self.handle(BufReader::new(io::stdin()), io::stdout())
+    }
+
+    /// Bridges the requests read from `reader`, and writes the replies to `writer`.
+    ///
+    /// Returns, when `reader` is closed.
+    pub fn handle<R, W>(&self, mut reader: R, mut writer: W) -> Result<()>
+    where
+        R: BufRead + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let mut addresses: HashMap<String, Option<String>> = HashMap::new();
+        let mut service: Option<Service> = None;
+
+        loop {
+            let mut buf = Vec::new();
+            if reader.read_until(0, &mut buf).map_err(map_context!())? == 0 {
+                return Ok(());
+            }
+            if buf.pop() != Some(0) {
+                return Err(context!(ErrorKind::ConnectionClosed));
+            }
+            let req: Request = serde_json::from_slice(&buf).map_err(|e| {
+                context!(
+                    e,
+                    ErrorKind::SerdeJsonDe(String::from_utf8_lossy(&buf).to_string())
+                )
+            })?;
+
+            if req.method == "org.varlink.service.GetInfo" {
+                self.reply_info(&mut Call::new(&mut writer, &req))?;
+                continue;
+            }
+
+            let interface = match self.interface_of(&req) {
+                Some(interface) => interface,
+                None => {
+                    Call::new(&mut writer, &req)
+                        .reply_interface_not_found(Some(req.method.to_string()))?;
+                    continue;
+                }
+            };
+            let address = addresses
+                .entry(interface.clone())
+                .or_insert_with(|| self.resolve(&interface))
+                .clone();
+            let address = match address {
+                Some(address) => address,
+                None => {
+                    Call::new(&mut writer, &req).reply_interface_not_found(Some(interface))?;
+                    continue;
+                }
+            };
+
+            let connected = match service {
+                Some(ref s) => s.address == address,
+                None => false,
+            };
+            if !connected {
+                service = match Service::connect(&address) {
+                    Ok(s) => Some(s),
+                    Err(_) => {
+                        Call::new(&mut writer, &req).reply_interface_not_found(Some(interface))?;
+                        continue;
+                    }
+                };
+            }
+            let mut s = service.take().unwrap();
+
+            buf.push(0);
+            s.writer.write_all(&buf).map_err(map_context!())?;
+            s.writer.flush().map_err(map_context!())?;
+            if req.oneway.unwrap_or(false) {
+                service = Some(s);
+                continue;
+            }
+
+            loop {
+                let mut reply = Vec::new();
+                s.reader.read_until(0, &mut reply).map_err(map_context!())?;
+                if reply.pop() != Some(0) {
+                    return Err(context!(ErrorKind::ConnectionClosed));
+                }
+                let r: Reply = serde_json::from_slice(&reply).map_err(map_context!())?;
+                reply.push(0);
+                writer.write_all(&reply).map_err(map_context!())?;
+                writer.flush().map_err(map_context!())?;
+
+                if req.upgrade.unwrap_or(false) && r.error.is_none() {
+                    return splice(reader, writer, s);
+                }
+                if !r.continues.unwrap_or(false) {
+                    break;
+                }
+            }
+            service = Some(s);
+        }
+    }
+
+    /// Returns the interface, which the request is routed to.
+    fn interface_of(&self, req: &Request) -> Option<String> {
+        if req.method == "org.varlink.service.GetInterfaceDescription" {
+            let parameters = req.parameters.clone().unwrap_or(Value::Null);
+            return serde_json::from_value::<GetInterfaceDescriptionArgs>(parameters)
+                .ok()
+                .map(|args| args.interface.into_owned());
+        }
+        req.method.rfind('.').map(|n| req.method[..n].to_string())
+    }
+
+    fn resolve(&self, interface: &str) -> Option<String> {
+        if let Some(address) = self.routes.get(interface) {
+            return Some(address.clone());
+        }
+        let resolver = self.resolver.as_ref()?;
+        if interface == "org.varlink.resolver" {
+            return Some(resolver.clone());
+        }
+        resolver::resolve(resolver, interface).ok()
+    }
+
+    fn reply_info(&self, call: &mut Call) -> Result<()> {
+        let mut interfaces: Vec<String> = self.routes.keys().cloned().collect();
+        if let Some(ref resolver) = self.resolver {
+            if let Ok(info) = resolver::get_info(resolver) {
+                interfaces.extend(info.interfaces.into_iter().map(|i| i.into_owned()));
+            }
+        }
+        interfaces.sort();
+        interfaces.dedup();
+        let info = ServiceInfo {
+            interfaces: interfaces.into_iter().map(Into::into).collect(),
+            ..self.info.clone()
+        };
+        call.reply_struct(Reply::parameters(Some(
+            serde_json::to_value(info).map_err(map_context!())?,
+        )))
+    }
+}
+
+/// Copies the data of an upgraded connection in both directions, until the client
+/// closes its side.
+fn splice<R, W>(mut reader: R, mut writer: W, mut service: Service) -> Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let mut service_reader = service.reader;
+    let to_client = thread::spawn(move || copy(&mut service_reader, &mut writer));
+    let ret = copy(&mut reader, &mut service.writer);
+    let _ = service.stream.shutdown();
+    let ret2 = to_client
+        .join()
+        .unwrap_or_else(|_| Err(io::ErrorKind::BrokenPipe.into()));
+    ret.and(ret2).map_err(map_context!())?;
+    Ok(())
+}
+
+/// Like `io::copy`, but flushes every chunk, so line buffered writers pass it at once.
+fn copy<R: Read + ?Sized, W: Write + ?Sized>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        writer.flush()?;
+    }
+}
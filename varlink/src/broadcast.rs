@@ -0,0 +1,285 @@
+//! Fan out of messages to the streaming calls of many clients
+//!
+//! A [`Broadcaster`] queues every published message for each of its [`Subscriber`]s,
+//! which are usually long running `more` calls, replying with the messages. A client,
+//! which does not receive its replies, lets the queue of its subscriber grow. With
+//! a lag limit, such a subscriber is evicted, its queue is dropped and the call can
+//! be answered with `org.varlink.broadcast.SlowConsumer`, so one stalled client does
+//! not hold the memory of the service.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # #![allow(non_camel_case_types)]
+//! # #![allow(non_snake_case)]
+//! # use std::time::Duration;
+//! # use varlink::broadcast::Broadcaster;
+//! # use varlink::{CallTrait, ErrorKind};
+//! # pub trait Call_Watch: varlink::CallTrait {
+//! #     fn reply(&mut self, _event: String) -> varlink::Result<()> {
+//! #         self.reply_struct(varlink::Reply::parameters(None))
+//! #     }
+//! # }
+//! # impl<'a> Call_Watch for varlink::Call<'a> {}
+//! struct Service {
+//!     events: Broadcaster<String>,
+//! }
+//!
+//! impl Service {
+//!     fn new() -> Self {
+//!         Service {
+//!             events: Broadcaster::new().max_lag_messages(1000),
+//!         }
+//!     }
+//!
+//!     fn watch(&self, call: &mut dyn Call_Watch) -> varlink::Result<()> {
+//!         let subscriber = self.events.subscribe();
+//!         call.set_continues(true);
+//!         loop {
+//!             call.yield_point()?;
+//!             match subscriber.recv_timeout(Duration::from_millis(100)) {
+//!                 Ok(Some(event)) => call.reply(event)?,
+//!                 Ok(None) => {}
+//!                 Err(ref e) if *e.kind() == ErrorKind::SlowConsumer => {
+//!                     call.set_continues(false);
+//!                     return call.reply_slow_consumer();
+//!                 }
+//!                 Err(e) => return Err(e),
+//!             }
+//!         }
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+//!
+//! [`Broadcaster`]: struct.Broadcaster.html
+//! [`Subscriber`]: struct.Subscriber.html
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_derive::Serialize;
+
+use crate::error::*;
+
+/// The lag of a single subscriber
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Lag {
+    /// The number of queued messages
+    pub messages: usize,
+    /// The serialized size of the queued messages in bytes
+    pub bytes: usize,
+}
+
+/// The lag of all subscribers of a broadcaster
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LagMetrics {
+    /// The lag of every subscriber, by subscriber id
+    pub subscribers: BTreeMap<u64, Lag>,
+    /// The highest lag of a subscriber
+    pub max: Lag,
+    /// The number of subscribers evicted so far
+    pub evicted: u64,
+}
+
+struct Queue<T> {
+    messages: VecDeque<(T, usize)>,
+    lag: Lag,
+    evicted: bool,
+}
+
+struct Subscribers<T> {
+    next_id: u64,
+    queues: BTreeMap<u64, Queue<T>>,
+    evicted: u64,
+}
+
+struct Shared<T> {
+    subscribers: Mutex<Subscribers<T>>,
+    /// signaled, when a message is published or a subscriber is evicted
+    published: Condvar,
+}
+
+/// Queues published messages for every subscriber, see the [module](index.html)
+pub struct Broadcaster<T> {
+    shared: Arc<Shared<T>>,
+    max_lag: Lag,
+}
+
+impl<T: Clone + Serialize> Default for Broadcaster<T> {
+    fn default() -> Self {
+        Broadcaster::new()
+    }
+}
+
+impl<T: Clone + Serialize> Broadcaster<T> {
+    /// Creates a broadcaster without lag limits.
+    pub fn new() -> Self {
+        Broadcaster {
+            shared: Arc::new(Shared {
+                subscribers: Mutex::new(Subscribers {
+                    next_id: 0,
+                    queues: BTreeMap::new(),
+                    evicted: 0,
+                }),
+                published: Condvar::new(),
+            }),
+            max_lag: Lag::default(),
+        }
+    }
+
+    /// Evict a subscriber, which has more than `messages` messages queued, `0` means unlimited.
+    pub fn max_lag_messages(mut self, messages: usize) -> Self {
+        self.max_lag.messages = messages;
+        self
+    }
+
+    /// Evict a subscriber, which has more than `bytes` bytes queued, `0` means unlimited.
+    ///
+    /// The size of a message is the length of its JSON serialization.
+    pub fn max_lag_bytes(mut self, bytes: usize) -> Self {
+        self.max_lag.bytes = bytes;
+        self
+    }
+
+    /// Adds a subscriber, which receives all messages published from now on.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let mut subscribers = self.shared.subscribers.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.queues.insert(
+            id,
+            Queue {
+                messages: VecDeque::new(),
+                lag: Lag::default(),
+                evicted: false,
+            },
+        );
+        Subscriber {
+            id,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Queues `message` for every subscriber and returns the number of subscribers,
+    /// which got it.
+    ///
+    /// Subscribers exceeding a lag limit with the message are evicted instead.
+    pub fn publish(&self, message: T) -> usize {
+        let size = serde_json::to_vec(&message).map(|v| v.len()).unwrap_or(0);
+        let max_lag = self.max_lag;
+        let exceeds = |limit: usize, value: usize| limit > 0 && value > limit;
+
+        let mut subscribers = self.shared.subscribers.lock().unwrap();
+        let mut receivers = 0;
+        let mut evicted = 0;
+        for queue in subscribers.queues.values_mut() {
+            if queue.evicted {
+                continue;
+            }
+            if exceeds(max_lag.messages, queue.lag.messages + 1)
+                || exceeds(max_lag.bytes, queue.lag.bytes + size)
+            {
+                queue.messages.clear();
+                queue.lag = Lag::default();
+                queue.evicted = true;
+                evicted += 1;
+                continue;
+            }
+            queue.messages.push_back((message.clone(), size));
+            queue.lag.messages += 1;
+            queue.lag.bytes += size;
+            receivers += 1;
+        }
+        subscribers.evicted += evicted;
+        self.shared.published.notify_all();
+        receivers
+    }
+
+    /// The number of subscribers, which are not evicted
+    pub fn num_subscribers(&self) -> usize {
+        let subscribers = self.shared.subscribers.lock().unwrap();
+        subscribers.queues.values().filter(|q| !q.evicted).count()
+    }
+
+    /// Returns the current lag of the subscribers.
+    pub fn lag_metrics(&self) -> LagMetrics {
+        let subscribers = self.shared.subscribers.lock().unwrap();
+        let mut metrics = LagMetrics {
+            evicted: subscribers.evicted,
+            ..Default::default()
+        };
+        for (id, queue) in subscribers.queues.iter().filter(|(_, q)| !q.evicted) {
+            metrics.max.messages = metrics.max.messages.max(queue.lag.messages);
+            metrics.max.bytes = metrics.max.bytes.max(queue.lag.bytes);
+            metrics.subscribers.insert(*id, queue.lag);
+        }
+        metrics
+    }
+}
+
+/// Receives the messages of a [`Broadcaster`](struct.Broadcaster.html)
+///
+/// Unsubscribes on drop.
+pub struct Subscriber<T> {
+    id: u64,
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Subscriber<T> {
+    /// The id of the subscriber in `LagMetrics::subscribers`
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the next message, or `None`, if there was none within `timeout`.
+    ///
+    /// Returns an error with `ErrorKind::SlowConsumer`, if the subscriber was evicted.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<T>> {
+        let deadline = Instant::now() + timeout;
+        let mut subscribers = self.shared.subscribers.lock().unwrap();
+        loop {
+            let queue = subscribers
+                .queues
+                .get_mut(&self.id)
+                .ok_or_else(|| context!(ErrorKind::SlowConsumer))?;
+            if queue.evicted {
+                return Err(context!(ErrorKind::SlowConsumer));
+            }
+            if let Some((message, size)) = queue.messages.pop_front() {
+                queue.lag.messages -= 1;
+                queue.lag.bytes -= size;
+                return Ok(Some(message));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            subscribers = self
+                .shared
+                .published
+                .wait_timeout(subscribers, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// The current lag of this subscriber
+    pub fn lag(&self) -> Lag {
+        let subscribers = self.shared.subscribers.lock().unwrap();
+        match subscribers.queues.get(&self.id) {
+            Some(queue) => queue.lag,
+            None => Lag::default(),
+        }
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        if let Ok(mut subscribers) = self.shared.subscribers.lock() {
+            subscribers.queues.remove(&self.id);
+        }
+    }
+}
@@ -2,69 +2,198 @@
 
 #![allow(dead_code)]
 
-use std::net::TcpStream;
-#[cfg(unix)]
+#[cfg(feature = "tcp")]
+use std::io;
+#[cfg(feature = "tcp")]
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(all(feature = "unix", unix))]
 use std::os::unix::io::IntoRawFd;
-#[cfg(unix)]
+#[cfg(all(feature = "unix", unix))]
 use std::os::unix::net::UnixStream;
+#[cfg(feature = "unix")]
 use std::process::Child;
+use std::time::Duration;
+#[cfg(feature = "tcp")]
+use std::time::Instant;
 
-#[cfg(unix)]
-use libc::{close, dup2, getpid};
+#[cfg(all(feature = "unix", unix))]
+use libc::{close, dup2, fcntl, F_SETFD};
+#[cfg(feature = "unix")]
 use tempfile::TempDir;
-#[cfg(windows)]
+#[cfg(all(feature = "unix", windows))]
 use uds_windows::UnixStream;
 
+use crate::address::{Address, Transport};
 use crate::error::*;
 use crate::stream::Stream;
+#[cfg(feature = "tls")]
+use crate::tls::TlsStream;
+use crate::ConnectConfig;
+#[cfg(feature = "tls")]
+use crate::TlsConfig;
 
 pub fn varlink_connect<S: ?Sized + AsRef<str>>(address: &S) -> Result<(Box<dyn Stream>, String)> {
+    varlink_connect_with_config(address, &ConnectConfig::default())
+}
+
+/// Like `varlink_connect`, but sets the options of `config` on the connection.
+///
+/// An error carries the address, see `Error::address`.
+pub fn varlink_connect_with_config<S: ?Sized + AsRef<str>>(
+    address: &S,
+    config: &ConnectConfig,
+) -> Result<(Box<dyn Stream>, String)> {
     let address = address.as_ref();
-    let new_address: String = address.into();
-
-    if new_address.starts_with("tcp:") {
-        Ok((
-            Box::new(TcpStream::connect(&new_address[4..]).map_err(map_context!())?),
-            new_address,
-        ))
-    } else if new_address.starts_with("unix:") {
-        let mut addr = String::from(new_address[5..].split(';').next().unwrap());
-        if addr.starts_with('@') {
-            addr = addr.replacen('@', "\0", 1);
-            return get_abstract_unixstream(&addr)
-                .and_then(|v| Ok((Box::new(v) as Box<dyn Stream>, new_address)));
+    connect(address, config).map_err(|e| e.with_address(address))
+}
+
+fn connect(address: &str, config: &ConnectConfig) -> Result<(Box<dyn Stream>, String)> {
+    event!(debug, "connecting to {}", address);
+
+    let parsed = Address::parse(address)?;
+    let stream = match parsed.transport {
+        Transport::Tcp { ref host, port } => {
+            let timeout = parsed.timeout.or(config.connect_timeout);
+            get_tcpstream(host, port, timeout, config)?
+        }
+        #[cfg(feature = "tls")]
+        Transport::TcpTls { .. } if parsed.has_tls_parameters() => {
+            get_tlsstream(&parsed, &TlsConfig::default())?
+        }
+        Transport::TcpTls { .. } => {
+            return Err(context!(ErrorKind::InvalidTlsConfig(
+                "tcp+tls: addresses need a TlsConfig, see Connection::with_tls".into()
+            )))
+        }
+        Transport::Unix(ref path) => get_unixstream(path)?,
+        Transport::UnixAbstract(ref name) => get_abstract_unixstream(name)?,
+        Transport::Vsock { cid, port } => get_vsockstream(cid, port)?,
+        Transport::Pipe(ref name) => get_pipestream(name)?,
+    };
+    Ok((stream, address.into()))
+}
+
+/// Connects to the addresses of `host` in turn, e.g. its IPv6 and IPv4 addresses, until
+/// one accepts the connection.
+///
+/// Every address gets an equal share of the time left of `timeout`, so an address,
+/// which does not answer, leaves time for the next ones.
+#[cfg(feature = "tcp")]
+fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(map_context!())?
+        .collect();
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut last_err = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    );
+    for (i, addr) in addrs.iter().enumerate() {
+        let ret = match deadline {
+            None => TcpStream::connect(addr),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    last_err = io::ErrorKind::TimedOut.into();
+                    break;
+                }
+                let share = (deadline - now) / (addrs.len() - i) as u32;
+                // a zero timeout is an error of `connect_timeout`
+                TcpStream::connect_timeout(addr, share.max(Duration::from_millis(1)))
+            }
+        };
+        match ret {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                event!(debug, "connecting to {} failed: {}", addr, e);
+                last_err = e;
+            }
         }
-        Ok((
-            Box::new(UnixStream::connect(addr).map_err(map_context!())?),
-            new_address,
-        ))
-    } else {
-        Err(context!(ErrorKind::InvalidAddress))?
     }
+    if last_err.kind() == io::ErrorKind::TimedOut {
+        return Err(context!(last_err, ErrorKind::Timeout));
+    }
+    Err(context!(last_err, ErrorKind::from(&last_err)))
 }
 
-#[cfg(any(target_os = "linux", target_os = "android"))]
-fn get_abstract_unixstream(addr: &str) -> Result<UnixStream> {
-    // FIXME: abstract unix domains sockets still not in std
-    // FIXME: https://github.com/rust-lang/rust/issues/14194
-    use std::os::unix::io::FromRawFd;
-    use unix_socket::UnixStream as AbstractStream;
-
-    unsafe {
-        Ok(UnixStream::from_raw_fd(
-            AbstractStream::connect(addr)
-                .map_err(map_context!())?
-                .into_raw_fd(),
-        ))
+#[cfg(feature = "tcp")]
+fn get_tcpstream(
+    host: &str,
+    port: u16,
+    timeout: Option<Duration>,
+    config: &ConnectConfig,
+) -> Result<Box<dyn Stream>> {
+    let stream = tcp_connect(host, port, timeout)?;
+    config.tcp.apply(&stream).map_err(map_context!())?;
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(feature = "tcp"))]
+fn get_tcpstream(
+    _host: &str,
+    _port: u16,
+    _timeout: Option<Duration>,
+    _config: &ConnectConfig,
+) -> Result<Box<dyn Stream>> {
+    Err(context!(ErrorKind::InvalidAddress))
+}
+
+/// Connects to the `tcp+tls:` `address` with `tls_config`, overridden by the TLS
+/// parameters of the address.
+#[cfg(feature = "tls")]
+pub(crate) fn get_tlsstream(address: &Address, tls_config: &TlsConfig) -> Result<Box<dyn Stream>> {
+    match address.transport {
+        Transport::TcpTls { ref host, port } => {
+            let config = address.tls_config(tls_config);
+            let socket = tcp_connect(host, port, address.timeout)?;
+            Ok(Box::new(TlsStream::connect(host, socket, &config)?))
+        }
+        _ => Err(context!(ErrorKind::InvalidAddress)),
     }
 }
 
-#[cfg(not(any(target_os = "linux", target_os = "android")))]
-fn get_abstract_unixstream(_addr: &str) -> Result<UnixStream> {
+#[cfg(feature = "unix")]
+fn get_unixstream(path: &str) -> Result<Box<dyn Stream>> {
+    Ok(Box::new(UnixStream::connect(path).map_err(map_context!())?))
+}
+
+#[cfg(not(feature = "unix"))]
+fn get_unixstream(_path: &str) -> Result<Box<dyn Stream>> {
+    Err(context!(ErrorKind::InvalidAddress))
+}
+
+#[cfg(all(feature = "unix", any(target_os = "linux", target_os = "android")))]
+fn get_abstract_unixstream(name: &str) -> Result<Box<dyn Stream>> {
+    Ok(Box::new(crate::abstract_socket::connect(name)?))
+}
+
+#[cfg(not(all(feature = "unix", any(target_os = "linux", target_os = "android"))))]
+fn get_abstract_unixstream(_name: &str) -> Result<Box<dyn Stream>> {
+    Err(context!(ErrorKind::InvalidAddress))
+}
+
+#[cfg(all(feature = "unix", any(target_os = "linux", target_os = "android")))]
+fn get_vsockstream(cid: Option<u32>, port: u32) -> Result<Box<dyn Stream>> {
+    Ok(Box::new(crate::vsock::connect(cid, port)?))
+}
+
+#[cfg(not(all(feature = "unix", any(target_os = "linux", target_os = "android"))))]
+fn get_vsockstream(_cid: Option<u32>, _port: u32) -> Result<Box<dyn Stream>> {
     Err(context!(ErrorKind::InvalidAddress))
 }
 
 #[cfg(windows)]
+fn get_pipestream(name: &str) -> Result<Box<dyn Stream>> {
+    Ok(Box::new(crate::pipe::PipeStream::connect(name)?))
+}
+
+#[cfg(not(windows))]
+fn get_pipestream(_name: &str) -> Result<Box<dyn Stream>> {
+    Err(context!(ErrorKind::InvalidAddress))
+}
+
+#[cfg(all(feature = "unix", windows))]
 pub fn varlink_exec<S: ?Sized + AsRef<str>>(
     _address: &S,
 ) -> Result<(Child, String, Option<TempDir>)> {
@@ -73,18 +202,18 @@ pub fn varlink_exec<S: ?Sized + AsRef<str>>(
     )));
 }
 
-#[cfg(unix)]
+#[cfg(all(feature = "unix", unix))]
 pub fn varlink_exec<S: ?Sized + AsRef<str>>(
     address: &S,
 ) -> Result<(Child, String, Option<TempDir>)> {
-    use std::env;
     use std::os::unix::process::CommandExt;
     use std::process::Command;
     use tempfile::tempdir;
 
-    let executable = String::from("exec ") + address.as_ref();
+    // the shell execs the service, so its pid is the pid of the service
+    let executable = String::from("export LISTEN_PID=$$; exec ") + address.as_ref();
 
-    use unix_socket::UnixListener;
+    use std::os::unix::net::UnixListener;
 
     let dir = tempdir().map_err(map_context!())?;
     let file_path = dir.path().join("varlink-socket");
@@ -96,20 +225,20 @@ pub fn varlink_exec<S: ?Sized + AsRef<str>>(
         Command::new("sh")
             .arg("-c")
             .arg(executable)
-            .pre_exec({
-                let file_path = file_path.clone();
-                move || {
-                    dup2(2, 1);
-                    if fd != 3 {
-                        close(3);
-                        dup2(fd, 3);
-                    }
-                    env::set_var("VARLINK_ADDRESS", format!("unix:{}", file_path.display()));
-                    env::set_var("LISTEN_FDS", "1");
-                    env::set_var("LISTEN_FDNAMES", "varlink");
-                    env::set_var("LISTEN_PID", format!("{}", getpid()));
-                    Ok(())
+            .env("VARLINK_ADDRESS", format!("unix:{}", file_path.display()))
+            .env("LISTEN_FDS", "1")
+            .env("LISTEN_FDNAMES", "varlink")
+            // only async-signal-safe calls after the fork, not even `env::set_var`
+            .pre_exec(move || {
+                dup2(2, 1);
+                if fd != 3 {
+                    close(3);
+                    dup2(fd, 3);
+                } else {
+                    // the socket is created with FD_CLOEXEC, which only dup2() clears
+                    fcntl(3, F_SETFD, 0);
                 }
+                Ok(())
             })
             .spawn()
             .map_err(map_context!())?
@@ -118,7 +247,7 @@ pub fn varlink_exec<S: ?Sized + AsRef<str>>(
     Ok((child, format!("unix:{}", file_path.display()), Some(dir)))
 }
 
-#[cfg(windows)]
+#[cfg(all(feature = "unix", windows))]
 pub fn varlink_bridge<S: ?Sized + AsRef<str>>(address: &S) -> Result<(Child, Box<dyn Stream>)> {
     use std::io::copy;
     use std::process::{Command, Stdio};
@@ -146,17 +275,17 @@ pub fn varlink_bridge<S: ?Sized + AsRef<str>>(address: &S) -> Result<(Child, Box
     Ok((child, Box::new(stream0)))
 }
 
-#[cfg(unix)]
+#[cfg(all(feature = "unix", unix))]
 pub fn varlink_bridge<S: ?Sized + AsRef<str>>(address: &S) -> Result<(Child, Box<dyn Stream>)> {
     use std::os::unix::io::FromRawFd;
     use std::process::Command;
 
     let executable = address.as_ref();
-    // use unix_socket::UnixStream;
     let (stream0, stream1) = UnixStream::pair().map_err(map_context!())?;
     let fd = stream1.into_raw_fd();
     let childin = unsafe { ::std::fs::File::from_raw_fd(fd) };
-    let childout = unsafe { ::std::fs::File::from_raw_fd(fd) };
+    // stdin and stdout of the child each own a descriptor of the socket
+    let childout = childin.try_clone().map_err(map_context!())?;
 
     let child = Command::new("sh")
         .arg("-c")
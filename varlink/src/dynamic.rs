@@ -0,0 +1,150 @@
+//! Interfaces loaded at runtime, which route all calls to one dynamic handler
+//!
+//! Instead of compiling generated code for every interface, a service can load the
+//! interface descriptions from `.varlink` files at runtime and answer all their calls
+//! with a [`DynamicHandler`], which gets the method name and the parameters as JSON.
+//! This serves plugin hosts and bridges to scripting languages.
+//!
+//! Calls of methods, which are not in the descriptions, are answered with
+//! `org.varlink.service.MethodNotFound` without calling the handler.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use serde_json::json;
+//! use varlink::{Reply, VarlinkService};
+//!
+//! let service = VarlinkService::from_dir(
+//!     "org.varlink",
+//!     "plugin host",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     "/usr/lib/myhost/interfaces",
+//!     |method: &str, parameters: serde_json::Value| match method {
+//!         "org.example.ping.Ping" => Ok(json!({ "pong": parameters["ping"] })),
+//!         _ => Err(Reply::error("org.varlink.service.MethodNotImplemented", None)),
+//!     },
+//! )
+//! .unwrap();
+//! ```
+//!
+//! [`DynamicHandler`]: trait.DynamicHandler.html
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::Value;
+use varlink_parser::IDL;
+
+use crate::error::*;
+use crate::{Call, CallTrait, Interface, Reply};
+
+/// Handles the calls of the interfaces loaded at runtime
+pub trait DynamicHandler: Send + Sync {
+    /// Handles the call of the fully qualified `method` with its `parameters`.
+    ///
+    /// Returns the parameters of the reply, or a complete error reply, e.g.
+    /// `Reply::error("org.example.ftl.NotFound", None)`.
+    fn call(&self, method: &str, parameters: Value) -> std::result::Result<Value, Reply>;
+}
+
+impl<F> DynamicHandler for F
+where
+    F: Fn(&str, Value) -> std::result::Result<Value, Reply> + Send + Sync,
+{
+    fn call(&self, method: &str, parameters: Value) -> std::result::Result<Value, Reply> {
+        self(method, parameters)
+    }
+}
+
+/// An interface with a description loaded at runtime, see the [module](index.html)
+pub struct DynamicInterface {
+    name: &'static str,
+    description: &'static str,
+    /// the names of the methods without the interface name
+    methods: HashSet<String>,
+    handler: Arc<dyn DynamicHandler>,
+}
+
+impl DynamicInterface {
+    /// Creates the interface of `description`, which passes its calls to `handler`.
+    ///
+    /// The description is kept for the lifetime of the process.
+    pub fn new(description: String, handler: Arc<dyn DynamicHandler>) -> Result<Self> {
+        let description: &'static str = Box::leak(description.into_boxed_str());
+        let idl = IDL::from_string(description)
+            .map_err(|e| context!(ErrorKind::InvalidInterface(e.to_string())))?;
+        Ok(DynamicInterface {
+            name: idl.name,
+            description,
+            methods: idl.methods.keys().map(|m| m.to_string()).collect(),
+            handler,
+        })
+    }
+}
+
+impl Interface for DynamicInterface {
+    fn get_description(&self) -> &'static str {
+        self.description
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.name
+    }
+
+    fn call_upgraded(&self, call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+        call.upgraded = false;
+        Ok(Vec::new())
+    }
+
+    fn call(&self, call: &mut Call) -> Result<()> {
+        let req = call.require_request()?;
+        let method = req.method.get(self.name.len() + 1..).unwrap_or("");
+        if !self.methods.contains(method) {
+            return call.reply_method_not_found(req.method.to_string());
+        }
+
+        let parameters = req
+            .parameters
+            .clone()
+            .unwrap_or_else(|| Value::Object(serde_json::Map::new()));
+        let reply = match self.handler.call(&req.method, parameters) {
+            Ok(parameters) => Reply::parameters(Some(parameters)),
+            Err(reply) => reply,
+        };
+        call.reply_struct(reply)
+    }
+}
+
+/// Loads the interfaces of all `*.varlink` files in `dir`, which pass their calls
+/// to `handler`.
+pub fn load_dir<P: AsRef<Path>>(
+    dir: P,
+    handler: Arc<dyn DynamicHandler>,
+) -> Result<Vec<Box<dyn Interface + Send + Sync>>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).map_err(map_context!())? {
+        let path = entry.map_err(map_context!())?.path();
+        if path.extension().map(|e| e == "varlink") == Some(true) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut interfaces: Vec<Box<dyn Interface + Send + Sync>> = Vec::new();
+    for path in paths {
+        let description = fs::read_to_string(&path).map_err(map_context!())?;
+        let interface = DynamicInterface::new(description, handler.clone()).map_err(|e| {
+            context!(ErrorKind::InvalidInterface(format!(
+                "{}: {}",
+                path.display(),
+                e
+            )))
+        })?;
+        interfaces.push(Box::new(interface));
+    }
+    Ok(interfaces)
+}
@@ -9,15 +9,26 @@ pub enum ErrorKind {
     InvalidParameter(String),
     MethodNotFound(String),
     MethodNotImplemented(String),
+    PermissionDenied,
+    ServiceBusy,
+    SlowConsumer,
+    ExpectedMore,
     VarlinkErrorReply(crate::Reply),
     CallContinuesMismatch,
     MethodCalledAlready,
     ConnectionBusy,
     IteratorOldReply,
     Server,
+    NoRequest,
     Timeout,
     ConnectionClosed,
     InvalidAddress,
+    InvalidTlsConfig(String),
+    InvalidInterface(String),
+    InvalidErrorName(String),
+    Shutdown,
+    MessageTooLarge(usize),
+    FdPassingNotSupported,
     Generic,
 }
 
@@ -31,6 +42,10 @@ impl ::std::fmt::Display for ErrorKind {
             ErrorKind::InvalidParameter(v) => write!(f, "Invalid parameter: '{}'", v),
             ErrorKind::MethodNotFound(v) => write!(f, "Method not found: '{}'", v),
             ErrorKind::MethodNotImplemented(v) => write!(f, "Method not implemented: '{}'", v),
+            ErrorKind::PermissionDenied => write!(f, "Permission denied"),
+            ErrorKind::ServiceBusy => write!(f, "Service busy"),
+            ErrorKind::SlowConsumer => write!(f, "Consumer too slow"),
+            ErrorKind::ExpectedMore => write!(f, "Method expects to be called with more"),
             ErrorKind::VarlinkErrorReply(v) => write!(f, "Varlink error reply: '{:#?}'", v),
             ErrorKind::CallContinuesMismatch => write!(
                 f,
@@ -40,9 +55,23 @@ impl ::std::fmt::Display for ErrorKind {
             ErrorKind::ConnectionBusy => write!(f, "Varlink: connection busy with other method"),
             ErrorKind::IteratorOldReply => write!(f, "Varlink: Iterator called on old reply"),
             ErrorKind::Server => write!(f, "Server Error"),
+            ErrorKind::NoRequest => write!(f, "Call without a request"),
             ErrorKind::Timeout => write!(f, "Timeout Error"),
             ErrorKind::ConnectionClosed => write!(f, "Connection Closed"),
             ErrorKind::InvalidAddress => write!(f, "Invalid varlink address URI"),
+            ErrorKind::InvalidTlsConfig(v) => write!(f, "Invalid TLS configuration: {}", v),
+            ErrorKind::InvalidInterface(v) => write!(f, "Invalid interface {}", v),
+            ErrorKind::InvalidErrorName(v) => write!(f, "Invalid varlink error name: '{}'", v),
+            ErrorKind::Shutdown => write!(f, "Server is shutting down"),
+            ErrorKind::MessageTooLarge(v) => {
+                write!(f, "Message exceeds the maximum size of {} bytes", v)
+            }
+            ErrorKind::FdPassingNotSupported => {
+                write!(
+                    f,
+                    "File descriptor passing is not supported by the connection"
+                )
+            }
             ErrorKind::Generic => Ok(()),
         }
     }
@@ -65,21 +94,85 @@ impl From<&serde_json::error::Error> for ErrorKind {
     }
 }
 
+/// Where an error happened, see [`Error::address`] and [`Error::method`]
+///
+/// [`Error::address`]: struct.Error.html#method.address
+/// [`Error::method`]: struct.Error.html#method.method
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ErrorContext {
+    /// The address of the connection
+    pub address: Option<String>,
+    /// The interface of the called method
+    pub interface: Option<String>,
+    /// The name of the called method without its interface
+    pub method: Option<String>,
+}
+
 pub struct Error(
     pub ErrorKind,
     pub Option<Box<dyn std::error::Error + 'static + Send + Sync>>,
     pub Option<&'static str>,
+    pub Option<Box<ErrorContext>>,
 );
 
 impl Error {
     pub fn kind(&self) -> &ErrorKind {
         &self.0
     }
+
+    /// The address of the connection, e.g. of a failed connect or call
+    pub fn address(&self) -> Option<&str> {
+        self.3
+            .as_ref()
+            .and_then(|c| c.address.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The interface of the method, whose call failed
+    pub fn interface(&self) -> Option<&str> {
+        self.3
+            .as_ref()
+            .and_then(|c| c.interface.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The name of the method without its interface, whose call failed
+    pub fn method(&self) -> Option<&str> {
+        self.3
+            .as_ref()
+            .and_then(|c| c.method.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Sets the address of the connection, unless the error has one already.
+    pub fn with_address(mut self, address: &str) -> Self {
+        let context = self.3.get_or_insert_with(Default::default);
+        if context.address.is_none() {
+            context.address = Some(address.into());
+        }
+        self
+    }
+
+    /// Sets the interface and the method of a fully qualified method name, e.g.
+    /// `org.example.ping.Ping`, unless the error has a method already.
+    pub fn with_method(mut self, method: &str) -> Self {
+        let context = self.3.get_or_insert_with(Default::default);
+        if context.method.is_none() {
+            match method.rfind('.') {
+                Some(n) => {
+                    context.interface = Some(method[..n].into());
+                    context.method = Some(method[n + 1..].into());
+                }
+                None => context.method = Some(method.into()),
+            }
+        }
+        self
+    }
 }
 
 impl From<ErrorKind> for Error {
     fn from(e: ErrorKind) -> Self {
-        Error(e, None, None)
+        Error(e, None, None, None)
     }
 }
 
@@ -93,7 +186,19 @@ impl std::error::Error for Error {
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        std::fmt::Display::fmt(&self.0, f)
+        std::fmt::Display::fmt(&self.0, f)?;
+        // the cause says, what is wrong with the address
+        if let (ErrorKind::InvalidAddress, Some(ref e)) = (&self.0, &self.1) {
+            write!(f, ": {}", e)?;
+        }
+        match (self.address(), self.interface(), self.method()) {
+            (None, _, None) => Ok(()),
+            (Some(a), _, None) => write!(f, " (address: {})", a),
+            (None, Some(i), Some(m)) => write!(f, " (method: {}.{})", i, m),
+            (None, None, Some(m)) => write!(f, " (method: {})", m),
+            (Some(a), Some(i), Some(m)) => write!(f, " (address: {}, method: {}.{})", a, i, m),
+            (Some(a), None, Some(m)) => write!(f, " (address: {}, method: {})", a, m),
+        }
     }
 }
 
@@ -106,6 +211,9 @@ impl std::fmt::Debug for Error {
         }
 
         std::fmt::Debug::fmt(&self.0, f)?;
+        if let Some(ref context) = self.3 {
+            write!(f, " {:?}", context)?;
+        }
         if let Some(e) = self.source() {
             std::fmt::Display::fmt("\nCaused by:\n", f)?;
             std::fmt::Debug::fmt(&e, f)?;
@@ -124,16 +232,17 @@ macro_rules! map_context {
 #[macro_export]
 macro_rules! context {
     ( $k:expr ) => {{
-        $crate::error::Error($k, None, Some(concat!(file!(), ":", line!(), ": ")))
+        $crate::error::Error($k, None, Some(concat!(file!(), ":", line!(), ": ")), None)
     }};
     ( None, $k:expr ) => {{
-        $crate::error::Error($k, None, Some(concat!(file!(), ":", line!(), ": ")))
+        $crate::error::Error($k, None, Some(concat!(file!(), ":", line!(), ": ")), None)
     }};
     ( $e:path, $k:expr ) => {{
         $crate::error::Error(
             $k,
             Some(Box::from($e)),
             Some(concat!(file!(), ":", line!(), ": ")),
+            None,
         )
     }};
 }
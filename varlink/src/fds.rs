@@ -0,0 +1,218 @@
+//! Passing file descriptors with `SCM_RIGHTS` over unix sockets
+//!
+//! A handler attaches file descriptors with [`Call::attach_fds`], they are sent with the
+//! first byte of its next reply. The reader of a client connection receives them and
+//! keeps them until [`Connection::take_fds`].
+//!
+//! [`Call::attach_fds`]: ../struct.Call.html#method.attach_fds
+//! [`Connection::take_fds`]: ../struct.Connection.html#method.take_fds
+
+#[cfg(unix)]
+use std::cell::RefCell;
+#[cfg(all(unix, feature = "client"))]
+use std::collections::VecDeque;
+#[cfg(unix)]
+use std::io;
+#[cfg(all(unix, feature = "client"))]
+use std::io::Read;
+#[cfg(all(unix, feature = "server"))]
+use std::io::Write;
+#[cfg(unix)]
+use std::mem;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+#[cfg(all(unix, feature = "client"))]
+use std::sync::Arc;
+#[cfg(all(unix, feature = "client"))]
+use std::sync::Mutex;
+
+#[cfg(all(unix, feature = "server"))]
+use crate::error::*;
+#[cfg(all(unix, feature = "client"))]
+use crate::stream::Stream;
+
+/// The most file descriptors received at once, like the limit of the kernel
+#[cfg(all(unix, feature = "client"))]
+const MAX_FDS: usize = 253;
+
+#[cfg(unix)]
+thread_local! {
+    /// The file descriptors for the next reply of the connection handled by the current
+    /// worker thread, `None`, if its writer cannot send them
+    static PENDING: RefCell<Option<Vec<RawFd>>> = const { RefCell::new(None) };
+}
+
+/// Lets the current thread attach file descriptors to the replies, which are written to
+/// an `FdWriter`, until called with `false`.
+#[cfg(all(unix, feature = "server"))]
+pub(crate) fn set_sending(sending: bool) {
+    PENDING.with(|p| *p.borrow_mut() = if sending { Some(Vec::new()) } else { None });
+}
+
+/// Queues `fds` for the next reply of the current thread.
+#[cfg(all(unix, feature = "server"))]
+pub(crate) fn attach(fds: &[RawFd]) -> Result<()> {
+    PENDING.with(|p| match *p.borrow_mut() {
+        Some(ref mut pending) => {
+            pending.extend_from_slice(fds);
+            Ok(())
+        }
+        None => Err(context!(ErrorKind::FdPassingNotSupported)),
+    })
+}
+
+/// Returns `true`, if `fd` is a unix socket, which can pass file descriptors.
+#[cfg(unix)]
+pub(crate) fn is_unix_socket(fd: RawFd) -> bool {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret =
+        unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) };
+    ret == 0 && i32::from(addr.ss_family) == libc::AF_UNIX
+}
+
+/// Writes to a unix socket and sends the file descriptors queued by the current thread
+/// with the first byte.
+#[cfg(all(unix, feature = "server"))]
+pub(crate) struct FdWriter {
+    pub(crate) inner: Box<dyn Write + Send + Sync>,
+    pub(crate) fd: RawFd,
+}
+
+#[cfg(all(unix, feature = "server"))]
+impl Write for FdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let fds = PENDING.with(|p| match *p.borrow_mut() {
+            Some(ref mut pending) => mem::take(pending),
+            None => Vec::new(),
+        });
+        if fds.is_empty() || buf.is_empty() {
+            return self.inner.write(buf);
+        }
+
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let data_len = (fds.len() * mem::size_of::<RawFd>()) as libc::c_uint;
+        let mut control = vec![0u64; unsafe { libc::CMSG_SPACE(data_len) } as usize / 8 + 1];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(data_len) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+            std::ptr::copy_nonoverlapping(
+                fds.as_ptr() as *const u8,
+                libc::CMSG_DATA(cmsg),
+                data_len as usize,
+            );
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let flags = libc::MSG_NOSIGNAL;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let flags = 0;
+
+        let n = unsafe { libc::sendmsg(self.fd, &msg, flags) };
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            // sent with the next try of the caller
+            PENDING.with(|p| {
+                if let Some(ref mut pending) = *p.borrow_mut() {
+                    pending.splice(0..0, fds);
+                }
+            });
+            return Err(e);
+        }
+        Ok(n as usize)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The file descriptors received by a connection, which are closed, if nobody takes them
+#[cfg(feature = "client")]
+#[derive(Default)]
+pub(crate) struct ReceivedFds {
+    #[cfg(unix)]
+    fds: Mutex<VecDeque<RawFd>>,
+}
+
+#[cfg(all(unix, feature = "client"))]
+impl ReceivedFds {
+    pub(crate) fn take(&self) -> Vec<RawFd> {
+        self.fds.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(all(unix, feature = "client"))]
+impl Drop for ReceivedFds {
+    fn drop(&mut self) {
+        for fd in self.take() {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+/// Reads from a unix socket and keeps the file descriptors received with the data.
+#[cfg(all(unix, feature = "client"))]
+pub(crate) struct FdReader {
+    /// keeps the socket open
+    pub(crate) stream: Box<dyn Stream>,
+    pub(crate) received: Arc<ReceivedFds>,
+}
+
+#[cfg(all(unix, feature = "client"))]
+impl Read for FdReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let space = unsafe { libc::CMSG_SPACE((MAX_FDS * mem::size_of::<RawFd>()) as _) };
+        let mut control = vec![0u64; space as usize / 8 + 1];
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let flags = libc::MSG_CMSG_CLOEXEC;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let flags = 0;
+
+        let n = unsafe { libc::recvmsg(self.stream.as_raw_fd(), &mut msg, flags) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut received = self.received.fds.lock().unwrap();
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                    let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                    for i in 0..len / mem::size_of::<RawFd>() {
+                        let fd = std::ptr::read_unaligned(data.add(i));
+                        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                        received.push_back(fd);
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok(n as usize)
+    }
+}
@@ -0,0 +1,110 @@
+//! Framing of messages on an upgraded connection
+//!
+//! After a method call with `upgrade`, client and service leave the varlink protocol
+//! and speak their own protocol on the connection. These helpers delimit the messages
+//! of such a protocol, either as lines, or as frames of binary data with a length prefix
+//! of 4 bytes in network byte order.
+//!
+//! On the service side, [`Interface::call_upgraded`] is called for every message on the
+//! upgraded connection with the reader positioned at its start, and writes its answer to
+//! `call.writer`. On the client side, [`MethodCall::upgrade_stream`] returns the
+//! connection as an [`Upgraded`] stream.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::framing::{read_frame, write_frame};
+//!
+//! let mut buf = Vec::new();
+//! write_frame(&mut buf, b"hello")?;
+//!
+//! let mut reader = &buf[..];
+//! assert_eq!(read_frame(&mut reader, 1024)?, Some(b"hello".to_vec()));
+//! assert_eq!(read_frame(&mut reader, 1024)?, None);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Interface::call_upgraded`]: ../trait.Interface.html#tymethod.call_upgraded
+//! [`MethodCall::upgrade_stream`]: ../struct.MethodCall.html#method.upgrade_stream
+//! [`Upgraded`]: ../struct.Upgraded.html
+
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read, Write};
+
+use crate::error::*;
+
+/// Writes `data` as a frame with its length and flushes `writer`.
+///
+/// Returns an error with `ErrorKind::InvalidParameter`, if `data` is larger than 4 GiB.
+pub fn write_frame<W: Write + ?Sized>(writer: &mut W, data: &[u8]) -> Result<()> {
+    let len = u32::try_from(data.len())
+        .map_err(|_| context!(ErrorKind::InvalidParameter("frame length".into())))?;
+    writer
+        .write_all(&len.to_be_bytes())
+        .map_err(map_context!())?;
+    writer.write_all(data).map_err(map_context!())?;
+    writer.flush().map_err(map_context!())?;
+    Ok(())
+}
+
+/// Reads a frame written by [`write_frame`](fn.write_frame.html).
+///
+/// Returns `None`, if the connection was closed before the frame. Returns an error with
+/// `ErrorKind::InvalidParameter`, if the frame is larger than `max_len`, and with
+/// `ErrorKind::ConnectionClosed`, if the connection was closed within the frame.
+pub fn read_frame<R: Read + ?Sized>(reader: &mut R, max_len: usize) -> Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    let mut filled = 0;
+    while filled < len.len() {
+        match reader.read(&mut len[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(context!(ErrorKind::ConnectionClosed)),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(context!(e, ErrorKind::from(&e))),
+        }
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max_len {
+        return Err(context!(ErrorKind::InvalidParameter("frame length".into())));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => context!(e, ErrorKind::ConnectionClosed),
+        _ => context!(e, ErrorKind::from(&e)),
+    })?;
+    Ok(Some(data))
+}
+
+/// Writes `line` with a trailing newline and flushes `writer`.
+pub fn write_line<W: Write + ?Sized>(writer: &mut W, line: &str) -> Result<()> {
+    writer.write_all(line.as_bytes()).map_err(map_context!())?;
+    writer.write_all(b"\n").map_err(map_context!())?;
+    writer.flush().map_err(map_context!())?;
+    Ok(())
+}
+
+/// Reads a line without its trailing newline.
+///
+/// Returns `None`, if the connection was closed before the line. Returns an error with
+/// `ErrorKind::InvalidParameter`, if the line is longer than `max_len` bytes or not UTF-8.
+pub fn read_line<R: BufRead + ?Sized>(reader: &mut R, max_len: usize) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let n = reader
+        .take(max_len as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .map_err(map_context!())?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+    } else if line.len() > max_len {
+        return Err(context!(ErrorKind::InvalidParameter("line length".into())));
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|e| context!(e, ErrorKind::InvalidParameter("line".into())))
+}
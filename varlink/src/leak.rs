@@ -0,0 +1,141 @@
+//! Counters of live objects, to find resource leaks in soak tests
+//!
+//! With the feature `leak-tracking`, the server counts its calls, connections and
+//! worker threads, and the client its connections. A soak test takes a baseline with
+//! [`live_objects`], runs its load and checks with [`wait_for_baseline`], that all
+//! objects are gone, after the connections closed. Without the feature, the counters
+//! cost nothing.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let baseline = varlink::live_objects();
+//! // connect, call, disconnect, …
+//! if let Err(live) = varlink::wait_for_baseline(baseline, Duration::from_secs(5)) {
+//!     panic!("leaked objects: {:?}", live);
+//! }
+//! ```
+//!
+//! [`live_objects`]: fn.live_objects.html
+//! [`wait_for_baseline`]: fn.wait_for_baseline.html
+
+#[cfg(feature = "leak-tracking")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "leak-tracking")]
+use std::thread;
+#[cfg(feature = "leak-tracking")]
+use std::time::{Duration, Instant};
+
+/// The kinds of counted objects
+// the client or the server might be disabled
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub(crate) enum Object {
+    Call = 0,
+    Stream,
+    Connection,
+    Worker,
+}
+
+#[cfg(feature = "leak-tracking")]
+static LIVE: [AtomicUsize; 4] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+/// Counts an object as live, until it is dropped
+///
+/// The `Default` counts nothing, for the default of an unconnected object.
+#[derive(Default)]
+pub(crate) struct Tracked {
+    #[cfg(feature = "leak-tracking")]
+    object: Option<Object>,
+}
+
+impl Tracked {
+    pub(crate) fn new(object: Object) -> Self {
+        #[cfg(feature = "leak-tracking")]
+        {
+            LIVE[object as usize].fetch_add(1, Ordering::SeqCst);
+            Tracked {
+                object: Some(object),
+            }
+        }
+        #[cfg(not(feature = "leak-tracking"))]
+        {
+            let _ = object;
+            Tracked {}
+        }
+    }
+}
+
+#[cfg(feature = "leak-tracking")]
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Some(object) = self.object {
+            LIVE[object as usize].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// The number of live objects of every kind
+#[cfg(feature = "leak-tracking")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LiveObjects {
+    /// Calls of the server, which are handled
+    pub calls: usize,
+    /// Connections accepted by the server, which are not closed
+    pub streams: usize,
+    /// Client connections
+    pub connections: usize,
+    /// Worker threads of the servers
+    pub workers: usize,
+}
+
+#[cfg(feature = "leak-tracking")]
+impl LiveObjects {
+    /// Returns `true`, if no kind has more live objects than in `baseline`.
+    pub fn within(&self, baseline: &LiveObjects) -> bool {
+        self.calls <= baseline.calls
+            && self.streams <= baseline.streams
+            && self.connections <= baseline.connections
+            && self.workers <= baseline.workers
+    }
+}
+
+/// Returns the number of live objects of the whole process.
+#[cfg(feature = "leak-tracking")]
+pub fn live_objects() -> LiveObjects {
+    let live = |object: Object| LIVE[object as usize].load(Ordering::SeqCst);
+    LiveObjects {
+        calls: live(Object::Call),
+        streams: live(Object::Stream),
+        connections: live(Object::Connection),
+        workers: live(Object::Worker),
+    }
+}
+
+/// Waits up to `timeout` for the live objects to return to `baseline`.
+///
+/// Idle worker threads stay alive for `ListenConfig::worker_idle_timeout`, so set
+/// `baseline.workers` to `usize::MAX` to ignore them, while a server runs.
+/// Returns the live objects as the error, if they did not return in time.
+#[cfg(feature = "leak-tracking")]
+pub fn wait_for_baseline(
+    baseline: LiveObjects,
+    timeout: Duration,
+) -> std::result::Result<(), LiveObjects> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let live = live_objects();
+        if live.within(&baseline) {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(live);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -186,8 +186,15 @@
 //! Currently supported address URIs are:
 //!
 //! - TCP `tcp:127.0.0.1:12345` hostname/IP address and port
-//! - UNIX socket `unix:/run/org.example.ftl` optional access `;mode=0666` parameter
-//! - UNIX abstract namespace socket `unix:@org.example.ftl` (on Linux only)
+//! - UNIX socket `unix:/run/org.example.ftl` optional access `;mode=0660` and
+//!   `;group=varlink` parameters
+//! - UNIX abstract namespace socket `unix:@org.example.ftl` (on Linux only), for a server
+//!   `unix:@` binds to a free name, see `Listener::local_address`
+//! - VSOCK `vsock:2:12345` CID and port, `vsock:any:12345` to listen on all CIDs (on Linux only)
+//! - Named pipe `pipe:org.example.ftl` for `\\.\pipe\org.example.ftl` (on Windows only)
+//! - TLS over TCP `tcp+tls:0.0.0.0:12345` with the `tls` feature, see `ListenConfig::tls`
+//!
+//! The [`address`] module parses the addresses and describes their parameters.
 //!
 //! # Client
 //!
@@ -218,7 +225,45 @@
 //! The reply struct is placed in a structure named after the method with ```_Reply``` appended.
 //! So, the reply to the ```Ping()``` method in our example is in a struct called ```Ping_Reply```.
 //!
+//! # Logging
+//!
+//! With the `log` feature, client and server emit events through the
+//! [log](https://docs.rs/log) crate with the target `varlink`: accepted, rejected and
+//! closed connections and worker errors on the `debug` and `error` levels, requests
+//! and replies with their method and error name on the `trace` level.
+//! The parameters of the calls are never logged.
+//!
+//! # Cargo features
+//!
+//! All of these are enabled by default:
+//!
+//! - `server`: `VarlinkService`, `listen()` and the server modules, which need `unix` and `tcp`
+//! - `client`: `Connection`, `MethodCall` and `OrgVarlinkServiceClient`
+//! - `unix`: `unix:`, `vsock:`, `exec:` and `bridge:` addresses,
+//!   `Connection::with_activate` and `Connection::with_bridge`
+//! - `tcp`: `tcp:` addresses
+//! - `generator-helpers`: `StringHashMap`, `StringHashSet` and the [`dispatch`] module,
+//!   used by the code of `varlink_generator`
+//!
+//! The code generated by `varlink_generator` contains the client and the server side of
+//! an interface, so it needs all of `server`, `client` and `generator-helpers`. A client,
+//! which calls the methods with `MethodCall` over a single transport, is built with:
+//!
+//! ```toml
+//! [dependencies]
+//! varlink = { version = "<version>", default-features = false, features = ["client", "unix"] }
+//! ```
+//!
+//! Addresses of a disabled transport fail with `ErrorKind::InvalidAddress`.
+//! `libc` is only a dependency of `server`, `client`, `unix` and `tcp`, and `tempfile`
+//! of `server` and `client`.
+//! The optional `tls` feature adds `tcp+tls:` addresses and `log` the events described above.
+//! `leak-tracking` counts live calls, connections and worker threads for soak tests,
+//! see [`live_objects`](fn.live_objects.html) and the `soak` example.
+//!
 //! [`connection builder`]: struct.Connection.html#methods
+//! [`address`]: address/index.html
+//! [`dispatch`]: dispatch/index.html
 //! [`varlink::listen`]: fn.listen.html
 //! [`generator functions`]: https://docs.rs/varlink_generator
 #![doc(
@@ -227,37 +272,141 @@
 )]
 
 use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+#[cfg(any(feature = "server", feature = "generator-helpers"))]
+use std::collections::HashMap;
+#[cfg(feature = "generator-helpers")]
+use std::collections::HashSet;
 use std::convert::From;
-use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(feature = "client")]
+use std::io::{self, BufReader};
+#[cfg(any(feature = "server", feature = "client"))]
+use std::io::{BufRead, Read, Write};
+#[cfg(feature = "client")]
 use std::marker::PhantomData;
+#[cfg(feature = "generator-helpers")]
 use std::ops::{Deref, DerefMut};
+#[cfg(all(feature = "server", unix))]
+use std::os::unix::io::RawFd;
+#[cfg(feature = "client")]
 use std::process::Child;
-use std::sync::{Arc, RwLock};
-
-use serde::de::{self, DeserializeOwned};
-use serde::ser::{Serialize, SerializeMap, Serializer};
+#[cfg(any(feature = "server", feature = "client"))]
+use std::sync::Arc;
+#[cfg(any(feature = "server", feature = "client"))]
+use std::sync::RwLock;
+#[cfg(feature = "client")]
+use std::time::Duration;
+#[cfg(any(feature = "server", feature = "client"))]
+use std::time::Instant;
+
+#[cfg(any(feature = "generator-helpers", feature = "server"))]
+use serde::de;
+#[cfg(feature = "client")]
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+#[cfg(feature = "generator-helpers")]
+use serde::ser::{SerializeMap, Serializer};
 use serde_derive::{Deserialize, Serialize};
-use serde_json::{json, Value};
+#[cfg(feature = "server")]
+use serde_json::json;
+use serde_json::Value;
+#[cfg(feature = "client")]
 use tempfile::TempDir;
 
-pub use crate::client::varlink_connect;
+#[cfg(feature = "server")]
+pub use crate::auth::{Authorizer, Decision, PeerInfo};
+#[cfg(all(feature = "client", feature = "unix"))]
 use crate::client::{varlink_bridge, varlink_exec};
+#[cfg(feature = "client")]
+pub use crate::client::{varlink_connect, varlink_connect_with_config};
+#[cfg(all(feature = "leak-tracking", any(feature = "server", feature = "client")))]
+pub use crate::leak::{live_objects, wait_for_baseline, LiveObjects};
+#[cfg(feature = "server")]
+pub use crate::middleware::{Fallback, Middleware};
+#[cfg(feature = "client")]
+pub use crate::pool::ConnectionPool;
 pub use crate::stream::Stream;
+#[cfg(feature = "tcp")]
+pub use crate::stream::TcpConfig;
 pub type VarlinkStream = Box<dyn Stream>;
 pub type ServerStream = Box<dyn Stream>;
 
-pub use crate::server::{listen, ListenConfig, Listener};
+#[cfg(feature = "server")]
+pub use crate::server::{
+    handle_stdio, listen, listen_on, stdio_is_connection, Executor, IpRange, ListenConfig,
+    Listener, OverloadPolicy, PanicHandler, ServerHandle, ShutdownToken, ThreadPerConnection,
+    ThreadPool,
+};
+#[cfg(feature = "tls")]
+pub use crate::tls::TlsConfig;
 
+#[macro_use]
+mod trace;
 #[macro_use]
 pub mod error;
-pub use error::{Error, ErrorKind, Result};
-
+pub use error::{Error, ErrorContext, ErrorKind, Result};
+
+#[cfg(all(
+    feature = "unix",
+    any(feature = "server", feature = "client"),
+    any(target_os = "linux", target_os = "android")
+))]
+mod abstract_socket;
+#[cfg(feature = "server")]
+pub mod activation;
+pub mod address;
+pub mod asyncio;
+#[cfg(feature = "server")]
+mod auth;
+#[cfg(all(feature = "client", feature = "server"))]
+pub mod bridge;
+#[cfg(feature = "server")]
+pub mod broadcast;
+#[cfg(feature = "client")]
 mod client;
+pub mod clock;
+pub mod conformance;
+#[cfg(feature = "generator-helpers")]
+pub mod dispatch;
+#[cfg(feature = "server")]
+pub mod dynamic;
+#[cfg(any(feature = "server", feature = "client"))]
+mod fds;
+pub mod framing;
+#[cfg(any(feature = "server", feature = "client"))]
+mod leak;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+mod middleware;
+pub mod monitor;
+#[cfg(windows)]
+mod pipe;
+#[cfg(feature = "client")]
+mod pool;
+#[cfg(all(feature = "client", feature = "server"))]
+pub mod proxy;
+pub mod redact;
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod resolver;
+#[cfg(feature = "server")]
 mod server;
+#[cfg(feature = "server")]
+pub mod stats;
 mod stream;
-#[cfg(test)]
-mod test;
+#[cfg(all(feature = "client", feature = "server"))]
+pub mod test;
+#[cfg(all(test, feature = "client", feature = "server"))]
+mod tests;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "server")]
+mod validate;
+#[cfg(all(
+    feature = "unix",
+    any(feature = "server", feature = "client"),
+    any(target_os = "linux", target_os = "android")
+))]
+mod vsock;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct ErrorInterfaceNotFound {
@@ -330,6 +479,18 @@ impl From<Reply> for ErrorKind {
                 },
                 _ => ErrorKind::MethodNotImplemented(String::new()),
             },
+            Reply {
+                error: Some(ref t), ..
+            } if t == "org.varlink.service.PermissionDenied" => ErrorKind::PermissionDenied,
+            Reply {
+                error: Some(ref t), ..
+            } if t == "org.varlink.server.ServiceBusy" => ErrorKind::ServiceBusy,
+            Reply {
+                error: Some(ref t), ..
+            } if t == "org.varlink.broadcast.SlowConsumer" => ErrorKind::SlowConsumer,
+            Reply {
+                error: Some(ref t), ..
+            } if t == "org.varlink.service.ExpectedMore" => ErrorKind::ExpectedMore,
             _ => ErrorKind::VarlinkErrorReply(e),
         }
     }
@@ -343,6 +504,10 @@ impl ErrorKind {
                 "org.varlink.service.InterfaceNotFound" => true,
                 "org.varlink.service.MethodNotFound" => true,
                 "org.varlink.service.MethodNotImplemented" => true,
+                "org.varlink.service.PermissionDenied" => true,
+                "org.varlink.server.ServiceBusy" => true,
+                "org.varlink.broadcast.SlowConsumer" => true,
+                "org.varlink.service.ExpectedMore" => true,
                 _ => false,
             },
             _ => false,
@@ -353,9 +518,27 @@ impl ErrorKind {
 /// This trait has to be implemented by any varlink interface implementor.
 /// All methods are generated by the varlink-rust-generator, so you don't have to care
 /// about them.
+#[cfg(feature = "server")]
 pub trait Interface {
     fn get_description(&self) -> &'static str;
     fn get_name(&self) -> &'static str;
+    /// The methods dispatched by `call()`, without the interface name
+    ///
+    /// `None`, if unknown. [`VarlinkService::try_new`] checks them against the
+    /// methods of the description.
+    ///
+    /// [`VarlinkService::try_new`]: struct.VarlinkService.html#method.try_new
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        None
+    }
+    /// Handles the next message on a connection, which a method of this interface
+    /// upgraded with `call.to_upgraded()` before its reply.
+    ///
+    /// It is called again for every message, with `bufreader` positioned at its start,
+    /// and writes its answer to `call.writer`. It returns bytes read from `bufreader`,
+    /// which belong to the next message, to be passed again, usually none. An error,
+    /// e.g. `ErrorKind::ConnectionClosed` at the end of the data, closes the connection.
+    /// See [`framing`](framing/index.html) for helpers to delimit the messages.
     fn call_upgraded(&self, call: &mut Call, bufreader: &mut dyn BufRead) -> Result<Vec<u8>>;
     fn call(&self, call: &mut Call) -> Result<()>;
 }
@@ -388,13 +571,108 @@ impl<'a> Request<'a> {
     }
 }
 
+/// A request read by the server, with the fields, which are not part of the varlink
+/// protocol, for [`CallTrait::get_extension`](trait.CallTrait.html#method.get_extension)
+#[cfg(feature = "server")]
+#[derive(Debug, PartialEq)]
+struct ParsedRequest {
+    request: Request<'static>,
+    extensions: serde_json::Map<String, Value>,
+}
+
+#[cfg(feature = "server")]
+impl<'de> de::Deserialize<'de> for ParsedRequest {
+    fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(field_identifier, rename_all = "lowercase")]
+        enum Field {
+            More,
+            Oneway,
+            Upgrade,
+            Method,
+            Parameters,
+            Extension(String),
+        }
+
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = ParsedRequest;
+
+            fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+                formatter.write_str("a varlink request")
+            }
+
+            fn visit_map<V>(self, mut visitor: V) -> ::std::result::Result<Self::Value, V::Error>
+            where
+                V: de::MapAccess<'de>,
+            {
+                let mut more = None;
+                let mut oneway = None;
+                let mut upgrade = None;
+                let mut method: Option<String> = None;
+                let mut parameters = None;
+                let mut extensions = serde_json::Map::new();
+
+                while let Some(key) = visitor.next_key()? {
+                    match key {
+                        Field::More if more.is_some() => {
+                            return Err(de::Error::duplicate_field("more"))
+                        }
+                        Field::More => more = Some(visitor.next_value()?),
+                        Field::Oneway if oneway.is_some() => {
+                            return Err(de::Error::duplicate_field("oneway"))
+                        }
+                        Field::Oneway => oneway = Some(visitor.next_value()?),
+                        Field::Upgrade if upgrade.is_some() => {
+                            return Err(de::Error::duplicate_field("upgrade"))
+                        }
+                        Field::Upgrade => upgrade = Some(visitor.next_value()?),
+                        Field::Method if method.is_some() => {
+                            return Err(de::Error::duplicate_field("method"))
+                        }
+                        Field::Method => method = Some(visitor.next_value()?),
+                        Field::Parameters if parameters.is_some() => {
+                            return Err(de::Error::duplicate_field("parameters"))
+                        }
+                        Field::Parameters => parameters = Some(visitor.next_value()?),
+                        Field::Extension(name) => {
+                            extensions.insert(name, visitor.next_value()?);
+                        }
+                    }
+                }
+
+                let method = method.ok_or_else(|| de::Error::missing_field("method"))?;
+                Ok(ParsedRequest {
+                    request: Request {
+                        more: more.unwrap_or_default(),
+                        oneway: oneway.unwrap_or_default(),
+                        upgrade: upgrade.unwrap_or_default(),
+                        method: method.into(),
+                        parameters: parameters.unwrap_or_default(),
+                    },
+                    extensions,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+#[cfg(feature = "generator-helpers")]
 pub type StringHashMap<T> = HashMap<String, T>;
 
+#[cfg(feature = "generator-helpers")]
 #[derive(Debug, PartialEq, Default, Clone)]
 pub struct StringHashSet {
     inner: HashSet<String>,
 }
 
+#[cfg(feature = "generator-helpers")]
 impl StringHashSet {
     pub fn new() -> StringHashSet {
         StringHashSet {
@@ -403,6 +681,7 @@ impl StringHashSet {
     }
 }
 
+#[cfg(feature = "generator-helpers")]
 impl Deref for StringHashSet {
     type Target = HashSet<String>;
 
@@ -411,12 +690,14 @@ impl Deref for StringHashSet {
     }
 }
 
+#[cfg(feature = "generator-helpers")]
 impl DerefMut for StringHashSet {
     fn deref_mut(&mut self) -> &mut HashSet<String> {
         &mut self.inner
     }
 }
 
+#[cfg(feature = "generator-helpers")]
 impl Serialize for StringHashSet {
     fn serialize<S>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error>
     where
@@ -432,6 +713,7 @@ impl Serialize for StringHashSet {
     }
 }
 
+#[cfg(feature = "generator-helpers")]
 impl<'de> de::Deserialize<'de> for StringHashSet {
     #[inline]
     fn deserialize<D>(deserializer: D) -> ::std::result::Result<Self, D::Error>
@@ -552,11 +834,22 @@ where
 /// # }
 /// # fn main() {}
 /// ```
+#[cfg(feature = "server")]
 pub struct Call<'a> {
     pub writer: &'a mut dyn Write,
     pub request: Option<&'a Request<'a>>,
     continues: bool,
     upgraded: bool,
+    middleware: &'a [Box<dyn Middleware>],
+    validator: Option<&'a validate::Validator>,
+    /// the first error replied
+    error: Option<Cow<'static, str>>,
+    /// the final reply was sent
+    finished: bool,
+    /// the serialized reply, reused for the replies of a connection
+    buffer: Vec<u8>,
+    /// the fields of the request, which are not part of the varlink protocol
+    extensions: Option<&'a serde_json::Map<String, Value>>,
 }
 
 /// CallTrait provides convenience methods for the `Call` struct, which is passed as
@@ -617,8 +910,12 @@ pub struct Call<'a> {
 /// # }
 /// # fn main() {}
 /// ```
+#[cfg(feature = "server")]
 pub trait CallTrait {
     ///  Don't use this directly. Rather use the standard `reply()` method.
+    ///
+    /// Every reply is written as one complete message, in the order of the calls.
+    /// In debug builds, a reply after the final reply of the call panics.
     fn reply_struct(&mut self, reply: Reply) -> Result<()>;
 
     /// Set this to `true` to indicate, that more replies are following.
@@ -655,13 +952,90 @@ pub trait CallTrait {
     fn to_upgraded(&mut self);
 
     /// True, if this request does not want a reply.
+    ///
+    /// The replies of such a call are not sent, so a method may skip preparing them.
     fn is_oneway(&self) -> bool;
 
     /// True, if this request accepts more than one reply.
     fn wants_more(&self) -> bool;
 
+    /// The parsed request of the call, `None` for a call on an upgraded connection.
     fn get_request(&self) -> Option<&Request>;
 
+    /// The fully qualified name of the called method.
+    fn get_method(&self) -> Option<&str> {
+        self.get_request().map(|r| r.method.as_ref())
+    }
+
+    /// The parameters of the request.
+    fn get_parameters(&self) -> Option<&Value> {
+        self.get_request().and_then(|r| r.parameters.as_ref())
+    }
+
+    /// True, if this request wants to upgrade the connection to a custom protocol.
+    fn wants_upgrade(&self) -> bool {
+        self.get_request().and_then(|r| r.upgrade) == Some(true)
+    }
+
+    /// The value of the field `name` of the request, which is not part of the
+    /// varlink protocol.
+    fn get_extension(&self, _name: &str) -> Option<&Value> {
+        None
+    }
+
+    /// True, while the client is connected.
+    ///
+    /// A long running or streaming method handler checks it to stop early, instead of
+    /// learning about a closed connection from a failing reply. The connection is
+    /// peeked without blocking, so a client, which only closed its sending side,
+    /// counts as gone. It is `true` outside of `listen()` and on Windows.
+    fn is_alive(&self) -> bool {
+        server::peer_is_alive()
+    }
+
+    /// Call this periodically in long running or streaming method handlers.
+    ///
+    /// Returns an error with `ErrorKind::Shutdown`, if the server is shutting down,
+    /// and with `ErrorKind::ConnectionClosed`, if the client went away, so the handler
+    /// can return and free its worker thread.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #![allow(non_camel_case_types)]
+    /// # #![allow(non_snake_case)]
+    /// # pub trait VarlinkCallError: varlink::CallTrait {}
+    /// # impl<'a> VarlinkCallError for varlink::Call<'a> {}
+    /// # pub trait Call_Monitor: VarlinkCallError {
+    /// #     fn reply(&mut self) -> varlink::Result<()> {
+    /// #         self.reply_struct(varlink::Reply::parameters(None))
+    /// #     }
+    /// # }
+    /// # impl<'a> Call_Monitor for varlink::Call<'a> {}
+    /// # struct TestService;
+    /// # impl TestService {
+    /// fn monitor(&self, call: &mut dyn Call_Monitor) -> varlink::Result<()> {
+    ///     call.set_continues(true);
+    ///     loop {
+    ///         call.yield_point()?;
+    ///         /* wait for the next event */
+    ///         call.reply( /* more args*/ )?;
+    ///     }
+    /// }
+    /// # }
+    /// # fn main() {}
+    /// ```
+    fn yield_point(&mut self) -> Result<()> {
+        if server::is_shutting_down() {
+            return Err(context!(ErrorKind::Shutdown));
+        }
+        if !self.is_alive() {
+            return Err(context!(ErrorKind::ConnectionClosed));
+        }
+        std::thread::yield_now();
+        Ok(())
+    }
+
     /// reply with the standard varlink `org.varlink.service.MethodNotFound` error
     fn reply_method_not_found(&mut self, method_name: String) -> Result<()> {
         self.reply_struct(Reply::error(
@@ -700,21 +1074,128 @@ pub trait CallTrait {
             ),
         ))
     }
+
+    /// reply with the standard varlink `org.varlink.service.InvalidParameter` error for
+    /// `error` of deserializing the parameters of the request
+    ///
+    /// The parameter is the name of a missing or unknown field, e.g. `name` for
+    /// ``missing field `name` ``, else the message of `error`.
+    fn reply_invalid_args(&mut self, error: &serde_json::Error) -> Result<()> {
+        let message = error.to_string();
+        let field = ["missing field `", "unknown field `"]
+            .iter()
+            .filter(|prefix| message.starts_with(*prefix))
+            .filter_map(|prefix| message[prefix.len()..].split('`').next())
+            .next()
+            .map(String::from);
+        self.reply_invalid_parameter(field.unwrap_or(message))
+    }
+
+    /// reply with the `org.varlink.broadcast.SlowConsumer` error, e.g. after a
+    /// [`broadcast::Subscriber`](broadcast/struct.Subscriber.html) was evicted
+    fn reply_slow_consumer(&mut self) -> Result<()> {
+        self.reply_struct(Reply::error("org.varlink.broadcast.SlowConsumer", None))
+    }
+
+    /// reply with the standard varlink `org.varlink.service.PermissionDenied` error
+    fn reply_permission_denied(&mut self) -> Result<()> {
+        self.reply_struct(Reply::error("org.varlink.service.PermissionDenied", None))
+    }
+
+    /// reply with the standard varlink `org.varlink.service.ExpectedMore` error, if a
+    /// method only streams its replies and the client did not call with `more`
+    fn reply_expected_more(&mut self) -> Result<()> {
+        self.reply_struct(Reply::error("org.varlink.service.ExpectedMore", None))
+    }
+
+    /// reply with the error `name`, e.g. `org.example.ftl.NotFound`, and its `parameters`
+    ///
+    /// Returns an error with `ErrorKind::InvalidErrorName` without sending, if `name`
+    /// is not qualified by the name of an interface.
+    fn reply_error(&mut self, name: &str, parameters: Option<Value>) -> Result<()> {
+        match name.rfind('.') {
+            Some(n) if n > 0 && n + 1 < name.len() && !name.contains("..") => {
+                self.reply_struct(Reply::error(name.to_string(), parameters))
+            }
+            _ => Err(context!(ErrorKind::InvalidErrorName(name.into()))),
+        }
+    }
+
+    /// Sends `reply` with `continues`, announcing more replies.
+    ///
+    /// Returns an error with `ErrorKind::CallContinuesMismatch` without sending, if the
+    /// client did not call with `more`.
+    fn reply_struct_continues(&mut self, reply: Reply) -> Result<()> {
+        if !self.wants_more() {
+            return Err(context!(ErrorKind::CallContinuesMismatch));
+        }
+        self.set_continues(true);
+        self.reply_struct(reply)
+    }
+
+    /// Sends `reply` as the last reply of the call.
+    fn reply_struct_final(&mut self, reply: Reply) -> Result<()> {
+        self.set_continues(false);
+        self.reply_struct(reply)
+    }
 }
 
+#[cfg(feature = "server")]
 impl<'a> CallTrait for Call<'a> {
     fn reply_struct(&mut self, mut reply: Reply) -> Result<()> {
+        if let (Some(request), Some(validator)) = (self.request, self.validator) {
+            if let Err(field) = validator.check_reply(request, &reply) {
+                // the client gets an error instead of a reply, which breaks the interface
+                event!(error, "invalid reply to {}: {}", request.method, field);
+                self.continues = false;
+                self.reply_invalid_parameter(field.clone())?;
+                return Err(context!(ErrorKind::InvalidParameter(field)));
+            }
+        }
         if self.continues && (!self.wants_more()) {
             Err(context!(ErrorKind::CallContinuesMismatch))?;
         }
         if self.continues {
             reply.continues = Some(true);
         }
-        // serde_json::to_writer(&mut *self.writer, &reply)?;
-        let b = serde_json::to_string(&reply).map_err(map_context!())? + "\0";
+        if let Some(request) = self.request {
+            // a second final reply would be taken as the reply of the next request
+            debug_assert!(
+                !self.finished,
+                "varlink: reply to {} after its final reply",
+                request.method
+            );
+            self.finished = !self.continues;
+        }
+        if self.error.is_none() {
+            self.error = reply.error.clone();
+        }
+        // the client does not read the replies of a oneway call
+        if self.is_oneway() {
+            return Ok(());
+        }
+        if let Some(request) = self.request {
+            event!(
+                trace,
+                "reply to {}{}{}",
+                request.method,
+                if self.continues { " (continues)" } else { "" },
+                match reply.error {
+                    Some(ref e) => format!(": {}", e),
+                    None => String::new(),
+                }
+            );
+            for m in self.middleware.iter().rev() {
+                m.on_reply(request, &reply);
+            }
+        }
+        // the reply and its zero byte are written at once
+        self.buffer.clear();
+        serde_json::to_writer(&mut self.buffer, &reply).map_err(map_context!())?;
+        self.buffer.push(0);
 
         self.writer
-            .write_all(b.as_bytes())
+            .write_all(&self.buffer)
             .map_err(map_context!())?;
         self.writer.flush().map_err(map_context!())?;
         Ok(())
@@ -750,8 +1231,12 @@ impl<'a> CallTrait for Call<'a> {
     fn get_request(&self) -> Option<&Request> {
         self.request
     }
+    fn get_extension(&self, name: &str) -> Option<&Value> {
+        self.extensions.and_then(|e| e.get(name))
+    }
 }
 
+#[cfg(feature = "server")]
 impl<'a> Call<'a> {
     pub fn new(writer: &'a mut dyn Write, request: &'a Request<'a>) -> Self {
         Call {
@@ -759,6 +1244,12 @@ impl<'a> Call<'a> {
             request: Some(request),
             continues: false,
             upgraded: false,
+            middleware: &[],
+            validator: None,
+            error: None,
+            finished: false,
+            buffer: Vec::new(),
+            extensions: None,
         }
     }
     fn new_upgraded(writer: &'a mut dyn Write) -> Self {
@@ -767,9 +1258,70 @@ impl<'a> Call<'a> {
             request: None,
             continues: false,
             upgraded: true,
+            middleware: &[],
+            validator: None,
+            error: None,
+            finished: false,
+            buffer: Vec::new(),
+            extensions: None,
         }
     }
 
+    /// Sends the file descriptors `fds` with the next reply of the call (on Unix only).
+    ///
+    /// The caller keeps owning the descriptors and has to keep them open, until the reply
+    /// is sent. The client takes them with [`Connection::take_fds`]. Fails with
+    /// `ErrorKind::FdPassingNotSupported`, if the connection is not a unix socket served
+    /// by a worker thread, e.g. TCP, stdio or `ListenConfig::single_threaded`.
+    ///
+    /// [`Connection::take_fds`]: struct.Connection.html#method.take_fds
+    #[cfg(unix)]
+    pub fn attach_fds(&mut self, fds: &[RawFd]) -> Result<()> {
+        // the reply of a oneway call is not sent
+        if self.is_oneway() {
+            return Ok(());
+        }
+        fds::attach(fds)
+    }
+
+    /// The parsed request of the call, which generated interfaces dispatch on.
+    ///
+    /// Returns an error with `ErrorKind::NoRequest` for a call on an upgraded connection.
+    pub fn require_request(&self) -> Result<&'a Request<'a>> {
+        match self.request {
+            Some(request) => Ok(request),
+            None => Err(context!(ErrorKind::NoRequest)),
+        }
+    }
+
+    /// Passes every reply to `middleware`, in reverse order.
+    fn with_middleware(mut self, middleware: &'a [Box<dyn Middleware>]) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Checks every reply with `validator`, before it is sent.
+    fn with_validator(mut self, validator: Option<&'a validate::Validator>) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Answers `get_extension` with the fields of `extensions`.
+    fn with_extensions(mut self, extensions: &'a serde_json::Map<String, Value>) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    /// Serializes the replies into `buffer`, which is returned by `take_buffer`.
+    fn with_buffer(mut self, buffer: Vec<u8>) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    fn take_buffer(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
     pub fn reply_interface_not_found(&mut self, arg: Option<String>) -> Result<()> {
         self.reply_struct(Reply::error(
             "org.varlink.service.InterfaceNotFound",
@@ -784,19 +1336,168 @@ impl<'a> Call<'a> {
     }
 
     fn reply_parameters(&mut self, parameters: Value) -> Result<()> {
-        let reply = Reply::parameters(Some(parameters));
-        //serde_json::to_writer(&mut *self.writer, &reply)?;
-        let b = serde_json::to_string(&reply).map_err(map_context!())? + "\0";
+        self.reply_struct(Reply::parameters(Some(parameters)))
+    }
+}
 
-        self.writer
-            .write_all(b.as_bytes())
-            .map_err(map_context!())?;
-        self.writer.flush().map_err(map_context!())?;
-        Ok(())
+/// What a [`Connection`] does, when a call fails, because the connection broke
+///
+/// A connection breaks e.g., if the service restarted. Set with
+/// [`Connection::set_reconnect_policy`].
+///
+/// [`Connection`]: struct.Connection.html
+/// [`Connection::set_reconnect_policy`]: struct.Connection.html#method.set_reconnect_policy
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReconnectPolicy {
+    /// Keep the broken connection, every following call fails
+    #[default]
+    Never,
+    /// Reconnect to the same address, so the following calls succeed, but let the
+    /// failed call fail
+    Reconnect,
+    /// Reconnect and repeat a failed [`call`] of an [idempotent] method once
+    ///
+    /// [`call`]: struct.MethodCall.html#method.call
+    /// [idempotent]: struct.MethodCall.html#method.idempotent
+    RetryIdempotent,
+    /// Reconnect and repeat every failed [`call`] once
+    ///
+    /// The service might execute a repeated call twice, if the connection broke
+    /// after the service received the request.
+    ///
+    /// [`call`]: struct.MethodCall.html#method.call
+    RetryAll,
+}
+
+/// The time a [`RetryPolicy`] waits before a retry
+///
+/// [`RetryPolicy`]: struct.RetryPolicy.html
+#[cfg(feature = "client")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// Retry at once
+    None,
+    /// Wait the same time before every retry
+    Fixed(Duration),
+    /// Wait `initial` before the first retry and double the time for every further
+    /// retry, up to `max`
+    Exponential { initial: Duration, max: Duration },
+}
+
+#[cfg(feature = "client")]
+impl Backoff {
+    /// Returns the time to wait before the `retry`th retry, counting from 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        match *self {
+            Backoff::None => Duration::default(),
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => 1u32
+                .checked_shl(retry.saturating_sub(1))
+                .and_then(|factor| initial.checked_mul(factor))
+                .unwrap_or(max)
+                .min(max),
+        }
+    }
+}
+
+/// Repeats a failed [`call`] of an [idempotent] method with a backoff, e.g. while a
+/// socket activated service starts, or restarts, set with [`MethodCall::retry_policy`]
+///
+/// After the connection broke with an error accepted by `retry_on`, the call waits for
+/// the backoff, reconnects and sends the request again. If the service does not accept
+/// the connection yet, the next attempt waits and reconnects again. A call with a
+/// [timeout](struct.MethodCall.html#method.with_timeout) fails, instead of waiting past
+/// its deadline.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let policy = varlink::RetryPolicy {
+///     max_attempts: 5,
+///     backoff: varlink::Backoff::Fixed(Duration::from_millis(200)),
+///     ..Default::default()
+/// };
+/// let reply = client.ping("Test".into()).retry_policy(policy).call()?;
+/// ```
+///
+/// [`call`]: struct.MethodCall.html#method.call
+/// [idempotent]: struct.MethodCall.html#method.idempotent
+/// [`MethodCall::retry_policy`]: struct.MethodCall.html#method.retry_policy
+#[cfg(feature = "client")]
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The attempts of the call including the first one, so `1` never retries
+    pub max_attempts: u32,
+    /// The time to wait before each retry
+    pub backoff: Backoff,
+    /// Decides, if the error of the broken connection is transient and worth a retry,
+    /// [`is_transient`](#method.is_transient) by default
+    ///
+    /// It is not asked for timeouts and error replies of the service.
+    pub retry_on: fn(&ErrorKind) -> bool,
+}
+
+#[cfg(feature = "client")]
+impl RetryPolicy {
+    /// Returns `true` for the errors of a connection, which was closed, reset or
+    /// refused, or the socket of which does not exist yet.
+    pub fn is_transient(kind: &ErrorKind) -> bool {
+        match kind {
+            ErrorKind::ConnectionClosed => true,
+            ErrorKind::Io(kind) => [
+                io::ErrorKind::BrokenPipe,
+                io::ErrorKind::ConnectionAborted,
+                io::ErrorKind::ConnectionRefused,
+                io::ErrorKind::ConnectionReset,
+                io::ErrorKind::NotFound,
+                io::ErrorKind::UnexpectedEof,
+            ]
+            .contains(kind),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "client")]
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 100ms before the first retry and 200ms before the second.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Backoff::Exponential {
+                initial: Duration::from_millis(100),
+                max: Duration::from_secs(5),
+            },
+            retry_on: RetryPolicy::is_transient,
+        }
     }
 }
 
+/// The options of a client connection, see [`Connection::with_config`]
+///
+/// [`Connection::with_config`]: struct.Connection.html#method.with_config
+#[cfg(feature = "client")]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConnectConfig {
+    /// The options of `tcp:` connections, e.g. `TCP_NODELAY`
+    #[cfg(feature = "tcp")]
+    pub tcp: TcpConfig,
+    /// The maximum size of a reply without its zero byte, `0` means unlimited
+    ///
+    /// A call receiving a larger reply fails with `ErrorKind::MessageTooLarge` and the
+    /// connection is closed.
+    pub max_message_size: usize,
+    /// The timeout of connecting to a `tcp:` address, `None` waits as long as the
+    /// system does
+    ///
+    /// A host name with several addresses shares the timeout between them. The
+    /// `timeout=` parameter of an address takes precedence.
+    pub connect_timeout: Option<Duration>,
+}
+
 /// A client connection builder to a varlink service.
+#[cfg(feature = "client")]
 #[derive(Default)]
 pub struct Connection {
     pub reader: Option<BufReader<Box<dyn Read + Send + Sync>>>,
@@ -806,8 +1507,14 @@ pub struct Connection {
     pub stream: Option<Box<dyn stream::Stream>>,
     pub child: Option<Child>,
     pub tempdir: Option<TempDir>,
+    reconnect_policy: ReconnectPolicy,
+    config: ConnectConfig,
+    /// the file descriptors received by the reader
+    fds: Option<Arc<fds::ReceivedFds>>,
+    _tracked: leak::Tracked,
 }
 
+#[cfg(feature = "client")]
 impl Connection {
     /// Create a connection with a varlink URI
     ///
@@ -827,16 +1534,64 @@ impl Connection {
     /// - TCP `tcp:127.0.0.1:12345` hostname/IP address and port
     /// - UNIX socket `unix:/run/org.example.ftl`
     /// - UNIX abstract namespace socket `unix:@org.example.ftl` (on Linux only)
+    /// - VSOCK `vsock:2:12345` CID and port (on Linux only)
+    /// - Named pipe `pipe:org.example.ftl` (on Windows only)
+    /// - Service executed with an activated socket `exec:/usr/libexec/org.example.ftl`, see
+    ///   [with_activate](#method.with_activate)
+    /// - Command speaking varlink on stdin/stdout `bridge:ssh host -- varlink bridge`, see
+    ///   [with_bridge](#method.with_bridge)
+    ///
+    /// For `tcp+tls:` addresses use [with_tls](#method.with_tls).
     ///
     /// # Examples
     ///
     /// ```rust,ignore
     /// let connection = Connection::with_address("unix:/tmp/org.example.myservice");
     /// let connection = Connection::with_address("tcp:127.0.0.1:12345");
+    /// let connection = Connection::with_address("exec:/usr/libexec/org.example.myservice");
     /// ```
     pub fn with_address<S: ?Sized + AsRef<str>>(address: &S) -> Result<Arc<RwLock<Self>>> {
-        let (mut stream, address) = varlink_connect(address)?;
-        let (r, w) = stream.split()?;
+        Self::with_config(address, &ConnectConfig::default())
+    }
+
+    /// Create a connection with a varlink URI like [with_address](#method.with_address),
+    /// and set the options of `config` on the connection
+    ///
+    /// The options are kept for [reconnect](#method.reconnect).
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # fn main() -> varlink::Result<()> {
+    /// use varlink::{ConnectConfig, Connection, TcpConfig};
+    ///
+    /// let config = ConnectConfig {
+    ///     tcp: TcpConfig {
+    ///         nodelay: true,
+    ///         keepalive: Some(60),
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// let connection = Connection::with_config("tcp:127.0.0.1:12345", &config)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_config<S: ?Sized + AsRef<str>>(
+        address: &S,
+        config: &ConnectConfig,
+    ) -> Result<Arc<RwLock<Self>>> {
+        let address = address.as_ref();
+        #[cfg(feature = "unix")]
+        {
+            if let Some(command) = address.strip_prefix("exec:") {
+                return Self::with_activate(command);
+            }
+            if let Some(command) = address.strip_prefix("bridge:") {
+                return Self::with_bridge(command);
+            }
+        }
+        let (mut stream, address) = varlink_connect_with_config(address, config)?;
+        let (r, w, fds) = split_stream(stream.as_mut())?;
         let bufreader = BufReader::new(r);
         Ok(Arc::new(RwLock::new(Connection {
             reader: Some(bufreader),
@@ -845,10 +1600,85 @@ impl Connection {
             stream: Some(stream),
             child: None,
             tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: config.clone(),
+            fds,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
+        })))
+    }
+
+    /// Create a connection like [with_address](#method.with_address), which gives up
+    /// connecting to a `tcp:` address after `timeout`
+    ///
+    /// The addresses of a host name, e.g. its IPv6 and IPv4 addresses, are tried in
+    /// turn, until one accepts the connection. Fails with `ErrorKind::Timeout`, if
+    /// none answers in time, see `ConnectConfig::connect_timeout`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # fn main() -> varlink::Result<()> {
+    /// use std::time::Duration;
+    /// use varlink::Connection;
+    ///
+    /// let connection =
+    ///     Connection::connect_with_timeout("tcp:example.org:12345", Duration::from_secs(5))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_with_timeout<S: ?Sized + AsRef<str>>(
+        address: &S,
+        timeout: Duration,
+    ) -> Result<Arc<RwLock<Self>>> {
+        Self::with_config(
+            address,
+            &ConnectConfig {
+                connect_timeout: Some(timeout),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a TLS connection with a `tcp+tls:` varlink URI, e.g. `tcp+tls:example.org:12345`
+    ///
+    /// The host must be a DNS name, which matches the certificate of the service.
+    /// The `ca=`, `cert=` and `key=` parameters of the address override the paths of
+    /// `tls_config`. Requires the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub fn with_tls<S: ?Sized + AsRef<str>>(
+        address: &S,
+        tls_config: &TlsConfig,
+    ) -> Result<Arc<RwLock<Self>>> {
+        let address = address.as_ref();
+        let mut stream = address::Address::parse(address)
+            .and_then(|parsed| client::get_tlsstream(&parsed, tls_config))
+            .map_err(|e| e.with_address(address))?;
+        let (r, w) = stream.split()?;
+        Ok(Arc::new(RwLock::new(Connection {
+            reader: Some(BufReader::new(r)),
+            writer: Some(w),
+            address: address.into(),
+            stream: Some(stream),
+            child: None,
+            tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
 
     pub fn with_address_no_rw<S: ?Sized + AsRef<str>>(address: &S) -> Result<Arc<RwLock<Self>>> {
+        let address = address.as_ref();
+        #[cfg(feature = "unix")]
+        {
+            if let Some(command) = address.strip_prefix("exec:") {
+                return Self::with_activate_no_rw(command);
+            }
+            if let Some(command) = address.strip_prefix("bridge:") {
+                return Self::with_bridge_no_rw(command);
+            }
+        }
         let (stream, address) = varlink_connect(address)?;
         Ok(Arc::new(RwLock::new(Connection {
             reader: None,
@@ -857,6 +1687,10 @@ impl Connection {
             stream: Some(stream),
             child: None,
             tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
 
@@ -865,17 +1699,18 @@ impl Connection {
     /// Create a connection to a service, which is started with `command` and passed a socket pair
     /// via socket activation. The address of the unix socket is set in the environment variable
     /// `VARLINK_ADDRESS`. Additionally the socket activation variables `LISTEN_FDS=1`,
-    /// `LISTEN_FDNAMES=varlink` and `LISTEN_PID` are set.
+    /// `LISTEN_FDNAMES=varlink` and `LISTEN_PID` are set. Requires the `unix` feature.
     ///
     /// # Examples
     ///
     /// ```rust,ignore
     /// let connection = Connection::with_activate("myservice --varlink=$VARLINK_ADDRESS");
     /// ```
+    #[cfg(feature = "unix")]
     pub fn with_activate<S: ?Sized + AsRef<str>>(command: &S) -> Result<Arc<RwLock<Self>>> {
         let (child, unix_address, temp_dir) = varlink_exec(command)?;
         let (mut stream, address) = varlink_connect(&unix_address)?;
-        let (reader, writer) = stream.split()?;
+        let (reader, writer, fds) = split_stream(stream.as_mut())?;
         let bufreader = BufReader::new(reader);
         Ok(Arc::new(RwLock::new(Connection {
             reader: Some(bufreader),
@@ -884,9 +1719,14 @@ impl Connection {
             stream: Some(stream),
             child: Some(child),
             tempdir: temp_dir,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
 
+    #[cfg(feature = "unix")]
     pub fn with_activate_no_rw<S: ?Sized + AsRef<str>>(command: &S) -> Result<Arc<RwLock<Self>>> {
         let (child, unix_address, temp_dir) = varlink_exec(command)?;
         let (stream, address) = varlink_connect(&unix_address)?;
@@ -897,6 +1737,10 @@ impl Connection {
             stream: Some(stream),
             child: Some(child),
             tempdir: temp_dir,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
     /// Create a connection to a service via stdin/stdout of a specified command.
@@ -907,13 +1751,14 @@ impl Connection {
     /// will multiplex to the wanted varlink services.
     ///
     /// Of course with `ssh` there are better options, like unix socket
-    /// forwarding `-L local_socket:remote_socket`.
+    /// forwarding `-L local_socket:remote_socket`. Requires the `unix` feature.
     ///
     /// # Examples
     ///
     /// ```rust,ignore
     /// let connection = Connection::with_bridge("ssh my.example.org -- varlink bridge");
     /// ```
+    #[cfg(feature = "unix")]
     pub fn with_bridge<S: ?Sized + AsRef<str>>(command: &S) -> Result<Arc<RwLock<Self>>> {
         let (child, mut stream) = varlink_bridge(command)?;
         let (r, w) = stream.split()?;
@@ -925,9 +1770,14 @@ impl Connection {
             stream: Some(stream),
             child: Some(child),
             tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
 
+    #[cfg(feature = "unix")]
     pub fn with_bridge_no_rw<S: ?Sized + AsRef<str>>(command: &S) -> Result<Arc<RwLock<Self>>> {
         let (child, stream) = varlink_bridge(command)?;
         Ok(Arc::new(RwLock::new(Connection {
@@ -937,9 +1787,71 @@ impl Connection {
             stream: Some(stream),
             child: Some(child),
             tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
         })))
     }
 
+    /// Create a connection to the service implementing `interface`, e.g. `org.example.ping`.
+    ///
+    /// The address of the service is resolved by the resolver of the system at
+    /// [`resolver::RESOLVER_ADDRESS`]. Fails with `ErrorKind::InterfaceNotFound`, if no
+    /// service is registered for the interface. To ask another resolver, pass the address
+    /// returned by [`resolver::resolve`] to [with_address](#method.with_address).
+    ///
+    /// [`resolver::RESOLVER_ADDRESS`]: resolver/constant.RESOLVER_ADDRESS.html
+    /// [`resolver::resolve`]: resolver/fn.resolve.html
+    pub fn with_interface<S: ?Sized + AsRef<str>>(interface: &S) -> Result<Arc<RwLock<Self>>> {
+        let address = resolver::resolve(resolver::RESOLVER_ADDRESS, interface.as_ref())?;
+        Self::with_address(&address)
+    }
+
+    /// Create a connection over any stream, e.g. one end of a socket pair, a TLS stream
+    /// of another library or an in-memory pipe.
+    ///
+    /// Reads and writes share the stream, so a blocking read in one thread delays
+    /// writes in another. [`MethodCall::with_timeout`] only limits the calls, if the
+    /// stream times out itself. The connection cannot be reconnected and its
+    /// [address](#method.address) is `stream`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let (ours, theirs) = std::os::unix::net::UnixStream::pair()?;
+    /// let connection = Connection::from_stream(ours);
+    /// ```
+    ///
+    /// [`MethodCall::with_timeout`]: struct.MethodCall.html#method.with_timeout
+    pub fn from_stream<S>(stream: S) -> Arc<RwLock<Self>>
+    where
+        S: Read + Write + Send + 'static,
+    {
+        let (r, w) = stream::SharedStream::split(stream);
+        Self::from_split(r, w, "stream")
+    }
+
+    /// Creates a connection over the halves of a stream, which cannot be reconnected.
+    pub(crate) fn from_split(
+        reader: Box<dyn Read + Send + Sync>,
+        writer: Box<dyn Write + Send + Sync>,
+        address: &str,
+    ) -> Arc<RwLock<Self>> {
+        Arc::new(RwLock::new(Connection {
+            reader: Some(BufReader::new(reader)),
+            writer: Some(writer),
+            address: address.into(),
+            stream: None,
+            child: None,
+            tempdir: None,
+            reconnect_policy: ReconnectPolicy::Never,
+            config: ConnectConfig::default(),
+            fds: None,
+            _tracked: leak::Tracked::new(leak::Object::Connection),
+        }))
+    }
+
     /// Return the `address` used by the connection.
     ///
     /// Only useful, if you want to clone a connection built
@@ -947,13 +1859,84 @@ impl Connection {
     pub fn address(&self) -> String {
         self.address.clone()
     }
-}
 
-impl Drop for Connection {
-    fn drop(&mut self) {
-        if let Some(ref mut stream) = self.stream {
-            let _r = stream.shutdown();
-        }
+    /// Sets, what a call does, when it fails, because the connection broke.
+    pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+        self.reconnect_policy = policy;
+    }
+
+    /// The policy set with [set_reconnect_policy](#method.set_reconnect_policy)
+    pub fn reconnect_policy(&self) -> ReconnectPolicy {
+        self.reconnect_policy
+    }
+
+    /// Connect again to the `address` of the connection, e.g. after the service restarted.
+    ///
+    /// A call waiting for its reply on the old connection fails. Connections built
+    /// [with_bridge](#method.with_bridge), [with_tls](#method.with_tls) or
+    /// [from_stream](#method.from_stream) cannot be reconnected.
+    pub fn reconnect(&mut self) -> Result<()> {
+        let (mut stream, _) = varlink_connect_with_config(&self.address, &self.config)?;
+        let (r, w, fds) = split_stream(stream.as_mut())?;
+        if let Some(ref mut old) = self.stream {
+            let _r = old.shutdown();
+        }
+        self.reader = Some(BufReader::new(r));
+        self.writer = Some(w);
+        self.stream = Some(stream);
+        self.fds = fds;
+        Ok(())
+    }
+
+    /// Returns the file descriptors passed by the service with the replies read so far,
+    /// in the order they were sent, see [`Call::attach_fds`]. The caller owns them and has
+    /// to close them.
+    ///
+    /// Only connections to unix sockets receive file descriptors. The ones not taken
+    /// are closed with the connection.
+    ///
+    /// [`Call::attach_fds`]: struct.Call.html#method.attach_fds
+    #[cfg(unix)]
+    pub fn take_fds(&self) -> Vec<std::os::unix::io::RawFd> {
+        match self.fds {
+            Some(ref fds) => fds.take(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Splits `stream` into the reader and writer of a connection. On a unix socket, the
+/// reader keeps the file descriptors passed by the service.
+#[cfg(feature = "client")]
+#[allow(clippy::type_complexity)]
+fn split_stream(
+    stream: &mut dyn stream::Stream,
+) -> Result<(
+    Box<dyn Read + Send + Sync>,
+    Box<dyn Write + Send + Sync>,
+    Option<Arc<fds::ReceivedFds>>,
+)> {
+    let (r, w) = stream.split()?;
+    #[cfg(unix)]
+    {
+        if fds::is_unix_socket(stream.as_raw_fd()) {
+            let received = Arc::new(fds::ReceivedFds::default());
+            let reader = fds::FdReader {
+                stream: stream.try_clone().map_err(map_context!())?,
+                received: received.clone(),
+            };
+            return Ok((Box::new(reader), w, Some(received)));
+        }
+    }
+    Ok((r, w, None))
+}
+
+#[cfg(feature = "client")]
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(ref mut stream) = self.stream {
+            let _r = stream.shutdown();
+        }
         /*
         if let Some(ref mut child) = self.child {
             let _res = child.kill();
@@ -968,6 +1951,7 @@ impl Drop for Connection {
     }
 }
 
+#[cfg(feature = "client")]
 pub struct MethodCall<MRequest, MReply, MError>
 where
     MRequest: Serialize,
@@ -977,13 +1961,21 @@ where
     connection: Arc<RwLock<Connection>>,
     request: Option<MRequest>,
     method: Option<Cow<'static, str>>,
+    /// the method for the context of the errors
+    name: Cow<'static, str>,
     reader: Option<BufReader<Box<dyn Read + Send + Sync>>>,
     writer: Option<Box<dyn Write + Send + Sync>>,
     continues: bool,
+    idempotent: bool,
+    retries: usize,
+    retry_policy: Option<RetryPolicy>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
     phantom_reply: PhantomData<MReply>,
     phantom_error: PhantomData<MError>,
 }
 
+#[cfg(feature = "client")]
 impl<MRequest, MReply, MError> Iterator for MethodCall<MRequest, MReply, MError>
 where
     MRequest: Serialize,
@@ -991,15 +1983,32 @@ where
     MError: From<Error>,
 {
     type Item = std::result::Result<MReply, MError>;
+
+    /// Returns the next reply of a [more](#method.more) call.
+    ///
+    /// A call, which was not sent yet, is sent with `more`, so the replies can be
+    /// iterated directly, e.g. `for reply in client.monitor() {}`. The iteration ends
+    /// after the last reply or the first error.
     fn next(&mut self) -> Option<std::result::Result<MReply, MError>> {
+        if self.method.is_some() {
+            if let Err(e) = self.more() {
+                self.continues = false;
+                return Some(Err(e));
+            }
+        }
         if !self.continues {
             return None;
         }
 
-        Some(self.recv())
+        let reply = self.recv();
+        if reply.is_err() {
+            self.continues = false;
+        }
+        Some(reply)
     }
 }
 
+#[cfg(feature = "client")]
 impl<MRequestParameters, MReply, MError> MethodCall<MRequestParameters, MReply, MError>
 where
     MRequestParameters: Serialize,
@@ -1011,78 +2020,320 @@ where
         method: S,
         parameters: MRequestParameters,
     ) -> Self {
+        let method = method.into();
         MethodCall::<MRequestParameters, MReply, MError> {
             connection,
             request: Some(parameters),
-            method: Some(method.into()),
+            name: method.clone(),
+            method: Some(method),
             continues: false,
             reader: None,
             writer: None,
+            idempotent: false,
+            retries: 0,
+            retry_policy: None,
+            timeout: None,
+            deadline: None,
             phantom_reply: PhantomData,
             phantom_error: PhantomData,
         }
     }
 
-    fn send(&mut self, oneway: bool, more: bool, upgrade: bool) -> std::result::Result<(), MError> {
-        {
-            let mut conn = self.connection.write().unwrap();
-            let mut req = match (self.method.take(), self.request.take()) {
-                (Some(method), Some(request)) => Request::create(
-                    method,
-                    Some(
-                        serde_json::to_value(request)
-                            .map_err(map_context!())
-                            .map_err(Error::from)?,
-                    ),
-                ),
-                _ => {
-                    return Err(MError::from(Error::from(context!(
-                        ErrorKind::MethodCalledAlready
-                    ))));
-                }
-            };
+    /// Marks the method as safe to call more than once, see [retries](#method.retries).
+    ///
+    /// The code generated by `varlink_generator` marks the methods annotated with
+    /// `# @idempotent` in the interface definition.
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
 
-            if conn.reader.is_none() || conn.writer.is_none() {
-                return Err(Error::from(context!(ErrorKind::ConnectionBusy)).into());
+    /// Retry [call](#method.call) up to `retries` times, if the connection fails before
+    /// the reply arrived.
+    ///
+    /// The connection is reestablished with [`Connection::reconnect`] before every retry.
+    /// Only [idempotent](#method.idempotent) methods are retried, because the service might
+    /// have executed the failed call already. For other methods `retries` has no effect.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// // `Ping` is annotated with `# @idempotent`
+    /// let reply = client.ping("Test".into()).retries(3).call()?;
+    /// // an explicit opt-in for a method without the annotation
+    /// let reply = client.set_name("Test".into()).idempotent().retries(3).call()?;
+    /// ```
+    ///
+    /// [`Connection::reconnect`]: struct.Connection.html#method.reconnect
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Retry [call](#method.call) after transient errors of the connection with the
+    /// backoff of `policy`, instead of retrying at once, see [`RetryPolicy`].
+    ///
+    /// Like with [retries](#method.retries), only [idempotent](#method.idempotent)
+    /// methods are retried.
+    ///
+    /// [`RetryPolicy`]: struct.RetryPolicy.html
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Fails the call with `ErrorKind::Timeout`, if it does not complete within `timeout`.
+    ///
+    /// The timeout covers sending the request, retries and receiving the replies, for
+    /// a [more](#method.more) call all of them together. A hung service does not block
+    /// the calling thread longer. After a timeout the connection is busy, until it is
+    /// reconnected with [`Connection::reconnect`], because the reply might still arrive.
+    ///
+    /// Connections [with_bridge](struct.Connection.html#method.with_bridge) have no
+    /// socket and are not subject to the timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let reply = client
+    ///     .ping("Test".into())
+    ///     .with_timeout(Duration::from_secs(5))
+    ///     .call()?;
+    /// ```
+    ///
+    /// [`Connection::reconnect`]: struct.Connection.html#method.reconnect
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the socket operations to the time left until the deadline of the call.
+    fn apply_deadline(&self, conn: &Connection) -> Result<()> {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => return Ok(()),
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(context!(ErrorKind::Timeout));
+        }
+        if let Some(ref stream) = conn.stream {
+            crate::stream::set_timeout(stream.as_ref(), Some(deadline - now))
+                .map_err(map_context!())?;
+        }
+        Ok(())
+    }
+
+    /// Removes the timeout of the call from the socket, when the connection is free again.
+    fn clear_deadline(&self, conn: &Connection) {
+        if self.deadline.is_some() {
+            if let Some(ref stream) = conn.stream {
+                let _ = crate::stream::set_timeout(stream.as_ref(), None);
             }
+        }
+    }
 
-            if oneway {
-                req.oneway = Some(true);
-            } else {
-                self.reader = conn.reader.take();
+    /// Adds the address of the connection and the method to `e`.
+    fn in_context(&self, e: Error) -> Error {
+        let address = self.connection.read().unwrap().address.clone();
+        e.with_address(&address).with_method(&self.name)
+    }
+
+    /// Maps an I/O error to `ErrorKind::Timeout`, if the deadline of the call expired.
+    fn map_io_error(&self, e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut if self.deadline.is_some() => {
+                context!(e, ErrorKind::Timeout)
             }
+            _ => context!(e, ErrorKind::from(&e)),
+        }
+    }
 
-            if more {
-                req.more = Some(true);
+    fn create_request(
+        &mut self,
+        oneway: bool,
+        more: bool,
+        upgrade: bool,
+    ) -> Result<Request<'static>> {
+        let mut req = match (self.method.take(), self.request.take()) {
+            (Some(method), Some(request)) => Request::create(
+                method,
+                Some(serde_json::to_value(request).map_err(map_context!())?),
+            ),
+            _ => {
+                return Err(context!(ErrorKind::MethodCalledAlready));
             }
+        };
+
+        if oneway {
+            req.oneway = Some(true);
+        }
+
+        if more {
+            req.more = Some(true);
+        }
+
+        if upgrade {
+            req.upgrade = Some(true);
+        }
+
+        Ok(req)
+    }
+
+    fn write_request(&mut self, req: &Request) -> Result<()> {
+        let mut conn = self.connection.write().unwrap();
 
-            if upgrade {
-                req.upgrade = Some(true);
+        if conn.reader.is_none() || conn.writer.is_none() {
+            return Err(context!(ErrorKind::ConnectionBusy));
+        }
+        self.apply_deadline(&conn)?;
+
+        let oneway = req.oneway == Some(true);
+        if !oneway {
+            self.reader = conn.reader.take();
+        }
+
+        let mut w = conn.writer.take().unwrap();
+
+        event!(
+            trace,
+            "call {} on {} (more: {}, oneway: {}, upgrade: {})",
+            req.method,
+            conn.address,
+            req.more == Some(true),
+            oneway,
+            req.upgrade == Some(true)
+        );
+        let b = serde_json::to_string(req).map_err(map_context!())? + "\0";
+
+        w.write_all(b.as_bytes())
+            .map_err(|e| self.map_io_error(e))?;
+        w.flush().map_err(|e| self.map_io_error(e))?;
+        if oneway {
+            conn.writer = Some(w);
+            self.clear_deadline(&conn);
+        } else {
+            self.writer = Some(w);
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, oneway: bool, more: bool, upgrade: bool) -> std::result::Result<(), MError> {
+        let req = self
+            .create_request(oneway, more, upgrade)
+            .map_err(|e| self.in_context(e))?;
+        self.deadline = self.timeout.map(|t| Instant::now() + t);
+        if let Err(e) = self.write_request(&req) {
+            self.reconnect_after(&e, false);
+            return Err(self.in_context(e).into());
+        }
+        Ok(())
+    }
+
+    /// Returns `true`, if the connection broke with `e` and can be reconnected.
+    fn connection_broke(e: &Error) -> bool {
+        match e.kind() {
+            // a timeout leaves the connection intact
+            ErrorKind::Io(io::ErrorKind::TimedOut) | ErrorKind::Io(io::ErrorKind::WouldBlock) => {
+                false
             }
+            ErrorKind::Io(_) | ErrorKind::ConnectionClosed => true,
+            _ => false,
+        }
+    }
 
-            let mut w = conn.writer.take().unwrap();
-
-            let b = serde_json::to_string(&req)
-                .map_err(map_context!())
-                .map_err(Error::from)?
-                + "\0";
-
-            w.write_all(b.as_bytes())
-                .map_err(map_context!())
-                .map_err(Error::from)?;
-            w.flush().map_err(map_context!()).map_err(Error::from)?;
-            if oneway {
-                conn.writer = Some(w);
-            } else {
-                self.writer = Some(w);
+    /// Reconnects, if the connection broke with `e` and the `ReconnectPolicy`
+    /// of the connection or `retry` allows it. Returns `true` on success.
+    fn reconnect_after(&mut self, e: &Error, retry: bool) -> bool {
+        if !Self::connection_broke(e) {
+            return false;
+        }
+        let mut conn = self.connection.write().unwrap();
+        if !retry && conn.reconnect_policy == ReconnectPolicy::Never {
+            return false;
+        }
+        self.reader = None;
+        self.writer = None;
+        event!(debug, "reconnecting to {} after: {}", conn.address, e);
+        conn.reconnect().is_ok()
+    }
+
+    /// Waits for the backoff of the retry policy and reconnects after `e`, until the
+    /// connection is established again, or the attempts are used up. Returns `true`, if
+    /// the call can be repeated.
+    fn retry_with_policy(&mut self, e: &Error, retry: &mut u32) -> bool {
+        let policy = match self.retry_policy {
+            Some(ref policy) => policy.clone(),
+            None => return false,
+        };
+        if !Self::connection_broke(e) || !(policy.retry_on)(e.kind()) {
+            self.reconnect_after(e, false);
+            return false;
+        }
+        loop {
+            *retry += 1;
+            if *retry >= policy.max_attempts {
+                self.reconnect_after(e, false);
+                return false;
             }
+            let delay = policy.backoff.delay(*retry);
+            if let Some(deadline) = self.deadline {
+                if Instant::now() + delay >= deadline {
+                    return false;
+                }
+            }
+            std::thread::sleep(delay);
+            if self.reconnect_after(e, true) {
+                return true;
+            }
+            event!(debug, "reconnecting failed, attempt {}", *retry + 1);
         }
-        Ok(())
     }
 
     pub fn call(&mut self) -> std::result::Result<MReply, MError> {
-        self.send(false, false, false)?;
-        self.recv()
+        let req = self
+            .create_request(false, false, false)
+            .map_err(|e| self.in_context(e))?;
+        self.deadline = self.timeout.map(|t| Instant::now() + t);
+        if self.idempotent && self.retry_policy.is_some() {
+            let mut retry = 0;
+            loop {
+                match self.write_request(&req).and_then(|_| self.recv_reply()) {
+                    Ok(reply) => {
+                        return Self::reply_parameters(reply).map_err(|e| self.in_context(e).into())
+                    }
+                    Err(e) => {
+                        if !self.retry_with_policy(&e, &mut retry) {
+                            return Err(self.in_context(e).into());
+                        }
+                        event!(debug, "retrying {} after: {}", req.method, e);
+                    }
+                }
+            }
+        }
+        let mut retries = if self.idempotent { self.retries } else { 0 };
+        let retry_once = match self.connection.read().unwrap().reconnect_policy {
+            ReconnectPolicy::RetryAll => true,
+            ReconnectPolicy::RetryIdempotent => self.idempotent,
+            _ => false,
+        };
+        if retry_once {
+            retries = retries.max(1);
+        }
+        loop {
+            match self.write_request(&req).and_then(|_| self.recv_reply()) {
+                Ok(reply) => {
+                    return Self::reply_parameters(reply).map_err(|e| self.in_context(e).into())
+                }
+                Err(e) => {
+                    if !self.reconnect_after(&e, retries > 0) || retries == 0 {
+                        return Err(self.in_context(e).into());
+                    }
+                    retries -= 1;
+                    event!(debug, "retrying {} after: {}", req.method, e);
+                }
+            }
+        }
     }
 
     pub fn upgrade(&mut self) -> std::result::Result<MReply, MError> {
@@ -1090,6 +2341,45 @@ where
         self.recv()
     }
 
+    /// Calls the method with `upgrade` and returns its reply and the connection as a
+    /// raw stream for the custom protocol of the service.
+    ///
+    /// The connection cannot be used for varlink calls afterwards. Data sent by the
+    /// service right after the reply is kept in the buffer of the stream.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// let (_reply, mut stream) = client.transfer().upgrade_stream()?;
+    /// varlink::framing::write_line(&mut stream, "GET README")?;
+    /// let answer = varlink::framing::read_line(&mut stream, 1024)?;
+    /// ```
+    pub fn upgrade_stream(&mut self) -> std::result::Result<(MReply, Upgraded), MError> {
+        let reply = self.upgrade()?;
+        let mut conn = self.connection.write().unwrap();
+        match (conn.reader.take(), conn.writer.take()) {
+            (Some(reader), Some(writer)) => Ok((
+                reply,
+                Upgraded {
+                    reader,
+                    writer,
+                    _connection: self.connection.clone(),
+                },
+            )),
+            _ => Err(context!(ErrorKind::ConnectionBusy).into()),
+        }
+    }
+
+    /// Sends the request with `"oneway": true` and returns without waiting for a reply.
+    ///
+    /// The service does not reply, so the connection is free for the next call at once.
+    /// An error of the method is not reported.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,ignore
+    /// client.notify("Test".into()).oneway()?;
+    /// ```
     pub fn oneway(&mut self) -> std::result::Result<(), MError> {
         self.send(true, false, false)
     }
@@ -1100,26 +2390,38 @@ where
         Ok(self)
     }
 
-    pub fn recv(&mut self) -> std::result::Result<MReply, MError> {
+    fn recv_reply(&mut self) -> Result<Reply> {
         if self.reader.is_none() || self.writer.is_none() {
-            return Err(Error::from(context!(ErrorKind::IteratorOldReply)).into());
+            return Err(context!(ErrorKind::IteratorOldReply));
         }
 
+        self.apply_deadline(&self.connection.read().unwrap())?;
+
         let mut buf = Vec::new();
 
+        let max = self.connection.read().unwrap().config.max_message_size;
         let mut reader = self.reader.take().unwrap();
-        reader
-            .read_until(0, &mut buf)
-            .map_err(map_context!())
-            .map_err(Error::from)?;
+        match max {
+            0 => reader.read_until(0, &mut buf),
+            max => (&mut reader).take(max as u64 + 1).read_until(0, &mut buf),
+        }
+        .map_err(|e| self.map_io_error(e))?;
         self.reader = Some(reader);
         if buf.is_empty() {
-            return Err(Error::from(context!(ErrorKind::ConnectionClosed)).into());
+            return Err(context!(ErrorKind::ConnectionClosed));
+        }
+        if max > 0 && buf.len() > max && buf.last() != Some(&0) {
+            let mut conn = self.connection.write().unwrap();
+            if let Some(ref mut stream) = conn.stream {
+                let _r = stream.shutdown();
+            }
+            conn.reader = self.reader.take();
+            conn.writer = self.writer.take();
+            self.clear_deadline(&conn);
+            return Err(context!(ErrorKind::MessageTooLarge(max)));
         }
         buf.pop();
-        let reply: Reply = serde_json::from_slice(&buf)
-            .map_err(map_context!())
-            .map_err(Error::from)?;
+        let reply: Reply = serde_json::from_slice(&buf).map_err(map_context!())?;
         match reply.continues {
             Some(true) => self.continues = true,
             _ => {
@@ -1127,10 +2429,18 @@ where
                 let mut conn = self.connection.write().unwrap();
                 conn.reader = self.reader.take();
                 conn.writer = self.writer.take();
+                self.clear_deadline(&conn);
             }
         }
+        if let Some(ref e) = reply.error {
+            event!(debug, "error reply {}", e);
+        }
+        Ok(reply)
+    }
+
+    fn reply_parameters(reply: Reply) -> Result<MReply> {
         if reply.error != None {
-            return Err(Error::from(context!(ErrorKind::from(reply))).into());
+            return Err(context!(ErrorKind::from(reply)));
         }
 
         match reply {
@@ -1138,9 +2448,7 @@ where
                 parameters: Some(p),
                 ..
             } => {
-                let mreply: MReply = serde_json::from_value(p)
-                    .map_err(map_context!())
-                    .map_err(Error::from)?;
+                let mreply: MReply = serde_json::from_value(p).map_err(map_context!())?;
                 Ok(mreply)
             }
             Reply {
@@ -1148,12 +2456,77 @@ where
             } => {
                 let mreply: MReply =
                     serde_json::from_value(serde_json::Value::Object(serde_json::Map::new()))
-                        .map_err(map_context!())
-                        .map_err(Error::from)?;
+                        .map_err(map_context!())?;
                 Ok(mreply)
             }
         }
     }
+
+    pub fn recv(&mut self) -> std::result::Result<MReply, MError> {
+        match self.recv_reply() {
+            Ok(reply) => Self::reply_parameters(reply).map_err(|e| self.in_context(e).into()),
+            Err(e) => {
+                self.reconnect_after(&e, false);
+                Err(self.in_context(e).into())
+            }
+        }
+    }
+}
+
+/// A connection switched to the custom protocol of a service, see
+/// [`MethodCall::upgrade_stream`](struct.MethodCall.html#method.upgrade_stream)
+///
+/// Reads are buffered. The connection is closed, when the stream is dropped.
+#[cfg(feature = "client")]
+pub struct Upgraded {
+    reader: BufReader<Box<dyn Read + Send + Sync>>,
+    writer: Box<dyn Write + Send + Sync>,
+    /// keeps the socket open
+    _connection: Arc<RwLock<Connection>>,
+}
+
+#[cfg(feature = "client")]
+impl Upgraded {
+    /// Splits the stream into its reader and writer, e.g. to use them from two threads.
+    ///
+    /// The connection is closed, when both are dropped.
+    pub fn into_parts(
+        self,
+    ) -> (
+        BufReader<Box<dyn Read + Send + Sync>>,
+        Box<dyn Write + Send + Sync>,
+    ) {
+        (self.reader, self.writer)
+    }
+}
+
+#[cfg(feature = "client")]
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(feature = "client")]
+impl BufRead for Upgraded {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+#[cfg(feature = "client")]
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
@@ -1181,16 +2554,19 @@ pub struct GetInterfaceDescriptionReply {
 
 impl VarlinkReply for GetInterfaceDescriptionReply {}
 
+#[cfg(feature = "client")]
 pub struct OrgVarlinkServiceClient {
     connection: Arc<RwLock<Connection>>,
 }
 
+#[cfg(feature = "client")]
 impl OrgVarlinkServiceClient {
     pub fn new(connection: Arc<RwLock<Connection>>) -> Self {
         OrgVarlinkServiceClient { connection }
     }
 }
 
+#[cfg(feature = "client")]
 pub trait OrgVarlinkServiceInterface {
     fn get_info(&mut self) -> Result<ServiceInfo>;
     fn get_interface_description<S: Into<Cow<'static, str>>>(
@@ -1199,6 +2575,7 @@ pub trait OrgVarlinkServiceInterface {
     ) -> Result<GetInterfaceDescriptionReply>;
 }
 
+#[cfg(feature = "client")]
 impl OrgVarlinkServiceInterface for OrgVarlinkServiceClient {
     fn get_info(&mut self) -> Result<ServiceInfo> {
         MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
@@ -1223,12 +2600,105 @@ impl OrgVarlinkServiceInterface for OrgVarlinkServiceClient {
     }
 }
 
+/// Typed calls of the `org.varlink.service` interface directly on a connection
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use varlink::{Connection, ConnectionExt};
+///
+/// # fn main() -> varlink::Result<()> {
+/// let connection = Connection::with_address("unix:/run/org.example.ftl")?;
+/// if connection.interfaces()?.iter().any(|i| i == "org.example.ftl") {
+///     println!("{}", connection.get_info()?.product);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "client")]
+pub trait ConnectionExt {
+    /// Calls `org.varlink.service.GetInfo`.
+    fn get_info(&self) -> Result<ServiceInfo>;
+
+    /// Returns the names of the interfaces implemented by the service.
+    fn interfaces(&self) -> Result<Vec<String>>;
+}
+
+#[cfg(feature = "client")]
+impl ConnectionExt for Arc<RwLock<Connection>> {
+    fn get_info(&self) -> Result<ServiceInfo> {
+        OrgVarlinkServiceClient::new(self.clone()).get_info()
+    }
+
+    fn interfaces(&self) -> Result<Vec<String>> {
+        Ok(self
+            .get_info()?
+            .interfaces
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect())
+    }
+}
+
 /// VarlinkService handles all the I/O and dispatches method calls to the registered interfaces.
+#[cfg(feature = "server")]
 pub struct VarlinkService {
     info: ServiceInfo,
-    ifaces: HashMap<Cow<'static, str>, Box<dyn Interface + Send + Sync>>,
+    ifaces: RwLock<Interfaces>,
+    authorizer: Option<Box<dyn Authorizer>>,
+    middleware: Vec<Box<dyn Middleware>>,
+    fallback: Option<Box<dyn Fallback>>,
+    max_message_size: usize,
+}
+
+/// The interfaces of a service, which are changed at runtime
+#[cfg(feature = "server")]
+#[derive(Default)]
+struct Interfaces {
+    ifaces: HashMap<Cow<'static, str>, Arc<dyn Interface + Send + Sync>>,
+    validator: Option<Arc<validate::Validator>>,
 }
 
+#[cfg(feature = "server")]
+impl Interfaces {
+    fn insert(&mut self, iface: Box<dyn Interface + Send + Sync>) -> Result<()> {
+        check_interface(iface.as_ref())?;
+        let name = iface.get_name();
+        if name == "org.varlink.service" || self.ifaces.contains_key(name) {
+            return Err(context!(ErrorKind::InvalidInterface(format!(
+                "{}: registered twice",
+                name
+            ))));
+        }
+        self.ifaces.insert(name.into(), Arc::from(iface));
+        Ok(())
+    }
+
+    /// Like `insert`, but registers an inconsistent interface anyway and only logs it.
+    fn insert_unchecked(&mut self, iface: Box<dyn Interface + Send + Sync>) {
+        if let Err(e) = check_interface(iface.as_ref()) {
+            event!(error, "VarlinkService: {}", e);
+        }
+        self.ifaces
+            .insert(iface.get_name().into(), Arc::from(iface));
+    }
+
+    /// Parses the descriptions of the interfaces for the validation of the calls.
+    fn set_validator(&mut self, service_description: &'static str) {
+        let mut descriptions = vec![service_description];
+        descriptions.extend(self.ifaces.values().map(|i| i.get_description()));
+        self.validator = Some(Arc::new(validate::Validator::new(&descriptions)));
+    }
+
+    /// Parses the descriptions again after a change, if the service validates the calls.
+    fn update_validator(&mut self, service_description: &'static str) {
+        if self.validator.is_some() {
+            self.set_validator(service_description);
+        }
+    }
+}
+
+#[cfg(feature = "server")]
 impl Interface for VarlinkService {
     fn get_description(&self) -> &'static str {
         r#"# The Varlink Service Interface is provided by every varlink service. It
@@ -1260,6 +2730,12 @@ error MethodNotImplemented (method: string)
 
 # One of the passed parameters is invalid.
 error InvalidParameter (parameter: string)
+
+# Client is denied access
+error PermissionDenied ()
+
+# The method expects to be called with more
+error ExpectedMore ()
 "#
     }
 
@@ -1275,9 +2751,13 @@ error InvalidParameter (parameter: string)
     fn call(&self, call: &mut Call) -> Result<()> {
         // call.request starts at least with "org.varlink.service."
 
-        match call.request.as_ref().unwrap() {
+        match call.require_request()? {
             Request { method: ref m, .. } if m == "org.varlink.service.GetInfo" => {
-                call.reply_parameters(serde_json::to_value(&self.info).map_err(map_context!())?)
+                let info = ServiceInfo {
+                    interfaces: self.interface_names(),
+                    ..self.info.clone()
+                };
+                call.reply_parameters(serde_json::to_value(&info).map_err(map_context!())?)
             }
 
             Request {
@@ -1291,10 +2771,13 @@ error InvalidParameter (parameter: string)
                     "org.varlink.service" => {
                         call.reply_parameters(json!({"description": self.get_description()}))
                     }
-                    key if self.ifaces.contains_key(key) => call.reply_parameters(
-                        json!({"description": self.ifaces[key].get_description()}),
-                    ),
-                    _ => call.reply_invalid_parameter("interface".into()),
+                    key => match (self.interface(key), &self.fallback) {
+                        (Some(iface), _) => {
+                            call.reply_parameters(json!({"description": iface.get_description()}))
+                        }
+                        (None, Some(fallback)) => fallback.call(key, call),
+                        (None, None) => call.reply_invalid_parameter("interface".into()),
+                    },
                 }
             }
 
@@ -1311,6 +2794,7 @@ error InvalidParameter (parameter: string)
     }
 }
 
+#[cfg(feature = "server")]
 impl VarlinkService {
     ///  Create a new `VarlinkService`.
     ///
@@ -1350,6 +2834,9 @@ impl VarlinkService {
     /// # }
     /// # fn main() {}
     /// ```
+    ///
+    /// An inconsistent interface is registered anyway, the error is only logged with the
+    /// `log` feature. Use [`try_new`](#method.try_new) to reject it.
     pub fn new<S: Into<Cow<'static, str>>>(
         vendor: S,
         product: S,
@@ -1357,35 +2844,227 @@ impl VarlinkService {
         url: S,
         interfaces: Vec<Box<dyn Interface + Send + Sync>>,
     ) -> Self {
-        let mut ifhashmap = HashMap::<Cow<'static, str>, Box<dyn Interface + Send + Sync>>::new();
+        let mut ifaces = Interfaces::default();
+        for i in interfaces {
+            ifaces.insert_unchecked(i);
+        }
+        Self::with_interfaces(vendor, product, version, url, ifaces)
+    }
+
+    /// Create a `VarlinkService` with the interfaces of all `*.varlink` files in `dir`,
+    /// whose calls are answered by `handler`.
+    ///
+    /// See the [`dynamic`](dynamic/index.html) module.
+    pub fn from_dir<S, P, H>(
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+        dir: P,
+        handler: H,
+    ) -> Result<Self>
+    where
+        S: Into<Cow<'static, str>>,
+        P: AsRef<std::path::Path>,
+        H: dynamic::DynamicHandler + 'static,
+    {
+        let interfaces = dynamic::load_dir(dir, Arc::new(handler))?;
+        Self::try_new(vendor, product, version, url, interfaces)
+    }
+
+    /// Like [`new`](#method.new), but returns an error for an inconsistent interface.
+    ///
+    /// The description of every interface must parse, declare the name returned by
+    /// `get_name()`, and only contain methods, which are dispatched by `call()`.
+    /// Every name may only be registered once.
+    pub fn try_new<S: Into<Cow<'static, str>>>(
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+        interfaces: Vec<Box<dyn Interface + Send + Sync>>,
+    ) -> Result<Self> {
+        let mut ifaces = Interfaces::default();
         for i in interfaces {
-            ifhashmap.insert(i.get_name().into(), i);
+            ifaces.insert(i)?;
         }
-        let mut ifnames: Vec<Cow<'static, str>> = Vec::new();
-        ifnames.push("org.varlink.service".into());
-        ifnames.extend(ifhashmap.keys().cloned());
+        Ok(Self::with_interfaces(vendor, product, version, url, ifaces))
+    }
+
+    fn with_interfaces<S: Into<Cow<'static, str>>>(
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+        ifaces: Interfaces,
+    ) -> Self {
         VarlinkService {
             info: ServiceInfo {
                 vendor: vendor.into(),
                 product: product.into(),
                 version: version.into(),
                 url: url.into(),
-                interfaces: ifnames,
+                interfaces: Vec::new(),
             },
-            ifaces: ifhashmap,
+            ifaces: RwLock::new(ifaces),
+            authorizer: None,
+            middleware: Vec::new(),
+            fallback: None,
+            max_message_size: 0,
+        }
+    }
+
+    /// Adds `interface` to the service, while it is serving.
+    ///
+    /// The interface is checked like the ones of [`try_new`](#method.try_new), and
+    /// `GetInfo` lists it for the following calls. To change a listening service, keep
+    /// a clone of an `Arc` of it, which is passed to `listen()`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// # use std::io;
+    /// # use std::sync::Arc;
+    /// # struct Plugin;
+    /// # impl varlink::Interface for Plugin {
+    /// # fn get_description(&self) -> &'static str {
+    /// #                    "interface org.example.plugin\nmethod Ping() -> ()" }
+    /// # fn get_name(&self) -> &'static str { "org.example.plugin" }
+    /// # fn call_upgraded(&self, call: &mut varlink::Call, _bufreader: &mut dyn io::BufRead) ->
+    /// # varlink::Result<Vec<u8>> { Ok(Vec::new()) }
+    /// # fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> { Ok(()) }
+    /// # }
+    /// # fn main() -> varlink::Result<()> {
+    /// let service = Arc::new(varlink::VarlinkService::new(
+    ///     "org.varlink",
+    ///     "test service",
+    ///     "0.1",
+    ///     "http://varlink.org",
+    ///     vec![],
+    /// ));
+    /// let listening = service.clone();
+    /// std::thread::spawn(move || {
+    ///     varlink::listen(listening, "unix:/run/org.example.plugins", &Default::default())
+    /// });
+    /// service.add_interface(Box::new(Plugin))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn add_interface(&self, interface: Box<dyn Interface + Send + Sync>) -> Result<()> {
+        let mut ifaces = self.ifaces.write().unwrap();
+        ifaces.insert(interface)?;
+        ifaces.update_validator(self::Interface::get_description(self));
+        Ok(())
+    }
+
+    /// Removes the interface `name` from the service, while it is serving.
+    ///
+    /// Running calls of the interface are finished, the following calls are answered
+    /// with `org.varlink.service.InterfaceNotFound`. Returns `false`, if no interface
+    /// was registered as `name`.
+    pub fn remove_interface(&self, name: &str) -> bool {
+        let mut ifaces = self.ifaces.write().unwrap();
+        if ifaces.ifaces.remove(name).is_none() {
+            return false;
         }
+        ifaces.update_validator(self::Interface::get_description(self));
+        true
+    }
+
+    /// The names of the interfaces, starting with `org.varlink.service`, as returned by
+    /// `GetInfo`
+    pub fn interface_names(&self) -> Vec<Cow<'static, str>> {
+        let mut names: Vec<Cow<'static, str>> =
+            self.ifaces.read().unwrap().ifaces.keys().cloned().collect();
+        names.sort();
+        names.insert(0, "org.varlink.service".into());
+        names
+    }
+
+    fn interface(&self, name: &str) -> Option<Arc<dyn Interface + Send + Sync>> {
+        self.ifaces.read().unwrap().ifaces.get(name).cloned()
+    }
+
+    fn validator(&self) -> Option<Arc<validate::Validator>> {
+        self.ifaces.read().unwrap().validator.clone()
+    }
+
+    /// Checks every call with `authorizer`, before it is dispatched.
+    ///
+    /// See [`Authorizer`](trait.Authorizer.html).
+    pub fn with_authorizer<A: Authorizer + 'static>(mut self, authorizer: A) -> Self {
+        self.authorizer = Some(Box::new(authorizer));
+        self
+    }
+
+    /// Adds `middleware` after the already added middleware.
+    ///
+    /// See [`Middleware`](trait.Middleware.html).
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Answers the calls of unknown interfaces with `fallback`, instead of replying
+    /// `org.varlink.service.InterfaceNotFound`.
+    ///
+    /// See [`Fallback`](trait.Fallback.html).
+    pub fn with_fallback<F: Fallback + 'static>(mut self, fallback: F) -> Self {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// Validates the parameters of every request and reply against the description of
+    /// the interface.
+    ///
+    /// A field, which is missing, not declared, or has the wrong type or enum value, is
+    /// answered with `org.varlink.service.InvalidParameter` naming the field, e.g.
+    /// `foo.bar[2]`, before the interface is called. A reply breaking the interface is
+    /// replaced by `InvalidParameter` and the method gets an error with
+    /// `ErrorKind::InvalidParameter`.
+    ///
+    /// Without it, unknown fields are ignored and only type errors of the deserialization
+    /// are reported. The interfaces added later are validated, too.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// let service = varlink::VarlinkService::new(
+    ///     "org.varlink",
+    ///     "test service",
+    ///     "0.1",
+    ///     "http://varlink.org",
+    ///     vec![/* Your varlink interfaces go here */],
+    /// )
+    /// .with_strict_validation();
+    /// ```
+    pub fn with_strict_validation(self) -> Self {
+        self.ifaces
+            .write()
+            .unwrap()
+            .set_validator(self::Interface::get_description(&self));
+        self
+    }
+
+    /// Limits the size of a request without its zero byte to `size` bytes, `0` means
+    /// unlimited.
+    ///
+    /// A larger request is answered with `org.varlink.service.InvalidParameter` for the
+    /// parameter `message length`, and the connection is closed with
+    /// `ErrorKind::MessageTooLarge`. The data of upgraded connections is not limited.
+    pub fn with_max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = size;
+        self
     }
 
     fn call(&self, iface: &str, call: &mut Call) -> Result<()> {
         match iface {
             "org.varlink.service" => self::Interface::call(self, call),
-            key => {
-                if self.ifaces.contains_key(key) {
-                    self.ifaces[key].call(call)
-                } else {
-                    call.reply_interface_not_found(Some(iface.into()))
-                }
-            }
+            key => match (self.interface(key), &self.fallback) {
+                (Some(i), _) => i.call(call),
+                (None, Some(fallback)) => fallback.call(key, call),
+                (None, None) => call.reply_interface_not_found(Some(iface.into())),
+            },
         }
     }
 
@@ -1397,18 +3076,88 @@ impl VarlinkService {
     ) -> Result<Vec<u8>> {
         match iface {
             "org.varlink.service" => self::Interface::call_upgraded(self, call, bufreader),
-            key => {
-                if self.ifaces.contains_key(key) {
-                    self.ifaces[key].call_upgraded(call, bufreader)
-                } else {
+            key => match (self.interface(key), &self.fallback) {
+                (Some(i), _) => i.call_upgraded(call, bufreader),
+                (None, Some(fallback)) => fallback.call_upgraded(key, call, bufreader),
+                (None, None) => {
                     call.reply_interface_not_found(Some(iface.into()))?;
                     Ok(Vec::new())
                 }
+            },
+        }
+    }
+}
+
+/// Checks the description of `iface` against its name and dispatched methods.
+#[cfg(feature = "server")]
+fn check_interface(iface: &(dyn Interface + Send + Sync)) -> Result<()> {
+    let name = iface.get_name();
+    let invalid =
+        |reason: String| context!(ErrorKind::InvalidInterface(format!("{}: {}", name, reason)));
+
+    let idl = varlink_parser::IDL::from_string(iface.get_description())
+        .map_err(|e| invalid(e.to_string()))?;
+    if idl.name != name {
+        return Err(invalid(format!(
+            "the description declares interface '{}'",
+            idl.name
+        )));
+    }
+    if let Some(methods) = iface.get_method_names() {
+        for method in idl.methods.keys() {
+            if !methods.contains(method) {
+                return Err(invalid(format!("method '{}' is not dispatched", method)));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+impl VarlinkService {
+    /// Answers the request of `call` with `reply` from the middleware, or dispatches it.
+    ///
+    /// Returns the interface, if it upgraded the connection.
+    fn dispatch(&self, call: &mut Call, reply: Option<Reply>) -> Result<Option<String>> {
+        let req = call.require_request()?;
+        if let Some(reply) = reply {
+            call.reply_struct(reply)?;
+            return Ok(None);
+        }
+
+        let iface = match req.method.rfind('.') {
+            None => {
+                call.reply_interface_not_found(Some(req.method.to_string()))?;
+                return Ok(None);
+            }
+            Some(n) => &req.method[..n],
+        };
+
+        if let Some(ref authorizer) = self.authorizer {
+            let peer = auth::current_peer();
+            if authorizer.authorize(&peer, iface, &req.method) == Decision::Deny {
+                call.reply_permission_denied()?;
+                return Ok(None);
+            }
+        }
+
+        if let Some(validator) = call.validator {
+            if let Err(field) = validator.check_request(req) {
+                call.reply_invalid_parameter(field)?;
+                return Ok(None);
             }
         }
+
+        self.call(iface, call)?;
+        if call.upgraded {
+            Ok(Some(iface.to_string()))
+        } else {
+            Ok(None)
+        }
     }
 }
 
+#[cfg(feature = "server")]
 pub trait ConnectionHandler {
     fn handle(
         &self,
@@ -1416,8 +3165,37 @@ pub trait ConnectionHandler {
         writer: &mut dyn Write,
         upgraded_iface: Option<String>,
     ) -> Result<(Vec<u8>, Option<String>)>;
+
+    /// The maximum size of a message, `0` means unlimited
+    ///
+    /// A server, which buffers the messages before `handle()`, passes a larger
+    /// incomplete message to `handle()`, which rejects it.
+    fn max_message_size(&self) -> usize {
+        0
+    }
 }
 
+#[cfg(feature = "server")]
+impl<H: ConnectionHandler> ConnectionHandler for Arc<H> {
+    fn handle(
+        &self,
+        bufreader: &mut dyn BufRead,
+        writer: &mut dyn Write,
+        upgraded_iface: Option<String>,
+    ) -> Result<(Vec<u8>, Option<String>)> {
+        (**self).handle(bufreader, writer, upgraded_iface)
+    }
+
+    fn max_message_size(&self) -> usize {
+        (**self).max_message_size()
+    }
+}
+
+/// The capacity of the request and reply buffers, which are kept for the next message
+#[cfg(feature = "server")]
+const MAX_REUSED_BUFFER: usize = 64 * 1024;
+
+#[cfg(feature = "server")]
 impl ConnectionHandler for VarlinkService {
     /// ```handle()``` consumes every null terminated message from ```reader```
     /// and writes the reply to ```writer```.
@@ -1457,17 +3235,23 @@ impl ConnectionHandler for VarlinkService {
         upgraded_last_interface: Option<String>,
     ) -> Result<(Vec<u8>, Option<String>)> {
         let mut upgraded_iface = upgraded_last_interface.clone();
+        // the buffers of the request and the replies are reused for the next request
+        let mut buf = Vec::new();
+        let mut out = Vec::new();
         loop {
             if let Some(iface) = upgraded_iface {
+                let _tracked = leak::Tracked::new(leak::Object::Call);
                 let mut call = Call::new_upgraded(writer);
                 let unread = self.call_upgraded(&iface, &mut call, bufreader)?;
                 return Ok((unread, Some(iface)));
             }
 
-            let mut buf = Vec::new();
-            let len = bufreader
-                .read_until(b'\0', &mut buf)
-                .map_err(map_context!())?;
+            buf.clear();
+            let len = match self.max_message_size {
+                0 => bufreader.read_until(b'\0', &mut buf),
+                max => bufreader.take(max as u64 + 1).read_until(b'\0', &mut buf),
+            }
+            .map_err(map_context!())?;
 
             if len == 0 {
                 // EOF
@@ -1475,6 +3259,17 @@ impl ConnectionHandler for VarlinkService {
             }
 
             if buf.get(len - 1).unwrap_or(&b'x') != &b'\0' {
+                if self.max_message_size > 0 && len > self.max_message_size {
+                    let reply = Reply::error(
+                        "org.varlink.service.InvalidParameter",
+                        Some(json!({ "parameter": "message length" })),
+                    );
+                    let mut b = serde_json::to_vec(&reply).map_err(map_context!())?;
+                    b.push(0);
+                    writer.write_all(&b).map_err(map_context!())?;
+                    writer.flush().map_err(map_context!())?;
+                    return Err(context!(ErrorKind::MessageTooLarge(self.max_message_size)));
+                }
                 // Incomplete message
                 return Ok((buf, None));
             }
@@ -1482,29 +3277,58 @@ impl ConnectionHandler for VarlinkService {
             // pop the last zero byte
             buf.pop();
 
-            let req: Request = serde_json::from_slice(&buf).map_err(|e| {
+            let ParsedRequest {
+                request: mut req,
+                extensions,
+            } = serde_json::from_slice(&buf).map_err(|e| {
                 context!(
                     e,
                     ErrorKind::SerdeJsonDe(String::from_utf8_lossy(&buf).to_string())
                 )
             })?;
 
-            let n: usize = match req.method.rfind('.') {
-                None => {
-                    let method: String = String::from(req.method.as_ref());
-                    let mut call = Call::new(writer, &req);
-                    call.reply_interface_not_found(Some(method))?;
-                    return Ok((Vec::new(), None));
-                }
-                Some(x) => x,
-            };
-
-            let iface = String::from(&req.method[..n]);
-
-            let mut call = Call::new(writer, &req);
-            self.call(&iface, &mut call)?;
+            event!(
+                trace,
+                "request {} (more: {}, oneway: {}, upgrade: {})",
+                req.method,
+                req.more == Some(true),
+                req.oneway == Some(true),
+                req.upgrade == Some(true)
+            );
+            let start = Instant::now();
+            let reply = self
+                .middleware
+                .iter()
+                .filter_map(|m| m.on_request(&mut req))
+                .next();
+            let _tracked = leak::Tracked::new(leak::Object::Call);
+            let validator = self.validator();
+            let mut call = Call::new(writer, &req)
+                .with_middleware(&self.middleware)
+                .with_validator(validator.as_ref().map(Arc::as_ref))
+                .with_extensions(&extensions)
+                .with_buffer(out);
+            let ret = self
+                .dispatch(&mut call, reply)
+                .map_err(|e| e.with_method(&req.method));
+            server::record_call(
+                &req.method,
+                call.error.as_ref().map(|e| e.as_ref()),
+                start.elapsed(),
+            );
+            out = call.take_buffer();
+            // a single large message should not keep its memory for the whole connection
+            if out.capacity() > MAX_REUSED_BUFFER {
+                out = Vec::new();
+            }
+            if buf.capacity() > MAX_REUSED_BUFFER {
+                buf = Vec::new();
+            }
 
-            if call.upgraded {
+            if let Err(ref e) = ret {
+                event!(debug, "call of {} failed: {}", req.method, e);
+            }
+            if let Some(iface) = ret? {
                 upgraded_iface = Some(iface);
                 break;
             }
@@ -1515,4 +3339,8 @@ impl ConnectionHandler for VarlinkService {
         #[cfg(not(any(feature = "bufreader_buffer", feature = "nightly")))]
         return Ok((Vec::new(), upgraded_iface));
     }
+
+    fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
 }
@@ -0,0 +1,149 @@
+//! Metrics of a running server, see [`ServerHandle::metrics`]
+//!
+//! The metrics are collected by [`listen`] for a `ListenConfig` with a `server_handle`.
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let handle = varlink::ServerHandle::new();
+//! // pass handle.clone() in ListenConfig::server_handle to varlink::listen()
+//!
+//! // later, from another thread
+//! let metrics = handle.metrics();
+//! println!("{}", serde_json::to_string_pretty(&metrics).unwrap());
+//! for (interface, m) in metrics.interfaces() {
+//!     println!("{}: {} calls, {} errors", interface, m.calls, m.errors);
+//! }
+//! ```
+//!
+//! [`ServerHandle::metrics`]: ../struct.ServerHandle.html#method.metrics
+//! [`listen`]: ../fn.listen.html
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde_derive::Serialize;
+
+use crate::stats::Histogram;
+
+/// The metrics of a single method
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MethodMetrics {
+    /// The number of calls
+    pub calls: u64,
+    /// The number of calls answered with at least one error reply
+    pub errors: u64,
+    /// The duration of the calls in microseconds
+    pub duration_us: Histogram,
+}
+
+impl MethodMetrics {
+    fn merge(&mut self, other: &MethodMetrics) {
+        self.calls += other.calls;
+        self.errors += other.errors;
+        self.duration_us.merge(&other.duration_us);
+    }
+}
+
+/// A snapshot of the metrics of a server
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    /// The number of connections accepted so far
+    pub accepted_connections: u64,
+    /// The number of connections closed right after accepting them, because of
+    /// `ListenConfig::allow_ips`, `ListenConfig::deny_ips`, `OverloadPolicy::Close`
+    /// or `OverloadPolicy::Busy`
+    pub rejected_connections: u64,
+    /// The number of connections currently handled by a worker
+    pub active_connections: u64,
+    /// The number of accepted connections waiting for a free worker
+    pub queued_connections: u64,
+    /// The number of worker threads
+    pub workers: u64,
+    /// The number of worker threads handling a connection
+    pub busy_workers: u64,
+    /// The maximum number of worker threads
+    pub max_workers: u64,
+    /// The number of calls answered with `InterfaceNotFound` or `MethodNotFound`
+    ///
+    /// These are not counted in `methods`.
+    pub unknown_method_calls: u64,
+    /// The metrics of every called method, by full method name
+    pub methods: BTreeMap<String, MethodMetrics>,
+}
+
+impl Metrics {
+    /// Returns the metrics of the methods summed up per interface.
+    pub fn interfaces(&self) -> BTreeMap<String, MethodMetrics> {
+        let mut interfaces = BTreeMap::<String, MethodMetrics>::new();
+        for (method, m) in &self.methods {
+            let interface = match method.rfind('.') {
+                Some(n) => &method[..n],
+                None => method,
+            };
+            interfaces
+                .entry(interface.to_string())
+                .or_default()
+                .merge(m);
+        }
+        interfaces
+    }
+
+    /// The fraction of the maximum number of workers handling a connection
+    pub fn worker_utilization(&self) -> f64 {
+        if self.max_workers == 0 {
+            return 0.0;
+        }
+        self.busy_workers as f64 / self.max_workers as f64
+    }
+}
+
+/// The metrics collected by the server, without the state of the thread pool
+#[derive(Default)]
+pub(crate) struct MetricsCollector {
+    pub(crate) accepted_connections: AtomicU64,
+    pub(crate) rejected_connections: AtomicU64,
+    pub(crate) active_connections: AtomicU64,
+    unknown_method_calls: AtomicU64,
+    methods: Mutex<HashMap<String, MethodMetrics>>,
+}
+
+impl MetricsCollector {
+    pub(crate) fn record_call(&self, method: &str, error: Option<&str>, duration: Duration) {
+        match error {
+            Some("org.varlink.service.InterfaceNotFound")
+            | Some("org.varlink.service.MethodNotFound") => {
+                self.unknown_method_calls.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            _ => {}
+        }
+        let duration_us = duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros());
+        let mut methods = self.methods.lock().unwrap();
+        let m = methods.entry(method.to_string()).or_default();
+        m.calls += 1;
+        if error.is_some() {
+            m.errors += 1;
+        }
+        m.duration_us.record(duration_us);
+    }
+
+    pub(crate) fn snapshot(&self) -> Metrics {
+        Metrics {
+            accepted_connections: self.accepted_connections.load(Ordering::SeqCst),
+            rejected_connections: self.rejected_connections.load(Ordering::SeqCst),
+            active_connections: self.active_connections.load(Ordering::SeqCst),
+            unknown_method_calls: self.unknown_method_calls.load(Ordering::SeqCst),
+            methods: self
+                .methods
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Hooks around the dispatch of the calls of a [`VarlinkService`](../struct.VarlinkService.html)
+
+use std::io::BufRead;
+
+use crate::error::*;
+use crate::{Call, Reply, Request};
+
+/// Inspects the requests and replies of a service, e.g. for logging or rate limiting
+///
+/// Registered with [`VarlinkService::with_middleware`]. For every request, the
+/// middleware is called in the order of registration, before the
+/// [`Authorizer`](trait.Authorizer.html) and the interface. Every reply of the call
+/// is passed to all middleware in the reverse order, before it is sent.
+///
+/// The calls on an upgraded connection do not pass the middleware.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use varlink::{Middleware, Reply, Request};
+///
+/// /// Allows 1000 calls, then answers every call with an error
+/// struct Limit(AtomicUsize);
+///
+/// impl Middleware for Limit {
+///     fn on_request(&self, _request: &mut Request) -> Option<Reply> {
+///         if self.0.fetch_add(1, Ordering::SeqCst) < 1000 {
+///             None
+///         } else {
+///             Some(Reply::error("org.example.limit.TooManyCalls", None))
+///         }
+///     }
+/// }
+///
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![/* Your varlink interfaces go here */],
+/// )
+/// .with_middleware(Limit(AtomicUsize::new(0)));
+/// ```
+///
+/// [`VarlinkService::with_middleware`]: ../struct.VarlinkService.html#method.with_middleware
+pub trait Middleware: Send + Sync {
+    /// Called with every received request, which may be modified.
+    ///
+    /// Returning a reply answers the call with it, without calling the following
+    /// middleware and the interface.
+    fn on_request(&self, _request: &mut Request) -> Option<Reply> {
+        None
+    }
+
+    /// Called with every reply to `request`, before it is sent.
+    fn on_reply(&self, _request: &Request, _reply: &Reply) {}
+}
+
+/// Answers the calls of the interfaces, which a service does not implement, e.g. to
+/// forward them to other services in a gateway
+///
+/// Set with [`VarlinkService::with_fallback`]. It is called after the middleware and
+/// the [`Authorizer`](trait.Authorizer.html), instead of replying
+/// `org.varlink.service.InterfaceNotFound`. `GetInterfaceDescription` of an unknown
+/// interface is passed to it, too, with the requested interface.
+///
+/// # Examples
+///
+/// ```rust
+/// use serde_json::json;
+/// use varlink::{Call, CallTrait};
+///
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![/* Your varlink interfaces go here */],
+/// )
+/// .with_fallback(|interface: &str, call: &mut Call| match interface {
+///     "org.example.legacy" => call.reply_error("org.example.legacy.Retired", None),
+///     _ => call.reply_interface_not_found(Some(interface.into())),
+/// });
+/// ```
+///
+/// [`VarlinkService::with_fallback`]: ../struct.VarlinkService.html#method.with_fallback
+pub trait Fallback: Send + Sync {
+    /// Answers `call` of a method of `interface`.
+    fn call(&self, interface: &str, call: &mut Call) -> Result<()>;
+
+    /// Handles the data of a connection, which was upgraded by a call of `interface`.
+    ///
+    /// Returns the unread data, like [`Interface::call_upgraded`]. By default, it
+    /// replies `org.varlink.service.InterfaceNotFound`.
+    ///
+    /// [`Interface::call_upgraded`]: trait.Interface.html#tymethod.call_upgraded
+    fn call_upgraded(
+        &self,
+        interface: &str,
+        call: &mut Call,
+        _bufreader: &mut dyn BufRead,
+    ) -> Result<Vec<u8>> {
+        call.reply_interface_not_found(Some(interface.into()))?;
+        Ok(Vec::new())
+    }
+}
+
+impl<F> Fallback for F
+where
+    F: Fn(&str, &mut Call) -> Result<()> + Send + Sync,
+{
+    fn call(&self, interface: &str, call: &mut Call) -> Result<()> {
+        self(interface, call)
+    }
+}
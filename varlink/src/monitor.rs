@@ -0,0 +1,364 @@
+//! A transparent proxy, which reports every message between the clients and a service
+//!
+//! [`monitor`] listens on an address, connects every accepted client to the service,
+//! and passes each request and reply to an observer, before forwarding it.
+//! Clients use the address of the monitor instead of the address of the service.
+//!
+//! After a request with `upgrade` and its reply, the connection only carries the
+//! data of the upgraded protocol, which is forwarded, but not reported.
+//!
+//! The fields annotated with `# @sensitive` are redacted in the reported messages,
+//! see [`redact`]. The monitor fetches the interface descriptions from the service
+//! for this, and redacts all parameters of an interface, whose description is not
+//! available.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! varlink::monitor::monitor(
+//!     "unix:/run/org.example.ping.monitor",
+//!     "unix:/run/org.example.ping",
+//!     &varlink::ListenConfig::default(),
+//!     |message| {
+//!         if message.matches("org.example.ping") {
+//!             println!("{:?} {} {}", message.direction, message.method, message.message);
+//!         }
+//!     },
+//! )
+//! .unwrap();
+//! ```
+//!
+//! [`monitor`]: fn.monitor.html
+//! [`redact`]: ../redact/index.html
+
+#[cfg(all(feature = "client", feature = "server"))]
+pub use self::proxy::monitor;
+
+use serde_json::Value;
+
+/// The sender of a [`Message`](struct.Message.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the client to the service
+    Request,
+    /// From the service to the client
+    Reply,
+}
+
+/// A message passing through the monitor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    /// The number of the client connection, counting from `0`
+    pub connection: usize,
+    pub direction: Direction,
+    /// The called method, for a reply the method of its request
+    ///
+    /// Empty, if the message is not a valid varlink message.
+    pub method: String,
+    /// The message as received with the sensitive fields redacted, or a string with
+    /// the raw data, if it is not valid JSON
+    pub message: Value,
+}
+
+impl Message {
+    /// Returns `true`, if the method is `filter` or a method of the interface `filter`.
+    pub fn matches(&self, filter: &str) -> bool {
+        self.method == filter
+            || (self.method.starts_with(filter)
+                && self.method[filter.len()..].starts_with('.')
+                && !self.method[filter.len() + 1..].contains('.'))
+    }
+}
+
+/// The proxy, which needs both the client and the server
+#[cfg(all(feature = "client", feature = "server"))]
+mod proxy {
+    use std::collections::{HashSet, VecDeque};
+    use std::io::{self, BufRead, BufReader, Read, Write};
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use serde_json::Value;
+
+    use super::{Direction, Message};
+    use crate::error::*;
+    use crate::redact::Redactor;
+    use crate::{
+        varlink_connect, Connection, ListenConfig, Listener, OrgVarlinkServiceClient,
+        OrgVarlinkServiceInterface, Stream,
+    };
+
+    /// A request waiting for its reply
+    struct Pending {
+        method: String,
+        upgrade: bool,
+    }
+
+    type Observer = dyn Fn(&Message) + Send + Sync;
+
+    /// The sensitive fields of the interfaces of the service, fetched on first use
+    struct Sensitive {
+        service_address: String,
+        /// the redactor and the interfaces without a description
+        redactor: Mutex<(Redactor, HashSet<String>)>,
+    }
+
+    impl Sensitive {
+        fn fetch_description(&self, interface: &str) -> Result<String> {
+            let connection = Connection::with_address(&self.service_address)?;
+            let reply = OrgVarlinkServiceClient::new(connection)
+                .get_interface_description(interface.to_string())?;
+            reply
+                .description
+                .ok_or_else(|| context!(ErrorKind::InterfaceNotFound(interface.into())))
+        }
+
+        /// Passes `message` with the sensitive fields redacted to `observer`.
+        fn observe(&self, observer: &Observer, mut message: Message) {
+            {
+                let mut guard = self.redactor.lock().unwrap();
+                let (ref mut redactor, ref mut unavailable) = *guard;
+                if let Some(n) = message.method.rfind('.') {
+                    let interface = &message.method[..n];
+                    if !redactor.knows_interface(interface) && !unavailable.contains(interface) {
+                        let added = self
+                            .fetch_description(interface)
+                            .and_then(|d| redactor.add_description(&d));
+                        if added.is_err() {
+                            unavailable.insert(interface.to_string());
+                        }
+                    }
+                }
+                redactor.redact_message(&message.method, message.direction, &mut message.message);
+            }
+            observer(&message);
+        }
+    }
+
+    /// Listens on `address` and forwards the connections to `service_address`.
+    ///
+    /// Every request and reply is passed to `observer` first, which is called from
+    /// the threads of the connections.
+    ///
+    /// `listen_config.server_handle` and `listen_config.stop_listening` stop the
+    /// monitor, all other options apply to the listening socket only.
+    pub fn monitor<S, F>(
+        address: &S,
+        service_address: &str,
+        listen_config: &ListenConfig,
+        observer: F,
+    ) -> Result<()>
+    where
+        S: ?Sized + AsRef<str>,
+        F: Fn(&Message) + Send + Sync + 'static,
+    {
+        let listener = Listener::new_with_config(address, listen_config)?;
+        listener.set_nonblocking(false)?;
+
+        let observer: Arc<Observer> = Arc::new(observer);
+        let sensitive = Arc::new(Sensitive {
+            service_address: service_address.to_string(),
+            redactor: Mutex::new((Redactor::new(), HashSet::new())),
+        });
+        let polling =
+            listen_config.server_handle.is_some() || listen_config.stop_listening.is_some();
+        let mut next_id: usize = 0;
+
+        loop {
+            let client = match listener.accept(if polling { 100 } else { 0 }) {
+                Err(ref e) if *e.kind() == ErrorKind::Timeout => {
+                    let stopped = match listen_config.server_handle {
+                        Some(ref handle) => handle.is_shutdown(),
+                        None => false,
+                    } || match listen_config.stop_listening {
+                        Some(ref stop) => stop.load(Ordering::SeqCst),
+                        None => false,
+                    };
+                    if stopped {
+                        return Ok(());
+                    }
+                    continue;
+                }
+                r => r?,
+            };
+
+            let id = next_id;
+            next_id = next_id.wrapping_add(1);
+            let service_address = service_address.to_string();
+            let observer = observer.clone();
+            let sensitive = sensitive.clone();
+            thread::spawn(move || {
+                let mut client = client;
+                match varlink_connect(&service_address) {
+                    Ok((service, _)) => {
+                        if let Err(e) = forward(id, client, service, observer, sensitive) {
+                            #[cfg(feature = "log")]
+                            event!(warn, "monitor connection {}: {:?}", id, e);
+                            #[cfg(not(feature = "log"))]
+                            eprintln!("Monitor connection {}: {:?}", id, e);
+                        }
+                    }
+                    Err(e) => {
+                        #[cfg(feature = "log")]
+                        event!(
+                            warn,
+                            "monitor: could not connect to {}: {:?}",
+                            service_address,
+                            e
+                        );
+                        #[cfg(not(feature = "log"))]
+                        eprintln!("Monitor: Could not connect to {}: {:?}", service_address, e);
+                        let _ = client.shutdown();
+                    }
+                }
+            });
+        }
+    }
+
+    fn parse(buf: &[u8]) -> Value {
+        serde_json::from_slice(buf)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(buf).into_owned()))
+    }
+
+    /// Reads the next message without the zero byte, `None` on EOF.
+    fn read_message(reader: &mut dyn BufRead) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = Vec::new();
+        if reader.read_until(0, &mut buf)? == 0 || buf.last() != Some(&0) {
+            return Ok(None);
+        }
+        buf.pop();
+        Ok(Some(buf))
+    }
+
+    fn write_message(writer: &mut dyn Write, buf: &[u8]) -> io::Result<()> {
+        writer.write_all(buf)?;
+        writer.write_all(b"\0")?;
+        writer.flush()
+    }
+
+    /// Copies the rest of an upgraded connection, including the already buffered data.
+    fn copy_upgraded<R: Read>(reader: &mut BufReader<R>, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(reader.buffer())?;
+        reader.consume(reader.buffer().len());
+        writer.flush()?;
+        let mut buf = vec![0u8; 8192];
+        loop {
+            match reader.get_mut().read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(len) => {
+                    writer.write_all(&buf[..len])?;
+                    writer.flush()?;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn forward(
+        id: usize,
+        mut client: Box<dyn Stream>,
+        mut service: Box<dyn Stream>,
+        observer: Arc<Observer>,
+        sensitive: Arc<Sensitive>,
+    ) -> Result<()> {
+        let (client_reader, mut client_writer) = client.split()?;
+        let (service_reader, mut service_writer) = service.split()?;
+        let pending: Arc<Mutex<VecDeque<Pending>>> = Default::default();
+
+        let replies = {
+            let pending = pending.clone();
+            let observer = observer.clone();
+            let sensitive = sensitive.clone();
+            let mut client = client.try_clone().map_err(map_context!())?;
+            thread::spawn(move || -> io::Result<()> {
+                let mut reader = BufReader::new(service_reader);
+                while let Some(buf) = read_message(&mut reader)? {
+                    let message = parse(&buf);
+                    let continues = message.get("continues") == Some(&Value::Bool(true));
+                    let (method, upgrade) = {
+                        let mut pending = pending.lock().unwrap();
+                        let p = if continues {
+                            pending.front().map(|p| (p.method.clone(), p.upgrade))
+                        } else {
+                            pending.pop_front().map(|p| (p.method, p.upgrade))
+                        };
+                        p.unwrap_or_default()
+                    };
+                    sensitive.observe(
+                        &*observer,
+                        Message {
+                            connection: id,
+                            direction: Direction::Reply,
+                            method,
+                            message,
+                        },
+                    );
+                    write_message(&mut client_writer, &buf)?;
+                    if upgrade {
+                        copy_upgraded(&mut reader, &mut client_writer)?;
+                        break;
+                    }
+                }
+                let _ = client.shutdown();
+                Ok(())
+            })
+        };
+
+        let requests = (|| -> io::Result<()> {
+            let mut reader = BufReader::new(client_reader);
+            while let Some(buf) = read_message(&mut reader)? {
+                let message = parse(&buf);
+                let method = match message.get("method") {
+                    Some(Value::String(m)) => m.clone(),
+                    _ => String::new(),
+                };
+                let flag = |name| message.get(name) == Some(&Value::Bool(true));
+                let upgrade = flag("upgrade");
+                if !flag("oneway") {
+                    pending.lock().unwrap().push_back(Pending {
+                        method: method.clone(),
+                        upgrade,
+                    });
+                }
+                sensitive.observe(
+                    &*observer,
+                    Message {
+                        connection: id,
+                        direction: Direction::Request,
+                        method,
+                        message,
+                    },
+                );
+                write_message(&mut service_writer, &buf)?;
+                if upgrade {
+                    return copy_upgraded(&mut reader, &mut service_writer);
+                }
+            }
+            Ok(())
+        })();
+        let _ = service.shutdown();
+
+        let replies = replies
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::BrokenPipe)));
+        disconnected(requests).map_err(map_context!())?;
+        disconnected(replies).map_err(map_context!())?;
+        Ok(())
+    }
+
+    /// Treats errors from a peer closing the connection as a regular end.
+    fn disconnected(r: io::Result<()>) -> io::Result<()> {
+        match r {
+            Err(ref e)
+                if e.kind() == io::ErrorKind::BrokenPipe
+                    || e.kind() == io::ErrorKind::ConnectionReset
+                    || e.kind() == io::ErrorKind::ConnectionAborted =>
+            {
+                Ok(())
+            }
+            r => r,
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Windows named pipe transport for `pipe:` addresses
+//!
+//! `pipe:org.example.ftl` is the pipe `\\.\pipe\org.example.ftl`, a full pipe path
+//! can be given as well, e.g. `pipe:\\server\pipe\org.example.ftl`.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::iter::once;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, AsRawSocket, FromRawHandle, RawSocket};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use winapi::shared::winerror::{ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::namedpipeapi::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, WaitNamedPipeW,
+};
+use winapi::um::winbase::{
+    PIPE_ACCESS_DUPLEX, PIPE_READMODE_BYTE, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use winapi::um::winnt::{GENERIC_READ, GENERIC_WRITE, HANDLE};
+
+use crate::error::*;
+use crate::stream::Stream;
+
+/// Time in milliseconds a client waits for a free pipe instance
+const CONNECT_TIMEOUT: u32 = 5000;
+
+const BUFFER_SIZE: u32 = 65536;
+
+fn pipe_name(address: &str) -> Vec<u16> {
+    let name = if address.starts_with(r"\\") {
+        address.to_string()
+    } else {
+        format!(r"\\.\pipe\{}", address)
+    };
+    OsStr::new(&name).encode_wide().chain(once(0)).collect()
+}
+
+/// One end of a named pipe
+pub struct PipeStream(File);
+
+impl PipeStream {
+    /// Connects to the pipe of the `pipe:` address without the scheme.
+    pub fn connect(address: &str) -> Result<PipeStream> {
+        let name = pipe_name(address);
+        loop {
+            let handle = unsafe {
+                CreateFileW(
+                    name.as_ptr(),
+                    GENERIC_READ | GENERIC_WRITE,
+                    0,
+                    ptr::null_mut(),
+                    OPEN_EXISTING,
+                    0,
+                    ptr::null_mut(),
+                )
+            };
+            if handle != INVALID_HANDLE_VALUE {
+                return Ok(PipeStream(unsafe { File::from_raw_handle(handle as _) }));
+            }
+            // all instances are busy, wait for the server to create a new one
+            if unsafe { GetLastError() } != ERROR_PIPE_BUSY
+                || unsafe { WaitNamedPipeW(name.as_ptr(), CONNECT_TIMEOUT) } == 0
+            {
+                return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+            }
+        }
+    }
+}
+
+impl Read for PipeStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for PipeStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// `Stream` requires a socket, the pipe handle stands in for it.
+impl AsRawSocket for PipeStream {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.0.as_raw_handle() as RawSocket
+    }
+}
+
+impl Stream for PipeStream {
+    fn split(&mut self) -> Result<(Box<dyn Read + Send + Sync>, Box<dyn Write + Send + Sync>)> {
+        Ok((
+            Box::new(PipeStream(self.0.try_clone().map_err(map_context!())?)),
+            Box::new(PipeStream(self.0.try_clone().map_err(map_context!())?)),
+        ))
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        // only succeeds for the server end, the client end is closed on drop
+        unsafe {
+            DisconnectNamedPipe(self.0.as_raw_handle() as HANDLE);
+        }
+        Ok(())
+    }
+
+    fn try_clone(&mut self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(PipeStream(self.0.try_clone()?)))
+    }
+
+    fn set_nonblocking(&mut self, _b: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The server end of a named pipe
+///
+/// `ConnectNamedPipe` cannot time out, so a thread waits for the clients
+/// and hands the connected pipe instances to `accept`.
+pub struct PipeListener {
+    name: Vec<u16>,
+    connected: Mutex<Receiver<io::Result<File>>>,
+}
+
+impl PipeListener {
+    /// Creates the pipe of the `pipe:` address without the scheme.
+    pub fn bind(address: &str) -> Result<PipeListener> {
+        let name = pipe_name(address);
+        // create the first instance now, to report errors and let clients connect
+        let first = create_instance(&name).map_err(map_context!())?;
+
+        let (sender, receiver) = mpsc::channel();
+        let thread_name = name.clone();
+        thread::spawn(move || {
+            let mut next = Ok(first);
+            loop {
+                let r = next.and_then(|pipe| {
+                    let handle = pipe.as_raw_handle() as HANDLE;
+                    if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0
+                        && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(pipe)
+                });
+                if sender.send(r).is_err() {
+                    break;
+                }
+                next = create_instance(&thread_name);
+            }
+        });
+
+        Ok(PipeListener {
+            name,
+            connected: Mutex::new(receiver),
+        })
+    }
+
+    /// Waits `timeout` milliseconds for a client, `0` waits forever.
+    pub fn accept(&self, timeout: u64) -> Result<PipeStream> {
+        let receiver = self.connected.lock().unwrap();
+        let r = if timeout > 0 {
+            match receiver.recv_timeout(Duration::from_millis(timeout)) {
+                Ok(r) => r,
+                Err(RecvTimeoutError::Timeout) => return Err(context!(ErrorKind::Timeout)),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(context!(ErrorKind::ConnectionClosed))
+                }
+            }
+        } else {
+            receiver
+                .recv()
+                .map_err(|_| context!(ErrorKind::ConnectionClosed))?
+        };
+        Ok(PipeStream(r.map_err(map_context!())?))
+    }
+}
+
+impl Drop for PipeListener {
+    fn drop(&mut self) {
+        // close the channel and wake up the thread waiting for a client
+        let (_, closed) = mpsc::channel();
+        *self.connected.lock().unwrap() = closed;
+        let handle = unsafe {
+            CreateFileW(
+                self.name.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                0,
+                ptr::null_mut(),
+                OPEN_EXISTING,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle != INVALID_HANDLE_VALUE {
+            unsafe {
+                CloseHandle(handle);
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for PipeListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PipeListener({})", String::from_utf16_lossy(&self.name))
+    }
+}
+
+fn create_instance(name: &[u16]) -> io::Result<File> {
+    let handle = unsafe {
+        CreateNamedPipeW(
+            name.as_ptr(),
+            PIPE_ACCESS_DUPLEX,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_handle(handle as _) })
+}
@@ -0,0 +1,140 @@
+//! A pool of client connections to the same service
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+use crate::Connection;
+
+/// A pool of connections to one varlink address, for concurrent calls from many threads
+///
+/// A connection can only serve one call at a time. [`get`](#method.get) hands out a
+/// connection, which is not used by anybody else, and connects a new one, if all are
+/// in use and the pool has less than `max_size` connections. A connection is in use,
+/// as long as a clone of its `Arc` exists outside of the pool, e.g. in a generated
+/// `VarlinkClient`, and returns to the pool, when the last clone is dropped.
+///
+/// Idle connections, which were closed by the service or broken by a failed call,
+/// are dropped from the pool, before a connection is handed out.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let pool = Arc::new(varlink::ConnectionPool::new("unix:/run/org.example.ping", 8));
+///
+/// // in every thread
+/// let mut client = VarlinkClient::new(pool.get_timeout(Duration::from_secs(1))?);
+/// let reply = client.ping("Test".into()).call()?;
+/// // the connection returns to the pool with `client`
+/// ```
+pub struct ConnectionPool {
+    address: String,
+    max_size: usize,
+    connections: Mutex<Vec<Arc<RwLock<Connection>>>>,
+}
+
+/// Returns `true`, if the connection of an idle client can take a call.
+fn is_usable(connection: &Connection) -> bool {
+    if connection.reader.is_none() || connection.writer.is_none() {
+        // a call failed or was dropped before its reply
+        return false;
+    }
+    #[cfg(unix)]
+    {
+        if let Some(ref stream) = connection.stream {
+            let mut fd = libc::pollfd {
+                fd: stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // an idle connection is readable only after the service closed it,
+            // or if it has unread replies of an abandoned call
+            if unsafe { libc::poll(&mut fd, 1, 0) } != 0 {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+impl ConnectionPool {
+    /// Creates an empty pool for connections to `address`, with at most `max_size`
+    /// connections, but at least one.
+    pub fn new<S: ?Sized + AsRef<str>>(address: &S, max_size: usize) -> Self {
+        ConnectionPool {
+            address: address.as_ref().into(),
+            max_size: max_size.max(1),
+            connections: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The address of the connections
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The maximum number of connections
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// The number of open connections, idle or in use
+    pub fn len(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    /// Returns `true`, if the pool has no open connection.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns an idle connection, or connects a new one.
+    ///
+    /// Returns an error with `ErrorKind::ConnectionBusy`, if all `max_size` connections
+    /// are in use.
+    pub fn get(&self) -> Result<Arc<RwLock<Connection>>> {
+        let mut connections = self.connections.lock().unwrap();
+
+        // only the pool holds an idle connection
+        connections.retain(|c| {
+            Arc::strong_count(c) > 1
+                || match c.try_read() {
+                    Ok(c) => is_usable(&c),
+                    Err(_) => true,
+                }
+        });
+
+        if let Some(c) = connections.iter().find(|c| Arc::strong_count(c) == 1) {
+            return Ok(c.clone());
+        }
+
+        if connections.len() >= self.max_size {
+            return Err(context!(ErrorKind::ConnectionBusy));
+        }
+
+        let connection = Connection::with_address(&self.address)?;
+        connections.push(connection.clone());
+        Ok(connection)
+    }
+
+    /// Like [`get`](#method.get), but waits up to `timeout` for a connection, if all
+    /// are in use.
+    ///
+    /// Returns an error with `ErrorKind::Timeout`, if none was returned to the pool
+    /// in time.
+    pub fn get_timeout(&self, timeout: Duration) -> Result<Arc<RwLock<Connection>>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.get() {
+                Err(ref e) if *e.kind() == ErrorKind::ConnectionBusy => {
+                    if Instant::now() >= deadline {
+                        return Err(context!(ErrorKind::Timeout));
+                    }
+                    thread::sleep(Duration::from_millis(5));
+                }
+                r => return r,
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! A proxy, which serves the interfaces of several services on one address
+//!
+//! The proxy accepts connections like [`listen`], and forwards every call to the service
+//! of its interface with a [`Bridge`]. The replies of calls with `more` and the data of
+//! upgraded connections are passed through unchanged. `org.varlink.service.GetInfo` is
+//! answered by the proxy itself with all routed interfaces.
+//!
+//! Every connection is served by its own thread, which connects to the services on its
+//! first call of them. Of the `ListenConfig`, the socket options, `max_connections`,
+//! `allow_ips`, `deny_ips`, `stop_listening`, `shutdown_token` and `server_handle` are
+//! applied.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::proxy::Proxy;
+//!
+//! Proxy::new()
+//!     .route("org.example.ping", "unix:/run/org.example.ping")
+//!     .route("org.example.more", "unix:/run/org.example.more")
+//!     .listen("unix:/run/org.example", &Default::default())
+//! # }
+//! ```
+//!
+//! [`listen`]: ../fn.listen.html
+//! [`Bridge`]: ../bridge/struct.Bridge.html
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::bridge::Bridge;
+use crate::error::*;
+use crate::server::{is_stopping, shutdown_read, OpenConnections, RunningGuard};
+use crate::{ListenConfig, Listener, Stream};
+
+/// Forwards the calls of its clients to the services, see the [module](index.html)
+pub struct Proxy {
+    bridge: Bridge,
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Proxy {
+    /// Creates a proxy without routes, which does not ask a resolver.
+    pub fn new() -> Self {
+        Proxy {
+            bridge: Bridge::new().with_resolver(None).with_info(
+                "varlink",
+                "proxy",
+                env!("CARGO_PKG_VERSION"),
+                "https://varlink.org",
+            ),
+        }
+    }
+
+    /// Routes the calls of `interface` to the service at `address`.
+    pub fn route(mut self, interface: &str, address: &str) -> Self {
+        self.bridge = self.bridge.route(interface, address);
+        self
+    }
+
+    /// Asks the resolver at `resolver` for the services of the interfaces without a
+    /// route, see [`Bridge::with_resolver`](../bridge/struct.Bridge.html#method.with_resolver).
+    pub fn with_resolver(mut self, resolver: Option<&str>) -> Self {
+        self.bridge = self.bridge.with_resolver(resolver);
+        self
+    }
+
+    /// Sets the identity of the proxy returned by `GetInfo`.
+    pub fn with_info<S: Into<Cow<'static, str>>>(
+        mut self,
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+    ) -> Self {
+        self.bridge = self.bridge.with_info(vendor, product, version, url);
+        self
+    }
+
+    /// Accepts the connections on `address` and forwards their calls.
+    ///
+    /// Returns, when the server is stopped with `listen_config`.
+    pub fn listen<S: ?Sized + AsRef<str>>(
+        self,
+        address: &S,
+        listen_config: &ListenConfig,
+    ) -> Result<()> {
+        let listener = Listener::new_with_config(address, listen_config)?;
+        self.listen_on(listener, listen_config)
+    }
+
+    /// Like [`listen`](#method.listen), but accepts the connections of a listener set up
+    /// by the caller.
+    pub fn listen_on<L: Into<Listener>>(
+        self,
+        listener: L,
+        listen_config: &ListenConfig,
+    ) -> Result<()> {
+        let _running = RunningGuard::new(listen_config.server_handle.as_ref());
+        let listener = listener.into();
+        listener.set_nonblocking(false)?;
+        if let Some(ref handle) = listen_config.server_handle {
+            handle.set_local_address(listener.local_address().ok());
+        }
+
+        let bridge = Arc::new(self.bridge);
+        // the open connections, to stop them on shutdown
+        let connections: Arc<Mutex<HashMap<usize, Box<dyn Stream>>>> = Default::default();
+        let open = Arc::new(OpenConnections::default());
+        let mut next_id: usize = 0;
+        let tcp = &listen_config.tcp;
+        let token = listen_config.shutdown_token.as_ref();
+
+        loop {
+            let (mut stream, peer) = match listener.accept_until(100, tcp, token) {
+                Ok(accepted) => accepted,
+                Err(ref e) if *e.kind() == ErrorKind::Timeout => {
+                    if is_stopping(listen_config) {
+                        for c in connections.lock().unwrap().values() {
+                            shutdown_read(c.as_ref());
+                        }
+                        open.wait_below(1, 0);
+                        return Ok(());
+                    }
+                    if let Some(ref stop) = listen_config.stop_listening {
+                        if stop.load(Ordering::SeqCst) {
+                            return Ok(());
+                        }
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let allowed = match peer {
+                Some(peer) => listen_config.ip_allowed(&peer.ip()),
+                None => true,
+            };
+            if !allowed || open.is_full(listen_config.max_connections) {
+                event!(debug, "proxy rejected connection from {:?}", peer);
+                let _ = stream.shutdown();
+                continue;
+            }
+            let (reader, writer) = match stream.split() {
+                Ok(split) => split,
+                Err(_) => continue,
+            };
+
+            let id = next_id;
+            next_id = next_id.wrapping_add(1);
+            if let Ok(c) = stream.try_clone() {
+                connections.lock().unwrap().insert(id, c);
+            }
+            event!(debug, "proxy accepted connection {} from {:?}", id, peer);
+
+            open.open();
+            let bridge = bridge.clone();
+            let connections = connections.clone();
+            let open = open.clone();
+            thread::spawn(move || {
+                if let Err(e) = bridge.handle(BufReader::new(reader), writer) {
+                    event!(debug, "proxy connection {}: {}", id, e);
+                }
+                let _ = stream.shutdown();
+                connections.lock().unwrap().remove(&id);
+                event!(debug, "proxy closed connection {}", id);
+                open.close();
+            });
+        }
+    }
+}
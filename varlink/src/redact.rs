@@ -0,0 +1,273 @@
+//! Removes sensitive fields from messages, before they are logged or recorded
+//!
+//! A field is sensitive, if its comment in the interface description has the
+//! annotation `# @sensitive`:
+//!
+//! ```varlink
+//! interface org.example.login
+//!
+//! type Credentials (
+//!   user: string,
+//!   # @sensitive
+//!   password: string
+//! )
+//!
+//! method Login(credentials: Credentials) -> (
+//!   # @sensitive
+//!   token: string
+//! )
+//! ```
+//!
+//! A [`Redactor`] replaces the values of these fields with `"<redacted>"`, in nested
+//! structs, arrays and maps as well. The slow call logger of [`stats`] and the
+//! [`monitor`] apply it, and it can be used in any custom
+//! [`Middleware`](../trait.Middleware.html), which logs the calls.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use serde_json::json;
+//! use varlink::monitor::Direction;
+//! use varlink::redact::Redactor;
+//!
+//! let redactor = Redactor::from_description(
+//!     "interface org.example.login\n\
+//!      method Login(user: string,\n# @sensitive\npassword: string) -> ()",
+//! )
+//! .unwrap();
+//!
+//! let mut parameters = json!({"user": "joe", "password": "secret"});
+//! redactor.redact_parameters("org.example.login.Login", Direction::Request, &mut parameters);
+//! assert_eq!(parameters, json!({"user": "joe", "password": "<redacted>"}));
+//! ```
+//!
+//! [`Redactor`]: struct.Redactor.html
+//! [`stats`]: ../stats/index.html
+//! [`monitor`]: ../monitor/index.html
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+use varlink_parser::{Argument, VStruct, VStructOrEnum, VType, VTypeExt, IDL};
+
+use crate::error::*;
+use crate::monitor::Direction;
+
+/// The replacement of the sensitive values
+pub const REDACTED: &str = "<redacted>";
+
+/// What to redact in a value
+#[derive(Debug, Clone)]
+enum Mask {
+    /// The whole value
+    Redact,
+    /// In the fields of an object
+    Fields(HashMap<String, Mask>),
+    /// In the value of the type with the full name
+    Type(String),
+    /// In the elements of an array or the values of a map
+    Elements(Box<Mask>),
+}
+
+/// Returns `true`, if the comment `doc` has the annotation `# @sensitive`.
+fn is_sensitive(doc: &str) -> bool {
+    doc.lines()
+        .map(|l| l.trim_start_matches(|c: char| c == '#' || c.is_whitespace()))
+        .filter(|l| l.starts_with('@'))
+        .any(|l| l[1..].split(':').next().unwrap_or("").trim() == "sensitive")
+}
+
+fn type_mask(interface: &str, vtype: &VTypeExt) -> Option<Mask> {
+    match vtype {
+        VTypeExt::Array(v) | VTypeExt::Dict(v) => {
+            type_mask(interface, v).map(|m| Mask::Elements(Box::new(m)))
+        }
+        VTypeExt::Option(v) => type_mask(interface, v),
+        VTypeExt::Plain(VType::Struct(s)) => struct_mask(interface, s),
+        VTypeExt::Plain(VType::Typename(name)) => {
+            Some(Mask::Type(format!("{}.{}", interface, name)))
+        }
+        VTypeExt::Plain(_) => None,
+    }
+}
+
+fn field_mask(interface: &str, argument: &Argument) -> Option<Mask> {
+    if is_sensitive(argument.doc) {
+        Some(Mask::Redact)
+    } else {
+        type_mask(interface, &argument.vtype)
+    }
+}
+
+fn struct_mask(interface: &str, vstruct: &VStruct) -> Option<Mask> {
+    let fields: HashMap<String, Mask> = vstruct
+        .elts
+        .iter()
+        .filter_map(|a| field_mask(interface, a).map(|m| (a.name.to_string(), m)))
+        .collect();
+    if fields.is_empty() {
+        None
+    } else {
+        Some(Mask::Fields(fields))
+    }
+}
+
+/// Redacts the sensitive fields of the messages of the known interfaces
+///
+/// The parameters of a method of an interface, which was not added, are redacted
+/// completely, so that nothing leaks, if a description is missing.
+#[derive(Debug, Clone)]
+pub struct Redactor {
+    interfaces: HashSet<String>,
+    /// by full method name
+    requests: HashMap<String, Mask>,
+    /// by full method name
+    replies: HashMap<String, Mask>,
+    /// by full error name
+    errors: HashMap<String, Mask>,
+    /// by full type name
+    types: HashMap<String, Mask>,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Redactor::new()
+    }
+}
+
+impl Redactor {
+    /// Creates a redactor, which knows only `org.varlink.service` yet.
+    pub fn new() -> Self {
+        let mut interfaces = HashSet::new();
+        // has no sensitive fields
+        interfaces.insert("org.varlink.service".to_string());
+        Redactor {
+            interfaces,
+            requests: HashMap::new(),
+            replies: HashMap::new(),
+            errors: HashMap::new(),
+            types: HashMap::new(),
+        }
+    }
+
+    /// Creates a redactor for the interface with `description`.
+    pub fn from_description(description: &str) -> Result<Self> {
+        let mut redactor = Redactor::new();
+        redactor.add_description(description)?;
+        Ok(redactor)
+    }
+
+    /// Adds the sensitive fields of the interface with `description`.
+    pub fn add_description(&mut self, description: &str) -> Result<()> {
+        let idl = IDL::from_string(description)
+            .map_err(|e| context!(ErrorKind::InvalidInterface(e.to_string())))?;
+        let interface = idl.name;
+
+        for method in idl.methods.values() {
+            let name = format!("{}.{}", interface, method.name);
+            if let Some(m) = struct_mask(interface, &method.input) {
+                self.requests.insert(name.clone(), m);
+            }
+            if let Some(m) = struct_mask(interface, &method.output) {
+                self.replies.insert(name, m);
+            }
+        }
+        for error in idl.errors.values() {
+            if let Some(m) = struct_mask(interface, &error.parm) {
+                self.errors
+                    .insert(format!("{}.{}", interface, error.name), m);
+            }
+        }
+        for typedef in idl.typedefs.values() {
+            if let VStructOrEnum::VStruct(ref s) = typedef.elt {
+                if let Some(m) = struct_mask(interface, s) {
+                    self.types
+                        .insert(format!("{}.{}", interface, typedef.name), m);
+                }
+            }
+        }
+        self.interfaces.insert(interface.to_string());
+        Ok(())
+    }
+
+    /// Returns `true`, if the interface was added.
+    pub fn knows_interface(&self, interface: &str) -> bool {
+        self.interfaces.contains(interface)
+    }
+
+    fn knows_method(&self, method: &str) -> bool {
+        match method.rfind('.') {
+            Some(n) => self.knows_interface(&method[..n]),
+            None => false,
+        }
+    }
+
+    fn apply(&self, mask: &Mask, value: &mut Value) {
+        match (mask, value) {
+            (Mask::Redact, value) => *value = Value::String(REDACTED.into()),
+            (Mask::Fields(fields), Value::Object(object)) => {
+                for (name, value) in object.iter_mut() {
+                    if let Some(m) = fields.get(name) {
+                        self.apply(m, value);
+                    }
+                }
+            }
+            (Mask::Type(name), value) => {
+                if let Some(m) = self.types.get(name) {
+                    self.apply(m, value);
+                }
+            }
+            (Mask::Elements(m), Value::Array(array)) => {
+                for value in array.iter_mut() {
+                    self.apply(m, value);
+                }
+            }
+            (Mask::Elements(m), Value::Object(object)) => {
+                for value in object.values_mut() {
+                    self.apply(m, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Redacts the parameters of a request or a non-error reply of `method`.
+    pub fn redact_parameters(&self, method: &str, direction: Direction, parameters: &mut Value) {
+        if !self.knows_method(method) {
+            *parameters = Value::String(REDACTED.into());
+            return;
+        }
+        let masks = match direction {
+            Direction::Request => &self.requests,
+            Direction::Reply => &self.replies,
+        };
+        if let Some(m) = masks.get(method) {
+            self.apply(m, parameters);
+        }
+    }
+
+    /// Redacts the parameters of an error reply.
+    pub fn redact_error(&self, error: &str, parameters: &mut Value) {
+        if !self.knows_method(error) {
+            *parameters = Value::String(REDACTED.into());
+            return;
+        }
+        if let Some(m) = self.errors.get(error) {
+            self.apply(m, parameters);
+        }
+    }
+
+    /// Redacts a whole request or reply message of `method`.
+    pub fn redact_message(&self, method: &str, direction: Direction, message: &mut Value) {
+        let error = match message.get("error") {
+            Some(Value::String(e)) if direction == Direction::Reply => Some(e.clone()),
+            _ => None,
+        };
+        if let Some(parameters) = message.get_mut("parameters") {
+            match error {
+                Some(e) => self.redact_error(&e, parameters),
+                None => self.redact_parameters(method, direction, parameters),
+            }
+        }
+    }
+}
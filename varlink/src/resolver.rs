@@ -0,0 +1,351 @@
+//! The `org.varlink.resolver` interface, which maps interface names to service addresses
+//!
+//! A resolver on the system knows the addresses of the services, which implement an
+//! interface. [`Connection::with_interface`] asks the resolver at [`RESOLVER_ADDRESS`]
+//! and connects to the service, so clients do not need to know its address.
+//!
+//! A resolver is served by mounting a [`ResolverInterface`] into a `VarlinkService`. Its
+//! [`Registry`] of addresses is changed at runtime and optionally kept in a file.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::{Connection, ConnectionExt};
+//!
+//! let connection = Connection::with_interface("org.example.ping")?;
+//! println!("{}", connection.get_info()?.product);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//!
+//! A resolver service:
+//!
+//! ```rust,no_run
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::resolver::{Registry, ResolverInterface};
+//! use varlink::VarlinkService;
+//!
+//! let registry = Registry::with_file("/var/lib/myresolver/registry.json")?;
+//! registry.add("org.example.ping", "unix:/run/org.example.ping")?;
+//! let service = VarlinkService::new(
+//!     "org.varlink",
+//!     "resolver",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     vec![Box::new(ResolverInterface::new(registry.clone()))],
+//! );
+//! varlink::listen(service, varlink::resolver::RESOLVER_ADDRESS, &Default::default())
+//! # }
+//! ```
+//!
+//! [`Connection::with_interface`]: ../struct.Connection.html#method.with_interface
+//! [`RESOLVER_ADDRESS`]: constant.RESOLVER_ADDRESS.html
+//! [`ResolverInterface`]: struct.ResolverInterface.html
+//! [`Registry`]: struct.Registry.html
+
+use std::borrow::Cow;
+#[cfg(feature = "server")]
+use std::collections::BTreeMap;
+#[cfg(feature = "server")]
+use std::fs;
+#[cfg(feature = "server")]
+use std::io::{self, BufRead};
+#[cfg(feature = "server")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "server")]
+use std::sync::{Arc, RwLock};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::*;
+use crate::ServiceInfo;
+#[cfg(feature = "server")]
+use crate::{Call, CallTrait, Interface, Reply};
+#[cfg(feature = "client")]
+use crate::{Connection, GetInfoArgs, MethodCall};
+
+/// The well-known address of the resolver of the system
+pub const RESOLVER_ADDRESS: &str = "unix:/run/org.varlink.resolver";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct ResolveArgs {
+    pub interface: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct ResolveReply {
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default, Clone)]
+pub struct ErrorInterfaceNotFound {
+    pub interface: Option<String>,
+}
+
+/// Asks the resolver at `resolver` for the address of the service implementing
+/// `interface`.
+///
+/// Returns an error with `ErrorKind::InterfaceNotFound`, if no service is registered
+/// for the interface.
+#[cfg(feature = "client")]
+pub fn resolve(resolver: &str, interface: &str) -> Result<String> {
+    let connection = Connection::with_address(resolver)?;
+    let reply = MethodCall::<ResolveArgs, ResolveReply, Error>::new(
+        connection,
+        "org.varlink.resolver.Resolve",
+        ResolveArgs {
+            interface: interface.into(),
+        },
+    )
+    .call()
+    .map_err(|e| match e.kind() {
+        ErrorKind::VarlinkErrorReply(r)
+            if r.error.as_ref().map(Cow::as_ref)
+                == Some("org.varlink.resolver.InterfaceNotFound") =>
+        {
+            context!(ErrorKind::InterfaceNotFound(interface.into()))
+        }
+        _ => e,
+    })?;
+    Ok(reply.address)
+}
+
+/// Returns the identity of the resolver at `resolver` and all resolvable interfaces.
+#[cfg(feature = "client")]
+pub fn get_info(resolver: &str) -> Result<ServiceInfo> {
+    MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+        Connection::with_address(resolver)?,
+        "org.varlink.resolver.GetInfo",
+        GetInfoArgs {},
+    )
+    .call()
+}
+
+#[cfg(feature = "server")]
+const DESCRIPTION: &str = r#"# Interface to resolve reverse-domain interface names to
+# service adresses
+interface org.varlink.resolver
+
+# Get a list of all resolvable interfaces and information
+# about the resolver's identity.
+method GetInfo() -> (
+  vendor: string,
+  product: string,
+  version: string,
+  url: string,
+  interfaces: []string
+)
+
+# Resolve an interface name to a registered varlink service address
+method Resolve(interface: string) -> (address: string)
+
+error InterfaceNotFound (interface: string)
+"#;
+
+#[cfg(feature = "server")]
+#[derive(Default)]
+struct Entries {
+    addresses: BTreeMap<String, String>,
+    path: Option<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+impl Entries {
+    /// Writes the addresses to a temporary file, which replaces the file atomically.
+    fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let data = serde_json::to_vec_pretty(&self.addresses).map_err(map_context!())?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, data).map_err(map_context!())?;
+        fs::rename(&tmp, path).map_err(map_context!())?;
+        Ok(())
+    }
+}
+
+/// The addresses of the services by their interfaces
+///
+/// Clones share the addresses, so the registry can be changed, while a
+/// [`ResolverInterface`](struct.ResolverInterface.html) serves it.
+#[cfg(feature = "server")]
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<RwLock<Entries>>,
+}
+
+#[cfg(feature = "server")]
+impl Registry {
+    /// Creates an empty registry, which is not persisted.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a registry, which is loaded from `path`, if it exists, and written back
+    /// after every change.
+    ///
+    /// The file is a JSON object with the addresses by their interfaces.
+    pub fn with_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let addresses = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(map_context!())?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(context!(e, ErrorKind::from(&e))),
+        };
+        Ok(Registry {
+            entries: Arc::new(RwLock::new(Entries {
+                addresses,
+                path: Some(path.into()),
+            })),
+        })
+    }
+
+    /// Registers `address` for `interface`, replacing a previous address.
+    pub fn add(&self, interface: &str, address: &str) -> Result<()> {
+        let mut entries = self.entries.write().unwrap();
+        entries.addresses.insert(interface.into(), address.into());
+        entries.save()
+    }
+
+    /// Removes `interface` and returns its address, if it was registered.
+    pub fn remove(&self, interface: &str) -> Result<Option<String>> {
+        let mut entries = self.entries.write().unwrap();
+        let address = entries.addresses.remove(interface);
+        if address.is_some() {
+            entries.save()?;
+        }
+        Ok(address)
+    }
+
+    /// Returns the address registered for `interface`.
+    pub fn resolve(&self, interface: &str) -> Option<String> {
+        self.entries
+            .read()
+            .unwrap()
+            .addresses
+            .get(interface)
+            .cloned()
+    }
+
+    /// Returns the registered interfaces, sorted by name.
+    pub fn interfaces(&self) -> Vec<String> {
+        self.entries
+            .read()
+            .unwrap()
+            .addresses
+            .keys()
+            .cloned()
+            .collect()
+    }
+}
+
+/// The `org.varlink.resolver` interface of a [`Registry`](struct.Registry.html)
+#[cfg(feature = "server")]
+pub struct ResolverInterface {
+    info: ServiceInfo,
+    registry: Registry,
+}
+
+#[cfg(feature = "server")]
+impl ResolverInterface {
+    /// Creates the interface, which resolves the interfaces of `registry`.
+    pub fn new(registry: Registry) -> Self {
+        ResolverInterface {
+            info: ServiceInfo {
+                vendor: "varlink".into(),
+                product: "resolver".into(),
+                version: env!("CARGO_PKG_VERSION").into(),
+                url: "https://varlink.org".into(),
+                interfaces: Vec::new(),
+            },
+            registry,
+        }
+    }
+
+    /// Sets the identity of the resolver returned by `GetInfo`.
+    pub fn with_info<S: Into<Cow<'static, str>>>(
+        mut self,
+        vendor: S,
+        product: S,
+        version: S,
+        url: S,
+    ) -> Self {
+        self.info.vendor = vendor.into();
+        self.info.product = product.into();
+        self.info.version = version.into();
+        self.info.url = url.into();
+        self
+    }
+
+    /// The registry served by the interface
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+#[cfg(feature = "server")]
+impl Interface for ResolverInterface {
+    fn get_description(&self) -> &'static str {
+        DESCRIPTION
+    }
+
+    fn get_name(&self) -> &'static str {
+        "org.varlink.resolver"
+    }
+
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        Some(&["GetInfo", "Resolve"])
+    }
+
+    fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn call(&self, call: &mut Call) -> Result<()> {
+        let req = call.require_request()?;
+        match req.method.as_ref() {
+            "org.varlink.resolver.GetInfo" => {
+                let info = ServiceInfo {
+                    interfaces: self
+                        .registry
+                        .interfaces()
+                        .into_iter()
+                        .map(Cow::Owned)
+                        .collect(),
+                    ..self.info.clone()
+                };
+                call.reply_struct(Reply::parameters(Some(
+                    serde_json::to_value(info).map_err(map_context!())?,
+                )))
+            }
+            "org.varlink.resolver.Resolve" => {
+                let args: ResolveArgs = match req.parameters {
+                    Some(ref p) => match serde_json::from_value(p.clone()) {
+                        Ok(args) => args,
+                        Err(_) => return call.reply_invalid_parameter("parameters".into()),
+                    },
+                    None => return call.reply_invalid_parameter("parameters".into()),
+                };
+                match self.registry.resolve(&args.interface) {
+                    Some(address) => call.reply_struct(Reply::parameters(Some(
+                        serde_json::to_value(ResolveReply { address }).map_err(map_context!())?,
+                    ))),
+                    None => call.reply_struct(Reply::error(
+                        "org.varlink.resolver.InterfaceNotFound",
+                        Some(
+                            serde_json::to_value(ErrorInterfaceNotFound {
+                                interface: Some(args.interface),
+                            })
+                            .map_err(map_context!())?,
+                        ),
+                    )),
+                }
+            }
+            m => call.reply_method_not_found(m.to_string()),
+        }
+    }
+}
@@ -1,169 +1,419 @@
 //! Handle network connections for a varlink service
 #![allow(dead_code)]
 
-use std::{env, fs, thread};
+use std::{fs, thread};
 //#![feature(getpid)]
 //use std::process;
-use std::io::{BufRead, BufReader};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::mem;
-use std::net::{TcpListener, TcpStream};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
 #[cfg(unix)]
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 #[cfg(unix)]
 use std::os::unix::net::{UnixListener, UnixStream};
 #[cfg(windows)]
 use std::os::windows::io::{AsRawSocket, FromRawSocket, IntoRawSocket, RawSocket};
-use std::process;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    mpsc, Arc, Mutex, RwLock,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc, Condvar, Mutex,
 };
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::Instant;
 
 #[cfg(windows)]
 use uds_windows::UnixListener;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::abstract_socket;
+use crate::activation;
+use crate::address::{Address, Transport};
+use crate::auth::{self, PeerInfo};
+use crate::clock::{Clock, SystemClock};
 use crate::error::*;
-use crate::stream::Stream;
+#[cfg(unix)]
+use crate::fds::{self, FdWriter};
+use crate::leak::{Object, Tracked};
+use crate::metrics::{Metrics, MetricsCollector};
+#[cfg(windows)]
+use crate::pipe::PipeListener;
+use crate::stream::{Stream, TcpConfig};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsAcceptor, TlsConfig};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use crate::vsock::VsockListener;
 
 #[derive(Debug)]
 pub enum Listener {
     TCP(Option<TcpListener>, bool),
-    UNIX(Option<UnixListener>, bool),
+    UNIX(Option<UnixListener>, bool, Option<PathBuf>),
+    #[cfg(feature = "tls")]
+    TLS(Option<TcpListener>, TlsAcceptor),
+    #[cfg(windows)]
+    PIPE(Option<PipeListener>),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    VSOCK(Option<VsockListener>),
 }
 
-fn activation_listener() -> Result<Option<usize>> {
-    let nfds: usize;
+/// Binds the socket in a temporary directory, which is only accessible by the owner,
+/// sets the permissions and the group and moves it to `addr`. So nobody can connect to
+/// the socket, before it has the final permissions.
+#[cfg(unix)]
+fn bind_unix_atomic(
+    addr: &str,
+    mode: Option<u32>,
+    group: Option<libc::gid_t>,
+) -> Result<UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
 
-    match env::var("LISTEN_FDS") {
-        Ok(ref n) => match n.parse::<usize>() {
-            Ok(n) if n >= 1 => nfds = n,
-            _ => return Ok(None),
-        },
-        _ => return Ok(None),
-    }
+    let path = Path::new(addr);
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let tmpdir = tempfile::Builder::new()
+        .prefix(".varlink")
+        .tempdir_in(dir)
+        .map_err(map_context!())?;
+    let tmppath = tmpdir.path().join("socket");
 
-    match env::var("LISTEN_PID") {
-        Ok(ref pid) if pid.parse::<usize>() == Ok(process::id() as usize) => {}
-        _ => return Ok(None),
+    let listener = UnixListener::bind(&tmppath).map_err(map_context!())?;
+    if let Some(group) = group {
+        use std::os::unix::ffi::OsStrExt;
+
+        let cpath = std::ffi::CString::new(tmppath.as_os_str().as_bytes())
+            .map_err(|_| context!(ErrorKind::InvalidAddress))?;
+        // an uid of -1 keeps the owner
+        if unsafe { libc::chown(cpath.as_ptr(), libc::uid_t::MAX, group) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(context!(e, ErrorKind::Io(e.kind())));
+        }
     }
+    if let Some(mode) = mode {
+        fs::set_permissions(&tmppath, fs::Permissions::from_mode(mode)).map_err(map_context!())?;
+    }
+    fs::rename(&tmppath, path).map_err(map_context!())?;
+    Ok(listener)
+}
 
-    if nfds == 1 {
-        return Ok(Some(3));
+/// Returns the id of `group`, which is a group name or a number.
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<libc::gid_t> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let name = std::ffi::CString::new(group).map_err(|_| context!(ErrorKind::InvalidAddress))?;
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut entry: libc::group = unsafe { mem::zeroed() };
+        let mut found: *mut libc::group = std::ptr::null_mut();
+        let ret = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut found,
+            )
+        };
+        if ret == libc::ERANGE {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+        if ret != 0 || found.is_null() {
+            return Err(context!(ErrorKind::InvalidAddress));
+        }
+        return Ok(entry.gr_gid);
     }
+}
 
-    let fdnames: String;
+/// Binds a TCP listener to the first address of `host`, which can be bound.
+fn bind_tcp(host: &str, port: u16, listen_config: &ListenConfig) -> Result<TcpListener> {
+    use std::net::ToSocketAddrs;
 
-    match env::var("LISTEN_FDNAMES") {
-        Ok(n) => {
-            fdnames = n;
+    let mut last_err = io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "could not resolve to any addresses",
+    );
+    for addr in (host, port).to_socket_addrs().map_err(map_context!())? {
+        match bind_tcp_addr(&addr, listen_config) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_err = e,
         }
-        _ => return Ok(None),
     }
+    Err(context!(ErrorKind::from(&last_err)))
+}
 
-    for (i, v) in fdnames.split(':').enumerate() {
-        if v == "varlink" {
-            return Ok(Some(3 + i as usize));
+/// Creates the socket with the options of `listen_config`, which must be set before `bind()`.
+#[cfg(unix)]
+fn bind_tcp_addr(addr: &SocketAddr, listen_config: &ListenConfig) -> io::Result<TcpListener> {
+    let check = |ret: libc::c_int| {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
         }
+    };
+    let set_option = |fd: RawFd, level: libc::c_int, option: libc::c_int, value: bool| {
+        let value = value as libc::c_int;
+        check(unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })
+    };
+
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = check(unsafe { libc::socket(family, libc::SOCK_STREAM, 0) })?;
+    // closes the socket on error
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+    check(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+
+    set_option(
+        fd,
+        libc::SOL_SOCKET,
+        libc::SO_REUSEADDR,
+        listen_config.reuse_address,
+    )?;
+    if listen_config.reuse_port {
+        set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, true)?;
+    }
+    if let (SocketAddr::V6(_), Some(only_v6)) = (addr, listen_config.only_v6) {
+        set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_V6ONLY, only_v6)?;
     }
 
-    Ok(None)
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(a) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = a.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(a.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = a.port().to_be();
+            sin6.sin6_flowinfo = a.flowinfo();
+            sin6.sin6_addr.s6_addr = a.ip().octets();
+            sin6.sin6_scope_id = a.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    check(unsafe {
+        libc::bind(
+            fd,
+            &storage as *const libc::sockaddr_storage as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    })?;
+    check(unsafe { libc::listen(fd, 128) })?;
+    Ok(listener)
 }
 
-#[cfg(any(target_os = "linux", target_os = "android"))]
-fn get_abstract_unixlistener(addr: &str) -> Result<UnixListener> {
-    // FIXME: abstract unix domains sockets still not in std
-    // FIXME: https://github.com/rust-lang/rust/issues/14194
-    use unix_socket::UnixListener as AbstractUnixListener;
-
-    unsafe {
-        Ok(UnixListener::from_raw_fd(
-            AbstractUnixListener::bind(addr)
-                .map_err(map_context!())?
-                .into_raw_fd(),
-        ))
-    }
+/// The socket options of `listen_config` are not applied on Windows.
+#[cfg(windows)]
+fn bind_tcp_addr(addr: &SocketAddr, _listen_config: &ListenConfig) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
 }
 
-#[cfg(not(any(target_os = "linux", target_os = "android")))]
-fn get_abstract_unixlistener(_addr: &str) -> Result<UnixListener> {
-    Err(context!(ErrorKind::InvalidAddress))
+/// Returns the `unix:` address of a bound unix socket, `@` marks an abstract address.
+#[cfg(unix)]
+fn unix_local_address(fd: RawFd) -> Result<String> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    if unsafe {
+        libc::getsockname(
+            fd,
+            &mut addr as *mut libc::sockaddr_un as *mut libc::sockaddr,
+            &mut len,
+        )
+    } < 0
+    {
+        return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+    }
+    let path_len = (len as usize).saturating_sub(mem::size_of::<libc::sa_family_t>());
+    let path: Vec<u8> = addr.sun_path[..path_len.min(addr.sun_path.len())]
+        .iter()
+        .map(|&c| c as u8)
+        .collect();
+    match path.split_first() {
+        // unnamed
+        None => Err(context!(ErrorKind::InvalidAddress)),
+        Some((0, name)) => Ok(format!("unix:@{}", String::from_utf8_lossy(name))),
+        Some(_) => {
+            let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+            Ok(format!("unix:{}", String::from_utf8_lossy(&path[..end])))
+        }
+    }
 }
 
 impl Listener {
     #[allow(clippy::new_ret_no_self)]
     pub fn new<S: ?Sized + AsRef<str>>(address: &S) -> Result<Self> {
-        let address = address.as_ref();
-        if let Some(l) = activation_listener()? {
+        Self::new_with_config(address, &ListenConfig::default())
+    }
+
+    /// Like `new`, but applies the socket options of `listen_config`.
+    pub fn new_with_config<S: ?Sized + AsRef<str>>(
+        address: &S,
+        listen_config: &ListenConfig,
+    ) -> Result<Self> {
+        let listener = Self::bind(address.as_ref(), listen_config)?;
+        // the backlog of an activation socket is configured by the service manager
+        if listen_config.backlog > 0 && !listener.is_activated() {
+            listener.set_backlog(listen_config.backlog)?;
+        }
+        Ok(listener)
+    }
+
+    fn bind(address: &str, listen_config: &ListenConfig) -> Result<Self> {
+        let parsed = Address::parse(address)?;
+        let name = match listen_config.activation_fd_name {
+            Some(ref name) => name.as_str(),
+            None => "varlink",
+        };
+        if let Some(l) = activation::activation_fd(name) {
             #[cfg(windows)]
             {
-                if address.starts_with("tcp:") {
-                    unsafe {
-                        return Ok(Listener::TCP(
+                return match parsed.transport {
+                    Transport::Tcp { .. } => unsafe {
+                        Ok(Listener::TCP(
                             Some(TcpListener::from_raw_socket(l as RawSocket)),
                             true,
-                        ));
-                    }
-                } else if address.starts_with("unix:") {
-                    unsafe {
-                        return Ok(Listener::UNIX(
+                        ))
+                    },
+                    Transport::Unix(_) | Transport::UnixAbstract(_) => unsafe {
+                        Ok(Listener::UNIX(
                             Some(UnixListener::from_raw_socket(l as RawSocket)),
                             true,
-                        ));
-                    }
-                } else {
-                    return Err(context!(ErrorKind::InvalidAddress));
-                }
+                            None,
+                        ))
+                    },
+                    _ => Err(context!(ErrorKind::InvalidAddress)),
+                };
             }
             #[cfg(unix)]
             {
-                if address.starts_with("tcp:") {
-                    unsafe {
-                        return Ok(Listener::TCP(
+                return match parsed.transport {
+                    Transport::Tcp { .. } => unsafe {
+                        Ok(Listener::TCP(
                             Some(TcpListener::from_raw_fd(l as RawFd)),
                             true,
-                        ));
-                    }
-                } else if address.starts_with("unix:") {
-                    unsafe {
-                        return Ok(Listener::UNIX(
+                        ))
+                    },
+                    Transport::Unix(_) | Transport::UnixAbstract(_) => unsafe {
+                        Ok(Listener::UNIX(
                             Some(UnixListener::from_raw_fd(l as RawFd)),
                             true,
-                        ));
-                    }
-                } else {
-                    return Err(Error::from(context!(ErrorKind::InvalidAddress)));
-                }
+                            None,
+                        ))
+                    },
+                    _ => Err(context!(ErrorKind::InvalidAddress)),
+                };
             }
         }
 
-        if address.starts_with("tcp:") {
-            Ok(Listener::TCP(
-                Some(TcpListener::bind(&address[4..]).map_err(map_context!())?),
+        match parsed.transport {
+            Transport::Tcp { ref host, port } => Ok(Listener::TCP(
+                Some(bind_tcp(host, port, listen_config)?),
                 false,
-            ))
-        } else if address.starts_with("unix:") {
-            let mut addr = String::from(address[5..].split(';').next().unwrap());
-            if addr.starts_with('@') {
-                addr = addr.replacen('@', "\0", 1);
-                return get_abstract_unixlistener(&addr)
-                    .and_then(|v| Ok(Listener::UNIX(Some(v), false)));
-            }
-            // ignore error on non-existant file
-            let _ = fs::remove_file(&*addr);
-            Ok(Listener::UNIX(
-                Some(UnixListener::bind(addr).map_err(map_context!())?),
+            )),
+            #[cfg(feature = "tls")]
+            Transport::TcpTls { ref host, port } => {
+                let tls = match listen_config.tls {
+                    Some(ref tls) => parsed.tls_config(tls),
+                    None if parsed.has_tls_parameters() => parsed.tls_config(&Default::default()),
+                    None => {
+                        return Err(context!(ErrorKind::InvalidTlsConfig(
+                            "tcp+tls: addresses need ListenConfig::tls".into()
+                        )))
+                    }
+                };
+                Ok(Listener::TLS(
+                    Some(bind_tcp(host, port, listen_config)?),
+                    TlsAcceptor::new(&tls)?,
+                ))
+            }
+            // `@` alone binds to a free name chosen by the kernel
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::UnixAbstract(ref name) => Ok(Listener::UNIX(
+                Some(abstract_socket::bind(name)?),
                 false,
-            ))
-        } else {
-            Err(Error::from(context!(ErrorKind::InvalidAddress)))
+                None,
+            )),
+            Transport::Unix(ref addr) => {
+                let mode = parsed.mode.or(listen_config.socket_mode);
+                let group = parsed
+                    .group
+                    .as_ref()
+                    .or(listen_config.socket_group.as_ref());
+
+                #[cfg(unix)]
+                {
+                    let group = match group {
+                        Some(group) => Some(group_id(group)?),
+                        None => None,
+                    };
+                    if mode.is_some() || group.is_some() {
+                        return Ok(Listener::UNIX(
+                            Some(bind_unix_atomic(addr, mode, group)?),
+                            false,
+                            Some(addr.into()),
+                        ));
+                    }
+                }
+                #[cfg(windows)]
+                let _ = (mode, group);
+
+                // ignore error on non-existant file
+                let _ = fs::remove_file(addr);
+                Ok(Listener::UNIX(
+                    Some(UnixListener::bind(addr).map_err(map_context!())?),
+                    false,
+                    Some(addr.into()),
+                ))
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Transport::Vsock { cid, port } => {
+                Ok(Listener::VSOCK(Some(VsockListener::bind(cid, port)?)))
+            }
+            #[cfg(windows)]
+            Transport::Pipe(ref name) => Ok(Listener::PIPE(Some(PipeListener::bind(name)?))),
+            _ => Err(context!(ErrorKind::InvalidAddress)),
         }
     }
 
     #[cfg(windows)]
-    pub fn accept(&self, timeout: u64) -> Result<Box<dyn Stream>> {
+    pub(crate) fn accept_with(
+        &self,
+        timeout: u64,
+        tcp_config: &TcpConfig,
+    ) -> Result<(Box<dyn Stream>, Option<SocketAddr>)> {
         use winapi::um::winsock2::WSAEINTR as EINTR;
         use winapi::um::winsock2::{fd_set, select, timeval};
 
+        if let Listener::PIPE(Some(ref l)) = *self {
+            return Ok((Box::new(l.accept(timeout)?), None));
+        }
+
         if timeout > 0 {
             let socket: usize =
                 self.as_raw_socket()
@@ -203,30 +453,61 @@ impl Listener {
 
         match self {
             &Listener::TCP(Some(ref l), _) => {
-                let (s, _addr) = l.accept().map_err(map_context!())?;
-                Ok(Box::new(s))
+                let (s, addr) = l.accept().map_err(map_context!())?;
+                tcp_config.apply(&s).map_err(map_context!())?;
+                Ok((Box::new(s), Some(addr)))
             }
-            Listener::UNIX(Some(ref l), _) => {
+            Listener::UNIX(Some(ref l), _, _) => {
                 let (s, _addr) = l.accept().map_err(map_context!())?;
-                Ok(Box::new(s))
+                Ok((Box::new(s), None))
+            }
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), ref tls) => {
+                let (s, addr) = l.accept().map_err(map_context!())?;
+                tcp_config.apply(&s).map_err(map_context!())?;
+                Ok((Box::new(tls.accept(s)?), Some(addr)))
             }
             _ => Err(context!(ErrorKind::ConnectionClosed)),
         }
     }
 
+    /// Accepts a connection like `accept_from` and sets the options of `tcp_config` on
+    /// TCP connections.
     #[cfg(unix)]
-    pub fn accept(&self, timeout: u64) -> Result<Box<dyn Stream>> {
+    pub(crate) fn accept_with(
+        &self,
+        timeout: u64,
+        tcp_config: &TcpConfig,
+    ) -> Result<(Box<dyn Stream>, Option<SocketAddr>)> {
+        self.accept_until(timeout, tcp_config, None)
+    }
+
+    /// Like `accept_with`, but returns `ErrorKind::Timeout` at once, when `token` is
+    /// triggered. With a token, a `timeout` of `0` waits without a limit.
+    #[cfg(unix)]
+    pub(crate) fn accept_until(
+        &self,
+        timeout: u64,
+        tcp_config: &TcpConfig,
+        token: Option<&ShutdownToken>,
+    ) -> Result<(Box<dyn Stream>, Option<SocketAddr>)> {
         use libc::{fd_set, select, timeval, EAGAIN, EINTR, FD_ISSET, FD_SET, FD_ZERO};
 
-        if timeout > 0 {
+        if timeout > 0 || token.is_some() {
             let fd = self
                 .as_raw_fd()
                 .ok_or_else(|| context!(ErrorKind::ConnectionClosed))?;
+            let wake_fd = token.map(ShutdownToken::wake_fd);
 
-            let mut timeout = timeval {
+            let mut timeval = timeval {
                 tv_sec: (timeout / 1000u64) as _,
                 tv_usec: ((timeout % 1000u64) * 1000u64) as _,
             };
+            let timeout: *mut timeval = if timeout > 0 {
+                &mut timeval
+            } else {
+                std::ptr::null_mut()
+            };
 
             unsafe {
                 let mut readfs = mem::MaybeUninit::<fd_set>::uninit();
@@ -241,49 +522,211 @@ impl Listener {
                     errorfds.assume_init();
 
                     FD_SET(fd, readfs.as_mut_ptr());
+                    if let Some(wake_fd) = wake_fd {
+                        FD_SET(wake_fd, readfs.as_mut_ptr());
+                    }
                     let ret = select(
-                        fd + 1,
+                        fd.max(wake_fd.unwrap_or(-1)) + 1,
                         readfs.as_mut_ptr(),
                         writefds.as_mut_ptr(),
                         errorfds.as_mut_ptr(),
-                        &mut timeout,
+                        timeout,
                     );
                     if ret != EINTR && ret != EAGAIN {
                         break;
                     }
                 }
-                if !FD_ISSET(fd, readfs.as_mut_ptr()) {
+                let woken = match wake_fd {
+                    Some(wake_fd) => FD_ISSET(wake_fd, readfs.as_mut_ptr()),
+                    None => false,
+                };
+                if woken || !FD_ISSET(fd, readfs.as_mut_ptr()) {
                     return Err(Error::from(context!(ErrorKind::Timeout)));
                 }
             }
         }
         match self {
             &Listener::TCP(Some(ref l), _) => {
-                let (s, _addr) = l.accept().map_err(map_context!())?;
-                Ok(Box::new(s))
+                let (s, addr) = l.accept().map_err(map_context!())?;
+                tcp_config.apply(&s).map_err(map_context!())?;
+                Ok((Box::new(s), Some(addr)))
             }
-            Listener::UNIX(Some(ref l), _) => {
+            Listener::UNIX(Some(ref l), _, _) => {
                 let (s, _addr) = l.accept().map_err(map_context!())?;
-                Ok(Box::new(s))
+                Ok((Box::new(s), None))
+            }
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), ref tls) => {
+                let (s, addr) = l.accept().map_err(map_context!())?;
+                tcp_config.apply(&s).map_err(map_context!())?;
+                Ok((Box::new(tls.accept(s)?), Some(addr)))
             }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Listener::VSOCK(Some(ref l)) => Ok((Box::new(l.accept()?), None)),
             _ => Err(Error::from(context!(ErrorKind::ConnectionClosed))),
         }
     }
 
+    /// Like `accept_with`, the token is checked by the caller on the timeout.
+    #[cfg(windows)]
+    pub(crate) fn accept_until(
+        &self,
+        timeout: u64,
+        tcp_config: &TcpConfig,
+        _token: Option<&ShutdownToken>,
+    ) -> Result<(Box<dyn Stream>, Option<SocketAddr>)> {
+        self.accept_with(timeout, tcp_config)
+    }
+
+    /// Like `accept`, but also returns the peer address of TCP connections.
+    pub fn accept_from(&self, timeout: u64) -> Result<(Box<dyn Stream>, Option<SocketAddr>)> {
+        self.accept_with(timeout, &TcpConfig::default())
+    }
+
+    /// Waits `timeout` milliseconds for a new connection and accepts it.
+    pub fn accept(&self, timeout: u64) -> Result<Box<dyn Stream>> {
+        self.accept_from(timeout).map(|(s, _)| s)
+    }
+
     pub fn set_nonblocking(&self, b: bool) -> Result<()> {
         match *self {
             Listener::TCP(Some(ref l), _) => l.set_nonblocking(b).map_err(map_context!())?,
-            Listener::UNIX(Some(ref l), _) => l.set_nonblocking(b).map_err(map_context!())?,
+            Listener::UNIX(Some(ref l), _, _) => l.set_nonblocking(b).map_err(map_context!())?,
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), _) => l.set_nonblocking(b).map_err(map_context!())?,
+            // the pipe listener waits with a timeout instead
+            #[cfg(windows)]
+            Listener::PIPE(Some(_)) => {}
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Listener::VSOCK(Some(ref l)) => l.set_nonblocking(b)?,
             _ => Err(context!(ErrorKind::ConnectionClosed))?,
         }
         Ok(())
     }
 
+    /// Returns the varlink address, which clients use to connect to the listener.
+    ///
+    /// This is the address chosen by the system for `tcp:` addresses with port `0`
+    /// and for the abstract address `unix:@`.
+    pub fn local_address(&self) -> Result<String> {
+        match *self {
+            Listener::TCP(Some(ref l), _) => {
+                Ok(format!("tcp:{}", l.local_addr().map_err(map_context!())?))
+            }
+            #[cfg(unix)]
+            Listener::UNIX(Some(ref l), _, _) => unix_local_address(l.as_raw_fd()),
+            #[cfg(windows)]
+            Listener::UNIX(Some(_), _, Some(ref path)) => Ok(format!("unix:{}", path.display())),
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), _) => Ok(format!(
+                "tcp+tls:{}",
+                l.local_addr().map_err(map_context!())?
+            )),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Listener::VSOCK(Some(ref l)) => l.local_address(),
+            _ => Err(context!(ErrorKind::InvalidAddress)),
+        }
+    }
+
+    /// Marks the socket as passed by socket activation, so its path is not removed on drop.
+    #[cfg(unix)]
+    pub(crate) fn mark_activated(&mut self) {
+        match self {
+            Listener::TCP(_, activated) | Listener::UNIX(_, activated, _) => *activated = true,
+            _ => {}
+        }
+    }
+
+    /// True, if the listener was passed by socket activation.
+    fn is_activated(&self) -> bool {
+        match *self {
+            Listener::TCP(_, activated) | Listener::UNIX(_, activated, _) => activated,
+            _ => false,
+        }
+    }
+
+    /// Sets the maximum length of the queue of pending connections.
+    #[cfg(unix)]
+    fn set_backlog(&self, backlog: u32) -> Result<()> {
+        let fd = match self.as_raw_fd() {
+            Some(fd) => fd,
+            None => return Ok(()),
+        };
+        let backlog = libc::c_int::try_from(backlog).unwrap_or(libc::SOMAXCONN);
+        // listen() on a listening socket changes its backlog
+        if unsafe { libc::listen(fd, backlog) } < 0 {
+            return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+        }
+        Ok(())
+    }
+
+    /// Sets the maximum length of the queue of pending connections.
+    #[cfg(windows)]
+    fn set_backlog(&self, backlog: u32) -> Result<()> {
+        use winapi::um::winsock2::{listen, SOCKET_ERROR, SOMAXCONN};
+
+        let socket = match self.as_raw_socket() {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+        let backlog = i32::try_from(backlog).unwrap_or(SOMAXCONN);
+        if unsafe { listen(socket as usize, backlog) } == SOCKET_ERROR {
+            return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+        }
+        Ok(())
+    }
+
+    /// True, if a connection is waiting to be accepted.
+    #[cfg(unix)]
+    fn is_pending(&self) -> bool {
+        let fd = match self.as_raw_fd() {
+            Some(fd) => fd,
+            None => return false,
+        };
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 0) > 0 }
+    }
+
+    /// True, if a connection is waiting to be accepted.
+    #[cfg(windows)]
+    fn is_pending(&self) -> bool {
+        use winapi::um::winsock2::{fd_set, select, timeval};
+
+        let socket = match self.as_raw_socket() {
+            Some(socket) => socket as usize,
+            None => return false,
+        };
+        let mut timeout = timeval {
+            tv_sec: 0,
+            tv_usec: 0,
+        };
+        unsafe {
+            let mut readfs: fd_set = mem::MaybeUninit::zeroed().assume_init();
+            readfs.fd_count = 1;
+            readfs.fd_array[0] = socket;
+            select(
+                0,
+                &mut readfs,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                &mut timeout,
+            ) > 0
+        }
+    }
+
     #[cfg(unix)]
     pub fn as_raw_fd(&self) -> Option<RawFd> {
         match *self {
             Listener::TCP(Some(ref l), _) => Some(l.as_raw_fd()),
-            Listener::UNIX(Some(ref l), _) => Some(l.as_raw_fd()),
+            Listener::UNIX(Some(ref l), _, _) => Some(l.as_raw_fd()),
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), _) => Some(l.as_raw_fd()),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Listener::VSOCK(Some(ref l)) => Some(l.as_raw_fd()),
             _ => None,
         }
     }
@@ -292,23 +735,57 @@ impl Listener {
     pub fn as_raw_socket(&self) -> Option<RawSocket> {
         match *self {
             Listener::TCP(Some(ref l), _) => Some(l.as_raw_socket()),
-            Listener::UNIX(Some(ref l), _) => Some(l.as_raw_socket()),
+            Listener::UNIX(Some(ref l), _, _) => Some(l.as_raw_socket()),
+            #[cfg(feature = "tls")]
+            Listener::TLS(Some(ref l), _) => Some(l.as_raw_socket()),
             _ => None,
         }
     }
 }
 
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::TCP(Some(listener), false)
+    }
+}
+
+/// The socket file of a listener bound to a path is not removed on drop.
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Self {
+        Listener::UNIX(Some(listener), false, None)
+    }
+}
+
+#[cfg(unix)]
+impl Listener {
+    /// Takes ownership of an inherited listening socket of type `AF_UNIX`, `AF_INET` or `AF_INET6`.
+    ///
+    /// On error, `fd` is not closed.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open socket, which is not owned by anything else.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> Result<Self> {
+        let mut addr: libc::sockaddr_storage = mem::zeroed();
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) < 0 {
+            return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+        }
+        match i32::from(addr.ss_family) {
+            libc::AF_UNIX => Ok(UnixListener::from_raw_fd(fd).into()),
+            libc::AF_INET | libc::AF_INET6 => Ok(TcpListener::from_raw_fd(fd).into()),
+            _ => Err(context!(ErrorKind::InvalidAddress)),
+        }
+    }
+}
+
 impl Drop for Listener {
     fn drop(&mut self) {
         match *self {
-            Listener::UNIX(Some(ref listener), false) => {
-                if let Ok(local_addr) = listener.local_addr() {
-                    if let Some(path) = local_addr.as_pathname() {
-                        let _ = fs::remove_file(path);
-                    }
-                }
+            Listener::UNIX(Some(_), false, Some(ref path)) => {
+                let _ = fs::remove_file(path);
             }
-            Listener::UNIX(ref mut listener, true) => {
+            Listener::UNIX(ref mut listener, true, _) => {
                 if let Some(l) = listener.take() {
                     #[cfg(unix)]
                     unsafe {
@@ -338,17 +815,98 @@ impl Drop for Listener {
     }
 }
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+struct PoolState {
+    jobs: VecDeque<Job>,
+    num_workers: usize,
+    num_idle: usize,
+    num_busy: usize,
+    terminate: bool,
+}
+
+/// Called with the message of a panic of a method handler, see
+/// `ListenConfig::panic_handler`
+pub type PanicHandler = Arc<dyn Fn(&str) + Send + Sync>;
+
+struct PoolShared {
+    state: Mutex<PoolState>,
+    /// called with the panics of the jobs
+    panic_handler: Mutex<Option<PanicHandler>>,
+    /// signaled, when a job is queued or the pool terminates
+    work: Condvar,
+    /// signaled, when a worker exits
+    exited: Condvar,
+    /// signaled, when a worker takes a job from the queue
+    space: Condvar,
+}
+
+/// Runs the connections accepted by [`listen`](fn.listen.html)
+///
+/// Set in `ListenConfig::executor`, e.g. to share one [`ThreadPool`] among several
+/// servers, to spawn a thread per connection with [`ThreadPerConnection`], or to run
+/// the connections on the pool of another library. Without it, `listen` creates a
+/// `ThreadPool` from the worker settings of the `ListenConfig`.
+///
+/// A job serves a connection until it is closed, so an executor with a fixed number
+/// of threads serves at most as many connections at once.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use varlink::{Executor, ListenConfig};
+///
+/// /// Runs every connection on a named thread
+/// struct Named;
+///
+/// impl Executor for Named {
+///     fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+///         std::thread::Builder::new()
+///             .name("varlink connection".into())
+///             .spawn(job)
+///             .unwrap();
+///     }
+/// }
+///
+/// let config = ListenConfig {
+///     executor: Some(Arc::new(Named)),
+///     ..Default::default()
+/// };
+/// ```
+///
+/// [`ThreadPool`]: struct.ThreadPool.html
+/// [`ThreadPerConnection`]: struct.ThreadPerConnection.html
+pub trait Executor: Send + Sync {
+    /// Runs `job` on another thread.
+    fn execute(&self, job: Box<dyn FnOnce() + Send>);
+
+    /// True, if a job would have to wait for a free thread, so the server applies its
+    /// `overload_policy` to the new connection.
+    fn is_full(&self) -> bool {
+        false
+    }
+}
+
+/// An [`Executor`](trait.Executor.html), which spawns a thread for every connection
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadPerConnection;
+
+impl Executor for ThreadPerConnection {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        thread::spawn(job);
+    }
 }
 
-struct ThreadPool {
+/// A pool of worker threads, which grows on demand up to `max_workers` and shrinks
+/// back to `min_workers`, when threads are idle for longer than `idle_timeout`.
+///
+/// It is the default [`Executor`](trait.Executor.html) of `listen`. Dropping the pool
+/// waits for the running jobs.
+pub struct ThreadPool {
+    min_workers: usize,
     max_workers: usize,
-    workers: Vec<Worker>,
-    num_busy: Arc<RwLock<usize>>,
-    sender: mpsc::Sender<Message>,
-    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+    idle_timeout: Duration,
+    max_queued: usize,
+    shared: Arc<PoolShared>,
 }
 
 trait FnBox {
@@ -366,139 +924,997 @@ type Job = Box<dyn FnBox + Send + 'static>;
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
-    /// The initial_worker is the number of threads in the pool.
+    /// The min_workers is the number of threads, which are always kept in the pool.
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if the initial_worker is zero.
-    pub fn new(initial_worker: usize, max_workers: usize) -> ThreadPool {
-        assert!(initial_worker > 0);
-
-        let (sender, receiver) = mpsc::channel();
+    /// The `new` function will panic if the min_workers is zero.
+    pub fn new(min_workers: usize, max_workers: usize, idle_timeout: Duration) -> ThreadPool {
+        assert!(min_workers > 0);
 
-        let receiver = Arc::new(Mutex::new(receiver));
+        let pool = ThreadPool {
+            min_workers,
+            max_workers: max_workers.max(min_workers),
+            idle_timeout,
+            max_queued: 0,
+            shared: Arc::new(PoolShared {
+                state: Mutex::new(PoolState {
+                    jobs: VecDeque::new(),
+                    num_workers: 0,
+                    num_idle: 0,
+                    num_busy: 0,
+                    terminate: false,
+                }),
+                panic_handler: Mutex::new(None),
+                work: Condvar::new(),
+                exited: Condvar::new(),
+                space: Condvar::new(),
+            }),
+        };
 
-        let mut workers = Vec::with_capacity(initial_worker);
+        {
+            let mut state = pool.shared.state.lock().unwrap();
+            for _ in 0..min_workers {
+                pool.spawn_worker(&mut state);
+            }
+        }
 
-        let num_busy = Arc::new(RwLock::new(0 as usize));
+        pool
+    }
 
-        for _ in 0..initial_worker {
-            workers.push(Worker::new(Arc::clone(&receiver), Arc::clone(&num_busy)));
-        }
+    /// Limit the number of jobs waiting for a free worker to `max_queued`, `0` means unlimited.
+    pub fn with_max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued;
+        self
+    }
 
-        ThreadPool {
-            max_workers,
-            workers,
-            sender,
-            receiver,
-            num_busy,
-        }
+    /// Calls `panic_handler` with the message of a panicking job, instead of logging it.
+    ///
+    /// The worker survives the panic and runs the next job.
+    pub fn with_panic_handler(self, panic_handler: Option<PanicHandler>) -> Self {
+        *self.shared.panic_handler.lock().unwrap() = panic_handler;
+        self
     }
 
-    pub fn execute<F>(&mut self, f: F)
+    /// Queue `f` to be run by a worker thread.
+    ///
+    /// Blocks, while the queue is full.
+    pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
-        if ((self.num_busy() + 1) >= self.workers.len()) && (self.workers.len() <= self.max_workers)
-        {
-            self.workers.push(Worker::new(
-                Arc::clone(&self.receiver),
-                Arc::clone(&self.num_busy),
-            ));
+        let mut state = self.shared.state.lock().unwrap();
+        while self.max_queued > 0 && state.jobs.len() >= self.max_queued {
+            state = self.shared.space.wait(state).unwrap();
+        }
+        state.jobs.push_back(Box::new(f));
+        if state.num_idle < state.jobs.len() && state.num_workers < self.max_workers {
+            self.spawn_worker(&mut state);
         }
+        self.shared.work.notify_one();
     }
 
+    /// The number of threads currently running a job
     pub fn num_busy(&self) -> usize {
-        let num_busy = self.num_busy.read().unwrap();
-        *num_busy
+        self.shared.state.lock().unwrap().num_busy
+    }
+
+    /// True, if `execute` would block, because the queue is full.
+    pub fn is_queue_full(&self) -> bool {
+        self.max_queued > 0 && self.shared.state.lock().unwrap().jobs.len() >= self.max_queued
+    }
+
+    /// The number of threads currently in the pool
+    pub fn num_workers(&self) -> usize {
+        self.shared.state.lock().unwrap().num_workers
+    }
+
+    fn spawn_worker(&self, state: &mut PoolState) {
+        state.num_workers += 1;
+        let shared = self.shared.clone();
+        let min_workers = self.min_workers;
+        let idle_timeout = self.idle_timeout;
+        thread::spawn(move || Worker::run(&shared, min_workers, idle_timeout));
+    }
+}
+
+impl Executor for ThreadPool {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        ThreadPool::execute(self, job)
+    }
+
+    fn is_full(&self) -> bool {
+        self.is_queue_full()
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        let mut state = self.shared.state.lock().unwrap();
+        state.terminate = true;
+        self.shared.work.notify_all();
+        while state.num_workers > 0 {
+            state = self.shared.exited.wait(state).unwrap();
         }
+    }
+}
 
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
+struct Worker<'a>(&'a PoolShared);
+
+/// Unregisters the worker from the pool, also if a job panicked.
+impl<'a> Drop for Worker<'a> {
+    fn drop(&mut self) {
+        let mut state = match self.0.state.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        };
+        if thread::panicking() {
+            state.num_busy -= 1;
         }
+        state.num_workers -= 1;
+        self.0.exited.notify_all();
     }
 }
 
-struct Worker {
-    thread: Option<thread::JoinHandle<()>>,
-}
+impl<'a> Worker<'a> {
+    fn run(shared: &PoolShared, min_workers: usize, idle_timeout: Duration) {
+        let _worker = Worker(shared);
+        let _tracked = Tracked::new(Object::Worker);
+        let mut state = shared.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                state.num_busy += 1;
+                shared.space.notify_one();
+                drop(state);
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(move || job.call_box()))
+                {
+                    let panic_handler = shared.panic_handler.lock().unwrap().clone();
+                    report_panic(panic_handler.as_ref(), payload.as_ref());
+                }
+                state = shared.state.lock().unwrap();
+                state.num_busy -= 1;
+                continue;
+            }
 
-impl Worker {
-    fn new(receiver: Arc<Mutex<mpsc::Receiver<Message>>>, num_busy: Arc<RwLock<usize>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+            if state.terminate {
+                break;
+            }
 
-            match message {
-                Message::NewJob(job) => {
-                    {
-                        let mut num_busy = num_busy.write().unwrap();
-                        *num_busy += 1;
-                    }
-                    job.call_box();
-                    {
-                        let mut num_busy = num_busy.write().unwrap();
-                        *num_busy -= 1;
-                    }
-                }
-                Message::Terminate => {
+            state.num_idle += 1;
+            if state.num_workers > min_workers {
+                let (s, timeout) = shared.work.wait_timeout(state, idle_timeout).unwrap();
+                state = s;
+                state.num_idle -= 1;
+                if timeout.timed_out() && state.jobs.is_empty() && state.num_workers > min_workers {
                     break;
                 }
+            } else {
+                state = shared.work.wait(state).unwrap();
+                state.num_idle -= 1;
             }
-        });
+        }
+    }
+}
 
-        Worker {
-            thread: Some(thread),
+/// Passes the panic of a job to `panic_handler`, or logs it.
+fn report_panic(panic_handler: Option<&PanicHandler>, payload: &(dyn Any + Send)) {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<Any>"
+    };
+    match panic_handler {
+        Some(f) => f(message),
+        None => {
+            #[cfg(feature = "log")]
+            event!(error, "worker panicked: {}", message);
+            #[cfg(not(feature = "log"))]
+            eprintln!("Worker panicked: {}", message);
         }
     }
 }
 
-/// `ListenConfig` specifies the configuration parameters for [`varlink::listen`]
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `fe80::/10`
 ///
-/// Examples:
+/// A single address without a prefix length matches only this address.
 ///
-/// ```rust
-/// let l = varlink::ListenConfig::default();
-/// assert_eq!(l.initial_worker_threads, 1);
-/// assert_eq!(l.max_worker_threads, 100);
-/// assert_eq!(l.idle_timeout, 0);
-/// assert!(l.stop_listening.is_none());
-/// ```
+///# Examples
 ///
-/// [`varlink::listen`]: fn.listen.html
-pub struct ListenConfig {
-    /// The amount of initial worker threads
-    pub initial_worker_threads: usize,
-    /// The maximum amount of worker threads
-    pub max_worker_threads: usize,
-    /// Time in seconds for the server to quit, when it is idle
-    pub idle_timeout: u64,
-    /// An optional AtomicBool as a global flag, which lets the server stop accepting new connections, when set to `true`
-    pub stop_listening: Option<Arc<AtomicBool>>,
+///```
+/// let range: varlink::IpRange = "192.168.0.0/16".parse().unwrap();
+/// assert!(range.contains(&"192.168.1.2".parse().unwrap()));
+/// assert!(!range.contains(&"10.0.0.1".parse().unwrap()));
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    addr: IpAddr,
+    prefix_len: u8,
 }
 
-impl Default for ListenConfig {
-    fn default() -> Self {
-        ListenConfig {
-            initial_worker_threads: 1,
-            max_worker_threads: 100,
-            idle_timeout: 0,
-            stop_listening: None,
+impl IpRange {
+    /// Creates a range of all addresses sharing the first `prefix_len` bits with `addr`.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Self> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(context!(ErrorKind::InvalidAddress));
         }
+        Ok(IpRange { addr, prefix_len })
     }
-}
 
-/// `listen` creates a server, with `num_worker` threads listening on `varlink_uri`.
+    /// Returns `true`, if `ip` is in the range.
+    ///
+    /// IPv4 addresses mapped to IPv6 (`::ffff:a.b.c.d`) are matched against IPv4 ranges.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match *ip {
+            IpAddr::V6(v6) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+                IpAddr::V4(v6.to_ipv4().unwrap())
+            }
+            ip => ip,
+        };
+
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = (!0u32)
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = (!0u128)
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '/');
+        let addr = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<IpAddr>()
+            .map_err(|_| context!(ErrorKind::InvalidAddress))?;
+        let prefix_len = match parts.next() {
+            Some(len) => len
+                .parse::<u8>()
+                .map_err(|_| context!(ErrorKind::InvalidAddress))?,
+            None => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+        };
+        IpRange::new(addr, prefix_len)
+    }
+}
+
+#[derive(Default)]
+struct ServerHandleInner {
+    shutdown: AtomicBool,
+    running: Mutex<bool>,
+    stopped: Condvar,
+    local_address: Mutex<Option<String>>,
+    metrics: MetricsCollector,
+    /// the pool of the running server and its maximum number of workers
+    pool: Mutex<Option<(Arc<PoolShared>, usize)>>,
+}
+
+/// A handle to stop a server started with [`varlink::listen`] from another thread
+///
+/// Pass a clone of the handle in `ListenConfig::server_handle`.
+///
+///# Examples
+///
+///```
+/// let handle = varlink::ServerHandle::new();
+/// let config = varlink::ListenConfig {
+///     server_handle: Some(handle.clone()),
+///     ..Default::default()
+/// };
+///
+/// let server = std::thread::spawn(move || {
+///     let service = varlink::VarlinkService::new(
+///         "org.varlink",
+///         "test service",
+///         "0.1",
+///         "http://varlink.org",
+///         vec![],
+///     );
+///     varlink::listen(service, "unix:test_server_handle", &config)
+/// });
+///
+/// handle.shutdown();
+/// assert!(server.join().unwrap().is_ok());
+///```
+///
+/// [`varlink::listen`]: fn.listen.html
+#[derive(Clone, Default)]
+pub struct ServerHandle(Arc<ServerHandleInner>);
+
+impl ServerHandle {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Stops the server and waits until `listen()` returned.
+    ///
+    /// No new connections are accepted and the receiving side of all open connections
+    /// is shut down. Calls already received are still answered. When all connections
+    /// are finished, the unix socket is removed and `listen()` returns `Ok(())`.
+    ///
+    /// If called before the server started, `listen()` returns immediately.
+    pub fn shutdown(&self) {
+        self.0.shutdown.store(true, Ordering::SeqCst);
+        let mut running = self.0.running.lock().unwrap();
+        while *running {
+            running = self.0.stopped.wait(running).unwrap();
+        }
+    }
+
+    /// Returns `true`, if `shutdown()` was called.
+    pub fn is_shutdown(&self) -> bool {
+        self.0.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Returns the address of the listening server, see [`Listener::local_address`].
+    ///
+    /// `None`, before the server listens and after it stopped. To know the address
+    /// before the server runs, bind a [`Listener`] and start it with [`listen_on`].
+    ///
+    /// [`Listener::local_address`]: enum.Listener.html#method.local_address
+    /// [`Listener`]: enum.Listener.html
+    /// [`listen_on`]: fn.listen_on.html
+    pub fn local_address(&self) -> Option<String> {
+        self.0.local_address.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_local_address(&self, address: Option<String>) {
+        *self.0.local_address.lock().unwrap() = address;
+    }
+
+    /// Returns the metrics of the server collected so far.
+    ///
+    /// The numbers of the connections and calls are kept, if the server is started
+    /// again with the same handle, the numbers of the workers are `0` while it is
+    /// not running. See the [`metrics`](metrics/index.html) module.
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = self.0.metrics.snapshot();
+        if let Some((ref pool, max_workers)) = *self.0.pool.lock().unwrap() {
+            let state = pool.state.lock().unwrap();
+            metrics.queued_connections = state.jobs.len() as u64;
+            metrics.workers = state.num_workers as u64;
+            metrics.busy_workers = state.num_busy as u64;
+            metrics.max_workers = max_workers as u64;
+        }
+        metrics
+    }
+}
+
+struct ShutdownTokenInner {
+    triggered: AtomicBool,
+    /// a pipe, which becomes readable, when the token is triggered
+    #[cfg(unix)]
+    pipe: [RawFd; 2],
+}
+
+impl Drop for ShutdownTokenInner {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::close(self.pipe[0]);
+            libc::close(self.pipe[1]);
+        }
+    }
+}
+
+/// A token to stop servers from a signal handler, without waiting for the next poll
+///
+/// Pass a clone of the token in `ListenConfig::shutdown_token`. When the token is
+/// triggered, the servers wake up at once, stop accepting connections, shut down the
+/// receiving side of the open connections, and return `Ok(())`, when the calls already
+/// received are answered. A triggered token stays triggered.
+///
+/// On Unix, the wake-up is a write to a pipe, so `trigger()` is async-signal-safe and
+/// may be called from the handler of `SIGTERM`, e.g. with the token in a static.
+/// On Windows, the servers check the token every 100ms.
+///
+///# Examples
+///
+///```
+/// # fn main() -> varlink::Result<()> {
+/// let token = varlink::ShutdownToken::new()?;
+/// let config = varlink::ListenConfig {
+///     shutdown_token: Some(token.clone()),
+///     ..Default::default()
+/// };
+///
+/// let server = std::thread::spawn(move || {
+///     let service = varlink::VarlinkService::new(
+///         "org.varlink",
+///         "test service",
+///         "0.1",
+///         "http://varlink.org",
+///         vec![],
+///     );
+///     varlink::listen(service, "unix:test_shutdown_token", &config)
+/// });
+///
+/// token.trigger();
+/// assert!(server.join().unwrap().is_ok());
+/// # Ok(())
+/// # }
+///```
+#[derive(Clone)]
+pub struct ShutdownToken(Arc<ShutdownTokenInner>);
+
+impl ShutdownToken {
+    /// Creates a token, which is not triggered.
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            let mut pipe: [RawFd; 2] = [-1; 2];
+            if unsafe { libc::pipe(pipe.as_mut_ptr()) } != 0 {
+                let e = io::Error::last_os_error();
+                return Err(context!(e, ErrorKind::Io(e.kind())));
+            }
+            for fd in pipe.iter() {
+                unsafe {
+                    libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                    libc::fcntl(*fd, libc::F_SETFL, libc::O_NONBLOCK);
+                }
+            }
+            Ok(ShutdownToken(Arc::new(ShutdownTokenInner {
+                triggered: AtomicBool::new(false),
+                pipe,
+            })))
+        }
+        #[cfg(windows)]
+        Ok(ShutdownToken(Arc::new(ShutdownTokenInner {
+            triggered: AtomicBool::new(false),
+        })))
+    }
+
+    /// Stops the servers of the token, without waiting for them.
+    pub fn trigger(&self) {
+        if self.0.triggered.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        #[cfg(unix)]
+        unsafe {
+            libc::write(self.0.pipe[1], [0u8].as_ptr() as *const libc::c_void, 1);
+        }
+    }
+
+    /// Returns `true`, if `trigger()` was called.
+    pub fn is_triggered(&self) -> bool {
+        self.0.triggered.load(Ordering::SeqCst)
+    }
+
+    /// The end of the pipe, which is readable after `trigger()`
+    #[cfg(unix)]
+    fn wake_fd(&self) -> RawFd {
+        self.0.pipe[0]
+    }
+}
+
+thread_local! {
+    /// The handle of the server, whose connection is handled by the current worker thread
+    static CURRENT_HANDLE: RefCell<Option<ServerHandle>> = const { RefCell::new(None) };
+}
+
+#[cfg(unix)]
+thread_local! {
+    /// The socket of the connection handled by the current worker thread
+    static CURRENT_FD: std::cell::Cell<Option<RawFd>> = const { std::cell::Cell::new(None) };
+}
+
+#[cfg(unix)]
+pub(crate) fn set_current_fd(fd: Option<RawFd>) {
+    CURRENT_FD.with(|f| f.set(fd));
+}
+
+/// Returns `false`, if the client of the connection handled by the current worker
+/// thread closed it.
+///
+/// The socket is peeked without blocking, so a closed sending side of the client, or
+/// a connection shut down by the server, counts as closed, too. Without a known
+/// socket, e.g. on Windows, it returns `true`.
+pub(crate) fn peer_is_alive() -> bool {
+    #[cfg(unix)]
+    {
+        let fd = match CURRENT_FD.with(|f| f.get()) {
+            Some(fd) => fd,
+            None => return true,
+        };
+        let mut buf = [0u8; 1];
+        let n = unsafe {
+            libc::recv(
+                fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                1,
+                libc::MSG_PEEK | libc::MSG_DONTWAIT,
+            )
+        };
+        if n >= 0 {
+            return n > 0;
+        }
+        let kind = io::Error::last_os_error().kind();
+        kind == io::ErrorKind::WouldBlock || kind == io::ErrorKind::Interrupted
+    }
+    #[cfg(windows)]
+    true
+}
+
+/// Records a call in the metrics of the server of the current worker thread.
+pub(crate) fn record_call(method: &str, error: Option<&str>, duration: Duration) {
+    CURRENT_HANDLE.with(|h| {
+        if let Some(ref h) = *h.borrow() {
+            h.0.metrics.record_call(method, error, duration);
+        }
+    })
+}
+
+/// Returns `true`, if the server of the current worker thread is shutting down.
+pub(crate) fn is_shutting_down() -> bool {
+    CURRENT_HANDLE.with(|h| match *h.borrow() {
+        Some(ref h) => h.is_shutdown(),
+        None => false,
+    })
+}
+
+/// Returns `true`, if the server of `listen_config` is shut down by its handle or token.
+pub(crate) fn is_stopping(listen_config: &ListenConfig) -> bool {
+    let shutdown = match listen_config.server_handle {
+        Some(ref handle) => handle.is_shutdown(),
+        None => false,
+    };
+    let triggered = match listen_config.shutdown_token {
+        Some(ref token) => token.is_triggered(),
+        None => false,
+    };
+    shutdown || triggered
+}
+
+/// Marks the server as running, until dropped.
+pub(crate) struct RunningGuard(Option<ServerHandle>);
+
+impl RunningGuard {
+    pub(crate) fn new(handle: Option<&ServerHandle>) -> Self {
+        if let Some(h) = handle {
+            *h.0.running.lock().unwrap() = true;
+        }
+        RunningGuard(handle.cloned())
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        if let Some(ref h) = self.0 {
+            *h.0.local_address.lock().unwrap() = None;
+            *h.0.pool.lock().unwrap() = None;
+            *h.0.running.lock().unwrap() = false;
+            h.0.stopped.notify_all();
+        }
+    }
+}
+
+/// Approximate memory held for a single connection, see `ListenConfig::connection_memory_budget`
+struct MemoryBudget {
+    limit: usize,
+    /// bytes of the request received so far
+    request: AtomicUsize,
+    /// bytes of the reply written since the last flush
+    reply: AtomicUsize,
+    exceeded: AtomicBool,
+}
+
+impl MemoryBudget {
+    fn new(limit: usize) -> Self {
+        MemoryBudget {
+            limit,
+            request: AtomicUsize::new(0),
+            reply: AtomicUsize::new(0),
+            exceeded: AtomicBool::new(false),
+        }
+    }
+
+    fn check(&self) -> io::Result<()> {
+        let used = self.request.load(Ordering::SeqCst) + self.reply.load(Ordering::SeqCst);
+        if used > self.limit {
+            self.exceeded.store(true, Ordering::SeqCst);
+            return Err(io::Error::other("connection memory budget exceeded"));
+        }
+        Ok(())
+    }
+}
+
+struct BudgetReader {
+    inner: Box<dyn Read + Send + Sync>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl Read for BudgetReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        // only the bytes after the last message delimiter are still buffered
+        match buf[..n].iter().rposition(|b| *b == 0) {
+            Some(pos) => self.budget.request.store(n - pos - 1, Ordering::SeqCst),
+            None => {
+                self.budget.request.fetch_add(n, Ordering::SeqCst);
+            }
+        }
+        self.budget.check()?;
+        Ok(n)
+    }
+}
+
+struct BudgetWriter {
+    inner: Box<dyn Write + Send + Sync>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl Write for BudgetWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.budget.reply.fetch_add(buf.len(), Ordering::SeqCst);
+        self.budget.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.budget.reply.store(0, Ordering::SeqCst);
+        self.inner.flush()
+    }
+}
+
+/// Sets the socket option `SO_RCVTIMEO` or `SO_SNDTIMEO`, zero disables the timeout.
+#[cfg(unix)]
+fn set_socket_timeout(fd: RawFd, option: libc::c_int, timeout: Duration) -> io::Result<()> {
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            option,
+            &tv as *const libc::timeval as *const libc::c_void,
+            mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Applies `idle` while waiting for a new request, and `read` within a request
+#[cfg(unix)]
+struct TimeoutReader {
+    inner: Box<dyn Read + Send + Sync>,
+    fd: RawFd,
+    idle: Duration,
+    read: Duration,
+    /// the last read ended with a complete message
+    idle_now: bool,
+    current: Option<Duration>,
+}
+
+#[cfg(unix)]
+impl Read for TimeoutReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = if self.idle_now { self.idle } else { self.read };
+        if self.current != Some(timeout) {
+            set_socket_timeout(self.fd, libc::SO_RCVTIMEO, timeout)?;
+            self.current = Some(timeout);
+        }
+        let n = match self.inner.read(buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            r => r?,
+        };
+        self.idle_now = buf[..n].last() == Some(&0);
+        Ok(n)
+    }
+}
+
+/// The number of connections passed to the workers and not closed yet
+#[derive(Default)]
+pub(crate) struct OpenConnections {
+    count: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl OpenConnections {
+    pub(crate) fn open(&self) {
+        *self.count.lock().unwrap() += 1;
+    }
+
+    pub(crate) fn close(&self) {
+        *self.count.lock().unwrap() -= 1;
+        self.closed.notify_all();
+    }
+
+    pub(crate) fn count(&self) -> usize {
+        *self.count.lock().unwrap()
+    }
+
+    /// True, if `max` connections are open, `0` means unlimited.
+    pub(crate) fn is_full(&self, max: usize) -> bool {
+        max > 0 && *self.count.lock().unwrap() >= max
+    }
+
+    /// Waits `timeout` milliseconds, `0` meaning forever, for less than `max` open connections.
+    pub(crate) fn wait_below(&self, max: usize, timeout: u64) {
+        let mut count = self.count.lock().unwrap();
+        if timeout == 0 {
+            while *count >= max {
+                count = self.closed.wait(count).unwrap();
+            }
+        } else if *count >= max {
+            let _ = self
+                .closed
+                .wait_timeout(count, Duration::from_millis(timeout))
+                .unwrap();
+        }
+    }
+}
+
+/// Waits for the open connections, when `listen` returns, also with an executor,
+/// whose threads are not joined on drop.
+struct WaitForConnections(Arc<OpenConnections>);
+
+impl Drop for WaitForConnections {
+    fn drop(&mut self) {
+        self.0.wait_below(1, 0);
+    }
+}
+
+/// Answers the first request of a connection with `org.varlink.server.ServiceBusy`.
+///
+/// The error is not part of the `org.varlink.service` interface, clients of this crate
+/// see it as `ErrorKind::ServiceBusy`.
+fn reply_busy(stream: &mut dyn Stream) {
+    let _ = stream.set_nonblocking(true);
+    let _ = stream.write_all(b"{\"error\":\"org.varlink.server.ServiceBusy\"}\0");
+    // read what the client sent already, so closing does not reset the connection
+    let mut buf = [0u8; 4096];
+    let _ = stream.read(&mut buf);
+}
+
+/// Shuts down the receiving side of a connection, so a worker waiting for the
+/// next request sees the end of the stream.
+pub(crate) fn shutdown_read(stream: &dyn Stream) {
+    #[cfg(unix)]
+    unsafe {
+        libc::shutdown(stream.as_raw_fd(), libc::SHUT_RD);
+    }
+    #[cfg(windows)]
+    unsafe {
+        use winapi::um::winsock2::{shutdown, SD_RECEIVE};
+        shutdown(stream.as_raw_socket() as _, SD_RECEIVE);
+    }
+}
+
+/// What [`varlink::listen`] does with a new connection, when the queue of connections
+/// waiting for a worker thread is full
+///
+/// [`varlink::listen`]: fn.listen.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverloadPolicy {
+    /// Stop accepting connections, until a worker thread is free
+    Block,
+    /// Close the new connection immediately
+    Close,
+    /// Reply `org.varlink.server.ServiceBusy` to the first request of the new
+    /// connection and close it
+    Busy,
+}
+
+/// `ListenConfig` specifies the configuration parameters for [`varlink::listen`]
+///
+/// Examples:
+///
+/// ```rust
+/// let l = varlink::ListenConfig::default();
+/// assert_eq!(l.initial_worker_threads, 1);
+/// assert_eq!(l.max_worker_threads, 100);
+/// assert_eq!(l.worker_idle_timeout, 10);
+/// assert_eq!(l.max_queued_connections, 0);
+/// assert!(!l.single_threaded);
+/// assert_eq!(l.max_connections, 0);
+/// assert_eq!(l.overload_policy, varlink::OverloadPolicy::Block);
+/// assert_eq!(l.backlog, 0);
+/// assert_eq!(l.accept_burst, 1);
+/// assert_eq!(l.connection_memory_budget, 0);
+/// assert_eq!(l.idle_timeout, 0);
+/// assert_eq!(l.connection_idle_timeout, 0);
+/// assert_eq!(l.connection_read_timeout, 0);
+/// assert_eq!(l.connection_write_timeout, 0);
+/// assert!(l.stop_listening.is_none());
+/// assert!(l.shutdown_token.is_none());
+/// assert!(l.activation_fd_name.is_none());
+/// assert!(l.reuse_address);
+/// assert!(!l.reuse_port);
+/// assert!(l.only_v6.is_none());
+/// assert!(l.clock.is_none());
+/// ```
+///
+/// [`varlink::listen`]: fn.listen.html
+#[derive(Clone)]
+pub struct ListenConfig {
+    /// The amount of initial worker threads
+    pub initial_worker_threads: usize,
+    /// The maximum amount of worker threads
+    ///
+    /// Threads are started on demand, when all running threads are busy.
+    pub max_worker_threads: usize,
+    /// Time in seconds, after which an idle worker thread exits,
+    /// if there are more than `initial_worker_threads`
+    pub worker_idle_timeout: u64,
+    /// The maximum amount of accepted connections waiting for a free worker thread,
+    /// `0` means unlimited
+    pub max_queued_connections: usize,
+    /// Serve all connections on the calling thread with non-blocking I/O, instead of
+    /// handing them to worker threads (on Unix only)
+    ///
+    /// The worker and queue settings are ignored. A method call blocks all other
+    /// connections, until it returns. Not available for `tcp+tls:` addresses.
+    pub single_threaded: bool,
+    /// The maximum amount of open connections, queued or handled by a worker thread,
+    /// `0` means unlimited
+    pub max_connections: usize,
+    /// What to do with a new connection, if `max_queued_connections` or
+    /// `max_connections` is reached
+    pub overload_policy: OverloadPolicy,
+    /// The maximum length of the queue of connections not accepted yet by the socket,
+    /// `0` keeps the system default of 128
+    ///
+    /// Not applied to sockets passed by socket activation.
+    pub backlog: u32,
+    /// The maximum amount of connections accepted at once, before the server
+    /// checks for a shutdown and waits again, at least `1`
+    ///
+    /// A higher value empties the backlog faster, if many clients connect at the same time.
+    pub accept_burst: usize,
+    /// The maximum amount of bytes of a partially received request plus a reply,
+    /// which a single connection may hold, `0` means unlimited
+    ///
+    /// A connection exceeding its budget is closed. Requests are delimited by the
+    /// zero byte, so this limits also the data of upgraded connections without zero bytes.
+    pub connection_memory_budget: usize,
+    /// Time in seconds for the server to quit, when it is idle
+    pub idle_timeout: u64,
+    /// Time in seconds, after which a connection without a new request is closed,
+    /// `0` means unlimited (on Unix only)
+    ///
+    /// Upgraded connections are subject to `connection_read_timeout` only.
+    pub connection_idle_timeout: u64,
+    /// Time in seconds, after which a connection is closed, if a started request
+    /// receives no more data, `0` means unlimited (on Unix only)
+    pub connection_read_timeout: u64,
+    /// Time in seconds, after which a connection is closed, if the client does not
+    /// receive a reply, `0` means unlimited (on Unix only)
+    pub connection_write_timeout: u64,
+    /// An optional AtomicBool as a global flag, which lets the server stop accepting new connections, when set to `true`
+    pub stop_listening: Option<Arc<AtomicBool>>,
+    /// A token to stop the server from a signal handler, see [`ShutdownToken`]
+    ///
+    /// [`ShutdownToken`]: struct.ShutdownToken.html
+    pub shutdown_token: Option<ShutdownToken>,
+    /// If not empty, only TCP connections from these source IP ranges are accepted
+    pub allow_ips: Vec<IpRange>,
+    /// TCP connections from these source IP ranges are rejected, even if they are in `allow_ips`
+    pub deny_ips: Vec<IpRange>,
+    /// File permissions of a unix socket bound to a path, e.g. `0o660`
+    ///
+    /// A `mode=` parameter of the address takes precedence. If set, the socket is created
+    /// under a temporary name and atomically moved into place with the permissions applied.
+    pub socket_mode: Option<u32>,
+    /// The group of a unix socket bound to a path, a name or a number, e.g. `varlink`
+    ///
+    /// A `group=` parameter of the address takes precedence. Like `socket_mode`, the
+    /// group is set before the socket is moved into place (on Unix only).
+    pub socket_group: Option<String>,
+    /// The name in `LISTEN_FDNAMES` of the socket to serve, if several sockets are
+    /// passed by socket activation, defaults to `varlink`
+    pub activation_fd_name: Option<String>,
+    /// Set `SO_REUSEADDR` on TCP sockets, so a restarted service can bind its port,
+    /// while connections of the previous instance are in `TIME_WAIT` (on Unix only)
+    pub reuse_address: bool,
+    /// Set `SO_REUSEPORT` on TCP sockets, so several processes can listen on the same
+    /// port and the kernel spreads the connections among them (on Unix only)
+    pub reuse_port: bool,
+    /// Whether a TCP socket bound to an IPv6 address, e.g. `tcp:[::]:12345`, accepts
+    /// only IPv6 connections, or IPv4 connections as mapped IPv6 addresses, too
+    /// (on Unix only)
+    ///
+    /// `None` keeps the system default, e.g. `net.ipv6.bindv6only` on Linux.
+    pub only_v6: Option<bool>,
+    /// The options of accepted TCP connections, e.g. `TCP_NODELAY`
+    pub tcp: TcpConfig,
+    /// A handle to stop the server from another thread
+    pub server_handle: Option<ServerHandle>,
+    /// The clock for `idle_timeout`, defaults to the system clock
+    ///
+    /// If set, the server checks the clock every 100ms, so a [`MockClock`] can be
+    /// advanced by a test.
+    ///
+    /// [`MockClock`]: clock/struct.MockClock.html
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Called with the message of a panic of a method handler, instead of logging it
+    ///
+    /// The connection of the panicking call is closed, and its worker thread serves the
+    /// next connection.
+    pub panic_handler: Option<PanicHandler>,
+    /// Runs the accepted connections instead of a pool created from the worker
+    /// settings above, see [`Executor`](trait.Executor.html)
+    pub executor: Option<Arc<dyn Executor>>,
+    /// The certificate and key for `tcp+tls:` addresses
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ListenConfig {
+    /// Returns `true`, if a connection from `ip` passes `allow_ips` and `deny_ips`.
+    pub fn ip_allowed(&self, ip: &IpAddr) -> bool {
+        if self.deny_ips.iter().any(|r| r.contains(ip)) {
+            return false;
+        }
+        self.allow_ips.is_empty() || self.allow_ips.iter().any(|r| r.contains(ip))
+    }
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            initial_worker_threads: 1,
+            max_worker_threads: 100,
+            worker_idle_timeout: 10,
+            max_queued_connections: 0,
+            single_threaded: false,
+            max_connections: 0,
+            overload_policy: OverloadPolicy::Block,
+            backlog: 0,
+            accept_burst: 1,
+            connection_memory_budget: 0,
+            idle_timeout: 0,
+            connection_idle_timeout: 0,
+            connection_read_timeout: 0,
+            connection_write_timeout: 0,
+            stop_listening: None,
+            shutdown_token: None,
+            allow_ips: Vec::new(),
+            deny_ips: Vec::new(),
+            socket_mode: None,
+            socket_group: None,
+            activation_fd_name: None,
+            reuse_address: true,
+            reuse_port: false,
+            only_v6: None,
+            tcp: TcpConfig::default(),
+            server_handle: None,
+            clock: None,
+            panic_handler: None,
+            executor: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// `listen` creates a server, with `num_worker` threads listening on `varlink_uri`.
 ///
 /// If an `listen_config.idle_timeout` != 0 is specified, this function returns after the specified
 /// amount of seconds, if no new connection is made in that time frame. It still waits for
@@ -538,82 +1954,796 @@ pub fn listen<S: ?Sized + AsRef<str>, H: crate::ConnectionHandler + Send + Sync
     address: &S,
     listen_config: &ListenConfig,
 ) -> Result<()> {
+    let listener = Listener::new_with_config(address, listen_config)?;
+    listen_on(handler, listener, listen_config)
+}
+
+/// Accepts a connection of a burst, which was pending a moment ago.
+///
+/// The client might have aborted it in the meantime, or the process might be out of
+/// file descriptors. Then the error is logged and `None` returned, so the listener goes
+/// back to waiting, instead of stopping.
+pub(crate) fn accept_pending(
+    listener: &Listener,
+    tcp_config: &TcpConfig,
+) -> Option<(Box<dyn Stream>, Option<SocketAddr>)> {
+    match listener.accept_with(0, tcp_config) {
+        Ok(accepted) => Some(accepted),
+        Err(e) => {
+            event!(debug, "failed to accept a pending connection: {}", e);
+            None
+        }
+    }
+}
+
+/// `listen_on` is the same as [`listen`], but serves connections from a listener
+/// set up by the caller.
+///
+/// This accepts a `TcpListener`, a `UnixListener`, or a [`Listener`], e.g. created
+/// from an inherited file descriptor with [`Listener::from_raw_fd`].
+/// The socket options of `listen_config` and `tls` are not applied.
+///
+///# Examples
+///
+///```
+/// extern crate varlink;
+///
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![/* Your varlink interfaces go here */],
+/// );
+///
+/// // let the system choose a free port
+/// let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+/// let address = format!("tcp:{}", listener.local_addr().unwrap());
+///
+/// if let Err(e) = varlink::listen_on(
+///         service,
+///         listener,
+///         &varlink::ListenConfig {
+///             idle_timeout: 1,
+///             ..Default::default()
+///         },
+///     ) {
+///     if *e.kind() != varlink::ErrorKind::Timeout {
+///         panic!("Error listen on {}: {:?}", address, e);
+///     }
+/// }
+///```
+///
+/// [`listen`]: fn.listen.html
+/// [`Listener`]: enum.Listener.html
+/// [`Listener::from_raw_fd`]: enum.Listener.html#method.from_raw_fd
+pub fn listen_on<L: Into<Listener>, H: crate::ConnectionHandler + Send + Sync + 'static>(
+    handler: H,
+    listener: L,
+    listen_config: &ListenConfig,
+) -> Result<()> {
+    // dropped last, after the workers are joined and the socket is removed
+    let _running = RunningGuard::new(listen_config.server_handle.as_ref());
+
     let handler = Arc::new(handler);
-    let listener = Listener::new(address)?;
+    let listener = listener.into();
 
     listener.set_nonblocking(false)?;
+    if let Some(ref handle) = listen_config.server_handle {
+        handle.set_local_address(listener.local_address().ok());
+    }
 
-    let mut pool = ThreadPool::new(
-        listen_config.initial_worker_threads,
-        listen_config.max_worker_threads,
-    );
+    if listen_config.single_threaded {
+        return listen_single_threaded(&*handler, &listener, listen_config);
+    }
+
+    // dropped after the connections are finished
+    let executor: Arc<dyn Executor> = match listen_config.executor {
+        Some(ref executor) => executor.clone(),
+        None => {
+            let pool = ThreadPool::new(
+                listen_config.initial_worker_threads,
+                listen_config.max_worker_threads,
+                Duration::from_secs(listen_config.worker_idle_timeout),
+            )
+            .with_max_queued(listen_config.max_queued_connections)
+            .with_panic_handler(listen_config.panic_handler.clone());
+            if let Some(ref handle) = listen_config.server_handle {
+                *handle.0.pool.lock().unwrap() = Some((pool.shared.clone(), pool.max_workers));
+            }
+            Arc::new(pool)
+        }
+    };
+
+    // the open connections, to stop them on shutdown
+    let connections: Arc<Mutex<HashMap<usize, Box<dyn Stream>>>> = Default::default();
+    let mut next_id: usize = 0;
+
+    let clock: Arc<dyn Clock> = match listen_config.clock {
+        Some(ref clock) => clock.clone(),
+        None => Arc::new(SystemClock::new()),
+    };
+    let idle_timeout = Duration::from_secs(listen_config.idle_timeout);
+
+    let token = listen_config.shutdown_token.as_ref();
+    let polling = listen_config.stop_listening.is_some()
+        || listen_config.server_handle.is_some()
+        || listen_config.clock.is_some()
+        || (cfg!(windows) && token.is_some());
+
+    // the connections left to accept without waiting
+    let mut burst = 0;
+    let open = Arc::new(OpenConnections::default());
+    let _wait = WaitForConnections(open.clone());
+    let max_connections = listen_config.max_connections;
+    let blocking = listen_config.overload_policy == OverloadPolicy::Block;
 
     loop {
-        let mut to_wait = listen_config.idle_timeout * 1000;
-        let wait_time = listen_config
-            .stop_listening
-            .as_ref()
-            .map(|_| 100)
-            .unwrap_or(to_wait);
-        let mut stream = loop {
-            match listener.accept(wait_time) {
-                Err(e) => match e.kind() {
-                    ErrorKind::Timeout => {
-                        if let Some(stop) = listen_config.stop_listening.as_ref() {
-                            if stop.load(Ordering::SeqCst) {
-                                return Ok(());
-                            }
-                            if listen_config.idle_timeout == 0 {
+        let mut idle_since = clock.now();
+        let (mut stream, peer) =
+            if burst > 0 && !(blocking && open.is_full(max_connections)) && listener.is_pending() {
+                burst -= 1;
+                match accept_pending(&listener, &listen_config.tcp) {
+                    Some(accepted) => accepted,
+                    None => {
+                        burst = 0;
+                        continue;
+                    }
+                }
+            } else {
+                burst = listen_config.accept_burst.saturating_sub(1);
+                loop {
+                    let wait_time = if polling {
+                        100
+                    } else if listen_config.idle_timeout == 0 {
+                        0
+                    } else {
+                        let remaining = idle_timeout
+                            .checked_sub(clock.now() - idle_since)
+                            .unwrap_or_default();
+                        (remaining.as_secs() * 1000 + u64::from(remaining.subsec_millis())).max(1)
+                    };
+                    let accepted = if blocking && open.is_full(max_connections) {
+                        // the token does not wake up the wait for a closed connection
+                        let wait_time = match token {
+                            Some(_) if wait_time == 0 || wait_time > 100 => 100,
+                            _ => wait_time,
+                        };
+                        open.wait_below(max_connections, wait_time);
+                        Err(context!(ErrorKind::Timeout))
+                    } else {
+                        listener.accept_until(wait_time, &listen_config.tcp, token)
+                    };
+                    match accepted {
+                        Err(e) => match e.kind() {
+                            ErrorKind::Timeout => {
+                                if is_stopping(listen_config) {
+                                    for c in connections.lock().unwrap().values() {
+                                        shutdown_read(c.as_ref());
+                                    }
+                                    return Ok(());
+                                }
+                                if let Some(stop) = listen_config.stop_listening.as_ref() {
+                                    if stop.load(Ordering::SeqCst) {
+                                        return Ok(());
+                                    }
+                                }
+                                if listen_config.idle_timeout == 0 {
+                                    continue;
+                                }
+
+                                if clock.now() - idle_since >= idle_timeout {
+                                    if open.count() == 0 {
+                                        return Err(e);
+                                    }
+                                    idle_since = clock.now();
+                                }
+
                                 continue;
                             }
-                        }
-
-                        if to_wait <= wait_time {
-                            if pool.num_busy() == 0 {
+                            _ => {
                                 return Err(e);
                             }
-                            to_wait = listen_config.idle_timeout * 1000;
-                        } else {
-                            to_wait -= wait_time;
-                        }
-
-                        continue;
-                    }
-                    _ => {
-                        return Err(e);
+                        },
+                        r => break r?,
                     }
-                },
-                r => break r?,
-            }
+                }
+            };
+
+        let metrics = listen_config.server_handle.as_ref().map(|h| h.0.clone());
+        if let Some(ref m) = metrics {
+            m.metrics
+                .accepted_connections
+                .fetch_add(1, Ordering::SeqCst);
+        }
+
+        let allowed = match peer {
+            Some(peer) => listen_config.ip_allowed(&peer.ip()),
+            None => true,
         };
+        let overloaded = executor.is_full() || open.is_full(max_connections);
+        if !allowed || (!blocking && overloaded) {
+            if let Some(ref m) = metrics {
+                m.metrics
+                    .rejected_connections
+                    .fetch_add(1, Ordering::SeqCst);
+            }
+            event!(debug, "rejected connection from {:?}", peer);
+            if allowed && listen_config.overload_policy == OverloadPolicy::Busy {
+                reply_busy(stream.as_mut());
+            }
+            let _ = stream.shutdown();
+            continue;
+        }
+
         let handler = handler.clone();
 
-        pool.execute(move || {
-            let (r, mut w) = stream.split().unwrap();
-            let mut br = BufReader::new(r);
-            let mut iface: Option<String> = None;
-            loop {
-                match handler.handle(&mut br, &mut w, iface.clone()) {
-                    Ok((_, i)) => {
-                        iface = i;
-                        match br.fill_buf() {
-                            Err(_) => break,
-                            Ok(buf) if buf.is_empty() => break,
-                            _ => {}
-                        }
+        let id = next_id;
+        next_id = next_id.wrapping_add(1);
+        let connections = connections.clone();
+        let server_handle = listen_config.server_handle.clone();
+        if listen_config.server_handle.is_some() || token.is_some() {
+            if is_stopping(listen_config) {
+                let _ = stream.shutdown();
+                continue;
+            }
+            if let Ok(c) = stream.try_clone() {
+                connections.lock().unwrap().insert(id, c);
+            }
+        }
+
+        event!(debug, "accepted connection {} from {:?}", id, peer);
+        let peer = PeerInfo::new(stream.as_ref(), peer);
+        let connection_memory_budget = listen_config.connection_memory_budget;
+        let timeouts = (
+            Duration::from_secs(listen_config.connection_idle_timeout),
+            Duration::from_secs(listen_config.connection_read_timeout),
+            Duration::from_secs(listen_config.connection_write_timeout),
+        );
+        open.open();
+        let open = open.clone();
+        let tracked = Tracked::new(Object::Stream);
+        let panic_handler = listen_config.panic_handler.clone();
+        executor.execute(Box::new(move || {
+            let _tracked = tracked;
+            CURRENT_HANDLE.with(|h| *h.borrow_mut() = server_handle);
+            auth::set_current_peer(Some(peer));
+            #[cfg(unix)]
+            set_current_fd(Some(stream.as_raw_fd()));
+            if let Some(ref m) = metrics {
+                m.metrics.active_connections.fetch_add(1, Ordering::SeqCst);
+            }
+            // the connection is cleaned up, before the panic is reported
+            let served = panic::catch_unwind(AssertUnwindSafe(|| {
+                let (mut r, mut w) = stream.split().unwrap();
+                #[cfg(unix)]
+                {
+                    let (idle, read, write) = timeouts;
+                    if write > Duration::from_secs(0) {
+                        let _ = set_socket_timeout(stream.as_raw_fd(), libc::SO_SNDTIMEO, write);
                     }
-                    Err(err) => {
-                        match err.kind() {
-                            ErrorKind::ConnectionClosed | ErrorKind::SerdeJsonDe(_) => {}
-                            _ => {
-                                eprintln!("Worker error: {:?}", err);
+                    if fds::is_unix_socket(stream.as_raw_fd()) {
+                        w = Box::new(FdWriter {
+                            inner: w,
+                            fd: stream.as_raw_fd(),
+                        });
+                        fds::set_sending(true);
+                    }
+                    if idle > Duration::from_secs(0) || read > Duration::from_secs(0) {
+                        r = Box::new(TimeoutReader {
+                            inner: r,
+                            fd: stream.as_raw_fd(),
+                            idle,
+                            read,
+                            idle_now: true,
+                            current: None,
+                        });
+                    }
+                }
+                #[cfg(windows)]
+                let _ = timeouts;
+                let budget = match connection_memory_budget {
+                    0 => None,
+                    limit => Some(Arc::new(MemoryBudget::new(limit))),
+                };
+                if let Some(ref budget) = budget {
+                    r = Box::new(BudgetReader {
+                        inner: r,
+                        budget: budget.clone(),
+                    });
+                    w = Box::new(BudgetWriter {
+                        inner: w,
+                        budget: budget.clone(),
+                    });
+                }
+                let mut br = BufReader::new(r);
+                let mut iface: Option<String> = None;
+                loop {
+                    match handler.handle(&mut br, &mut w, iface.clone()) {
+                        Ok((_, i)) => {
+                            iface = i;
+                            match br.fill_buf() {
+                                Err(_) => break,
+                                Ok([]) => break,
+                                _ => {}
                             }
                         }
-                        let _ = stream.shutdown();
-                        break;
+                        Err(err) => {
+                            let exceeded = match budget {
+                                Some(ref b) => b.exceeded.load(Ordering::SeqCst),
+                                None => false,
+                            };
+                            match err.kind() {
+                                ErrorKind::ConnectionClosed
+                                | ErrorKind::SerdeJsonDe(_)
+                                | ErrorKind::MessageTooLarge(_)
+                                | ErrorKind::Shutdown
+                                | ErrorKind::Io(io::ErrorKind::TimedOut)
+                                | ErrorKind::Io(io::ErrorKind::WouldBlock) => {
+                                    event!(debug, "connection {}: {}", id, err)
+                                }
+                                _ if exceeded => {
+                                    event!(debug, "connection {}: memory budget exceeded", id)
+                                }
+                                _ => {
+                                    #[cfg(feature = "log")]
+                                    event!(error, "connection {}: {:?}", id, err);
+                                    #[cfg(not(feature = "log"))]
+                                    eprintln!("Worker error: {:?}", err);
+                                }
+                            }
+                            let _ = stream.shutdown();
+                            break;
+                        }
                     }
                 }
+            }));
+            if served.is_err() {
+                let _ = stream.shutdown();
+            }
+            connections.lock().unwrap().remove(&id);
+            event!(debug, "closed connection {}", id);
+            if let Some(ref m) = metrics {
+                m.metrics.active_connections.fetch_sub(1, Ordering::SeqCst);
             }
+            CURRENT_HANDLE.with(|h| *h.borrow_mut() = None);
+            auth::set_current_peer(None);
+            #[cfg(unix)]
+            {
+                set_current_fd(None);
+                fds::set_sending(false);
+            }
+            open.close();
+            if let Err(payload) = served {
+                report_panic(panic_handler.as_ref(), payload.as_ref());
+            }
+        }));
+    }
+}
+
+/// Returns `true`, if stdin is a connected socket.
+///
+/// This is the case for a service spawned per connection by an inetd style activator,
+/// e.g. systemd with `Accept=yes` and `StandardInput=socket`. Such a service
+/// serves its only connection with [`handle_stdio`](fn.handle_stdio.html).
+#[cfg(unix)]
+pub fn stdio_is_connection() -> bool {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(0, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return false;
+    }
+    let mut listening: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            0,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut listening as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    ret == 0 && listening == 0
+}
+
+/// Returns `true`, if stdin is a connected socket.
+#[cfg(windows)]
+pub fn stdio_is_connection() -> bool {
+    false
+}
+
+/// Serves a single connection, which is read from stdin and written to stdout.
+///
+/// For a service, which is launched per connection, e.g. by an inetd style activator
+/// (see [`stdio_is_connection`]) or by a client with the `bridge:` transport, instead
+/// of running as a daemon. Returns, when the client closed the connection.
+///
+///# Examples
+///
+///```no_run
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![/* Your varlink interfaces go here */],
+/// );
+///
+/// if varlink::stdio_is_connection() {
+///     varlink::handle_stdio(service).unwrap();
+/// } else {
+///     varlink::listen(service, "unix:/run/org.example.service", &Default::default()).unwrap();
+/// }
+///```
+///
+/// [`stdio_is_connection`]: fn.stdio_is_connection.html
+pub fn handle_stdio<H: crate::ConnectionHandler>(handler: H) -> Result<()> {
+    #[cfg(unix)]
+    let peer = if stdio_is_connection() {
+        // borrows stdin, which must not be closed on drop
+        let stream = mem::ManuallyDrop::new(unsafe { UnixStream::from_raw_fd(0) });
+        set_current_fd(Some(0));
+        PeerInfo::new(&*stream, None)
+    } else {
+        PeerInfo::default()
+    };
+    #[cfg(windows)]
+    let peer = PeerInfo::default();
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    auth::set_current_peer(Some(peer));
+    let ret = handle_connection(&handler, &mut stdin.lock(), &mut stdout.lock());
+    auth::set_current_peer(None);
+    #[cfg(unix)]
+    set_current_fd(None);
+    ret
+}
+
+/// Handles the messages of `reader` until the end of the stream.
+pub(crate) fn handle_connection<H: crate::ConnectionHandler>(
+    handler: &H,
+    reader: &mut dyn BufRead,
+    writer: &mut dyn Write,
+) -> Result<()> {
+    let mut iface: Option<String> = None;
+    loop {
+        match handler.handle(reader, writer, iface.clone()) {
+            Ok((_, i)) => {
+                iface = i;
+                if reader.fill_buf().map_err(map_context!())?.is_empty() {
+                    return Ok(());
+                }
+            }
+            Err(ref e) if *e.kind() == ErrorKind::ConnectionClosed => return Ok(()),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A connection served by [`listen_single_threaded`](fn.listen_single_threaded.html)
+#[cfg(unix)]
+struct EventConnection {
+    id: usize,
+    stream: Box<dyn Stream>,
+    peer: PeerInfo,
+    /// received data, which is not handled yet
+    input: Vec<u8>,
+    /// replies, which are not sent yet
+    output: Vec<u8>,
+    iface: Option<String>,
+    /// the client closed its side of the connection
+    eof: bool,
+    last_activity: Instant,
+    _tracked: Tracked,
+}
+
+#[cfg(unix)]
+impl EventConnection {
+    /// Reads the available data, returns `false`, if the connection failed.
+    fn read(&mut self) -> bool {
+        let mut buf = [0u8; 8192];
+        match self.stream.read(&mut buf) {
+            Ok(0) => self.eof = true,
+            Ok(n) => {
+                self.input.extend_from_slice(&buf[..n]);
+                self.last_activity = Instant::now();
+            }
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+        true
+    }
+
+    /// Writes as much of the replies as possible, returns `false`, if the connection failed.
+    fn write(&mut self) -> bool {
+        while !self.output.is_empty() {
+            match self.stream.write(&self.output) {
+                Ok(0) => return false,
+                Ok(n) => {
+                    self.output.drain(..n);
+                    self.last_activity = Instant::now();
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Handles the complete requests received so far, returns `false` on an error.
+    fn handle<H: crate::ConnectionHandler>(&mut self, handler: &H) -> bool {
+        // an incomplete request is passed on only, if it is already too large
+        let max = handler.max_message_size();
+        if self.input.is_empty()
+            || (self.iface.is_none()
+                && !self.input.contains(&0)
+                && (max == 0 || self.input.len() <= max))
+        {
+            return true;
+        }
+        let input = self.input.split_off(0);
+        let mut rest = input.as_slice();
+        auth::set_current_peer(Some(self.peer.clone()));
+        set_current_fd(Some(self.stream.as_raw_fd()));
+        let ret = handler.handle(&mut rest, &mut self.output, self.iface.clone());
+        set_current_fd(None);
+        auth::set_current_peer(None);
+        match ret {
+            Ok((unread, iface)) => {
+                // an upgrade leaves the data after its request in `rest`
+                self.input = unread;
+                self.input.extend_from_slice(rest);
+                self.iface = iface;
+                true
+            }
+            Err(err) => {
+                match err.kind() {
+                    ErrorKind::ConnectionClosed
+                    | ErrorKind::SerdeJsonDe(_)
+                    | ErrorKind::Shutdown => {
+                        event!(debug, "connection {}: {}", self.id, err)
+                    }
+                    ErrorKind::MessageTooLarge(_) => {
+                        event!(debug, "connection {}: {}", self.id, err);
+                        // the error reply, before the connection is closed
+                        self.write();
+                    }
+                    _ => {
+                        #[cfg(feature = "log")]
+                        event!(error, "connection {}: {:?}", self.id, err);
+                        #[cfg(not(feature = "log"))]
+                        eprintln!("Worker error: {:?}", err);
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    /// Returns `true`, if the connection exceeded one of its timeouts at `now`.
+    fn timed_out(&self, listen_config: &ListenConfig, now: Instant) -> bool {
+        let timeout = if !self.output.is_empty() {
+            listen_config.connection_write_timeout
+        } else if !self.input.is_empty() || self.iface.is_some() {
+            listen_config.connection_read_timeout
+        } else {
+            listen_config.connection_idle_timeout
+        };
+        timeout > 0 && now - self.last_activity >= Duration::from_secs(timeout)
+    }
+}
+
+/// Serves all connections of `listener` on the calling thread, see `ListenConfig::single_threaded`.
+#[cfg(unix)]
+fn listen_single_threaded<H: crate::ConnectionHandler>(
+    handler: &H,
+    listener: &Listener,
+    listen_config: &ListenConfig,
+) -> Result<()> {
+    use libc::{poll, pollfd, EINTR, POLLERR, POLLHUP, POLLIN, POLLOUT};
+
+    #[cfg(feature = "tls")]
+    {
+        if let Listener::TLS(..) = listener {
+            return Err(context!(ErrorKind::InvalidTlsConfig(
+                "tcp+tls: is not available with ListenConfig::single_threaded".into()
+            )));
+        }
+    }
+
+    let listener_fd = listener
+        .as_raw_fd()
+        .ok_or_else(|| context!(ErrorKind::ConnectionClosed))?;
+    listener.set_nonblocking(true)?;
+
+    let metrics = listen_config.server_handle.as_ref().map(|h| h.0.clone());
+    CURRENT_HANDLE.with(|h| *h.borrow_mut() = listen_config.server_handle.clone());
+
+    let clock: Arc<dyn Clock> = match listen_config.clock {
+        Some(ref clock) => clock.clone(),
+        None => Arc::new(SystemClock::new()),
+    };
+    let idle_timeout = Duration::from_secs(listen_config.idle_timeout);
+    let mut idle_since = clock.now();
+
+    let polling = listen_config.stop_listening.is_some()
+        || listen_config.server_handle.is_some()
+        || listen_config.clock.is_some()
+        || listen_config.connection_idle_timeout > 0
+        || listen_config.connection_read_timeout > 0
+        || listen_config.connection_write_timeout > 0;
+
+    let max_connections = listen_config.max_connections;
+    let mut connections: Vec<EventConnection> = Vec::new();
+    let mut next_id: usize = 0;
+
+    let ret = loop {
+        if is_stopping(listen_config) {
+            break Ok(());
+        }
+        if let Some(stop) = listen_config.stop_listening.as_ref() {
+            if stop.load(Ordering::SeqCst) {
+                break Ok(());
+            }
+        }
+        if !connections.is_empty() {
+            idle_since = clock.now();
+        } else if listen_config.idle_timeout > 0 && clock.now() - idle_since >= idle_timeout {
+            break Err(context!(ErrorKind::Timeout));
+        }
+
+        let full = max_connections > 0 && connections.len() >= max_connections;
+        let accepting = !(full && listen_config.overload_policy == OverloadPolicy::Block);
+        let mut fds: Vec<pollfd> = connections
+            .iter()
+            .map(|c| pollfd {
+                fd: c.stream.as_raw_fd(),
+                events: if c.eof { 0 } else { POLLIN }
+                    | if c.output.is_empty() { 0 } else { POLLOUT },
+                revents: 0,
+            })
+            .collect();
+        fds.push(pollfd {
+            fd: match listen_config.shutdown_token {
+                Some(ref token) => token.wake_fd(),
+                None => -1,
+            },
+            events: POLLIN,
+            revents: 0,
         });
+        fds.push(pollfd {
+            fd: if accepting { listener_fd } else { -1 },
+            events: POLLIN,
+            revents: 0,
+        });
+
+        let wait_time: libc::c_int = if polling {
+            100
+        } else if listen_config.idle_timeout == 0 || !connections.is_empty() {
+            -1
+        } else {
+            let remaining = idle_timeout
+                .checked_sub(clock.now() - idle_since)
+                .unwrap_or_default();
+            // rounded up, and checked at least once a day
+            let ms = remaining.as_secs() * 1000 + u64::from(remaining.subsec_millis()) + 1;
+            ms.min(86_400_000) as libc::c_int
+        };
+        if unsafe { poll(fds.as_mut_ptr(), fds.len() as _, wait_time) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() == Some(EINTR) {
+                continue;
+            }
+            break Err(context!(e, ErrorKind::Io(e.kind())));
+        }
+
+        let now = Instant::now();
+        let mut closed = Vec::new();
+        for (c, fd) in connections.iter_mut().zip(fds.iter()) {
+            let mut alive = true;
+            if fd.revents & (POLLIN | POLLHUP | POLLERR) != 0 && !c.eof {
+                alive = c.read() && c.handle(handler);
+            }
+            if alive && !c.output.is_empty() {
+                alive = c.write();
+            }
+            if alive && listen_config.connection_memory_budget > 0 {
+                alive = c.input.len() + c.output.len() <= listen_config.connection_memory_budget;
+            }
+            if !alive || c.timed_out(listen_config, now) || (c.eof && c.output.is_empty()) {
+                let _ = c.stream.shutdown();
+                event!(debug, "closed connection {}", c.id);
+                if let Some(ref m) = metrics {
+                    m.metrics.active_connections.fetch_sub(1, Ordering::SeqCst);
+                }
+                closed.push(c.id);
+            }
+        }
+        connections.retain(|c| !closed.contains(&c.id));
+
+        if fds[fds.len() - 1].revents & POLLIN == 0 {
+            continue;
+        }
+
+        for _ in 0..listen_config.accept_burst.max(1) {
+            let full = max_connections > 0 && connections.len() >= max_connections;
+            if full && listen_config.overload_policy == OverloadPolicy::Block {
+                break;
+            }
+            let (mut stream, peer) = match listener.accept_with(0, &listen_config.tcp) {
+                Ok(r) => r,
+                Err(e) => match e.kind() {
+                    ErrorKind::Io(io::ErrorKind::WouldBlock) => break,
+                    ErrorKind::Io(io::ErrorKind::Interrupted)
+                    | ErrorKind::Io(io::ErrorKind::ConnectionAborted)
+                    | ErrorKind::ConnectionClosed => continue,
+                    _ => return Err(e),
+                },
+            };
+            if let Some(ref m) = metrics {
+                m.metrics
+                    .accepted_connections
+                    .fetch_add(1, Ordering::SeqCst);
+            }
+
+            let allowed = match peer {
+                Some(peer) => listen_config.ip_allowed(&peer.ip()),
+                None => true,
+            };
+            if !allowed || full || stream.set_nonblocking(true).is_err() {
+                if let Some(ref m) = metrics {
+                    m.metrics
+                        .rejected_connections
+                        .fetch_add(1, Ordering::SeqCst);
+                }
+                event!(debug, "rejected connection from {:?}", peer);
+                if allowed && listen_config.overload_policy == OverloadPolicy::Busy {
+                    reply_busy(stream.as_mut());
+                }
+                let _ = stream.shutdown();
+                continue;
+            }
+
+            event!(debug, "accepted connection {} from {:?}", next_id, peer);
+            if let Some(ref m) = metrics {
+                m.metrics.active_connections.fetch_add(1, Ordering::SeqCst);
+            }
+            connections.push(EventConnection {
+                id: next_id,
+                peer: PeerInfo::new(stream.as_ref(), peer),
+                stream,
+                input: Vec::new(),
+                output: Vec::new(),
+                iface: None,
+                eof: false,
+                last_activity: Instant::now(),
+                _tracked: Tracked::new(Object::Stream),
+            });
+            next_id = next_id.wrapping_add(1);
+        }
+    };
+
+    for c in connections.iter_mut() {
+        let _ = c.stream.shutdown();
+        if let Some(ref m) = metrics {
+            m.metrics.active_connections.fetch_sub(1, Ordering::SeqCst);
+        }
     }
+    CURRENT_HANDLE.with(|h| *h.borrow_mut() = None);
+    ret
+}
+
+#[cfg(windows)]
+fn listen_single_threaded<H: crate::ConnectionHandler>(
+    _handler: &H,
+    _listener: &Listener,
+    _listen_config: &ListenConfig,
+) -> Result<()> {
+    Err(context!(ErrorKind::MethodNotImplemented(
+        "ListenConfig::single_threaded".into()
+    )))
 }
@@ -0,0 +1,373 @@
+//! Per method statistics and slow call logging for varlink services
+//!
+//! [`wrap`] puts a varlink interface behind a [`CallStats`] collector, which records
+//! the request size, reply size and duration of every call in histograms per method.
+//! Calls exceeding the configured thresholds are logged with their parameters,
+//! without the values of the fields annotated with `# @sensitive`, see [`redact`].
+//!
+//! # Examples
+//!
+//! ```rust,ignore
+//! let stats = Arc::new(
+//!     varlink::stats::CallStats::new()
+//!         .slow_call_threshold(Duration::from_millis(100))
+//!         .redact(|_method, _parameters| json!("<redacted>")),
+//! );
+//!
+//! let service = varlink::VarlinkService::new(
+//!     "org.varlink",
+//!     "test service",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     vec![varlink::stats::wrap(stats.clone(), Box::new(myinterface))],
+//! );
+//!
+//! // later
+//! for (method, s) in stats.snapshot() {
+//!     println!("{}: p99 {}µs", method, s.duration_us.percentile(0.99));
+//! }
+//! ```
+//!
+//! [`wrap`]: fn.wrap.html
+//! [`CallStats`]: struct.CallStats.html
+//! [`redact`]: ../redact/index.html
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_derive::Serialize;
+use serde_json::Value;
+
+use crate::clock::{Clock, SystemClock};
+use crate::monitor::Direction;
+use crate::redact::Redactor;
+use crate::{Call, Interface, Request, Result};
+
+/// A histogram with power of two buckets
+///
+/// Bucket `i` counts the values `v` with `2^(i-1) <= v < 2^i`, bucket `0` counts zeros.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: vec![0; 65],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let i = (64 - value.leading_zeros()) as usize;
+        self.buckets[i] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        if value > self.max {
+            self.max = value;
+        }
+    }
+
+    /// Adds the values recorded in `other`.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.max = self.max.max(other.max);
+    }
+
+    /// The number of recorded values
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The largest recorded value
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// The mean of all recorded values
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// Returns an upper bound for the `p` percentile, with `p` between `0.0` and `1.0`.
+    ///
+    /// The bound is the upper limit of the bucket containing the percentile, but never
+    /// more than the largest recorded value.
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let upper = match i {
+                    0 => 0,
+                    64 => self.max,
+                    i => (1u64 << i) - 1,
+                };
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// The statistics of a single method
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodStats {
+    /// Duration of the calls in microseconds
+    pub duration_us: Histogram,
+    /// Size of the requests in bytes
+    pub request_size: Histogram,
+    /// Size of all replies of a call in bytes
+    pub reply_size: Histogram,
+}
+
+type RedactFn = Box<dyn Fn(&str, &Value) -> Value + Send + Sync>;
+type Logger = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Collects the statistics of the interfaces wrapped with [`wrap`](fn.wrap.html)
+pub struct CallStats {
+    methods: Mutex<HashMap<String, MethodStats>>,
+    slow_call: Option<Duration>,
+    large_message: Option<usize>,
+    redactor: Option<RedactFn>,
+    logger: Logger,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for CallStats {
+    fn default() -> Self {
+        CallStats {
+            methods: Mutex::new(HashMap::new()),
+            slow_call: None,
+            large_message: None,
+            redactor: None,
+            logger: Box::new(|msg| eprintln!("{}", msg)),
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+}
+
+impl CallStats {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Log calls, which take longer than `threshold`.
+    pub fn slow_call_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_call = Some(threshold);
+        self
+    }
+
+    /// Log calls with a request or reply larger than `bytes`.
+    pub fn large_message_threshold(mut self, bytes: usize) -> Self {
+        self.large_message = Some(bytes);
+        self
+    }
+
+    /// Replace the parameters of a logged call with the value returned by `f`.
+    ///
+    /// `f` is called with the method name and the call parameters, in which the
+    /// sensitive fields are already redacted.
+    pub fn redact<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str, &Value) -> Value + Send + Sync + 'static,
+    {
+        self.redactor = Some(Box::new(f));
+        self
+    }
+
+    /// Write the log messages with `f` instead of printing them to stderr.
+    pub fn logger<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.logger = Box::new(f);
+        self
+    }
+
+    /// Measure the call durations with `clock` instead of the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns a copy of the statistics collected so far, sorted by method name.
+    pub fn snapshot(&self) -> BTreeMap<String, MethodStats> {
+        self.methods
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    /// Forget all statistics collected so far.
+    pub fn reset(&self) {
+        self.methods.lock().unwrap().clear();
+    }
+
+    fn record(
+        &self,
+        request: &Request,
+        sensitive: &Redactor,
+        reply_size: usize,
+        duration: Duration,
+    ) {
+        let request_size = serde_json::to_vec(request).map(|v| v.len()).unwrap_or(0);
+        let duration_us = duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros());
+
+        {
+            let mut methods = self.methods.lock().unwrap();
+            let stats = methods.entry(request.method.to_string()).or_default();
+            stats.duration_us.record(duration_us);
+            stats.request_size.record(request_size as u64);
+            stats.reply_size.record(reply_size as u64);
+        }
+
+        let slow = match self.slow_call {
+            Some(t) => duration > t,
+            None => false,
+        };
+        let large = match self.large_message {
+            Some(t) => request_size > t || reply_size > t,
+            None => false,
+        };
+
+        if !(slow || large) {
+            return;
+        }
+
+        let mut parameters = request.parameters.clone().unwrap_or(Value::Null);
+        sensitive.redact_parameters(&request.method, Direction::Request, &mut parameters);
+        let parameters = match self.redactor {
+            Some(ref f) => f(&request.method, &parameters),
+            None => parameters,
+        };
+
+        (self.logger)(&format!(
+            "varlink call {} took {}µs, request {} bytes, reply {} bytes, parameters: {}",
+            request.method, duration_us, request_size, reply_size, parameters
+        ));
+    }
+}
+
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: usize,
+}
+
+impl<'a> Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct StatsInterface {
+    inner: Box<dyn Interface + Send + Sync>,
+    stats: Arc<CallStats>,
+    sensitive: Redactor,
+}
+
+/// Wraps `interface`, so that its calls are recorded in `stats`.
+pub fn wrap(
+    stats: Arc<CallStats>,
+    interface: Box<dyn Interface + Send + Sync>,
+) -> Box<dyn Interface + Send + Sync> {
+    // without a valid description, the parameters are not logged at all
+    let sensitive = Redactor::from_description(interface.get_description()).unwrap_or_default();
+    Box::new(StatsInterface {
+        inner: interface,
+        stats,
+        sensitive,
+    })
+}
+
+impl Interface for StatsInterface {
+    fn get_description(&self) -> &'static str {
+        self.inner.get_description()
+    }
+
+    fn get_name(&self) -> &'static str {
+        self.inner.get_name()
+    }
+
+    fn get_method_names(&self) -> Option<&'static [&'static str]> {
+        self.inner.get_method_names()
+    }
+
+    fn call_upgraded(&self, call: &mut Call, bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+        self.inner.call_upgraded(call, bufreader)
+    }
+
+    fn call(&self, call: &mut Call) -> Result<()> {
+        let request = match call.request {
+            Some(r) => r,
+            None => return self.inner.call(call),
+        };
+
+        let start = self.stats.clock.now();
+        let mut writer = CountingWriter {
+            inner: &mut *call.writer,
+            count: 0,
+        };
+        let mut counted = Call {
+            writer: &mut writer,
+            request: Some(request),
+            continues: call.continues,
+            upgraded: call.upgraded,
+            middleware: call.middleware,
+            validator: call.validator,
+            error: None,
+            finished: call.finished,
+            buffer: std::mem::take(&mut call.buffer),
+            extensions: call.extensions,
+        };
+
+        let ret = self.inner.call(&mut counted);
+
+        let buffer = counted.take_buffer();
+        let (continues, upgraded, error, finished) = (
+            counted.continues,
+            counted.upgraded,
+            counted.error,
+            counted.finished,
+        );
+        let duration = self.stats.clock.now() - start;
+        let reply_size = writer.count;
+        call.continues = continues;
+        call.upgraded = upgraded;
+        call.finished = finished;
+        call.buffer = buffer;
+        if call.error.is_none() {
+            call.error = error;
+        }
+
+        self.stats
+            .record(request, &self.sensitive, reply_size, duration);
+        ret
+    }
+}
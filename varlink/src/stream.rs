@@ -1,13 +1,22 @@
+#[cfg(any(feature = "client", feature = "server"))]
+use std::io;
 use std::io::{Read, Write};
-use std::net::{Shutdown, TcpStream};
+#[cfg(any(feature = "tcp", feature = "unix"))]
+use std::net::Shutdown;
+#[cfg(feature = "tcp")]
+use std::net::TcpStream;
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
-#[cfg(unix)]
+#[cfg(all(feature = "unix", unix))]
 use std::os::unix::net::UnixStream;
 #[cfg(windows)]
 use std::os::windows::io::AsRawSocket;
+#[cfg(feature = "client")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "client")]
+use std::time::Duration;
 
-#[cfg(windows)]
+#[cfg(all(feature = "unix", windows))]
 use uds_windows::UnixStream;
 
 use crate::error::*;
@@ -28,6 +37,43 @@ pub trait Stream: Read + Write + Send + Sync + AsRawSocket {
     fn set_nonblocking(&mut self, b: bool) -> Result<()>;
 }
 
+/// A stream of another transport, e.g. from `Connection::from_stream`
+///
+/// The halves returned by `split()` share the stream, so a read blocks a write
+/// from another thread, until data is received.
+#[cfg(feature = "client")]
+pub(crate) struct SharedStream<S>(Arc<Mutex<S>>);
+
+#[cfg(feature = "client")]
+impl<S: Read + Write + Send + 'static> SharedStream<S> {
+    pub(crate) fn split(stream: S) -> (Box<dyn Read + Send + Sync>, Box<dyn Write + Send + Sync>) {
+        let shared = Arc::new(Mutex::new(stream));
+        (
+            Box::new(SharedStream(shared.clone())),
+            Box::new(SharedStream(shared)),
+        )
+    }
+}
+
+#[cfg(feature = "client")]
+impl<S: Read> Read for SharedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+#[cfg(feature = "client")]
+impl<S: Write> Write for SharedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+#[cfg(feature = "tcp")]
 impl Stream for TcpStream {
     #[inline]
     fn split(&mut self) -> Result<(Box<dyn Read + Send + Sync>, Box<dyn Write + Send + Sync>)> {
@@ -55,6 +101,7 @@ impl Stream for TcpStream {
     }
 }
 
+#[cfg(feature = "unix")]
 impl Stream for UnixStream {
     #[inline]
     fn split(&mut self) -> Result<(Box<dyn Read + Send + Sync>, Box<dyn Write + Send + Sync>)> {
@@ -81,3 +128,149 @@ impl Stream for UnixStream {
         Ok(())
     }
 }
+
+/// Sets the read and write timeout of the socket of `stream`, `None` disables them.
+#[cfg(all(feature = "client", unix))]
+pub(crate) fn set_timeout(stream: &dyn Stream, timeout: Option<Duration>) -> io::Result<()> {
+    // a zero timeval disables the timeout
+    let timeout = match timeout {
+        Some(t) => t.max(Duration::from_micros(1)),
+        None => Duration::from_secs(0),
+    };
+    let tv = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    for option in &[libc::SO_RCVTIMEO, libc::SO_SNDTIMEO] {
+        let ret = unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                *option,
+                &tv as *const libc::timeval as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Sets the read and write timeout of the socket of `stream`, `None` disables them.
+#[cfg(all(feature = "client", windows))]
+pub(crate) fn set_timeout(stream: &dyn Stream, timeout: Option<Duration>) -> io::Result<()> {
+    use winapi::shared::minwindef::DWORD;
+    use winapi::um::winsock2::{setsockopt, SOCKET_ERROR, SOL_SOCKET, SO_RCVTIMEO, SO_SNDTIMEO};
+
+    // zero milliseconds disable the timeout
+    let millis = match timeout {
+        Some(t) => (t.as_secs() * 1000 + u64::from(t.subsec_millis())).max(1) as DWORD,
+        None => 0,
+    };
+    for option in &[SO_RCVTIMEO, SO_SNDTIMEO] {
+        let ret = unsafe {
+            setsockopt(
+                stream.as_raw_socket() as usize,
+                SOL_SOCKET,
+                *option,
+                &millis as *const DWORD as *const i8,
+                std::mem::size_of::<DWORD>() as i32,
+            )
+        };
+        if ret == SOCKET_ERROR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Options of TCP connections, set on the accepted connections by
+/// [`ListenConfig::tcp`] and on the client connections by [`ConnectConfig::tcp`]
+///
+/// [`ListenConfig::tcp`]: struct.ListenConfig.html#structfield.tcp
+/// [`ConnectConfig::tcp`]: struct.ConnectConfig.html#structfield.tcp
+#[cfg(feature = "tcp")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TcpConfig {
+    /// Set `TCP_NODELAY`, so small requests and replies are sent at once, instead of
+    /// being delayed by Nagle's algorithm
+    pub nodelay: bool,
+    /// Set `SO_KEEPALIVE` with the time in seconds, after which an idle connection is
+    /// probed, so a dead peer is detected, `0` keeps the system default time
+    ///
+    /// The time is set on Linux and macOS only.
+    pub keepalive: Option<u64>,
+}
+
+#[cfg(all(feature = "tcp", any(feature = "server", feature = "client")))]
+impl TcpConfig {
+    pub(crate) fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        if let Some(idle) = self.keepalive {
+            set_keepalive(stream, idle)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "tcp", any(feature = "server", feature = "client"), unix))]
+fn set_keepalive(stream: &TcpStream, idle: u64) -> io::Result<()> {
+    let set_option = |level: libc::c_int, option: libc::c_int, value: libc::c_int| {
+        let ret = unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                level,
+                option,
+                &value as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+
+    set_option(libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1)?;
+    if idle > 0 {
+        let idle = idle.min(libc::c_int::MAX as u64) as libc::c_int;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        set_option(libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, idle)?;
+        #[cfg(any(target_os = "macos", target_os = "ios"))]
+        set_option(libc::IPPROTO_TCP, libc::TCP_KEEPALIVE, idle)?;
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        let _ = idle;
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "tcp", any(feature = "server", feature = "client"), windows))]
+fn set_keepalive(stream: &TcpStream, _idle: u64) -> io::Result<()> {
+    use winapi::shared::minwindef::BOOL;
+    use winapi::um::winsock2::{setsockopt, SOCKET_ERROR, SOL_SOCKET, SO_KEEPALIVE};
+
+    let value: BOOL = 1;
+    let ret = unsafe {
+        setsockopt(
+            stream.as_raw_socket() as usize,
+            SOL_SOCKET,
+            SO_KEEPALIVE,
+            &value as *const BOOL as *const i8,
+            std::mem::size_of::<BOOL>() as i32,
+        )
+    };
+    if ret == SOCKET_ERROR {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,210 +1,279 @@
-use crate::*;
-use serde_json::{from_slice, from_value};
-use std::{thread, time};
-
-#[test]
-fn test_listen() -> Result<()> {
-    fn run_app<S: ?Sized + AsRef<str>>(address: &S, timeout: u64) -> Result<()> {
-        let service = VarlinkService::new(
-            "org.varlink",
-            "test service",
-            "0.1",
-            "http://varlink.org",
-            vec![], // Your varlink interfaces go here
-        );
-
-        if let Err(e) = listen(
-            service,
-            &address,
-            &ListenConfig {
-                idle_timeout: timeout,
-                ..Default::default()
-            },
-        ) {
-            if *e.kind() != ErrorKind::Timeout {
-                panic!("Error listen: {:#?}", e);
-            }
-        }
-        Ok(())
-    }
-
-    fn run_client_app<S: ?Sized + AsRef<str>>(address: &S) -> Result<()> {
-        let conn = Connection::new(address)?;
-        let mut call = OrgVarlinkServiceClient::new(conn.clone());
-        {
-            let info = call.get_info()?;
-            assert_eq!(&info.vendor, "org.varlink");
-            assert_eq!(&info.product, "test service");
-            assert_eq!(&info.version, "0.1");
-            assert_eq!(&info.url, "http://varlink.org");
-            assert_eq!(
-                info.interfaces.get(0).unwrap().as_ref(),
-                "org.varlink.service"
-            );
-        }
-        let e = call.get_interface_description("org.varlink.unknown");
-        assert!(e.is_err());
-
-        match e.err().unwrap().kind() {
-            ErrorKind::InvalidParameter(i) => assert_eq!(*i, "interface".to_string()),
-            kind => {
-                panic!("Unknown error {:?}", kind);
-            }
-        }
-
-        let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
-            conn.clone(),
-            "org.varlink.service.GetInfos",
-            GetInfoArgs {},
-        )
-        .call();
-
-        match e.err().unwrap().kind() {
-            ErrorKind::MethodNotFound(i) => {
-                assert_eq!(*i, "org.varlink.service.GetInfos".to_string())
-            }
-            kind => {
-                panic!("Unknown error {:?}", kind);
-            }
-        }
-
-        let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
-            conn.clone(),
-            "org.varlink.unknowninterface.Foo",
-            GetInfoArgs {},
-        )
-        .call();
-
-        match e.err().unwrap().kind() {
-            ErrorKind::InterfaceNotFound(i) => {
-                assert_eq!(*i, "org.varlink.unknowninterface".to_string())
-            }
-            kind => {
-                panic!("Unknown error {:?}", kind);
-            }
-        }
-
-        let description = call.get_interface_description("org.varlink.service")?;
+//! Helpers to test services and clients without real sockets
+//!
+//! [`loopback`] connects a client `Connection` to a service over in-process pipes, so
+//! the handlers of generated interfaces can be tested without binding sockets or
+//! creating files in `/tmp`. [`pair`] returns both ends, to drive the server side of
+//! the connection by hand.
+//!
+//! To test with real sockets, [`run_service`] starts a service on a unique address,
+//! which the clients get from the returned [`TestServer`]. The server is stopped, when
+//! it is dropped.
+//!
+//! # Examples
+//!
+//! ```rust
+//! # fn main() -> varlink::Result<()> {
+//! use varlink::{OrgVarlinkServiceClient, OrgVarlinkServiceInterface, VarlinkService};
+//!
+//! let service = VarlinkService::new(
+//!     "org.varlink",
+//!     "test service",
+//!     "0.1",
+//!     "http://varlink.org",
+//!     vec![],
+//! );
+//! let connection = varlink::test::loopback(service);
+//! let info = OrgVarlinkServiceClient::new(connection).get_info()?;
+//! assert_eq!(info.product, "test service");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`loopback`]: fn.loopback.html
+//! [`pair`]: fn.pair.html
+//! [`run_service`]: fn.run_service.html
+//! [`TestServer`]: struct.TestServer.html
+
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+
+use crate::error::*;
+use crate::{server, Connection, ConnectionHandler, ListenConfig, Listener, ServerHandle};
+
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
 
-        assert_eq!(
-            &description.description.unwrap(),
-            r#"# The Varlink Service Interface is provided by every varlink service. It
-# describes the service and the interfaces it implements.
-interface org.varlink.service
+#[derive(Default)]
+struct Pipe {
+    buffer: Mutex<Buffer>,
+    readable: Condvar,
+}
 
-# Get a list of all the interfaces a service provides and information
-# about the implementation.
-method GetInfo() -> (
-  vendor: string,
-  product: string,
-  version: string,
-  url: string,
-  interfaces: []string
-)
+/// The reading end of an in-process pipe
+pub struct PipeReader(Arc<Pipe>);
 
-# Get the description of an interface that is implemented by this service.
-method GetInterfaceDescription(interface: string) -> (description: string)
+/// The writing end of an in-process pipe
+pub struct PipeWriter(Arc<Pipe>);
 
-# The requested interface was not found.
-error InterfaceNotFound (interface: string)
+/// Returns the ends of an in-process pipe.
+///
+/// A read blocks until data was written, and returns `0` after the writer was
+/// dropped. A write fails with `io::ErrorKind::BrokenPipe` after the reader was dropped.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe::default());
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
 
-# The requested method was not found
-error MethodNotFound (method: string)
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        while buffer.data.is_empty() && !buffer.writer_closed && !buf.is_empty() {
+            buffer = self.0.readable.wait(buffer).unwrap();
+        }
+        let n = buf.len().min(buffer.data.len());
+        for (b, d) in buf.iter_mut().zip(buffer.data.drain(..n)) {
+            *b = d;
+        }
+        Ok(n)
+    }
+}
 
-# The interface defines the requested method, but the service does not
-# implement it.
-error MethodNotImplemented (method: string)
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        buffer.reader_closed = true;
+        buffer.data.clear();
+    }
+}
 
-# One of the passed parameters is invalid.
-error InvalidParameter (parameter: string)
-"#
-        );
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffer = self.0.buffer.lock().unwrap();
+        if buffer.reader_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        buffer.data.extend(buf);
+        self.0.readable.notify_all();
+        Ok(buf.len())
+    }
 
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
+}
 
-    let address = "unix:test_listen_timeout";
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.buffer.lock().unwrap().writer_closed = true;
+        self.0.readable.notify_all();
+    }
+}
 
-    let child = thread::spawn(move || {
-        if let Err(e) = run_app(address, 3) {
-            panic!("error: {}", e);
-        }
-    });
+/// The server end of a connection returned by [`pair`](fn.pair.html)
+pub struct ServerStream {
+    pub reader: PipeReader,
+    pub writer: PipeWriter,
+}
 
-    // give server time to start
-    thread::sleep(time::Duration::from_secs(1));
+impl Read for ServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
 
-    run_client_app(address)?;
+impl Write for ServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
 
-    assert!(child.join().is_ok());
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
 
-    Ok(())
+/// Returns a client connection and the server end of it, connected by in-process pipes.
+///
+/// The [address](../struct.Connection.html#method.address) of the connection is
+/// `loopback`. Dropping one end lets the other end read the end of the stream.
+pub fn pair() -> (Arc<RwLock<Connection>>, ServerStream) {
+    let (client_reader, server_writer) = pipe();
+    let (server_reader, client_writer) = pipe();
+    let connection =
+        Connection::from_split(Box::new(client_reader), Box::new(client_writer), "loopback");
+    let stream = ServerStream {
+        reader: server_reader,
+        writer: server_writer,
+    };
+    (connection, stream)
 }
 
-#[test]
-fn test_handle() -> Result<()> {
-    let service = VarlinkService::new(
-        "org.varlink",
-        "test service",
-        "0.1",
-        "http://varlink.org",
-        vec![],
-    );
+/// Returns a client connection to `handler`, e.g. a `VarlinkService`.
+///
+/// A background thread serves the connection, until it is dropped.
+pub fn loopback<H>(handler: H) -> Arc<RwLock<Connection>>
+where
+    H: ConnectionHandler + Send + 'static,
+{
+    let (connection, stream) = pair();
+    thread::spawn(move || {
+        let ServerStream { reader, mut writer } = stream;
+        let mut reader = BufReader::new(reader);
+        let _ = server::handle_connection(&handler, &mut reader, &mut writer);
+    });
+    connection
+}
 
-    let br = concat!(r#"{"method" : "org.varlink.service.GetInfo"}"#, "\0").as_bytes();
+/// A service started by [`run_service`](fn.run_service.html)
+///
+/// Dropping it stops the service and waits for its thread.
+pub struct TestServer {
+    address: String,
+    handle: ServerHandle,
+    thread: Option<JoinHandle<Result<()>>>,
+}
 
-    let a = br[0..10].to_vec();
-    let b = br[10..20].to_vec();
-    let c = br[20..].to_vec();
+impl TestServer {
+    /// The address of the service, e.g. for `Connection::with_address`
+    pub fn address(&self) -> &str {
+        &self.address
+    }
 
-    let mut w = vec![];
+    /// Connects a new client to the service.
+    pub fn connect(&self) -> Result<Arc<RwLock<Connection>>> {
+        Connection::with_address(&self.address)
+    }
 
-    let mut buf = Vec::<u8>::new();
+    /// The handle of the service, e.g. for its metrics
+    pub fn handle(&self) -> &ServerHandle {
+        &self.handle
+    }
 
-    for mut i in vec![a, b, c] {
-        buf.append(&mut i);
+    /// Stops the service and returns the result of `listen()`.
+    pub fn stop(mut self) -> Result<()> {
+        self.join()
+    }
 
-        match {
-            let mut br = buf.as_slice();
-            service.handle(&mut br, &mut w, None)?
-        } {
-            (_, Some(iface)) => {
-                panic!("Unexpected handle return value {}", iface);
-            }
-            (v, None) => {
-                if v.len() == 0 {
-                    break;
-                }
-                //eprintln!("unhandled: {}", String::from_utf8_lossy(&v));
-                buf.clone_from(&v);
-            }
+    fn join(&mut self) -> Result<()> {
+        self.handle.shutdown();
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(context!(ErrorKind::Generic))),
+            None => Ok(()),
         }
     }
+}
 
-    w.pop();
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
 
-    assert_eq!(
-        w,
-        concat!(
-            r#"{"parameters":{"interfaces":["org.varlink.service"],"product":"test service","#,
-            r#""url":"http://varlink.org","vendor":"org.varlink","version":"0.1"}}"#
+/// Returns a unique address for a test service.
+///
+/// On Linux an abstract unix socket, which leaves no file behind, else TCP on a free port.
+fn test_address() -> String {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        format!(
+            "unix:@org.varlink.test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
         )
-        .as_bytes()
-    );
-
-    let reply = from_slice::<Reply>(&w).unwrap();
-
-    let si = from_value::<ServiceInfo>(reply.parameters.unwrap()).map_err(map_context!())?;
+    } else {
+        "tcp:127.0.0.1:0".into()
+    }
+}
 
-    assert_eq!(
-        si,
-        ServiceInfo {
-            vendor: "org.varlink".into(),
-            product: "test service".into(),
-            version: "0.1".into(),
-            url: "http://varlink.org".into(),
-            interfaces: vec!["org.varlink.service".into()],
-        }
-    );
-    Ok(())
+/// Starts `handler`, e.g. a `VarlinkService`, on a unique address in a background thread.
+///
+/// The service is listening, when this returns, so clients can connect to
+/// [`TestServer::address`](struct.TestServer.html#method.address) without waiting.
+///
+/// # Examples
+///
+/// ```rust
+/// # fn main() -> varlink::Result<()> {
+/// use varlink::{OrgVarlinkServiceClient, OrgVarlinkServiceInterface, VarlinkService};
+///
+/// let service = VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     vec![],
+/// );
+/// let server = varlink::test::run_service(service)?;
+/// let info = OrgVarlinkServiceClient::new(server.connect()?).get_info()?;
+/// assert_eq!(info.product, "test service");
+/// server.stop()
+/// # }
+/// ```
+pub fn run_service<H>(handler: H) -> Result<TestServer>
+where
+    H: ConnectionHandler + Send + Sync + 'static,
+{
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let listener = Listener::new_with_config(&test_address(), &config)?;
+    let address = listener.local_address()?;
+    let thread = thread::spawn(move || server::listen_on(handler, listener, &config));
+    Ok(TestServer {
+        address,
+        handle,
+        thread: Some(thread),
+    })
 }
@@ -0,0 +1,4480 @@
+use crate::*;
+use serde_json::{from_slice, from_value};
+use std::{thread, time};
+
+#[test]
+fn test_listen() -> Result<()> {
+    fn run_app<S: ?Sized + AsRef<str>>(address: &S, timeout: u64) -> Result<()> {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![], // Your varlink interfaces go here
+        );
+
+        if let Err(e) = listen(
+            service,
+            &address,
+            &ListenConfig {
+                idle_timeout: timeout,
+                ..Default::default()
+            },
+        ) {
+            if *e.kind() != ErrorKind::Timeout {
+                panic!("Error listen: {:#?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    fn run_client_app<S: ?Sized + AsRef<str>>(address: &S) -> Result<()> {
+        let conn = Connection::new(address)?;
+        let mut call = OrgVarlinkServiceClient::new(conn.clone());
+        {
+            let info = call.get_info()?;
+            assert_eq!(&info.vendor, "org.varlink");
+            assert_eq!(&info.product, "test service");
+            assert_eq!(&info.version, "0.1");
+            assert_eq!(&info.url, "http://varlink.org");
+            assert_eq!(
+                info.interfaces.first().unwrap().as_ref(),
+                "org.varlink.service"
+            );
+        }
+        let e = call.get_interface_description("org.varlink.unknown");
+        assert!(e.is_err());
+
+        match e.err().unwrap().kind() {
+            ErrorKind::InvalidParameter(i) => assert_eq!(*i, "interface".to_string()),
+            kind => {
+                panic!("Unknown error {:?}", kind);
+            }
+        }
+
+        let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+            conn.clone(),
+            "org.varlink.service.GetInfos",
+            GetInfoArgs {},
+        )
+        .call();
+
+        match e.err().unwrap().kind() {
+            ErrorKind::MethodNotFound(i) => {
+                assert_eq!(*i, "org.varlink.service.GetInfos".to_string())
+            }
+            kind => {
+                panic!("Unknown error {:?}", kind);
+            }
+        }
+
+        let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+            conn.clone(),
+            "org.varlink.unknowninterface.Foo",
+            GetInfoArgs {},
+        )
+        .call();
+
+        match e.err().unwrap().kind() {
+            ErrorKind::InterfaceNotFound(i) => {
+                assert_eq!(*i, "org.varlink.unknowninterface".to_string())
+            }
+            kind => {
+                panic!("Unknown error {:?}", kind);
+            }
+        }
+
+        let description = call.get_interface_description("org.varlink.service")?;
+
+        assert_eq!(
+            &description.description.unwrap(),
+            r#"# The Varlink Service Interface is provided by every varlink service. It
+# describes the service and the interfaces it implements.
+interface org.varlink.service
+
+# Get a list of all the interfaces a service provides and information
+# about the implementation.
+method GetInfo() -> (
+  vendor: string,
+  product: string,
+  version: string,
+  url: string,
+  interfaces: []string
+)
+
+# Get the description of an interface that is implemented by this service.
+method GetInterfaceDescription(interface: string) -> (description: string)
+
+# The requested interface was not found.
+error InterfaceNotFound (interface: string)
+
+# The requested method was not found
+error MethodNotFound (method: string)
+
+# The interface defines the requested method, but the service does not
+# implement it.
+error MethodNotImplemented (method: string)
+
+# One of the passed parameters is invalid.
+error InvalidParameter (parameter: string)
+
+# Client is denied access
+error PermissionDenied ()
+
+# The method expects to be called with more
+error ExpectedMore ()
+"#
+        );
+
+        Ok(())
+    }
+
+    let address = "unix:test_listen_timeout";
+
+    let child = thread::spawn(move || {
+        if let Err(e) = run_app(address, 3) {
+            panic!("error: {}", e);
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_secs(1));
+
+    run_client_app(address)?;
+
+    assert!(child.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_handle() -> Result<()> {
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    );
+
+    let br = concat!(r#"{"method" : "org.varlink.service.GetInfo"}"#, "\0").as_bytes();
+
+    let a = br[0..10].to_vec();
+    let b = br[10..20].to_vec();
+    let c = br[20..].to_vec();
+
+    let mut w = vec![];
+
+    let mut buf = Vec::<u8>::new();
+
+    for mut i in [a, b, c] {
+        buf.append(&mut i);
+
+        let mut br = buf.as_slice();
+        let handled = service.handle(&mut br, &mut w, None)?;
+        match handled {
+            (_, Some(iface)) => {
+                panic!("Unexpected handle return value {}", iface);
+            }
+            (v, None) => {
+                if v.is_empty() {
+                    break;
+                }
+                //eprintln!("unhandled: {}", String::from_utf8_lossy(&v));
+                buf.clone_from(&v);
+            }
+        }
+    }
+
+    w.pop();
+
+    assert_eq!(
+        w,
+        concat!(
+            r#"{"parameters":{"interfaces":["org.varlink.service"],"product":"test service","#,
+            r#""url":"http://varlink.org","vendor":"org.varlink","version":"0.1"}}"#
+        )
+        .as_bytes()
+    );
+
+    let reply = from_slice::<Reply>(&w).unwrap();
+
+    let si = from_value::<ServiceInfo>(reply.parameters.unwrap()).map_err(map_context!())?;
+
+    assert_eq!(
+        si,
+        ServiceInfo {
+            vendor: "org.varlink".into(),
+            product: "test service".into(),
+            version: "0.1".into(),
+            url: "http://varlink.org".into(),
+            interfaces: vec!["org.varlink.service".into()],
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn test_middleware() -> Result<()> {
+    use std::sync::Mutex;
+
+    struct Log(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Log {
+        fn on_request(&self, request: &mut Request) -> Option<Reply> {
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{} request {}", self.0, request.method));
+            None
+        }
+
+        fn on_reply(&self, request: &Request, reply: &Reply) {
+            self.1.lock().unwrap().push(format!(
+                "{} reply {} {:?}",
+                self.0, request.method, reply.error
+            ));
+        }
+    }
+
+    /// Renames `Info` and answers `org.example.blocked` itself
+    struct Rewrite;
+
+    impl Middleware for Rewrite {
+        fn on_request(&self, request: &mut Request) -> Option<Reply> {
+            if request.method == "org.varlink.service.Info" {
+                request.method = "org.varlink.service.GetInfo".into();
+            }
+            if request.method.starts_with("org.example.blocked.") {
+                return Some(Reply::error("org.example.blocked.Blocked", None));
+            }
+            None
+        }
+    }
+
+    let log: Arc<Mutex<Vec<String>>> = Default::default();
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    )
+    .with_middleware(Log("a", log.clone()))
+    .with_middleware(Rewrite)
+    .with_middleware(Log("b", log.clone()));
+
+    let mut w = vec![];
+    let mut br = concat!(
+        r#"{"method":"org.varlink.service.Info"}"#,
+        "\0",
+        r#"{"method":"org.example.blocked.Call"}"#,
+        "\0",
+        r#"{"method":"org.example.blocked.Call","oneway":true}"#,
+        "\0"
+    )
+    .as_bytes();
+    service.handle(&mut br, &mut w, None)?;
+
+    let replies: Vec<Reply> = w
+        .split(|b| *b == 0)
+        .filter(|r| !r.is_empty())
+        .map(|r| from_slice(r).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 2);
+    assert_eq!(replies[0].error, None);
+    assert_eq!(replies[1].error, Some("org.example.blocked.Blocked".into()));
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "a request org.varlink.service.Info",
+            "b request org.varlink.service.GetInfo",
+            "b reply org.varlink.service.GetInfo None",
+            "a reply org.varlink.service.GetInfo None",
+            "a request org.example.blocked.Call",
+            "b reply org.example.blocked.Call Some(\"org.example.blocked.Blocked\")",
+            "a reply org.example.blocked.Call Some(\"org.example.blocked.Blocked\")",
+            "a request org.example.blocked.Call",
+        ]
+    );
+    Ok(())
+}
+
+/// Runs `f` to completion on the current thread.
+fn block_on<F: std::future::Future>(mut f: F) -> F::Output {
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    unsafe fn clone(t: *const ()) -> RawWaker {
+        RawWaker::new(t, &VTABLE)
+    }
+    unsafe fn wake(t: *const ()) {
+        (*(t as *const thread::Thread)).unpark();
+    }
+    unsafe fn drop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+    let current = thread::current();
+    let waker =
+        unsafe { Waker::from_raw(RawWaker::new(&current as *const _ as *const (), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut f = unsafe { Pin::new_unchecked(&mut f) };
+    loop {
+        if let Poll::Ready(v) = f.as_mut().poll(&mut cx) {
+            return v;
+        }
+        thread::park();
+    }
+}
+
+#[test]
+fn test_asyncio() -> Result<()> {
+    let address = "unix:test_asyncio";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        if let Err(e) = listen(
+            service,
+            &address,
+            &ListenConfig {
+                idle_timeout: 1,
+                ..Default::default()
+            },
+        ) {
+            if *e.kind() != ErrorKind::Timeout {
+                panic!("Error listen: {:#?}", e);
+            }
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_secs(1));
+
+    let client = asyncio::wrap_blocking(OrgVarlinkServiceClient::new(Connection::new(address)?));
+
+    let info = block_on(client.call(|c| c.get_info()))??;
+    assert_eq!(&info.product, "test service");
+
+    let e: Result<()> = block_on(client.call(|_| panic!("client thread panics")));
+    assert_eq!(*e.err().unwrap().kind(), ErrorKind::ConnectionClosed);
+
+    let e = block_on(client.call(|c| c.get_info()));
+    assert_eq!(*e.err().unwrap().kind(), ErrorKind::ConnectionClosed);
+
+    drop(client);
+    assert!(child.join().is_ok());
+
+    // the calls of a client run in order, the other clients don't wait for them
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let blocked = asyncio::wrap_blocking(Vec::new());
+    let first = blocked.call(move |v: &mut Vec<i32>| {
+        receiver.recv().unwrap();
+        v.push(1)
+    });
+    let second = blocked.call(|v: &mut Vec<i32>| {
+        v.push(2);
+        v.clone()
+    });
+
+    let other = asyncio::wrap_blocking(0);
+    assert_eq!(block_on(other.call(|n: &mut i32| *n + 1))?, 1);
+
+    sender.send(()).unwrap();
+    block_on(first)?;
+    assert_eq!(block_on(second)?, vec![1, 2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_listen_ip_filter() -> Result<()> {
+    let range: IpRange = "10.1.0.0/16".parse()?;
+    assert!(range.contains(&"10.1.2.3".parse().unwrap()));
+    assert!(range.contains(&"::ffff:10.1.2.3".parse().unwrap()));
+    assert!(!range.contains(&"10.2.0.1".parse().unwrap()));
+    assert!(!range.contains(&"::1".parse().unwrap()));
+    assert!("::/0"
+        .parse::<IpRange>()?
+        .contains(&"fe80::1".parse().unwrap()));
+    assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    assert!("localhost".parse::<IpRange>().is_err());
+
+    let address = "tcp:127.0.0.1:23456";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+
+        if let Err(e) = listen(
+            service,
+            &address,
+            &ListenConfig {
+                idle_timeout: 1,
+                allow_ips: vec!["127.0.0.0/8".parse().unwrap()],
+                deny_ips: vec!["127.0.0.1".parse().unwrap()],
+                ..Default::default()
+            },
+        ) {
+            if *e.kind() != ErrorKind::Timeout {
+                panic!("Error listen: {:#?}", e);
+            }
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let conn = Connection::new(address)?;
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    assert!(call.get_info().is_err());
+
+    assert!(child.join().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_stats() -> Result<()> {
+    use std::io::BufRead;
+    use std::sync::{Arc, Mutex};
+
+    struct Echo(Arc<clock::MockClock>);
+
+    impl Interface for Echo {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.echo\nmethod Echo(word: string) -> (word: string)"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.echo"
+        }
+
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            let parameters = call.request.unwrap().parameters.clone();
+            if call.request.unwrap().method == "org.example.echo.Slow" {
+                self.0.advance(time::Duration::from_millis(20));
+            }
+            call.reply_parameters(parameters.unwrap())
+        }
+    }
+
+    let clock = Arc::new(clock::MockClock::new());
+    let log = Arc::new(Mutex::new(Vec::<String>::new()));
+    let log2 = log.clone();
+    let stats = Arc::new(
+        stats::CallStats::new()
+            .slow_call_threshold(time::Duration::from_millis(10))
+            .redact(|_, _| serde_json::json!("<redacted>"))
+            .logger(move |msg| log2.lock().unwrap().push(msg.into()))
+            .clock(clock.clone()),
+    );
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![stats::wrap(stats.clone(), Box::new(Echo(clock)))],
+    );
+
+    let mut w = vec![];
+    let mut br = concat!(
+        r#"{"method":"org.example.echo.Echo","parameters":{"word":"a"}}"#,
+        "\0",
+        r#"{"method":"org.example.echo.Echo","parameters":{"word":"bb"}}"#,
+        "\0",
+        r#"{"method":"org.example.echo.Slow","parameters":{"word":"secret"}}"#,
+        "\0"
+    )
+    .as_bytes();
+    service.handle(&mut br, &mut w, None)?;
+
+    assert_eq!(
+        String::from_utf8_lossy(&w),
+        concat!(
+            r#"{"parameters":{"word":"a"}}"#,
+            "\0",
+            r#"{"parameters":{"word":"bb"}}"#,
+            "\0",
+            r#"{"parameters":{"word":"secret"}}"#,
+            "\0"
+        )
+    );
+
+    let snapshot = stats.snapshot();
+    let echo = &snapshot["org.example.echo.Echo"];
+    assert_eq!(echo.duration_us.count(), 2);
+    assert_eq!(echo.duration_us.max(), 0);
+    assert_eq!(echo.reply_size.max(), 29);
+    assert_eq!(echo.reply_size.mean(), 28);
+    assert_eq!(echo.reply_size.percentile(0.99), 29);
+    assert_eq!(snapshot["org.example.echo.Slow"].duration_us.max(), 20_000);
+
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 1);
+    assert!(log[0].starts_with("varlink call org.example.echo.Slow took 20000µs"));
+    assert!(log[0].ends_with("parameters: \"<redacted>\""));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_socket_options() -> Result<()> {
+    let config = ListenConfig {
+        reuse_port: true,
+        ..Default::default()
+    };
+    let first = Listener::new_with_config("tcp:127.0.0.1:0", &config)?;
+    let address = first.local_address()?;
+
+    // a second socket can share the port only with SO_REUSEPORT
+    assert!(Listener::new(&address).is_err());
+    let _second = Listener::new_with_config(&address, &config)?;
+
+    // a dual stack socket accepts IPv4 connections, if the host has IPv6
+    if let Ok(listener) = Listener::new_with_config(
+        "tcp:[::]:0",
+        &ListenConfig {
+            only_v6: Some(false),
+            ..Default::default()
+        },
+    ) {
+        let address = listener.local_address()?;
+        let port = &address[address.rfind(':').unwrap()..];
+        std::net::TcpStream::connect(format!("127.0.0.1{}", port)).map_err(map_context!())?;
+        assert!(listener.accept(1).is_ok());
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_tcp_config() -> Result<()> {
+    fn get_option(fd: std::os::unix::io::RawFd, level: libc::c_int, option: libc::c_int) -> i32 {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                level,
+                option,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        value
+    }
+
+    let tcp = TcpConfig {
+        nodelay: true,
+        keepalive: Some(30),
+    };
+    let listener = Listener::new("tcp:127.0.0.1:0")?;
+    let address = listener.local_address()?;
+
+    let connection = Connection::with_config(
+        &address,
+        &ConnectConfig {
+            tcp,
+            ..Default::default()
+        },
+    )?;
+    let (server, _) = listener.accept_with(1000, &tcp)?;
+
+    for fd in &[
+        server.as_raw_fd(),
+        connection
+            .read()
+            .unwrap()
+            .stream
+            .as_ref()
+            .unwrap()
+            .as_raw_fd(),
+    ] {
+        assert_ne!(get_option(*fd, libc::IPPROTO_TCP, libc::TCP_NODELAY), 0);
+        assert_ne!(get_option(*fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        assert_eq!(get_option(*fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 30);
+    }
+
+    // the options are off by default
+    let plain = Connection::with_address(&address)?;
+    let _ = listener.accept(1000)?;
+    let fd = plain.read().unwrap().stream.as_ref().unwrap().as_raw_fd();
+    assert_eq!(get_option(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY), 0);
+    assert_eq!(get_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE), 0);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_socket_mode() -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let dir = tempfile::tempdir().map_err(map_context!())?;
+    let path = dir.path().join("socket");
+    let address = format!("unix:{};mode=0600", path.display());
+    let server_address = address.clone();
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+
+        if let Err(e) = listen(
+            service,
+            &server_address,
+            &ListenConfig {
+                idle_timeout: 1,
+                socket_mode: Some(0o666),
+                ..Default::default()
+            },
+        ) {
+            if *e.kind() != ErrorKind::Timeout {
+                panic!("Error listen: {:#?}", e);
+            }
+        }
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let meta = std::fs::metadata(&path).map_err(map_context!())?;
+    assert!(meta.file_type().is_socket());
+    assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+    // only the socket is left in the directory
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    {
+        let conn = Connection::new(&address)?;
+        let mut call = OrgVarlinkServiceClient::new(conn);
+        assert_eq!(call.get_info()?.product, "test service");
+    }
+
+    assert!(child.join().is_ok());
+    assert!(!path.exists());
+
+    assert!(Listener::new("unix:/tmp/foo;mode=abc").is_err());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_socket_group() -> Result<()> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = tempfile::tempdir().map_err(map_context!())?;
+    let path = dir.path().join("socket");
+    let gid = unsafe { libc::getegid() };
+
+    // the group of the address takes precedence
+    let config = ListenConfig {
+        socket_group: Some("no-such-group-of-varlink".into()),
+        ..Default::default()
+    };
+    let address = format!("unix:{};group={};mode=0660", path.display(), gid);
+    let listener = Listener::new_with_config(&address, &config)?;
+    let meta = std::fs::metadata(&path).map_err(map_context!())?;
+    assert_eq!(meta.gid(), gid);
+    assert_eq!(meta.permissions().mode() & 0o777, 0o660);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    drop(listener);
+
+    // a group name without a mode keeps the default permissions
+    let entry = unsafe { libc::getgrgid(gid) };
+    if !entry.is_null() {
+        let name = unsafe { std::ffi::CStr::from_ptr((*entry).gr_name) }
+            .to_string_lossy()
+            .to_string();
+        let config = ListenConfig {
+            socket_group: Some(name),
+            ..Default::default()
+        };
+        let _listener = Listener::new_with_config(&format!("unix:{}", path.display()), &config)?;
+        assert_eq!(std::fs::metadata(&path).map_err(map_context!())?.gid(), gid);
+    }
+
+    let address = format!("unix:{};group=no-such-group-of-varlink", path.display());
+    assert_eq!(
+        *Listener::new(&address).unwrap_err().kind(),
+        ErrorKind::InvalidAddress
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_server_handle() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_server_handle_shutdown";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, &address, &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    // an idle connection must not keep the server running
+    let conn = Connection::new(address)?;
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    assert_eq!(call.get_info()?.product, "test service");
+
+    handle.shutdown();
+    assert!(handle.is_shutdown());
+    assert!(child.join().unwrap().is_ok());
+    assert!(!std::path::Path::new("test_server_handle_shutdown").exists());
+    assert!(call.get_info().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_yield_point() -> Result<()> {
+    use std::io::BufRead;
+
+    struct Monitor;
+
+    impl Interface for Monitor {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.monitor\nmethod Monitor() -> (tick: int)"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.monitor"
+        }
+
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            call.set_continues(true);
+            for tick in 0.. {
+                call.yield_point()?;
+                call.reply_struct(Reply::parameters(Some(serde_json::json!({ "tick": tick }))))?;
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            Ok(())
+        }
+    }
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_yield_point";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![Box::new(Monitor)],
+        );
+        listen(service, &address, &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let conn = Connection::new(address)?;
+    let mut call = MethodCall::<GetInfoArgs, serde_json::Value, Error>::new(
+        conn,
+        "org.example.monitor.Monitor",
+        GetInfoArgs {},
+    );
+    let replies = call.more()?;
+    assert_eq!(replies.next().unwrap()?["tick"], 0);
+    assert_eq!(replies.next().unwrap()?["tick"], 1);
+
+    // the streaming handler must not block the shutdown
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_thread_pool_grows_and_shrinks() {
+    use crate::server::ThreadPool;
+    use std::sync::{Arc, Barrier};
+
+    let pool = ThreadPool::new(1, 3, time::Duration::from_millis(100));
+    assert_eq!(pool.num_workers(), 1);
+
+    // four blocking jobs, but never more than three threads
+    let barrier = Arc::new(Barrier::new(4));
+    for _ in 0..3 {
+        let barrier = barrier.clone();
+        pool.execute(move || {
+            barrier.wait();
+        });
+    }
+    let (sender, receiver) = std::sync::mpsc::channel();
+    pool.execute(move || sender.send(()).unwrap());
+
+    thread::sleep(time::Duration::from_millis(100));
+    assert_eq!(pool.num_workers(), 3);
+    assert_eq!(pool.num_busy(), 3);
+
+    barrier.wait();
+    receiver.recv().unwrap();
+
+    // the additional threads exit after the idle timeout
+    thread::sleep(time::Duration::from_millis(500));
+    assert_eq!(pool.num_workers(), 1);
+    assert_eq!(pool.num_busy(), 0);
+}
+
+#[test]
+fn test_thread_pool_panic() {
+    use crate::server::ThreadPool;
+    use std::sync::{mpsc, Arc, Mutex};
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    let pool = ThreadPool::new(1, 1, time::Duration::from_millis(100)).with_panic_handler(Some(
+        Arc::new(move |message: &str| sender.lock().unwrap().send(message.to_string()).unwrap()),
+    ));
+
+    pool.execute(|| panic!("job {}", 1));
+    assert_eq!(
+        receiver.recv_timeout(time::Duration::from_secs(5)),
+        Ok("job 1".to_string())
+    );
+
+    // the worker survived and runs the next job
+    let (done, finished) = mpsc::channel();
+    pool.execute(move || done.send(()).unwrap());
+    finished.recv_timeout(time::Duration::from_secs(5)).unwrap();
+    assert_eq!(pool.num_workers(), 1);
+    // the job is finished, when the worker is idle again
+    for _ in 0..100 {
+        if pool.num_busy() == 0 {
+            break;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    assert_eq!(pool.num_busy(), 0);
+}
+
+#[test]
+fn test_listen_overload_close() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        initial_worker_threads: 1,
+        max_worker_threads: 1,
+        max_queued_connections: 1,
+        overload_policy: OverloadPolicy::Close,
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_listen_overload_close";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, &address, &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    {
+        // keeps the only worker busy
+        let mut first = OrgVarlinkServiceClient::new(Connection::new(address)?);
+        assert_eq!(first.get_info()?.product, "test service");
+
+        // waits in the queue
+        let second = Connection::new(address)?;
+        thread::sleep(time::Duration::from_millis(200));
+
+        // the queue is full
+        let mut third = OrgVarlinkServiceClient::new(Connection::new(address)?);
+        assert!(third.get_info().is_err());
+
+        drop(first);
+        let mut second = OrgVarlinkServiceClient::new(second);
+        assert_eq!(second.get_info()?.product, "test service");
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_single_threaded() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        single_threaded: true,
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+
+    let child = {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            let ret = listen(service, "tcp:127.0.0.1:0", &config);
+            if ret.is_err() {
+                handle.shutdown();
+            }
+            ret
+        })
+    };
+
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    {
+        // all connections are served at the same time by the listening thread
+        let mut clients = Vec::new();
+        for _ in 0..3 {
+            clients.push(OrgVarlinkServiceClient::new(Connection::new(&address)?));
+        }
+        for _ in 0..2 {
+            for client in clients.iter_mut().rev() {
+                assert_eq!(client.get_info()?.product, "test service");
+            }
+        }
+        assert_eq!(handle.metrics().active_connections, 3);
+
+        // several requests in one write
+        let connection = Connection::new(&address)?;
+        let mut conn = connection.write().unwrap();
+        let request = concat!(
+            r#"{"method":"org.varlink.service.GetInfo"}"#,
+            "\0",
+            r#"{"method":"org.varlink.service.GetInterfaceDescription","#,
+            r#""parameters":{"interface":"org.varlink.service"}}"#,
+            "\0"
+        );
+        let writer = conn.writer.as_mut().unwrap();
+        writer
+            .write_all(request.as_bytes())
+            .map_err(map_context!())?;
+        writer.flush().map_err(map_context!())?;
+        let reader = conn.reader.as_mut().unwrap();
+        for _ in 0..2 {
+            let mut reply = Vec::new();
+            reader.read_until(0, &mut reply).map_err(map_context!())?;
+            reply.pop();
+            let reply: Reply = from_slice(&reply).map_err(map_context!())?;
+            assert!(reply.error.is_none());
+        }
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_idempotent_retries() -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let address = format!("tcp:{}", listener.local_addr().map_err(map_context!())?);
+
+    // drops the first two connections after reading the request, serves the third
+    let child = thread::spawn(move || -> Result<()> {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        for i in 0..3 {
+            let (mut stream, _) = listener.accept().map_err(map_context!())?;
+            let mut request = Vec::new();
+            BufReader::new(stream.try_clone().map_err(map_context!())?)
+                .read_until(0, &mut request)
+                .map_err(map_context!())?;
+            if i == 2 {
+                let mut reply = Vec::new();
+                service.handle(&mut request.as_slice(), &mut reply, None)?;
+                stream.write_all(&reply).map_err(map_context!())?;
+            }
+        }
+        Ok(())
+    });
+
+    let get_info = |connection| {
+        MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+            connection,
+            "org.varlink.service.GetInfo",
+            GetInfoArgs {},
+        )
+        .retries(1)
+    };
+
+    // not retried without the opt-in
+    let e = get_info(Connection::new(&address)?).call().unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::ConnectionClosed);
+
+    let connection = Connection::new(&address)?;
+    let info = get_info(connection.clone()).idempotent().call()?;
+    assert_eq!(info.product, "test service");
+    assert_eq!(connection.read().unwrap().address(), address);
+
+    child.join().unwrap()
+}
+
+#[test]
+fn test_retry_policy() -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let backoff = Backoff::Exponential {
+        initial: time::Duration::from_millis(100),
+        max: time::Duration::from_millis(300),
+    };
+    assert_eq!(backoff.delay(1), time::Duration::from_millis(100));
+    assert_eq!(backoff.delay(2), time::Duration::from_millis(200));
+    assert_eq!(backoff.delay(3), time::Duration::from_millis(300));
+    assert_eq!(backoff.delay(100), time::Duration::from_millis(300));
+    assert!(RetryPolicy::is_transient(&ErrorKind::ConnectionClosed));
+    assert!(!RetryPolicy::is_transient(&ErrorKind::Timeout));
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let local_addr = listener.local_addr().map_err(map_context!())?;
+    let address = format!("tcp:{}", local_addr);
+
+    // drops the connection after reading the request and refuses connections for a
+    // while, like a restarting service, then serves the calls
+    let child = thread::spawn(move || -> Result<()> {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        for _ in 0..3 {
+            let (stream, _) = listener.accept().map_err(map_context!())?;
+            let mut request = Vec::new();
+            BufReader::new(stream)
+                .read_until(0, &mut request)
+                .map_err(map_context!())?;
+        }
+        drop(listener);
+        thread::sleep(time::Duration::from_millis(500));
+
+        let listener = TcpListener::bind(local_addr).map_err(map_context!())?;
+        let (mut stream, _) = listener.accept().map_err(map_context!())?;
+        let mut reader = BufReader::new(stream.try_clone().map_err(map_context!())?);
+        loop {
+            let mut request = Vec::new();
+            if reader.read_until(0, &mut request).map_err(map_context!())? == 0 {
+                return Ok(());
+            }
+            let mut reply = Vec::new();
+            service.handle(&mut request.as_slice(), &mut reply, None)?;
+            stream.write_all(&reply).map_err(map_context!())?;
+        }
+    });
+
+    let get_info = |connection| {
+        MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+            connection,
+            "org.varlink.service.GetInfo",
+            GetInfoArgs {},
+        )
+        .retry_policy(RetryPolicy {
+            max_attempts: 20,
+            backoff: Backoff::Fixed(time::Duration::from_millis(50)),
+            ..Default::default()
+        })
+    };
+
+    // not retried without the opt-in
+    let e = get_info(Connection::new(&address)?).call().unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::ConnectionClosed);
+
+    // not retried, if `retry_on` rejects the error
+    let e = get_info(Connection::new(&address)?)
+        .idempotent()
+        .retry_policy(RetryPolicy {
+            retry_on: |_| false,
+            ..Default::default()
+        })
+        .call()
+        .unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::ConnectionClosed);
+
+    // reconnects, until the service accepts the connection again
+    let connection = Connection::new(&address)?;
+    let start = time::Instant::now();
+    let info = get_info(connection.clone()).idempotent().call()?;
+    assert_eq!(info.product, "test service");
+    assert!(start.elapsed() >= time::Duration::from_millis(400));
+
+    let info = get_info(connection.clone()).call()?;
+    assert_eq!(info.product, "test service");
+    drop(connection);
+
+    child.join().unwrap()
+}
+
+#[test]
+fn test_listen_max_connections() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        max_worker_threads: 4,
+        max_connections: 1,
+        overload_policy: OverloadPolicy::Busy,
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+
+    let child = {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            let ret = listen(service, "tcp:127.0.0.1:0", &config);
+            if ret.is_err() {
+                handle.shutdown();
+            }
+            ret
+        })
+    };
+
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    {
+        let mut first = OrgVarlinkServiceClient::new(Connection::new(&address)?);
+        assert_eq!(first.get_info()?.product, "test service");
+
+        // the only connection slot is taken
+        let mut second = OrgVarlinkServiceClient::new(Connection::new(&address)?);
+        match second.get_info() {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::ServiceBusy),
+            Ok(_) => panic!("second connection was served"),
+        }
+
+        drop(first);
+        // the server frees the slot, after the worker noticed the closed connection
+        let mut tries = 0;
+        loop {
+            let mut third = OrgVarlinkServiceClient::new(Connection::new(&address)?);
+            match third.get_info() {
+                Ok(info) => {
+                    assert_eq!(info.product, "test service");
+                    break;
+                }
+                Err(ref e) if *e.kind() == ErrorKind::ServiceBusy && tries < 50 => {
+                    tries += 1;
+                    thread::sleep(time::Duration::from_millis(20));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_listen_idle_timeout_mock_clock() -> Result<()> {
+    use std::sync::Arc;
+
+    let clock = Arc::new(clock::MockClock::new());
+    let config = ListenConfig {
+        idle_timeout: 3600,
+        clock: Some(clock.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_listen_idle_timeout_mock_clock";
+
+    let start = time::Instant::now();
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, &address, &config)
+    });
+
+    thread::sleep(time::Duration::from_millis(300));
+    clock.advance(time::Duration::from_secs(3599));
+    thread::sleep(time::Duration::from_millis(300));
+
+    // a connection resets the idle time
+    {
+        let mut client = OrgVarlinkServiceClient::new(Connection::new(address)?);
+        assert_eq!(client.get_info()?.product, "test service");
+    }
+    thread::sleep(time::Duration::from_millis(300));
+    clock.advance(time::Duration::from_secs(3599));
+    thread::sleep(time::Duration::from_millis(300));
+    clock.advance(time::Duration::from_secs(1));
+
+    let e = child.join().unwrap().unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Timeout);
+    assert!(start.elapsed() < time::Duration::from_secs(10));
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_listen_memory_budget() -> Result<()> {
+    use std::io::{Read, Write};
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        connection_memory_budget: 1024,
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_listen_memory_budget";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, &address, &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let mut client = OrgVarlinkServiceClient::new(Connection::new(address)?);
+    assert_eq!(client.get_info()?.product, "test service");
+
+    // a request, which never ends
+    let mut s = std::os::unix::net::UnixStream::connect("test_listen_memory_budget")
+        .map_err(map_context!())?;
+    let _ = s.write_all(&[b' '; 4096]);
+    let mut buf = Vec::new();
+    let _ = s.read_to_end(&mut buf);
+    assert!(buf.is_empty());
+
+    assert_eq!(client.get_info()?.product, "test service");
+    drop(client);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_listen_tls() -> Result<()> {
+    let data = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        tls: Some(TlsConfig {
+            cert_chain: Some(data.join("cert.pem")),
+            private_key: Some(data.join("key.pem")),
+            ca_certs: None,
+            ..Default::default()
+        }),
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp+tls:127.0.0.1:23457", &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let tls = TlsConfig {
+        ca_certs: Some(data.join("ca.pem")),
+        ..Default::default()
+    };
+    {
+        let mut client =
+            OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23457", &tls)?);
+        assert_eq!(client.get_info()?.product, "test service");
+        assert_eq!(client.get_info()?.vendor, "org.varlink");
+    }
+
+    // the server certificate is not signed by this CA
+    let untrusted = TlsConfig {
+        ca_certs: Some(data.join("cert.pem")),
+        ..Default::default()
+    };
+    let mut client =
+        OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23457", &untrusted)?);
+    assert!(client.get_info().is_err());
+    drop(client);
+
+    match Connection::new("tcp+tls:localhost:23457") {
+        Err(e) => match e.kind() {
+            ErrorKind::InvalidTlsConfig(_) => {}
+            k => panic!("unexpected error {:?}", k),
+        },
+        Ok(_) => panic!("connected without TlsConfig"),
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls_pinning_client_auth() -> Result<()> {
+    let data = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    let handle = ServerHandle::new();
+    // the server only accepts clients with a certificate signed by the CA
+    let config = ListenConfig {
+        tls: Some(
+            TlsConfig::new()
+                .with_cert(data.join("cert.pem"), data.join("key.pem"))
+                .with_ca_certs(data.join("ca.pem")),
+        ),
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp+tls:127.0.0.1:23458", &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    let fingerprint = TlsConfig::fingerprint(data.join("cert.pem"))?;
+    assert_eq!(fingerprint[..4], [0x08, 0x53, 0x7b, 0x70]);
+
+    // pinned without a CA, and pinned and signed by the CA
+    for tls in &[
+        TlsConfig::new().with_pinned_cert(fingerprint),
+        TlsConfig::new()
+            .with_ca_certs(data.join("ca.pem"))
+            .with_pinned_cert([0; 32])
+            .with_pinned_cert(fingerprint),
+    ] {
+        let tls = tls
+            .clone()
+            .with_cert(data.join("cert.pem"), data.join("key.pem"));
+        let mut client =
+            OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23458", &tls)?);
+        assert_eq!(client.get_info()?.product, "test service");
+    }
+
+    // another certificate is pinned
+    let tls = TlsConfig::new()
+        .with_ca_certs(data.join("ca.pem"))
+        .with_pinned_cert([0; 32])
+        .with_cert(data.join("cert.pem"), data.join("key.pem"));
+    let mut client =
+        OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23458", &tls)?);
+    assert!(client.get_info().is_err());
+    drop(client);
+
+    // pinned, but signed by an untrusted CA
+    let tls = TlsConfig::new()
+        .with_ca_certs(data.join("cert.pem"))
+        .with_pinned_cert(fingerprint)
+        .with_cert(data.join("cert.pem"), data.join("key.pem"));
+    let mut client =
+        OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23458", &tls)?);
+    assert!(client.get_info().is_err());
+    drop(client);
+
+    // the server rejects a client without certificate
+    let tls = TlsConfig::new().with_pinned_cert(fingerprint);
+    let mut client =
+        OrgVarlinkServiceClient::new(Connection::with_tls("tcp+tls:localhost:23458", &tls)?);
+    assert!(client.get_info().is_err());
+    drop(client);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_connection_ext() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let address = "unix:test_connection_ext";
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, &address, &config)
+    });
+
+    // give server time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    {
+        let connection = Connection::with_address(address)?;
+        assert_eq!(connection.get_info()?.product, "test service");
+        assert_eq!(connection.interfaces()?, vec!["org.varlink.service"]);
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_address_parse() -> Result<()> {
+    use crate::address::{Address, Transport};
+
+    let address = Address::parse("tcp:[::1]:12345;timeout=500")?;
+    assert_eq!(
+        address.transport,
+        Transport::Tcp {
+            host: "::1".into(),
+            port: 12345
+        }
+    );
+    assert_eq!(address.timeout, Some(time::Duration::from_millis(500)));
+    assert_eq!(address.to_string(), "tcp:[::1]:12345;timeout=500");
+
+    let address: Address = "unix:/run/org.example;mode=660;group=varlink".parse()?;
+    assert_eq!(
+        address.transport,
+        Transport::Unix("/run/org.example".into())
+    );
+    assert_eq!(address.mode, Some(0o660));
+    assert_eq!(address.group, Some("varlink".into()));
+    assert_eq!(
+        address.to_string(),
+        "unix:/run/org.example;mode=0660;group=varlink"
+    );
+
+    assert_eq!(
+        Address::parse("unix:@")?.transport,
+        Transport::UnixAbstract("".into())
+    );
+    assert_eq!(
+        Address::parse("vsock:any:1024")?.transport,
+        Transport::Vsock {
+            cid: None,
+            port: 1024
+        }
+    );
+    let address = Address::parse("tcp+tls:example.org:443;ca=/etc/ca.pem")?;
+    assert_eq!(address.ca_certs, Some("/etc/ca.pem".into()));
+
+    for (address, cause) in &[
+        ("localhost:12345", "unknown transport 'localhost'"),
+        ("tcp:localhost", "missing port"),
+        ("tcp::12345", "missing host"),
+        ("tcp:::1:12345", "an IPv6 host must be written as '[host]'"),
+        ("tcp:127.0.0.1:123456", "invalid port '123456'"),
+        ("unix:", "missing path"),
+        (
+            "unix:/run/org.example;mode=0999",
+            "invalid octal mode '0999'",
+        ),
+        (
+            "unix:/run/org.example;mode",
+            "expected 'key=value', not 'mode'",
+        ),
+        ("unix:/run/org.example;", "expected 'key=value', not ''"),
+        ("unix:/run/org.example;mode=", "empty parameter 'mode'"),
+        (
+            "unix:/run/org.example;mode=600;mode=660",
+            "duplicate parameter 'mode'",
+        ),
+        (
+            "unix:/run/org.example;color=blue",
+            "unknown parameter 'color'",
+        ),
+        (
+            "unix:@org.example;mode=0600",
+            "parameter 'mode' does not apply to the transport",
+        ),
+        (
+            "tcp:127.0.0.1:12345;ca=/etc/ca.pem",
+            "parameter 'ca' does not apply to the transport",
+        ),
+        ("vsock:host:1024", "invalid CID 'host'"),
+    ] {
+        let e = Address::parse(address).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidAddress, "{}", address);
+        assert_eq!(
+            e.to_string(),
+            format!("Invalid varlink address URI: {} in '{}'", cause, address)
+        );
+    }
+
+    // the client and the server reject the same addresses
+    let address = "unix:/tmp/org.example.address;mode=0600;color=blue";
+    match Connection::with_address(address) {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidAddress),
+        Ok(_) => panic!("connected to {}", address),
+    }
+    assert_eq!(
+        *Listener::new(address).unwrap_err().kind(),
+        ErrorKind::InvalidAddress
+    );
+    Ok(())
+}
+
+#[test]
+fn test_connect_with_timeout() -> Result<()> {
+    let timeout = time::Duration::from_secs(5);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let port = listener.local_addr().map_err(map_context!())?.port();
+
+    // the addresses of localhost are tried in turn
+    Connection::connect_with_timeout(&format!("tcp:localhost:{}", port), timeout)?;
+    Connection::with_address(&format!("tcp:localhost:{};timeout=5000", port))?;
+
+    drop(listener);
+    let start = time::Instant::now();
+    match Connection::connect_with_timeout(&format!("tcp:127.0.0.1:{}", port), timeout) {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::Io(std::io::ErrorKind::ConnectionRefused)
+        ),
+        Ok(_) => panic!("connected to a closed port"),
+    }
+    assert!(start.elapsed() < timeout);
+
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_vsock_address() {
+    for address in &["vsock:", "vsock:2", "vsock:host:1024", "vsock:2:port"] {
+        match Connection::new(address) {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidAddress, "{}", address),
+            Ok(_) => panic!("connected to {}", address),
+        }
+    }
+
+    // the kernel might have no vsock support
+    if let Ok(listener) = Listener::new("vsock:any:23459") {
+        match listener.accept(100) {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::Timeout),
+            Ok(_) => panic!("unexpected connection"),
+        }
+    }
+}
+
+#[test]
+fn test_listen_on() -> Result<()> {
+    fn serve<L: Into<Listener>>(listener: L, address: String) -> Result<()> {
+        let handle = ServerHandle::new();
+        let config = ListenConfig {
+            server_handle: Some(handle.clone()),
+            ..Default::default()
+        };
+        let listener = listener.into();
+
+        let child = thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            listen_on(service, listener, &config)
+        });
+
+        {
+            let connection = Connection::with_address(&address)?;
+            assert_eq!(connection.get_info()?.product, "test service");
+        }
+
+        handle.shutdown();
+        assert!(child.join().unwrap().is_ok());
+        Ok(())
+    }
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let address = format!("tcp:{}", listener.local_addr().map_err(map_context!())?);
+    serve(listener, address)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::IntoRawFd;
+
+        let path = "test_listen_on";
+        let _ = std::fs::remove_file(path);
+        let fd = std::os::unix::net::UnixListener::bind(path)
+            .map_err(map_context!())?
+            .into_raw_fd();
+        let listener = unsafe { Listener::from_raw_fd(fd)? };
+        serve(listener, format!("unix:{}", path))?;
+        let _ = std::fs::remove_file(path);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_try_new() {
+    struct TestInterface(&'static str, &'static str, Option<&'static [&'static str]>);
+
+    impl Interface for TestInterface {
+        fn get_description(&self) -> &'static str {
+            self.1
+        }
+        fn get_name(&self) -> &'static str {
+            self.0
+        }
+        fn get_method_names(&self) -> Option<&'static [&'static str]> {
+            self.2
+        }
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        fn call(&self, call: &mut Call) -> Result<()> {
+            call.reply_method_not_implemented(String::new())
+        }
+    }
+
+    fn try_new(interfaces: Vec<TestInterface>) -> std::result::Result<(), String> {
+        VarlinkService::try_new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            interfaces
+                .into_iter()
+                .map(|i| Box::new(i) as Box<dyn Interface + Send + Sync>)
+                .collect(),
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+    }
+
+    const PING: &str = "interface org.example.ping\nmethod Ping(ping: string) -> (pong: string)";
+
+    assert_eq!(
+        try_new(vec![
+            TestInterface("org.example.ping", PING, Some(&["Ping"])),
+            TestInterface(
+                "org.example.other",
+                "interface org.example.other\nmethod A() -> ()",
+                None
+            ),
+        ]),
+        Ok(())
+    );
+    assert_eq!(
+        try_new(vec![TestInterface("org.example.pong", PING, None)]),
+        Err("Invalid interface org.example.pong: the description declares interface 'org.example.ping'".into())
+    );
+    assert_eq!(
+        try_new(vec![TestInterface("org.example.ping", PING, Some(&[]))]),
+        Err("Invalid interface org.example.ping: method 'Ping' is not dispatched".into())
+    );
+    assert!(try_new(vec![TestInterface(
+        "org.example.ping",
+        "interface org.example.ping\n",
+        None
+    )])
+    .unwrap_err()
+    .starts_with("Invalid interface org.example.ping: "));
+    assert_eq!(
+        try_new(vec![
+            TestInterface("org.example.ping", PING, None),
+            TestInterface("org.example.ping", PING, None),
+        ]),
+        Err("Invalid interface org.example.ping: registered twice".into())
+    );
+
+    // `new` registers inconsistent interfaces anyway, like it always did
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![
+            Box::new(TestInterface("org.example.pong", PING, Some(&[]))),
+            Box::new(TestInterface(
+                "org.example.broken",
+                "interface org.example.broken\n",
+                None,
+            )),
+        ],
+    )
+    .with_strict_validation();
+    let mut reply = Vec::new();
+    service
+        .handle(
+            &mut &b"{\"method\":\"org.varlink.service.GetInfo\"}\0"[..],
+            &mut reply,
+            None,
+        )
+        .unwrap();
+    reply.pop();
+    let reply: Reply = from_slice(&reply).unwrap();
+    let info: ServiceInfo = from_value(reply.parameters.unwrap()).unwrap();
+    assert_eq!(info.interfaces.len(), 3);
+}
+
+#[test]
+fn test_local_address() -> Result<()> {
+    fn serve(address: &str) -> Result<String> {
+        let handle = ServerHandle::new();
+        let config = ListenConfig {
+            server_handle: Some(handle.clone()),
+            ..Default::default()
+        };
+        let address = address.to_string();
+        let child = thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            listen(service, &address, &config)
+        });
+
+        let local_address = loop {
+            if let Some(address) = handle.local_address() {
+                break address;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        };
+        {
+            let connection = Connection::with_address(&local_address)?;
+            assert_eq!(connection.get_info()?.product, "test service");
+        }
+
+        handle.shutdown();
+        assert!(child.join().unwrap().is_ok());
+        assert_eq!(handle.local_address(), None);
+        Ok(local_address)
+    }
+
+    assert!(serve("tcp:127.0.0.1:0")?.starts_with("tcp:127.0.0.1:"));
+    assert_eq!(serve("unix:test_local_address")?, "unix:test_local_address");
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let listener = Listener::new("unix:@")?;
+        let first = listener.local_address()?;
+        assert!(first.starts_with("unix:@") && first.len() > "unix:@".len());
+        let second = serve("unix:@")?;
+        assert!(second.starts_with("unix:@"));
+        assert_ne!(first, second);
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_abstract_socket() -> Result<()> {
+    let address = format!("unix:@org.varlink.test-abstract-{}", std::process::id());
+
+    match Connection::with_address(&address) {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::Io(std::io::ErrorKind::ConnectionRefused)
+        ),
+        Ok(_) => panic!("connected to an unbound name"),
+    }
+
+    let listener = Listener::new(&address)?;
+    assert_eq!(listener.local_address()?, address);
+    // the name is taken
+    assert!(Listener::new(&address).is_err());
+
+    // a std socket, which honors the timeouts like other unix sockets
+    let server = thread::spawn(move || -> Result<()> {
+        let mut stream = listener.accept(0)?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).map_err(map_context!())?;
+        Ok(())
+    });
+    let connection = Connection::with_address(&address)?;
+    let e =
+        MethodCall::<Value, Value, Error>::new(connection.clone(), "org.example.Slow", json!({}))
+            .with_timeout(time::Duration::from_millis(100))
+            .call()
+            .unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Timeout);
+    drop(connection);
+    server.join().unwrap()?;
+
+    let long = format!("unix:@{}", "x".repeat(200));
+    assert_eq!(
+        *Listener::new(&long).unwrap_err().kind(),
+        ErrorKind::InvalidAddress
+    );
+    match Connection::with_address(&long) {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidAddress),
+        Ok(_) => panic!("connected to a name longer than sun_path"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_authorizer() -> Result<()> {
+    use std::sync::Mutex;
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let peers: Arc<Mutex<Vec<PeerInfo>>> = Default::default();
+    let seen = peers.clone();
+    let listener = Listener::new("unix:test_authorizer")?;
+
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        )
+        .with_authorizer(move |peer: &PeerInfo, interface: &str, method: &str| {
+            assert_eq!(interface, "org.varlink.service");
+            seen.lock().unwrap().push(peer.clone());
+            if method == "org.varlink.service.GetInterfaceDescription" {
+                Decision::Deny
+            } else {
+                Decision::Allow
+            }
+        });
+        listen_on(service, listener, &config)
+    });
+
+    {
+        let connection = Connection::with_address("unix:test_authorizer")?;
+        assert_eq!(connection.get_info()?.product, "test service");
+        let e = OrgVarlinkServiceClient::new(connection.clone())
+            .get_interface_description("org.varlink.service")
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::PermissionDenied);
+        // the connection is still usable after a denied call
+        assert_eq!(connection.get_info()?.product, "test service");
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    let peers = peers.lock().unwrap();
+    assert_eq!(peers.len(), 3);
+    assert_eq!(peers[0].address, None);
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(peers[0].pid, Some(std::process::id()));
+        assert_eq!(peers[0].uid, Some(unsafe { libc::getuid() }));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_monitor() -> Result<()> {
+    use crate::monitor::{Direction, Message};
+    use std::sync::Mutex;
+
+    let service_handle = ServerHandle::new();
+    let service_config = ListenConfig {
+        server_handle: Some(service_handle.clone()),
+        ..Default::default()
+    };
+    let service = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "unix:test_monitor_service", &service_config)
+    });
+
+    let messages: Arc<Mutex<Vec<Message>>> = Default::default();
+    let monitor_handle = ServerHandle::new();
+    let monitor_config = ListenConfig {
+        server_handle: Some(monitor_handle.clone()),
+        ..Default::default()
+    };
+    let monitor = {
+        let messages = messages.clone();
+        thread::spawn(move || {
+            monitor::monitor(
+                "unix:test_monitor",
+                "unix:test_monitor_service",
+                &monitor_config,
+                move |m| messages.lock().unwrap().push(m.clone()),
+            )
+        })
+    };
+
+    // give server and monitor time to start
+    thread::sleep(time::Duration::from_millis(500));
+
+    {
+        let connection = Connection::with_address("unix:test_monitor")?;
+        assert_eq!(connection.get_info()?.product, "test service");
+        assert!(MethodCall::<_, ServiceInfo, Error>::new(
+            connection,
+            "org.example.missing.Login",
+            serde_json::json!({"password": "secret"}),
+        )
+        .call()
+        .is_err());
+    }
+
+    monitor_handle.shutdown();
+    assert!(monitor.join().unwrap().is_ok());
+    service_handle.shutdown();
+    assert!(service.join().unwrap().is_ok());
+
+    let messages = messages.lock().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[0].direction, Direction::Request);
+    assert_eq!(messages[1].direction, Direction::Reply);
+    // without a description of the interface, all parameters are redacted
+    assert_eq!(messages[2].method, "org.example.missing.Login");
+    assert_eq!(messages[2].message["parameters"], "<redacted>");
+    assert_eq!(
+        messages[3].message["error"],
+        "org.varlink.service.InterfaceNotFound"
+    );
+    assert_eq!(
+        messages[3].message["parameters"]["interface"],
+        "org.example.missing"
+    );
+    for m in messages[..2].iter() {
+        assert_eq!(m.connection, 0);
+        assert_eq!(m.method, "org.varlink.service.GetInfo");
+        assert!(m.matches("org.varlink.service"));
+        assert!(m.matches("org.varlink.service.GetInfo"));
+        assert!(!m.matches("org.varlink"));
+        assert!(!m.matches("org.varlink.serv"));
+    }
+    assert_eq!(messages[1].message["parameters"]["product"], "test service");
+
+    Ok(())
+}
+
+/// A single bucket table for `names`, like `varlink_generator` builds for larger interfaces
+fn dispatch_table(names: &[&'static str]) -> dispatch::Table {
+    let size = (names.len() * 2).next_power_of_two();
+    let seed = (1..)
+        .find(|&seed| {
+            let mut slots: Vec<usize> = names
+                .iter()
+                .map(|n| dispatch::mix(dispatch::hash(n), seed) as usize & (size - 1))
+                .collect();
+            slots.sort();
+            slots.dedup();
+            slots.len() == names.len()
+        })
+        .unwrap();
+    let mut slots = vec![""; size];
+    for n in names {
+        slots[dispatch::mix(dispatch::hash(n), seed) as usize & (size - 1)] = n;
+    }
+    dispatch::Table {
+        displacements: Box::leak(vec![seed].into_boxed_slice()),
+        names: Box::leak(slots.into_boxed_slice()),
+    }
+}
+
+const DISPATCH_NAMES: [&str; 8] = [
+    "GetInfo",
+    "GetInterfaceDescription",
+    "Ping",
+    "Upgrade",
+    "TestMore",
+    "StopServing",
+    "Resolve",
+    "Monitor",
+];
+
+#[test]
+fn test_dispatch_table() {
+    let table = dispatch_table(&DISPATCH_NAMES);
+    for name in DISPATCH_NAMES.iter() {
+        let slot = table
+            .lookup("org.example", &format!("org.example.{}", name))
+            .unwrap();
+        assert_eq!(table.names[slot], *name);
+    }
+    for method in &[
+        "org.example.Missing",
+        "org.example.",
+        "org.example",
+        "org.exampleX.Ping",
+        "org.other.Ping",
+        "Ping",
+        "",
+    ] {
+        assert_eq!(table.lookup("org.example", method), None, "{}", method);
+    }
+}
+
+/// Run with `cargo test --release -- --ignored --nocapture bench_dispatch`
+///
+/// With these 8 methods the `match` is faster, the table only wins from about 100 methods.
+#[test]
+#[ignore]
+fn bench_dispatch() {
+    fn match_dispatch(method: &str) -> Option<usize> {
+        match method {
+            "org.example.GetInfo" => Some(0),
+            "org.example.GetInterfaceDescription" => Some(1),
+            "org.example.Ping" => Some(2),
+            "org.example.Upgrade" => Some(3),
+            "org.example.TestMore" => Some(4),
+            "org.example.StopServing" => Some(5),
+            "org.example.Resolve" => Some(6),
+            "org.example.Monitor" => Some(7),
+            _ => None,
+        }
+    }
+
+    let table = dispatch_table(&DISPATCH_NAMES);
+    let methods: Vec<String> = DISPATCH_NAMES
+        .iter()
+        .map(|n| format!("org.example.{}", n))
+        .collect();
+    const ROUNDS: usize = 1_000_000;
+
+    let start = time::Instant::now();
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        for m in &methods {
+            found += table.lookup("org.example", m).is_some() as usize;
+        }
+    }
+    let table_time = start.elapsed();
+    assert_eq!(found, ROUNDS * methods.len());
+
+    let start = time::Instant::now();
+    let mut found = 0;
+    for _ in 0..ROUNDS {
+        for m in &methods {
+            found += match_dispatch(m).is_some() as usize;
+        }
+    }
+    let match_time = start.elapsed();
+    assert_eq!(found, ROUNDS * methods.len());
+
+    eprintln!(
+        "{} lookups: table {:?}, match {:?}",
+        ROUNDS * methods.len(),
+        table_time,
+        match_time
+    );
+}
+
+#[test]
+fn test_metrics() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+    {
+        let conn = Connection::with_address(&address)?;
+        let mut call = OrgVarlinkServiceClient::new(conn.clone());
+        call.get_info()?;
+        call.get_info()?;
+        assert!(call
+            .get_interface_description("org.example.missing")
+            .is_err());
+        assert!(MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+            conn.clone(),
+            "org.example.missing.Call",
+            GetInfoArgs {},
+        )
+        .call()
+        .is_err());
+        // the server records a call after replying to it
+        let metrics = loop {
+            let metrics = handle.metrics();
+            if metrics.unknown_method_calls == 1 {
+                break metrics;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        };
+        assert_eq!(metrics.accepted_connections, 1);
+        assert_eq!(metrics.rejected_connections, 0);
+        assert_eq!(metrics.active_connections, 1);
+        assert_eq!(metrics.busy_workers, 1);
+        assert!(metrics.worker_utilization() > 0.0);
+
+        let info = &metrics.methods["org.varlink.service.GetInfo"];
+        assert_eq!((info.calls, info.errors), (2, 0));
+        assert_eq!(info.duration_us.count(), 2);
+        let description = &metrics.methods["org.varlink.service.GetInterfaceDescription"];
+        assert_eq!((description.calls, description.errors), (1, 1));
+
+        let interfaces = metrics.interfaces();
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces["org.varlink.service"].calls, 3);
+        assert_eq!(interfaces["org.varlink.service"].errors, 1);
+
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["methods"]["org.varlink.service.GetInfo"]["calls"], 2);
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+/// Replies three times to `Ticks` with `more`, and twice to `Twice`
+struct Ticker;
+
+impl Interface for Ticker {
+    fn get_description(&self) -> &'static str {
+        "interface org.example.ticker\nmethod Ticks() -> (n: int)\nmethod Twice() -> ()"
+    }
+
+    fn get_name(&self) -> &'static str {
+        "org.example.ticker"
+    }
+
+    fn call_upgraded(
+        &self,
+        _call: &mut Call,
+        _bufreader: &mut dyn std::io::BufRead,
+    ) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn call(&self, call: &mut Call) -> Result<()> {
+        if call.request.unwrap().method == "org.example.ticker.Twice" {
+            call.reply_parameters(serde_json::json!({}))?;
+            return call.reply_parameters(serde_json::json!({}));
+        }
+        if call.wants_more() {
+            call.set_continues(true);
+            call.reply_parameters(serde_json::json!({"n": 0}))?;
+            call.reply_parameters(serde_json::json!({"n": 1}))?;
+            call.set_continues(false);
+        }
+        call.reply_parameters(serde_json::json!({"n": 2}))
+    }
+}
+
+#[test]
+fn test_reused_buffers() -> Result<()> {
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Ticker)],
+    );
+    // a long reply is followed by shorter ones
+    let requests = [
+        concat!(
+            r#"{"method":"org.varlink.service.GetInterfaceDescription","#,
+            r#""parameters":{"interface":"org.varlink.service"}}"#,
+            "\0"
+        ),
+        concat!(r#"{"method":"org.example.ticker.Ticks","more":true}"#, "\0"),
+        concat!(r#"{"method":"org.varlink.service.GetInfo"}"#, "\0"),
+        concat!(r#"{"method":"org.example.ticker.Ticks"}"#, "\0"),
+    ];
+
+    let mut expected = Vec::new();
+    for request in requests.iter() {
+        service.handle(&mut request.as_bytes(), &mut expected, None)?;
+    }
+    let mut replies = Vec::new();
+    service.handle(&mut requests.concat().as_bytes(), &mut replies, None)?;
+    assert_eq!(
+        String::from_utf8_lossy(&replies),
+        String::from_utf8_lossy(&expected)
+    );
+    assert_eq!(replies.iter().filter(|b| **b == 0).count(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn test_max_message_size() -> Result<()> {
+    let service = || {
+        VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        )
+        .with_max_message_size(64)
+    };
+
+    // a request within the limit is served
+    let request = concat!(r#"{"method":"org.varlink.service.GetInfo"}"#, "\0");
+    let mut reply = Vec::new();
+    service().handle(&mut request.as_bytes(), &mut reply, None)?;
+    assert!(String::from_utf8_lossy(&reply).contains("test service"));
+
+    // a larger one is rejected, even without its zero byte
+    let request = format!(
+        r#"{{"method":"org.varlink.service.GetInfo","x":"{}"}}"#,
+        "a".repeat(64)
+    );
+    for request in &[request.clone() + "\0", request] {
+        let mut reply = Vec::new();
+        match service().handle(&mut request.as_bytes(), &mut reply, None) {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::MessageTooLarge(64)),
+            Ok(_) => panic!("request of {} bytes accepted", request.len()),
+        }
+        assert_eq!(
+            String::from_utf8_lossy(&reply),
+            concat!(
+                r#"{"error":"org.varlink.service.InvalidParameter","#,
+                r#""parameters":{"parameter":"message length"}}"#,
+                "\0"
+            )
+        );
+    }
+
+    // a client rejects a larger reply and closes the connection
+    let server = test::run_service(service())?;
+    let connection = Connection::with_config(
+        server.address(),
+        &ConnectConfig {
+            max_message_size: 64,
+            ..Default::default()
+        },
+    )?;
+    match connection.get_info() {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::MessageTooLarge(64)),
+        Ok(info) => panic!("reply {:?} accepted", info),
+    }
+    assert!(connection.get_info().is_err());
+
+    let connection = Connection::with_address(server.address())?;
+    assert_eq!(connection.get_info()?.product, "test service");
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_conformance() -> Result<()> {
+    use crate::conformance::{check, check_replies, Violation};
+    use crate::monitor::Direction;
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Ticker)],
+    );
+    let requests = concat!(
+        r#"{"method":"org.example.ticker.Ticks","more":true}"#,
+        "\0",
+        r#"{"method":"org.example.ticker.Ticks"}"#,
+        "\0",
+        r#"{"method":"org.varlink.service.GetInterfaceDescription"}"#,
+        "\0"
+    );
+    let mut replies = Vec::new();
+    service.handle(&mut requests.as_bytes(), &mut replies, None)?;
+    assert_eq!(check(requests.as_bytes(), &replies), Ok(()));
+    assert_eq!(check_replies(&replies), Ok(3));
+
+    let oneway = concat!(
+        r#"{"method":"org.example.ticker.Ticks","oneway":true}"#,
+        "\0"
+    );
+    assert_eq!(check(oneway.as_bytes(), b""), Ok(()));
+
+    let more = concat!(r#"{"method":"org.example.ticker.Ticks","more":true}"#, "\0");
+    let call = concat!(r#"{"method":"org.example.ticker.Ticks"}"#, "\0");
+    let reply = concat!(r#"{"parameters":{}}"#, "\0");
+    let continued = concat!(r#"{"continues":true,"parameters":{}}"#, "\0");
+    let error = concat!(r#"{"continues":true,"error":"org.example.Error"}"#, "\0");
+
+    assert_eq!(
+        check(call.as_bytes(), continued.as_bytes()),
+        Err(Violation::UnexpectedContinues { index: 0 })
+    );
+    assert_eq!(
+        check(more.as_bytes(), continued.as_bytes()),
+        Err(Violation::MissingReply { request: 0 })
+    );
+    assert_eq!(
+        check(more.as_bytes(), error.as_bytes()),
+        Err(Violation::ContinuedError { index: 0 })
+    );
+    assert_eq!(
+        check(call.as_bytes(), [reply, reply].concat().as_bytes()),
+        Err(Violation::UnexpectedReply { index: 1 })
+    );
+    assert_eq!(
+        check(call.as_bytes(), br#"{"parameters":{}}"#),
+        Err(Violation::Unterminated {
+            direction: Direction::Reply,
+            offset: 0
+        })
+    );
+    assert_eq!(
+        check(b"{}\0", reply.as_bytes()),
+        Err(Violation::InvalidMessage {
+            direction: Direction::Request,
+            index: 0,
+            message: "{}".into()
+        })
+    );
+    assert_eq!(
+        check_replies([reply, continued].concat().as_bytes()),
+        Err(Violation::MissingReply { request: 1 })
+    );
+    Ok(())
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "reply to org.example.ticker.Twice after its final reply")]
+fn test_reply_after_final_reply() {
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Ticker)],
+    );
+    let mut w = Vec::new();
+    let mut br = concat!(r#"{"method":"org.example.ticker.Twice"}"#, "\0").as_bytes();
+    let _ = service.handle(&mut br, &mut w, None);
+}
+
+#[test]
+fn test_redact() -> Result<()> {
+    use crate::monitor::Direction;
+    use crate::redact::Redactor;
+    use serde_json::json;
+
+    let redactor = Redactor::from_description(
+        r#"
+interface org.example.login
+
+type Credentials (
+  user: string,
+  # The password of the user
+  # @sensitive
+  password: string,
+  # @sensitive: legacy
+  pin: ?int
+)
+
+type Node (
+  credentials: ?Credentials,
+  children: []Node
+)
+
+method Login(credentials: Credentials, fallback: []Credentials) -> (
+  # @sensitive
+  token: string
+)
+method Tree(root: Node, nodes: [string](key: string, # @sensitive
+value: string)) -> ()
+
+error LoginFailed (credentials: Credentials)
+"#,
+    )?;
+
+    let mut login = json!({
+        "credentials": {"user": "joe", "password": "secret", "pin": 1234},
+        "fallback": [{"user": "root", "password": "toor"}]
+    });
+    redactor.redact_parameters("org.example.login.Login", Direction::Request, &mut login);
+    assert_eq!(
+        login,
+        json!({
+            "credentials": {"user": "joe", "password": "<redacted>", "pin": "<redacted>"},
+            "fallback": [{"user": "root", "password": "<redacted>"}]
+        })
+    );
+
+    let mut reply = json!({"parameters": {"token": "abc"}});
+    redactor.redact_message("org.example.login.Login", Direction::Reply, &mut reply);
+    assert_eq!(reply, json!({"parameters": {"token": "<redacted>"}}));
+
+    let mut error = json!({
+        "error": "org.example.login.LoginFailed",
+        "parameters": {"credentials": {"user": "joe", "password": "secret"}}
+    });
+    redactor.redact_message("org.example.login.Login", Direction::Reply, &mut error);
+    assert_eq!(error["parameters"]["credentials"]["password"], "<redacted>");
+
+    // recursive types and maps
+    let mut tree = json!({
+        "root": {"children": [{"credentials": {"user": "a", "password": "b"}, "children": []}]},
+        "nodes": {"x": {"key": "k", "value": "v"}}
+    });
+    redactor.redact_parameters("org.example.login.Tree", Direction::Request, &mut tree);
+    assert_eq!(
+        tree,
+        json!({
+            "root": {"children": [{"credentials": {"user": "a", "password": "<redacted>"}, "children": []}]},
+            "nodes": {"x": {"key": "k", "value": "<redacted>"}}
+        })
+    );
+
+    // the standard interface is known, others are redacted completely
+    let mut info = json!({"interface": "org.example.login"});
+    redactor.redact_parameters(
+        "org.varlink.service.GetInterfaceDescription",
+        Direction::Request,
+        &mut info,
+    );
+    assert_eq!(info, json!({"interface": "org.example.login"}));
+    let mut unknown = json!({"password": "secret"});
+    redactor.redact_parameters("org.example.other.Login", Direction::Request, &mut unknown);
+    assert_eq!(unknown, json!("<redacted>"));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "log")]
+fn test_log() -> Result<()> {
+    use std::sync::Mutex;
+
+    struct Capture(Mutex<Vec<String>>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "varlink"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    );
+    let mut w = Vec::new();
+    let mut br = concat!(
+        r#"{"method":"org.varlink.service.GetInfo","more":true}"#,
+        "\0",
+        r#"{"method":"org.example.logged.Call","parameters":{"secret":"s3cr3t"}}"#,
+        "\0"
+    )
+    .as_bytes();
+    service.handle(&mut br, &mut w, None)?;
+
+    let events = CAPTURE.0.lock().unwrap();
+    let events: Vec<&String> = events
+        .iter()
+        .filter(|e| e.contains("org.varlink.service.GetInfo") || e.contains("org.example.logged"))
+        .collect();
+    assert_eq!(
+        events,
+        vec![
+            "request org.varlink.service.GetInfo (more: true, oneway: false, upgrade: false)",
+            "reply to org.varlink.service.GetInfo",
+            "request org.example.logged.Call (more: false, oneway: false, upgrade: false)",
+            "reply to org.example.logged.Call: org.varlink.service.InterfaceNotFound",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_accept_burst() -> Result<()> {
+    let config = ListenConfig {
+        backlog: 32,
+        accept_burst: 8,
+        ..Default::default()
+    };
+    let listener = Listener::new_with_config("tcp:127.0.0.1:0", &config)?;
+    let address = listener.local_address()?;
+
+    // queued in the backlog, before the server accepts any connection
+    let connections = (0..20)
+        .map(|_| Connection::with_address(&address))
+        .collect::<Result<Vec<_>>>()?;
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        max_worker_threads: 20,
+        ..config
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen_on(service, listener, &config)
+    });
+
+    for connection in connections {
+        assert_eq!(connection.get_info()?.product, "test service");
+    }
+    assert_eq!(handle.metrics().accepted_connections, 20);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_accept_burst_aborted() -> Result<()> {
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    // a connection, which is gone, when the pending connection is accepted
+    let tcp = TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let local_addr = tcp.local_addr().map_err(map_context!())?;
+    let stealer = tcp.try_clone().map_err(map_context!())?;
+    stealer.set_nonblocking(true).map_err(map_context!())?;
+    let listener = Listener::from(tcp);
+    let _client = TcpStream::connect(local_addr).map_err(map_context!())?;
+    thread::sleep(time::Duration::from_millis(100));
+    assert!(stealer.accept().is_ok());
+    assert!(crate::server::accept_pending(&listener, &TcpConfig::default()).is_none());
+    drop(stealer);
+
+    // clients resetting their connections in the backlog do not stop the server
+    let config = ListenConfig {
+        backlog: 32,
+        accept_burst: 8,
+        ..Default::default()
+    };
+    let listener = Listener::new_with_config("tcp:127.0.0.1:0", &config)?;
+    let address = listener.local_address()?;
+    let addr = address.trim_start_matches("tcp:").to_string();
+    for _ in 0..16 {
+        let stream = TcpStream::connect(&addr).map_err(map_context!())?;
+        let linger = libc::linger {
+            l_onoff: 1,
+            l_linger: 0,
+        };
+        unsafe {
+            libc::setsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_LINGER,
+                &linger as *const libc::linger as *const libc::c_void,
+                std::mem::size_of::<libc::linger>() as libc::socklen_t,
+            );
+        }
+    }
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..config
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen_on(service, listener, &config)
+    });
+
+    let connection = Connection::with_address(&address)?;
+    assert_eq!(connection.get_info()?.product, "test service");
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+#[test]
+#[cfg(unix)]
+fn test_connection_timeouts() -> Result<()> {
+    use std::io::Read;
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        connection_idle_timeout: 1,
+        connection_read_timeout: 1,
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    // an idle connection is closed after its last reply
+    {
+        let connection = Connection::with_address(&address)?;
+        thread::sleep(time::Duration::from_millis(500));
+        assert_eq!(connection.get_info()?.product, "test service");
+        thread::sleep(time::Duration::from_millis(500));
+        assert_eq!(connection.get_info()?.product, "test service");
+        thread::sleep(time::Duration::from_millis(1500));
+        assert!(connection.get_info().is_err());
+    }
+
+    // a started request must be completed in time
+    {
+        let mut stream = std::net::TcpStream::connect(&address["tcp:".len()..]).unwrap();
+        stream
+            .write_all(br#"{"method":"org.varlink.servi"#)
+            .unwrap();
+        let start = time::Instant::now();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert!(start.elapsed() >= time::Duration::from_millis(900));
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    Ok(())
+}
+
+#[test]
+fn test_request_metadata() -> Result<()> {
+    use std::io::BufRead;
+
+    struct Meta;
+
+    impl Interface for Meta {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.meta\nmethod Get() -> (method: string, more: bool, trace: ?string)"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.meta"
+        }
+
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            assert!(!call.wants_upgrade());
+            assert_eq!(call.get_parameters(), None);
+            let reply = serde_json::json!({
+                "method": call.get_method(),
+                "more": call.wants_more(),
+                "trace": call.get_extension("trace"),
+            });
+            if call.wants_more() {
+                call.set_continues(false);
+            }
+            call.reply_parameters(reply)
+        }
+    }
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Meta)],
+    );
+
+    let mut w = vec![];
+    let mut br = concat!(
+        r#"{"method":"org.example.meta.Get","trace":"abc"}"#,
+        "\0",
+        r#"{"method":"org.example.meta.Get","more":true}"#,
+        "\0"
+    )
+    .as_bytes();
+    service.handle(&mut br, &mut w, None)?;
+
+    assert_eq!(
+        String::from_utf8_lossy(&w),
+        concat!(
+            r#"{"parameters":{"method":"org.example.meta.Get","more":false,"trace":"abc"}}"#,
+            "\0",
+            r#"{"parameters":{"method":"org.example.meta.Get","more":true,"trace":null}}"#,
+            "\0"
+        )
+    );
+
+    let parsed: ParsedRequest =
+        serde_json::from_str(r#"{"method":"a.b.C","more":true,"trace":"abc"}"#).unwrap();
+    assert_eq!(parsed.request.method, "a.b.C");
+    assert_eq!(parsed.request.more, Some(true));
+    assert_eq!(
+        parsed.extensions.get("trace"),
+        Some(&serde_json::json!("abc"))
+    );
+    assert!(serde_json::from_str::<ParsedRequest>(r#"{"more":true}"#).is_err());
+    assert!(
+        serde_json::from_str::<ParsedRequest>(r#"{"method":"a.b.C","method":"a.b.D"}"#).is_err()
+    );
+
+    let mut w = vec![];
+    let call = Call::new_upgraded(&mut w);
+    assert_eq!(call.get_method(), None);
+    assert_eq!(
+        call.require_request().unwrap_err().kind(),
+        &ErrorKind::NoRequest
+    );
+    Ok(())
+}
+
+#[test]
+fn test_handle_connection() -> Result<()> {
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    );
+
+    let requests = concat!(
+        r#"{"method":"org.varlink.service.GetInfo"}"#,
+        "\0",
+        r#"{"method":"org.varlink.service.GetInterfaceDescription","parameters":{"interface":"org.varlink.service"}}"#,
+        "\0"
+    );
+    let mut w = vec![];
+    server::handle_connection(&service, &mut requests.as_bytes(), &mut w)?;
+
+    assert_eq!(conformance::check(requests.as_bytes(), &w), Ok(()));
+    Ok(())
+}
+
+#[test]
+fn test_broadcast_lag() {
+    use broadcast::{Broadcaster, Lag};
+
+    let broadcaster = Broadcaster::new().max_lag_messages(2);
+    let fast = broadcaster.subscribe();
+    let slow = broadcaster.subscribe();
+
+    assert_eq!(broadcaster.publish("a".to_string()), 2);
+    assert_eq!(broadcaster.publish("b".to_string()), 2);
+    assert_eq!(
+        slow.lag(),
+        Lag {
+            messages: 2,
+            bytes: 6
+        }
+    );
+    let timeout = time::Duration::from_millis(10);
+    assert_eq!(fast.recv_timeout(timeout).unwrap(), Some("a".to_string()));
+    assert_eq!(fast.recv_timeout(timeout).unwrap(), Some("b".to_string()));
+    assert_eq!(fast.recv_timeout(timeout).unwrap(), None);
+
+    // the third message exceeds the lag of the slow subscriber
+    assert_eq!(broadcaster.publish("c".to_string()), 1);
+    assert_eq!(
+        *slow.recv_timeout(timeout).unwrap_err().kind(),
+        ErrorKind::SlowConsumer
+    );
+    assert_eq!(fast.recv_timeout(timeout).unwrap(), Some("c".to_string()));
+
+    let metrics = broadcaster.lag_metrics();
+    assert_eq!(metrics.evicted, 1);
+    assert_eq!(metrics.subscribers.len(), 1);
+    assert_eq!(broadcaster.num_subscribers(), 1);
+
+    drop(fast);
+    drop(slow);
+    assert_eq!(broadcaster.publish("d".to_string()), 0);
+    assert!(broadcaster.lag_metrics().subscribers.is_empty());
+
+    let mut w = vec![];
+    let request = Request::create("org.example.Watch", None);
+    let mut call = Call::new(&mut w, &request);
+    call.reply_slow_consumer().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&w),
+        "{\"error\":\"org.varlink.broadcast.SlowConsumer\"}\0"
+    );
+}
+
+#[test]
+fn test_connection_pool() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        max_worker_threads: 4,
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+
+    let child = {
+        let handle = handle.clone();
+        thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            let ret = listen(service, "tcp:127.0.0.1:0", &config);
+            if ret.is_err() {
+                handle.shutdown();
+            }
+            ret
+        })
+    };
+
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    let pool = ConnectionPool::new(&address, 2);
+    assert!(pool.is_empty());
+    {
+        let first = pool.get()?;
+        let mut client = OrgVarlinkServiceClient::new(pool.get()?);
+        assert_eq!(client.get_info()?.product, "test service");
+        assert_eq!(pool.len(), 2);
+
+        // both connections are in use
+        match pool.get() {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::ConnectionBusy),
+            Ok(_) => panic!("got a connection in use"),
+        }
+        match pool.get_timeout(time::Duration::from_millis(20)) {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::Timeout),
+            Ok(_) => panic!("got a connection in use"),
+        }
+
+        drop(client);
+        let again = pool.get()?;
+        assert!(!Arc::ptr_eq(&again, &first));
+        let mut client = OrgVarlinkServiceClient::new(again);
+        assert_eq!(client.get_info()?.product, "test service");
+    }
+
+    // the service closes the idle connections
+    handle.shutdown();
+    child.join().unwrap()?;
+    thread::sleep(time::Duration::from_millis(50));
+    assert!(pool.get().is_err());
+    assert!(pool.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_dynamic_service() -> Result<()> {
+    use std::fs;
+
+    let dir = tempfile::tempdir().map_err(map_context!())?;
+    fs::write(
+        dir.path().join("org.example.ping.varlink"),
+        "interface org.example.ping\nmethod Ping(ping: string) -> (pong: string)\nerror Empty ()",
+    )
+    .map_err(map_context!())?;
+    fs::write(dir.path().join("README"), "not an interface").map_err(map_context!())?;
+
+    let service = VarlinkService::from_dir(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        dir.path(),
+        |method: &str, parameters: Value| {
+            assert_eq!(method, "org.example.ping.Ping");
+            match parameters["ping"].as_str() {
+                Some("") => Err(Reply::error("org.example.ping.Empty", None)),
+                _ => Ok(serde_json::json!({ "pong": parameters["ping"] })),
+            }
+        },
+    )?;
+
+    let mut w = vec![];
+    let mut br = concat!(
+        r#"{"method":"org.example.ping.Ping","parameters":{"ping":"a"}}"#,
+        "\0",
+        r#"{"method":"org.example.ping.Ping","parameters":{"ping":""}}"#,
+        "\0",
+        r#"{"method":"org.example.ping.Pong"}"#,
+        "\0",
+        r#"{"method":"org.varlink.service.GetInterfaceDescription","parameters":{"interface":"org.example.ping"}}"#,
+        "\0"
+    )
+    .as_bytes();
+    service.handle(&mut br, &mut w, None)?;
+
+    let replies: Vec<&str> = std::str::from_utf8(&w)
+        .unwrap()
+        .split_terminator('\0')
+        .collect();
+    assert_eq!(replies[0], r#"{"parameters":{"pong":"a"}}"#);
+    assert_eq!(replies[1], r#"{"error":"org.example.ping.Empty"}"#);
+    assert_eq!(
+        replies[2],
+        r#"{"error":"org.varlink.service.MethodNotFound","parameters":{"method":"org.example.ping.Pong"}}"#
+    );
+    assert!(replies[3].contains("method Ping(ping: string) -> (pong: string)"));
+
+    fs::write(dir.path().join("broken.varlink"), "interface").map_err(map_context!())?;
+    match VarlinkService::from_dir("a", "b", "c", "d", dir.path(), |_: &str, v: Value| Ok(v)) {
+        Err(e) => match e.kind() {
+            ErrorKind::InvalidInterface(m) => assert!(m.contains("broken.varlink")),
+            k => panic!("unexpected error {}", k),
+        },
+        Ok(_) => panic!("loaded a broken interface"),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reconnect_policy() -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let address = format!("tcp:{}", listener.local_addr().map_err(map_context!())?);
+
+    // serves a single call per connection, like a service restarting after every call
+    let child = thread::spawn(move || -> Result<()> {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        for _ in 0..4 {
+            let (mut stream, _) = listener.accept().map_err(map_context!())?;
+            let mut request = Vec::new();
+            BufReader::new(stream.try_clone().map_err(map_context!())?)
+                .read_until(0, &mut request)
+                .map_err(map_context!())?;
+            let mut reply = Vec::new();
+            service.handle(&mut request.as_slice(), &mut reply, None)?;
+            stream.write_all(&reply).map_err(map_context!())?;
+        }
+        Ok(())
+    });
+
+    let connection = Connection::new(&address)?;
+    assert_eq!(
+        connection.read().unwrap().reconnect_policy(),
+        ReconnectPolicy::Never
+    );
+    connection
+        .write()
+        .unwrap()
+        .set_reconnect_policy(ReconnectPolicy::Reconnect);
+    let mut client = OrgVarlinkServiceClient::new(connection.clone());
+    assert_eq!(client.get_info()?.product, "test service");
+    // the failed call is not repeated, but the next one succeeds
+    assert!(client.get_info().is_err());
+    assert_eq!(client.get_info()?.product, "test service");
+
+    let connection = Connection::new(&address)?;
+    connection
+        .write()
+        .unwrap()
+        .set_reconnect_policy(ReconnectPolicy::RetryAll);
+    let mut client = OrgVarlinkServiceClient::new(connection);
+    assert_eq!(client.get_info()?.product, "test service");
+    assert_eq!(client.get_info()?.product, "test service");
+
+    child.join().unwrap()
+}
+
+#[test]
+fn test_call_timeout() -> Result<()> {
+    // a service, which accepts the connection, but never replies
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+    let address = format!("tcp:{}", listener.local_addr().map_err(map_context!())?);
+    let conn = Connection::with_address(&address)?;
+    let (_stream, _) = listener.accept().map_err(map_context!())?;
+
+    let start = time::Instant::now();
+    let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+        conn.clone(),
+        "org.varlink.service.GetInfo",
+        GetInfoArgs {},
+    )
+    .with_timeout(time::Duration::from_millis(100))
+    .call()
+    .unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::Timeout);
+    assert!(start.elapsed() < time::Duration::from_secs(5));
+
+    // the reply of the first call might still arrive
+    let e = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+        conn,
+        "org.varlink.service.GetInfo",
+        GetInfoArgs {},
+    )
+    .call()
+    .unwrap_err();
+    assert_eq!(*e.kind(), ErrorKind::ConnectionBusy);
+
+    Ok(())
+}
+
+#[test]
+fn test_oneway() -> Result<()> {
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    let conn = Connection::with_address(&address)?;
+    MethodCall::<GetInterfaceDescriptionArgs, GetInterfaceDescriptionReply, Error>::new(
+        conn.clone(),
+        "org.varlink.service.GetInterfaceDescription",
+        GetInterfaceDescriptionArgs {
+            interface: "org.varlink.service".into(),
+        },
+    )
+    .oneway()?;
+    // also an error of a oneway call is not replied
+    MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+        conn.clone(),
+        "org.example.unknown.Method",
+        GetInfoArgs {},
+    )
+    .oneway()?;
+
+    // the next call gets its own reply
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    assert_eq!(call.get_info()?.product, "test service");
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_more_replies() -> Result<()> {
+    use serde_json::{json, Value};
+    use std::io::BufRead;
+
+    struct Count;
+
+    impl Interface for Count {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.count\nmethod Count(n: int) -> (i: int)\nerror NoMore ()"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.count"
+        }
+
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            let n = call
+                .get_parameters()
+                .and_then(|p| p["n"].as_i64())
+                .unwrap_or(1);
+            for i in 0..n - 1 {
+                if let Err(e) =
+                    call.reply_struct_continues(Reply::parameters(Some(json!({ "i": i }))))
+                {
+                    assert_eq!(*e.kind(), ErrorKind::CallContinuesMismatch);
+                    return call.reply_struct_final(Reply::error("org.example.count.NoMore", None));
+                }
+            }
+            call.reply_struct_final(Reply::parameters(Some(json!({ "i": n - 1 }))))
+        }
+    }
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![Box::new(Count)],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    let conn = Connection::with_address(&address)?;
+    let count = |n: i64| {
+        MethodCall::<Value, Value, Error>::new(
+            conn.clone(),
+            "org.example.count.Count",
+            json!({ "n": n }),
+        )
+    };
+
+    // a call, which is not sent yet, is sent with `more` by the iterator
+    let replies: Vec<i64> = count(3)
+        .map(|r| r.map(|v| v["i"].as_i64().unwrap()))
+        .collect::<Result<_>>()?;
+    assert_eq!(replies, vec![0, 1, 2]);
+
+    // a reply with `continues` needs `more`
+    let e = count(3).call().unwrap_err();
+    match e.kind() {
+        ErrorKind::VarlinkErrorReply(r) => {
+            assert_eq!(r.error, Some("org.example.count.NoMore".into()))
+        }
+        k => panic!("unexpected error {:?}", k),
+    }
+
+    // the same as a stream
+    let client = asyncio::wrap_blocking(conn.clone());
+    let mut stream = client.stream(|c| {
+        MethodCall::<Value, Value, Error>::new(
+            c.clone(),
+            "org.example.count.Count",
+            json!({ "n": 2 }),
+        )
+    });
+    assert_eq!(block_on(stream.next()).unwrap()?["i"], 0);
+    assert_eq!(block_on(stream.next()).unwrap()?["i"], 1);
+    assert!(block_on(stream.next()).is_none());
+    drop(client);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_upgrade_stream() -> Result<()> {
+    use crate::framing::{read_frame, write_frame};
+    use serde_json::{json, Value};
+    use std::io::BufRead;
+
+    struct Echo;
+
+    impl Interface for Echo {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.echo\nmethod Echo() -> ()"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.echo"
+        }
+
+        fn call_upgraded(&self, call: &mut Call, bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            match read_frame(bufreader, 1024)? {
+                Some(frame) => write_frame(call.writer, &frame)?,
+                None => return Err(context!(ErrorKind::ConnectionClosed)),
+            }
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            call.to_upgraded();
+            call.reply_struct(Reply::parameters(None))
+        }
+    }
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![Box::new(Echo)],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    let conn = Connection::with_address(&address)?;
+    let (_, mut stream) =
+        MethodCall::<Value, Value, Error>::new(conn.clone(), "org.example.echo.Echo", json!({}))
+            .upgrade_stream()?;
+    for data in &[&b"hello"[..], b"", b"world"] {
+        write_frame(&mut stream, data)?;
+        assert_eq!(read_frame(&mut stream, 1024)?, Some(data.to_vec()));
+    }
+
+    // the connection belongs to the stream now
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    assert_eq!(
+        *call.get_info().unwrap_err().kind(),
+        ErrorKind::ConnectionBusy
+    );
+    drop(stream);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_from_stream() -> Result<()> {
+    use std::net::{TcpListener, TcpStream};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let child = thread::spawn(move || -> Result<()> {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        server::handle_connection(&service, &mut reader, &mut writer)
+    });
+
+    let conn = Connection::from_stream(TcpStream::connect(address).unwrap());
+    assert_eq!(conn.read().unwrap().address(), "stream");
+    let mut call = OrgVarlinkServiceClient::new(conn.clone());
+    assert_eq!(call.get_info()?.product, "test service");
+    assert_eq!(call.get_info()?.product, "test service");
+
+    match conn.write().unwrap().reconnect() {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidAddress),
+        Ok(()) => panic!("reconnected a stream"),
+    }
+    drop(call);
+    drop(conn);
+    assert!(child.join().unwrap().is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback() -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    );
+    let conn = test::loopback(service);
+    assert_eq!(conn.read().unwrap().address(), "loopback");
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    assert_eq!(call.get_info()?.product, "test service");
+    match call.get_interface_description("org.example.unknown") {
+        Err(e) => match e.kind() {
+            ErrorKind::InvalidParameter(p) => assert_eq!(p, "interface"),
+            k => panic!("unexpected error {:?}", k),
+        },
+        Ok(_) => panic!("unknown interface found"),
+    }
+
+    // the server end of a pair is driven by hand
+    let (conn, stream) = test::pair();
+    let server = thread::spawn(move || {
+        let test::ServerStream { reader, mut writer } = stream;
+        let mut reader = BufReader::new(reader);
+        let mut request = Vec::new();
+        reader.read_until(0, &mut request).unwrap();
+        let req: Request = from_slice(&request[..request.len() - 1]).unwrap();
+        assert_eq!(req.method, "org.varlink.service.GetInfo");
+        writer
+            .write_all(b"{\"error\":\"org.example.Failed\"}\0")
+            .unwrap();
+        // the client closed the connection
+        request.clear();
+        assert_eq!(reader.read_until(0, &mut request).unwrap(), 0);
+    });
+    let mut call = OrgVarlinkServiceClient::new(conn);
+    match call.get_info() {
+        Err(e) => match e.kind() {
+            ErrorKind::VarlinkErrorReply(r) => {
+                assert_eq!(r.error, Some("org.example.Failed".into()))
+            }
+            k => panic!("unexpected error {:?}", k),
+        },
+        Ok(_) => panic!("error reply expected"),
+    }
+    drop(call);
+    server.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_run_service() -> Result<()> {
+    let service = || {
+        VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        )
+    };
+    let server = test::run_service(service())?;
+    let other = test::run_service(service())?;
+    assert_ne!(server.address(), other.address());
+
+    let mut call = OrgVarlinkServiceClient::new(server.connect()?);
+    assert_eq!(call.get_info()?.product, "test service");
+    let mut call = OrgVarlinkServiceClient::new(Connection::with_address(other.address())?);
+    assert_eq!(call.get_info()?.product, "test service");
+
+    // dropping stops the server
+    let address = other.address().to_string();
+    drop(other);
+    assert!(Connection::with_address(&address).is_err());
+
+    server.stop()
+}
+
+#[test]
+fn test_resolve() -> Result<()> {
+    use serde_json::json;
+    use std::io::BufRead;
+
+    struct Resolver(String);
+
+    impl Interface for Resolver {
+        fn get_description(&self) -> &'static str {
+            "interface org.varlink.resolver\nmethod Resolve(interface: string) -> (address: string)\nerror InterfaceNotFound (interface: string)"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.varlink.resolver"
+        }
+
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            let interface = call
+                .get_parameters()
+                .and_then(|p| p["interface"].as_str().map(String::from))
+                .unwrap_or_default();
+            if interface == "org.varlink.service" {
+                call.reply_struct(Reply::parameters(Some(json!({ "address": self.0 }))))
+            } else {
+                call.reply_struct(Reply::error(
+                    "org.varlink.resolver.InterfaceNotFound",
+                    Some(json!({ "interface": interface })),
+                ))
+            }
+        }
+    }
+
+    let service = |interfaces| {
+        VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            interfaces,
+        )
+    };
+    let server = test::run_service(service(vec![]))?;
+    let resolver = test::run_service(service(vec![Box::new(Resolver(server.address().into()))]))?;
+
+    let address = resolver::resolve(resolver.address(), "org.varlink.service")?;
+    assert_eq!(address, server.address());
+    let mut call = OrgVarlinkServiceClient::new(Connection::with_address(&address)?);
+    assert_eq!(call.get_info()?.product, "test service");
+
+    match resolver::resolve(resolver.address(), "org.example.unknown") {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.unknown".into())
+        ),
+        Ok(a) => panic!("resolved to {}", a),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_resolver_service() -> Result<()> {
+    use resolver::{Registry, ResolverInterface};
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("registry.json");
+    let registry = Registry::with_file(&path)?;
+    registry.add("org.example.ping", "unix:/run/org.example.ping")?;
+    registry.add("org.example.more", "tcp:127.0.0.1:12345")?;
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "resolver",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(
+            ResolverInterface::new(registry.clone()).with_info(
+                "org.varlink",
+                "test resolver",
+                "0.1",
+                "http://varlink.org",
+            ),
+        )],
+    );
+    let server = test::run_service(service)?;
+
+    let info = resolver::get_info(server.address())?;
+    assert_eq!(info.product, "test resolver");
+    assert_eq!(
+        info.interfaces,
+        vec!["org.example.more", "org.example.ping"]
+    );
+    assert_eq!(
+        resolver::resolve(server.address(), "org.example.ping")?,
+        "unix:/run/org.example.ping"
+    );
+
+    // changes are served at once
+    assert_eq!(
+        registry.remove("org.example.ping")?,
+        Some("unix:/run/org.example.ping".into())
+    );
+    assert_eq!(registry.remove("org.example.ping")?, None);
+    match resolver::resolve(server.address(), "org.example.ping") {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.ping".into())
+        ),
+        Ok(a) => panic!("resolved to {}", a),
+    }
+
+    // and persisted
+    let registry = Registry::with_file(&path)?;
+    assert_eq!(registry.interfaces(), vec!["org.example.more"]);
+    assert_eq!(
+        registry.resolve("org.example.more"),
+        Some("tcp:127.0.0.1:12345".into())
+    );
+
+    server.stop()
+}
+
+#[test]
+fn test_bridge() -> Result<()> {
+    use crate::framing::{read_line, write_line};
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader};
+
+    struct Echo;
+
+    impl Interface for Echo {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.echo\nmethod Echo() -> ()"
+        }
+
+        fn get_name(&self) -> &'static str {
+            "org.example.echo"
+        }
+
+        fn call_upgraded(&self, call: &mut Call, bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            match read_line(bufreader, 1024)? {
+                Some(line) => write_line(call.writer, &line)?,
+                None => return Err(context!(ErrorKind::ConnectionClosed)),
+            }
+            Ok(Vec::new())
+        }
+
+        fn call(&self, call: &mut Call) -> Result<()> {
+            call.to_upgraded();
+            call.reply_struct(Reply::parameters(None))
+        }
+    }
+
+    let server = test::run_service(VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Echo)],
+    ))?;
+
+    let bridge = bridge::Bridge::new()
+        .with_resolver(None)
+        .route("org.example.echo", server.address())
+        .route("org.varlink.service", server.address());
+    let (conn, stream) = test::pair();
+    let child = thread::spawn(move || {
+        let test::ServerStream { reader, writer } = stream;
+        bridge.handle(BufReader::new(reader), writer)
+    });
+
+    // answered by the bridge
+    let mut call = OrgVarlinkServiceClient::new(conn.clone());
+    let info = call.get_info()?;
+    assert_eq!(info.product, "bridge");
+    assert_eq!(
+        info.interfaces,
+        vec!["org.example.echo", "org.varlink.service"]
+    );
+
+    // forwarded
+    let description = call
+        .get_interface_description("org.example.echo")?
+        .description
+        .unwrap();
+    assert!(description.starts_with("interface org.example.echo"));
+    match call.get_interface_description("org.example.unknown") {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.unknown".into())
+        ),
+        Ok(_) => panic!("unknown interface found"),
+    }
+
+    // an upgraded connection is passed through
+    let (_, mut stream) =
+        MethodCall::<Value, Value, Error>::new(conn, "org.example.echo.Echo", json!({}))
+            .upgrade_stream()?;
+    write_line(&mut stream, "hello")?;
+    assert_eq!(read_line(&mut stream, 1024)?, Some("hello".into()));
+    drop(stream);
+    drop(call);
+    assert!(child.join().unwrap().is_ok());
+
+    server.stop()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bridge_address() -> Result<()> {
+    use std::io::{BufRead, Write};
+
+    // `cat` echoes the messages of the client
+    let conn = Connection::with_address("bridge:cat")?;
+    let mut conn = conn.write().unwrap();
+    assert_eq!(conn.address(), "bridge");
+    {
+        let writer = conn.writer.as_mut().unwrap();
+        writer.write_all(b"{}\0").unwrap();
+        writer.flush().unwrap();
+    }
+    let mut buf = Vec::new();
+    conn.reader
+        .as_mut()
+        .unwrap()
+        .read_until(0, &mut buf)
+        .unwrap();
+    assert_eq!(buf, b"{}\0");
+
+    Ok(())
+}
+
+#[test]
+fn test_strict_validation() -> Result<()> {
+    use crate::dynamic::DynamicInterface;
+    use std::sync::Arc;
+
+    let interface = DynamicInterface::new(
+        r#"
+interface org.example.strict
+type Item (name: string, kind: (file, dir), tags: ?[]string)
+method Add(item: Item, count: int) -> (id: int)
+method Broken() -> (id: int)
+error Full (limit: int)
+"#
+        .into(),
+        Arc::new(|method: &str, parameters: Value| match method {
+            "org.example.strict.Add" if parameters["count"] == 0 => Err(Reply::error(
+                "org.example.strict.Full",
+                Some(serde_json::json!({"limit": 10})),
+            )),
+            "org.example.strict.Add" => Ok(serde_json::json!({ "id": 1 })),
+            _ => Ok(serde_json::json!({ "id": "one" })),
+        }),
+    )?;
+    let service = VarlinkService::try_new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(interface)],
+    )?
+    .with_strict_validation();
+
+    let call = |request: &str| -> Result<String> {
+        let mut w = vec![];
+        let br = format!("{}\0", request);
+        service.handle(&mut br.as_bytes(), &mut w, None)?;
+        Ok(String::from_utf8(w).unwrap().trim_end_matches('\0').into())
+    };
+    let invalid = |parameter: &str| {
+        format!(
+            r#"{{"error":"org.varlink.service.InvalidParameter","parameters":{{"parameter":"{}"}}}}"#,
+            parameter
+        )
+    };
+
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir","tags":["x"]},"count":2}}"#
+        )?,
+        r#"{"parameters":{"id":1}}"#
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir"},"count":0}}"#
+        )?,
+        r#"{"error":"org.example.strict.Full","parameters":{"limit":10}}"#
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir"}}}"#
+        )?,
+        invalid("count")
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"link"},"count":1}}"#
+        )?,
+        invalid("item.kind")
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir","tags":["x",1]},"count":1}}"#
+        )?,
+        invalid("item.tags[1]")
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir"},"count":1.5}}"#
+        )?,
+        invalid("count")
+    );
+    assert_eq!(
+        call(
+            r#"{"method":"org.example.strict.Add","parameters":{"item":{"name":"a","kind":"dir"},"count":1,"extra":true}}"#
+        )?,
+        invalid("extra")
+    );
+    assert_eq!(
+        call(r#"{"method":"org.example.strict.Add","parameters":[]}"#)?,
+        invalid("parameters")
+    );
+    assert_eq!(
+        call(r#"{"method":"org.varlink.service.GetInterfaceDescription","parameters":{}}"#)?,
+        invalid("interface")
+    );
+
+    // a reply breaking the interface is replaced and fails the call
+    let mut w = vec![];
+    let br = concat!(r#"{"method":"org.example.strict.Broken"}"#, "\0");
+    match service.handle(&mut br.as_bytes(), &mut w, None) {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidParameter("id".into())),
+        Ok(_) => panic!("invalid reply was sent"),
+    }
+    assert_eq!(String::from_utf8(w).unwrap(), invalid("id") + "\0");
+
+    Ok(())
+}
+
+#[test]
+fn test_error_context() -> Result<()> {
+    let address = "unix:/nonexistent/org.example.context";
+    match Connection::with_address(address) {
+        Err(e) => {
+            assert_eq!(e.address(), Some(address));
+            assert_eq!(e.method(), None);
+            assert!(e.to_string().ends_with(&format!(" (address: {})", address)));
+        }
+        Ok(_) => panic!("connected to {}", address),
+    }
+
+    let server = test::run_service(VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    ))?;
+    let connection = Connection::with_address(server.address())?;
+    let mut call = MethodCall::<GetInfoArgs, ServiceInfo, Error>::new(
+        connection.clone(),
+        "org.example.unknown.Ping",
+        GetInfoArgs {},
+    );
+    match call.call() {
+        Err(e) => {
+            match e.kind() {
+                ErrorKind::InterfaceNotFound(ref i) => assert_eq!(i, "org.example.unknown"),
+                kind => panic!("unexpected error {:?}", kind),
+            }
+            assert_eq!(e.address(), Some(server.address()));
+            assert_eq!(e.interface(), Some("org.example.unknown"));
+            assert_eq!(e.method(), Some("Ping"));
+            assert!(e.to_string().ends_with(&format!(
+                " (address: {}, method: org.example.unknown.Ping)",
+                server.address()
+            )));
+        }
+        Ok(info) => panic!("unexpected reply {:?}", info),
+    }
+
+    // the first context is kept
+    let e = Error::from(ErrorKind::ConnectionClosed)
+        .with_method("org.example.ping.Ping")
+        .with_method("org.example.other.Other");
+    assert_eq!(e.interface(), Some("org.example.ping"));
+    assert_eq!(e.method(), Some("Ping"));
+    assert_eq!(e.address(), None);
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_reply_error_helpers() -> Result<()> {
+    let request = Request::create("org.example.Watch", None);
+    let reply = |f: &dyn Fn(&mut Call) -> Result<()>| -> Result<(String, Reply)> {
+        let mut w = vec![];
+        f(&mut Call::new(&mut w, &request))?;
+        let reply = if w.is_empty() {
+            Reply::default()
+        } else {
+            serde_json::from_slice(&w[..w.len() - 1]).map_err(map_context!())?
+        };
+        Ok((String::from_utf8_lossy(&w).to_string(), reply))
+    };
+
+    let (w, r) = reply(&|call| call.reply_permission_denied())?;
+    assert_eq!(w, "{\"error\":\"org.varlink.service.PermissionDenied\"}\0");
+    assert_eq!(ErrorKind::from(r), ErrorKind::PermissionDenied);
+
+    let (w, r) = reply(&|call| call.reply_expected_more())?;
+    assert_eq!(w, "{\"error\":\"org.varlink.service.ExpectedMore\"}\0");
+    assert!(ErrorKind::is_error(&r));
+    assert_eq!(ErrorKind::from(r), ErrorKind::ExpectedMore);
+
+    let (w, _) = reply(&|call| {
+        call.reply_error(
+            "org.example.ftl.NotFound",
+            Some(serde_json::json!({"name": "x"})),
+        )
+    })?;
+    assert_eq!(
+        w,
+        "{\"error\":\"org.example.ftl.NotFound\",\"parameters\":{\"name\":\"x\"}}\0"
+    );
+
+    for name in &["NotFound", ".NotFound", "org.example.", "org..NotFound", ""] {
+        match reply(&|call| call.reply_error(name, None)) {
+            Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidErrorName(name.to_string())),
+            Ok((w, _)) => panic!("{:?} sent as {:?}", name, w),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_runtime_interfaces() -> Result<()> {
+    struct Ping;
+
+    impl Interface for Ping {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.ping\nmethod Ping(ping: string) -> (pong: string)"
+        }
+        fn get_name(&self) -> &'static str {
+            "org.example.ping"
+        }
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        fn call(&self, call: &mut Call) -> Result<()> {
+            let ping = call.get_parameters().map(|p| p["ping"].clone());
+            call.reply_parameters(serde_json::json!({ "pong": ping }))
+        }
+    }
+
+    let service = Arc::new(
+        VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        )
+        .with_strict_validation(),
+    );
+    let server = test::run_service(service.clone())?;
+    let connection = Connection::with_address(server.address())?;
+    let ping = |ping: Value| {
+        MethodCall::<Value, Value, Error>::new(
+            connection.clone(),
+            "org.example.ping.Ping",
+            json!({ "ping": ping }),
+        )
+        .call()
+    };
+
+    match ping(json!("a")) {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.ping".into())
+        ),
+        Ok(reply) => panic!("unexpected reply {:?}", reply),
+    }
+
+    service.add_interface(Box::new(Ping))?;
+    assert_eq!(
+        connection.get_info()?.interfaces,
+        vec!["org.varlink.service", "org.example.ping"]
+    );
+    assert_eq!(ping(json!("a"))?, json!({"pong": "a"}));
+    // the added interface is validated
+    match ping(json!(1)) {
+        Err(e) => assert_eq!(*e.kind(), ErrorKind::InvalidParameter("ping".into())),
+        Ok(reply) => panic!("unexpected reply {:?}", reply),
+    }
+    assert!(service.add_interface(Box::new(Ping)).is_err());
+
+    assert!(service.remove_interface("org.example.ping"));
+    assert!(!service.remove_interface("org.example.ping"));
+    assert!(!service.remove_interface("org.varlink.service"));
+    assert_eq!(
+        connection.get_info()?.interfaces,
+        vec!["org.varlink.service"]
+    );
+    assert!(ping(json!("a")).is_err());
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_fallback() -> Result<()> {
+    const ECHO: &str = "interface org.example.echo\nmethod Echo(a: int) -> (a: int)";
+
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    )
+    .with_fallback(|interface: &str, call: &mut Call| {
+        if interface != "org.example.echo" {
+            return call.reply_interface_not_found(Some(interface.into()));
+        }
+        if call.get_method() == Some("org.varlink.service.GetInterfaceDescription") {
+            return call.reply_parameters(json!({ "description": ECHO }));
+        }
+        let parameters = call.get_parameters().cloned().unwrap_or(Value::Null);
+        call.reply_parameters(parameters)
+    });
+    let server = test::run_service(service)?;
+    let connection = Connection::with_address(server.address())?;
+    let call = |method: &'static str, parameters: Value| {
+        MethodCall::<Value, Value, Error>::new(connection.clone(), method, parameters).call()
+    };
+
+    assert_eq!(
+        call("org.example.echo.Echo", json!({"a": 1}))?,
+        json!({"a": 1})
+    );
+    assert_eq!(
+        call(
+            "org.varlink.service.GetInterfaceDescription",
+            json!({"interface": "org.example.echo"})
+        )?,
+        json!({ "description": ECHO })
+    );
+    match call("org.example.other.Other", json!({})) {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.other".into())
+        ),
+        Ok(reply) => panic!("unexpected reply {:?}", reply),
+    }
+    // the interfaces of the fallback are not listed
+    assert_eq!(
+        connection.get_info()?.interfaces,
+        vec!["org.varlink.service"]
+    );
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_proxy() -> Result<()> {
+    use crate::proxy::Proxy;
+
+    let service = |interfaces: Vec<Box<dyn Interface + Send + Sync>>| {
+        VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            interfaces,
+        )
+    };
+    let ticker = test::run_service(service(vec![Box::new(Ticker)]))?;
+    let other = test::run_service(service(vec![]))?;
+
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        ..Default::default()
+    };
+    let proxy = Proxy::new()
+        .route("org.example.ticker", ticker.address())
+        .route("org.example.other", other.address());
+    let child = thread::spawn(move || proxy.listen("tcp:127.0.0.1:0", &config));
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    let connection = Connection::with_address(&address)?;
+    let info = connection.get_info()?;
+    assert_eq!(info.product, "proxy");
+    assert_eq!(
+        info.interfaces,
+        vec!["org.example.other", "org.example.ticker"]
+    );
+
+    // the replies of a call with more are forwarded
+    let mut call = MethodCall::<GetInfoArgs, Value, Error>::new(
+        connection.clone(),
+        "org.example.ticker.Ticks",
+        GetInfoArgs {},
+    );
+    let ticks: Vec<Value> = call
+        .more()?
+        .map(|r| r.map(|v| v["n"].clone()))
+        .collect::<Result<_>>()?;
+    assert_eq!(ticks, vec![json!(0), json!(1), json!(2)]);
+
+    // the other service does not implement the interface routed to it
+    let mut call = MethodCall::<GetInfoArgs, Value, Error>::new(
+        connection.clone(),
+        "org.example.other.Other",
+        GetInfoArgs {},
+    );
+    match call.call() {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.other".into())
+        ),
+        Ok(reply) => panic!("unexpected reply {:?}", reply),
+    }
+    let mut call = MethodCall::<GetInfoArgs, Value, Error>::new(
+        connection,
+        "org.example.unknown.Unknown",
+        GetInfoArgs {},
+    );
+    match call.call() {
+        Err(e) => assert_eq!(
+            *e.kind(),
+            ErrorKind::InterfaceNotFound("org.example.unknown".into())
+        ),
+        Ok(reply) => panic!("unexpected reply {:?}", reply),
+    }
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    ticker.stop()?;
+    other.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_is_alive() -> Result<()> {
+    use std::sync::mpsc;
+    use std::sync::Mutex;
+
+    /// Streams, until the client goes away
+    struct Stream(Mutex<mpsc::Sender<ErrorKind>>);
+
+    impl Interface for Stream {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.stream\nmethod Stream() -> ()"
+        }
+        fn get_name(&self) -> &'static str {
+            "org.example.stream"
+        }
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        fn call(&self, call: &mut Call) -> Result<()> {
+            call.set_continues(true);
+            call.reply_parameters(json!({}))?;
+            assert!(call.is_alive());
+            let ret = loop {
+                if let Err(e) = call.yield_point() {
+                    break e;
+                }
+                thread::sleep(time::Duration::from_millis(10));
+            };
+            self.0.lock().unwrap().send(ret.kind().clone()).unwrap();
+            Err(ret)
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let server = test::run_service(VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![Box::new(Stream(Mutex::new(sender)))],
+    ))?;
+
+    {
+        let connection = Connection::with_address(server.address())?;
+        let mut call = MethodCall::<GetInfoArgs, Value, Error>::new(
+            connection,
+            "org.example.stream.Stream",
+            GetInfoArgs {},
+        );
+        assert_eq!(call.more()?.next().unwrap()?, json!({}));
+        assert!(receiver
+            .recv_timeout(time::Duration::from_millis(100))
+            .is_err());
+    }
+
+    // the handler notices the closed connection without replying
+    assert_eq!(
+        receiver.recv_timeout(time::Duration::from_secs(5)),
+        Ok(ErrorKind::ConnectionClosed)
+    );
+
+    server.stop()?;
+    Ok(())
+}
+
+#[test]
+fn test_listen_panic() -> Result<()> {
+    use std::sync::Mutex;
+
+    struct Panic;
+
+    impl Interface for Panic {
+        fn get_description(&self) -> &'static str {
+            "interface org.example.panic\nmethod Panic() -> ()"
+        }
+        fn get_name(&self) -> &'static str {
+            "org.example.panic"
+        }
+        fn call_upgraded(&self, _call: &mut Call, _bufreader: &mut dyn BufRead) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        fn call(&self, _call: &mut Call) -> Result<()> {
+            panic!("the handler panicked")
+        }
+    }
+
+    let panics = Arc::new(Mutex::new(Vec::new()));
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        max_worker_threads: 1,
+        server_handle: Some(handle.clone()),
+        panic_handler: Some({
+            let panics = panics.clone();
+            Arc::new(move |message: &str| panics.lock().unwrap().push(message.to_string()))
+        }),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![Box::new(Panic)],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    for _ in 0..2 {
+        let connection = Connection::with_address(&address)?;
+        let mut call = MethodCall::<GetInfoArgs, Value, Error>::new(
+            connection,
+            "org.example.panic.Panic",
+            GetInfoArgs {},
+        );
+        assert!(call.call().is_err());
+    }
+
+    // the only worker survived and the connections were closed
+    let connection = Connection::with_address(&address)?;
+    assert_eq!(connection.get_info()?.product, "test service");
+    drop(connection);
+    assert_eq!(
+        *panics.lock().unwrap(),
+        vec!["the handler panicked", "the handler panicked"]
+    );
+    let metrics = handle.metrics();
+    assert_eq!(metrics.workers, 1);
+
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    assert_eq!(handle.metrics().active_connections, 0);
+    Ok(())
+}
+
+#[test]
+fn test_listen_executor() -> Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Spawns a thread per connection and counts them
+    struct Counting(AtomicUsize);
+
+    impl Executor for Counting {
+        fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ThreadPerConnection.execute(job)
+        }
+    }
+
+    let executor = Arc::new(Counting(AtomicUsize::new(0)));
+    let handle = ServerHandle::new();
+    let config = ListenConfig {
+        server_handle: Some(handle.clone()),
+        executor: Some(executor.clone()),
+        ..Default::default()
+    };
+    let child = thread::spawn(move || {
+        let service = VarlinkService::new(
+            "org.varlink",
+            "test service",
+            "0.1",
+            "http://varlink.org",
+            vec![],
+        );
+        listen(service, "tcp:127.0.0.1:0", &config)
+    });
+    let address = loop {
+        if let Some(address) = handle.local_address() {
+            break address;
+        }
+        thread::sleep(time::Duration::from_millis(10));
+    };
+
+    // both connections are served at once by their own threads
+    let first = Connection::with_address(&address)?;
+    let second = Connection::with_address(&address)?;
+    assert_eq!(first.get_info()?.product, "test service");
+    assert_eq!(second.get_info()?.product, "test service");
+    assert_eq!(executor.0.load(Ordering::SeqCst), 2);
+    assert_eq!(handle.metrics().workers, 0);
+
+    // listen waits for the connections of the executor
+    handle.shutdown();
+    assert!(child.join().unwrap().is_ok());
+    assert_eq!(handle.metrics().active_connections, 0);
+    Ok(())
+}
+
+#[test]
+fn test_shutdown_token() -> Result<()> {
+    let modes: &[bool] = if cfg!(unix) { &[false, true] } else { &[false] };
+    for single_threaded in modes {
+        let token = ShutdownToken::new()?;
+        // no handle and no flag, so the server waits for connections without polling
+        let config = ListenConfig {
+            shutdown_token: Some(token.clone()),
+            single_threaded: *single_threaded,
+            ..Default::default()
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").map_err(map_context!())?;
+        let address = format!("tcp:{}", listener.local_addr().map_err(map_context!())?);
+
+        let child = thread::spawn(move || {
+            let service = VarlinkService::new(
+                "org.varlink",
+                "test service",
+                "0.1",
+                "http://varlink.org",
+                vec![],
+            );
+            listen_on(service, listener, &config)
+        });
+
+        let connection = Connection::with_address(&address)?;
+        assert_eq!(connection.get_info()?.product, "test service");
+        assert!(!token.is_triggered());
+
+        token.trigger();
+        assert!(token.is_triggered());
+        assert!(child.join().unwrap().is_ok());
+
+        // the open connection is closed by the server
+        assert!(connection.get_info().is_err());
+        assert!(Connection::with_address(&address).is_err());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_pass_fds() -> Result<()> {
+    use std::io::Read;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    // replies the read end of a pipe with "hello" in it
+    let service = VarlinkService::new(
+        "org.varlink",
+        "test service",
+        "0.1",
+        "http://varlink.org",
+        vec![],
+    )
+    .with_fallback(|_interface: &str, call: &mut Call| {
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+        let reader = unsafe { std::fs::File::from_raw_fd(pipe[0]) };
+        let mut writer = unsafe { std::fs::File::from_raw_fd(pipe[1]) };
+        writer.write_all(b"hello").unwrap();
+        drop(writer);
+        // the client gets its own copy, when the reply is sent
+        let attached = call.attach_fds(&[reader.as_raw_fd()]).is_ok();
+        call.reply_parameters(json!({ "attached": attached }))
+    });
+    let service = Arc::new(service);
+
+    let serve = |address: &str| -> Result<(ServerHandle, thread::JoinHandle<Result<()>>, String)> {
+        let listener = Listener::new(address)?;
+        let address = listener.local_address()?;
+        let handle = ServerHandle::new();
+        let config = ListenConfig {
+            server_handle: Some(handle.clone()),
+            ..Default::default()
+        };
+        let service = service.clone();
+        let server = thread::spawn(move || listen_on(service, listener, &config));
+        Ok((handle, server, address))
+    };
+    let open = |connection: &Arc<RwLock<Connection>>| {
+        MethodCall::<Value, Value, Error>::new(
+            connection.clone(),
+            "org.example.fds.Open",
+            json!({}),
+        )
+        .call()
+    };
+
+    let dir = tempfile::tempdir().map_err(map_context!())?;
+    let (handle, server, address) =
+        serve(&format!("unix:{}", dir.path().join("socket").display()))?;
+    let connection = Connection::with_address(&address)?;
+    for _ in 0..2 {
+        assert_eq!(open(&connection)?, json!({ "attached": true }));
+
+        let fds = connection.read().unwrap().take_fds();
+        assert_eq!(fds.len(), 1);
+        let mut buf = String::new();
+        unsafe { std::fs::File::from_raw_fd(fds[0]) }
+            .read_to_string(&mut buf)
+            .map_err(map_context!())?;
+        assert_eq!(buf, "hello");
+        assert!(connection.read().unwrap().take_fds().is_empty());
+    }
+    handle.shutdown();
+    assert!(server.join().unwrap().is_ok());
+
+    // TCP connections cannot pass file descriptors
+    let (handle, server, address) = serve("tcp:127.0.0.1:0")?;
+    let connection = Connection::with_address(&address)?;
+    assert_eq!(open(&connection)?, json!({ "attached": false }));
+    assert!(connection.read().unwrap().take_fds().is_empty());
+    handle.shutdown();
+    assert!(server.join().unwrap().is_ok());
+    Ok(())
+}
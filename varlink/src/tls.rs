@@ -0,0 +1,353 @@
+//! TLS transport for `tcp+tls:` addresses, enabled with the `tls` feature
+
+#[cfg(feature = "server")]
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rustls::internal::pemfile;
+#[cfg(feature = "server")]
+use rustls::{AllowAnyAuthenticatedClient, NoClientAuth, ServerConfig, ServerSession};
+use rustls::{Certificate, PrivateKey, RootCertStore, Session, StreamOwned};
+#[cfg(feature = "client")]
+use rustls::{ClientConfig, ClientSession, ServerCertVerified, ServerCertVerifier, TLSError};
+
+use crate::error::*;
+use crate::stream::Stream;
+
+/// The certificates and keys for `tcp+tls:` addresses
+///
+/// A server needs `cert_chain` and `private_key`. If `ca_certs` is set, the server
+/// only accepts clients with a certificate signed by one of these CAs.
+///
+/// A client verifies the server certificate against `ca_certs`, and authenticates
+/// itself with `cert_chain` and `private_key`, if set. With `pinned_certs`, the client
+/// only accepts a server certificate with one of these SHA-256 fingerprints, in addition
+/// to the verification against `ca_certs`, or instead of it, if `ca_certs` is not set,
+/// e.g. for a self-signed certificate.
+///
+/// All files are PEM encoded.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// let tls = varlink::TlsConfig::new()
+///     .with_ca_certs("/etc/pki/varlink/ca.pem")
+///     .with_cert("/etc/pki/varlink/client.pem", "/etc/pki/varlink/client.key");
+/// let connection = varlink::Connection::with_tls("tcp+tls:example.org:12345", &tls)?;
+/// ```
+///
+/// Pinning the certificate of the service:
+///
+/// ```rust,ignore
+/// let fingerprint = varlink::TlsConfig::fingerprint("/etc/pki/varlink/service.pem")?;
+/// let tls = varlink::TlsConfig::new().with_pinned_cert(fingerprint);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// The certificate, followed by the intermediate certificates
+    pub cert_chain: Option<PathBuf>,
+    /// The private key of the certificate, in PKCS#8 or RSA format
+    pub private_key: Option<PathBuf>,
+    /// The certificates of the trusted CAs
+    pub ca_certs: Option<PathBuf>,
+    /// The SHA-256 fingerprints of the DER encoded server certificates, which a client
+    /// accepts
+    pub pinned_certs: Vec<[u8; 32]>,
+}
+
+fn tls_error<S: Into<String>>(msg: S) -> Error {
+    context!(ErrorKind::InvalidTlsConfig(msg.into()))
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(path).map_err(map_context!())?))
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
+    match pemfile::certs(&mut open(path)?) {
+        Ok(ref certs) if certs.is_empty() => Err(tls_error(format!(
+            "no certificates in '{}'",
+            path.display()
+        ))),
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(tls_error(format!(
+            "invalid certificates in '{}'",
+            path.display()
+        ))),
+    }
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+    }
+    if keys.is_empty() {
+        return Err(tls_error(format!("no private key in '{}'", path.display())));
+    }
+    Ok(keys.remove(0))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(&cert)
+            .map_err(|e| tls_error(format!("invalid CA in '{}': {:?}", path.display(), e)))?;
+    }
+    Ok(roots)
+}
+
+/// Accepts the server certificates with one of the pinned fingerprints, which are also
+/// signed by one of the CAs, if there are any.
+#[cfg(feature = "client")]
+struct PinnedCertVerifier {
+    pinned_certs: Vec<[u8; 32]>,
+    /// provides the default verifier of rustls, if the chain is verified against CAs
+    default: Option<ClientConfig>,
+}
+
+#[cfg(feature = "client")]
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> std::result::Result<ServerCertVerified, TLSError> {
+        if let Some(ref default) = self.default {
+            default.get_verifier().verify_server_cert(
+                roots,
+                presented_certs,
+                dns_name,
+                ocsp_response,
+            )?;
+        }
+        let cert = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        if self.pinned_certs.contains(&sha256(&cert.0)) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(
+                "the server certificate is not pinned".into(),
+            ))
+        }
+    }
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut fingerprint = [0; 32];
+    fingerprint.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, data).as_ref());
+    fingerprint
+}
+
+impl TlsConfig {
+    /// Returns an empty configuration, which is completed with the `with_*` methods.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Verifies the certificates of the peers against the CAs in the PEM file `ca_certs`.
+    pub fn with_ca_certs<P: Into<PathBuf>>(mut self, ca_certs: P) -> Self {
+        self.ca_certs = Some(ca_certs.into());
+        self
+    }
+
+    /// Authenticates with the certificates in the PEM file `cert_chain` and the key in
+    /// the PEM file `private_key`, which a server needs and a client uses for client
+    /// authentication.
+    pub fn with_cert<P: Into<PathBuf>, K: Into<PathBuf>>(
+        mut self,
+        cert_chain: P,
+        private_key: K,
+    ) -> Self {
+        self.cert_chain = Some(cert_chain.into());
+        self.private_key = Some(private_key.into());
+        self
+    }
+
+    /// Adds the SHA-256 `fingerprint` of a server certificate to the pinned certificates
+    /// of a client.
+    ///
+    /// See [`fingerprint`](#method.fingerprint).
+    pub fn with_pinned_cert(mut self, fingerprint: [u8; 32]) -> Self {
+        self.pinned_certs.push(fingerprint);
+        self
+    }
+
+    /// Returns the SHA-256 fingerprint of the first certificate in the PEM file `path`,
+    /// as shown by `openssl x509 -noout -fingerprint -sha256`.
+    pub fn fingerprint<P: AsRef<Path>>(path: P) -> Result<[u8; 32]> {
+        let certs = load_certs(path.as_ref())?;
+        Ok(sha256(&certs[0].0))
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn server_config(&self) -> Result<Arc<ServerConfig>> {
+        let (cert_chain, private_key) = match (&self.cert_chain, &self.private_key) {
+            (Some(c), Some(k)) => (c, k),
+            _ => return Err(tls_error("a server needs `cert_chain` and `private_key`")),
+        };
+
+        let mut config = match self.ca_certs {
+            Some(ref ca) => ServerConfig::new(AllowAnyAuthenticatedClient::new(load_roots(ca)?)),
+            None => ServerConfig::new(NoClientAuth::new()),
+        };
+        config
+            .set_single_cert(load_certs(cert_chain)?, load_private_key(private_key)?)
+            .map_err(|e| tls_error(e.to_string()))?;
+        Ok(Arc::new(config))
+    }
+
+    #[cfg(feature = "client")]
+    pub(crate) fn client_config(&self) -> Result<Arc<ClientConfig>> {
+        let mut config = ClientConfig::new();
+        match self.ca_certs {
+            Some(ref ca) => config.root_store = load_roots(ca)?,
+            None if !self.pinned_certs.is_empty() => {}
+            None => return Err(tls_error("a client needs `ca_certs` or `pinned_certs`")),
+        }
+        if !self.pinned_certs.is_empty() {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(PinnedCertVerifier {
+                    pinned_certs: self.pinned_certs.clone(),
+                    default: self.ca_certs.as_ref().map(|_| ClientConfig::new()),
+                }));
+        }
+        if let (Some(c), Some(k)) = (&self.cert_chain, &self.private_key) {
+            config.set_single_client_cert(load_certs(c)?, load_private_key(k)?);
+        }
+        Ok(Arc::new(config))
+    }
+}
+
+/// A TLS session over a TCP connection
+///
+/// The halves returned by `split()` share the session, so a read blocks a write
+/// from another thread, until data is received.
+pub(crate) struct TlsStream<S: Session> {
+    session: Arc<Mutex<StreamOwned<S, TcpStream>>>,
+    socket: TcpStream,
+}
+
+#[cfg(feature = "client")]
+impl TlsStream<ClientSession> {
+    /// Starts the session over `socket` connected to `host`, which must be a DNS name.
+    pub(crate) fn connect(host: &str, socket: TcpStream, config: &TlsConfig) -> Result<Self> {
+        let name = webpki::DNSNameRef::try_from_ascii_str(host)
+            .map_err(|_| tls_error(format!("'{}' is not a DNS name", host)))?;
+        let session = ClientSession::new(&config.client_config()?, name);
+        TlsStream::new(session, socket)
+    }
+}
+
+impl<S: Session> TlsStream<S> {
+    fn new(session: S, socket: TcpStream) -> Result<Self> {
+        let clone = socket.try_clone().map_err(map_context!())?;
+        Ok(TlsStream {
+            session: Arc::new(Mutex::new(StreamOwned::new(session, clone))),
+            socket,
+        })
+    }
+
+    fn clone_stream(&self) -> io::Result<Self> {
+        Ok(TlsStream {
+            session: self.session.clone(),
+            socket: self.socket.try_clone()?,
+        })
+    }
+}
+
+impl<S: Session> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.session.lock().unwrap().read(buf)
+    }
+}
+
+impl<S: Session> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.session.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.session.lock().unwrap().flush()
+    }
+}
+
+#[cfg(unix)]
+impl<S: Session> AsRawFd for TlsStream<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl<S: Session> AsRawSocket for TlsStream<S> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+impl<S: Session + 'static> Stream for TlsStream<S> {
+    fn split(&mut self) -> Result<(Box<dyn Read + Send + Sync>, Box<dyn Write + Send + Sync>)> {
+        Ok((
+            Box::new(self.clone_stream().map_err(map_context!())?),
+            Box::new(self.clone_stream().map_err(map_context!())?),
+        ))
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        // a blocked reader holds the session, then the peer gets no close_notify
+        if let Ok(mut s) = self.session.try_lock() {
+            s.sess.send_close_notify();
+            let _ = s.flush();
+        }
+        self.socket
+            .shutdown(Shutdown::Both)
+            .map_err(map_context!())?;
+        Ok(())
+    }
+
+    fn try_clone(&mut self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(self.clone_stream()?))
+    }
+
+    fn set_nonblocking(&mut self, b: bool) -> Result<()> {
+        self.socket.set_nonblocking(b).map_err(map_context!())?;
+        Ok(())
+    }
+}
+
+/// The server side configuration of a `tcp+tls:` listener
+#[cfg(feature = "server")]
+#[derive(Clone)]
+pub struct TlsAcceptor(Arc<ServerConfig>);
+
+#[cfg(feature = "server")]
+impl TlsAcceptor {
+    pub(crate) fn new(config: &TlsConfig) -> Result<Self> {
+        Ok(TlsAcceptor(config.server_config()?))
+    }
+
+    pub(crate) fn accept(&self, socket: TcpStream) -> Result<TlsStream<ServerSession>> {
+        TlsStream::new(ServerSession::new(&self.0), socket)
+    }
+}
+
+#[cfg(feature = "server")]
+impl fmt::Debug for TlsAcceptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TlsAcceptor")
+    }
+}
@@ -0,0 +1,21 @@
+//! Events for the `log` crate, compiled out without the `log` feature
+
+/// Emits a log event with the target `varlink` on the level `trace`, `debug`, `warn` or `error`.
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        log::$level!(target: "varlink", $($arg)+)
+    };
+}
+
+/// Emits a log event with the target `varlink` on the level `trace`, `debug`, `warn` or `error`.
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    };
+}
@@ -0,0 +1,203 @@
+//! Validation of the parameters of requests and replies against the interface definitions
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+use varlink_parser::{VStruct, VStructOrEnum, VType, VTypeExt, IDL};
+
+use crate::{Reply, Request};
+
+/// The parsed descriptions of the interfaces of a service
+///
+/// Created by [`VarlinkService::with_strict_validation`].
+///
+/// [`VarlinkService::with_strict_validation`]: ../struct.VarlinkService.html#method.with_strict_validation
+pub(crate) struct Validator {
+    idls: HashMap<&'static str, IDL<'static>>,
+}
+
+impl Validator {
+    /// Parses `descriptions`.
+    ///
+    /// A description, which does not parse, is logged and skipped, so the calls of its
+    /// interface are not checked.
+    pub(crate) fn new<'a, I>(descriptions: I) -> Self
+    where
+        I: IntoIterator<Item = &'a &'static str>,
+    {
+        let mut idls = HashMap::new();
+        for description in descriptions {
+            match IDL::from_string(description) {
+                Ok(idl) => {
+                    idls.insert(idl.name, idl);
+                }
+                Err(e) => event!(error, "not validating an interface: {}", e),
+            }
+        }
+        Validator { idls }
+    }
+
+    /// Checks the parameters of `request` against the input of its method.
+    ///
+    /// Returns the path of the first offending field, e.g. `foo.bar[2]`. Methods of
+    /// unknown interfaces are not checked, they are answered by the dispatch.
+    pub(crate) fn check_request(&self, request: &Request) -> std::result::Result<(), String> {
+        match self.method(&request.method) {
+            Some((idl, method)) => {
+                let input = &idl.methods[method].input;
+                check_struct(idl, input, request.parameters.as_ref(), "")
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the parameters of `reply` to `request` against the output of the method,
+    /// or against the parameters of an error of the same interface.
+    ///
+    /// Returns the path of the first offending field.
+    pub(crate) fn check_reply(
+        &self,
+        request: &Request,
+        reply: &Reply,
+    ) -> std::result::Result<(), String> {
+        let (idl, method) = match self.method(&request.method) {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let fields = match reply.error {
+            None => &idl.methods[method].output,
+            Some(ref error) => match error.rfind('.') {
+                Some(n) if &error[..n] == idl.name => match idl.errors.get(&error[n + 1..]) {
+                    Some(e) => &e.parm,
+                    None => return Err(error.to_string()),
+                },
+                // errors of other interfaces, e.g. org.varlink.service, are not known
+                _ => return Ok(()),
+            },
+        };
+        check_struct(idl, fields, reply.parameters.as_ref(), "")
+    }
+
+    /// Returns the interface and the name of `method`, if the interface is known and
+    /// defines it.
+    fn method<'b>(&self, method: &'b str) -> Option<(&IDL<'static>, &'b str)> {
+        let n = method.rfind('.')?;
+        let idl = self.idls.get(&method[..n])?;
+        let name = &method[n + 1..];
+        if idl.methods.contains_key(name) {
+            Some((idl, name))
+        } else {
+            None
+        }
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.into()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// A missing object is an empty object, e.g. the parameters of `GetInfo()`.
+fn check_struct(
+    idl: &IDL,
+    fields: &VStruct,
+    value: Option<&Value>,
+    path: &str,
+) -> std::result::Result<(), String> {
+    let empty = Map::new();
+    let object = match value {
+        None | Some(Value::Null) => &empty,
+        Some(Value::Object(ref object)) => object,
+        Some(_) if path.is_empty() => return Err("parameters".into()),
+        Some(_) => return Err(path.into()),
+    };
+
+    for name in object.keys() {
+        if !fields.elts.iter().any(|a| a.name == name) {
+            return Err(join(path, name));
+        }
+    }
+    for argument in fields.elts.iter() {
+        check_type(
+            idl,
+            &argument.vtype,
+            object.get(argument.name),
+            &join(path, argument.name),
+        )?;
+    }
+    Ok(())
+}
+
+fn check_type(
+    idl: &IDL,
+    vtype: &VTypeExt,
+    value: Option<&Value>,
+    path: &str,
+) -> std::result::Result<(), String> {
+    let value = match (vtype, value) {
+        (VTypeExt::Option(_), None) | (VTypeExt::Option(_), Some(Value::Null)) => return Ok(()),
+        (VTypeExt::Option(ref t), value) => return check_type(idl, t, value, path),
+        (_, None) | (_, Some(Value::Null)) => return Err(path.into()),
+        (_, Some(value)) => value,
+    };
+
+    let valid = match (vtype, value) {
+        (VTypeExt::Array(ref t), Value::Array(ref values)) => {
+            for (i, v) in values.iter().enumerate() {
+                check_type(idl, t, Some(v), &format!("{}[{}]", path, i))?;
+            }
+            true
+        }
+        (VTypeExt::Dict(ref t), Value::Object(ref values)) => {
+            for (k, v) in values.iter() {
+                check_type(idl, t, Some(v), &join(path, k))?;
+            }
+            true
+        }
+        (VTypeExt::Plain(ref t), value) => return check_plain(idl, t, value, path),
+        _ => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(path.into())
+    }
+}
+
+fn check_plain(
+    idl: &IDL,
+    vtype: &VType,
+    value: &Value,
+    path: &str,
+) -> std::result::Result<(), String> {
+    let valid = match *vtype {
+        VType::Bool => value.is_boolean(),
+        VType::Int => value.is_i64() || value.is_u64(),
+        VType::Float => value.is_number(),
+        VType::String => value.is_string(),
+        VType::Object => true,
+        VType::Struct(ref fields) => return check_struct(idl, fields, Some(value), path),
+        VType::Enum(ref values) => match value.as_str() {
+            Some(s) => values.elts.contains(&s),
+            None => false,
+        },
+        VType::Typename(name) => match idl.typedefs.get(name).map(|t| &t.elt) {
+            Some(VStructOrEnum::VStruct(ref fields)) => {
+                return check_struct(idl, fields, Some(value), path)
+            }
+            Some(VStructOrEnum::VEnum(ref values)) => match value.as_str() {
+                Some(s) => values.elts.contains(&s),
+                None => false,
+            },
+            None => false,
+        },
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(path.into())
+    }
+}
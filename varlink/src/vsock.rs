@@ -0,0 +1,147 @@
+//! AF_VSOCK transport for `vsock:cid:port` addresses between virtual machines and their host
+//!
+//! The CID is a number, e.g. `2` for the host, or `any` to listen on all CIDs.
+
+use std::io;
+use std::mem;
+#[cfg(feature = "server")]
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+
+use libc::{sockaddr, sockaddr_vm, socklen_t, AF_VSOCK, SOCK_CLOEXEC, SOCK_STREAM, VMADDR_CID_ANY};
+
+use crate::error::*;
+
+fn socket_address(cid: Option<u32>, port: u32) -> sockaddr_vm {
+    let mut addr: sockaddr_vm = unsafe { mem::zeroed() };
+    addr.svm_family = AF_VSOCK as _;
+    addr.svm_cid = cid.unwrap_or(VMADDR_CID_ANY);
+    addr.svm_port = port;
+    addr
+}
+
+/// A socket file descriptor, which is closed on drop
+struct Socket(RawFd);
+
+impl Socket {
+    fn new() -> io::Result<Socket> {
+        let fd = unsafe { libc::socket(AF_VSOCK, SOCK_STREAM | SOCK_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Socket(fd))
+    }
+
+    #[cfg(feature = "client")]
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.0;
+        mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+fn check(ret: libc::c_int) -> Result<()> {
+    if ret < 0 {
+        return Err(context!(ErrorKind::from(&io::Error::last_os_error())));
+    }
+    Ok(())
+}
+
+/// Connects to the `cid:port` of a `vsock:` address, `None` is any CID.
+///
+/// The connection is returned as a `UnixStream`, because the standard library
+/// has no type for vsock streams, and all stream operations work the same.
+#[cfg(feature = "client")]
+pub fn connect(cid: Option<u32>, port: u32) -> Result<UnixStream> {
+    let addr = socket_address(cid, port);
+    let socket = Socket::new().map_err(map_context!())?;
+    check(unsafe {
+        libc::connect(
+            socket.0,
+            &addr as *const sockaddr_vm as *const sockaddr,
+            mem::size_of::<sockaddr_vm>() as socklen_t,
+        )
+    })?;
+    Ok(unsafe { UnixStream::from_raw_fd(socket.into_raw_fd()) })
+}
+
+/// A vsock socket listening on the `cid:port` of a `vsock:` address
+#[cfg(feature = "server")]
+pub struct VsockListener(Socket);
+
+#[cfg(feature = "server")]
+impl VsockListener {
+    pub fn bind(cid: Option<u32>, port: u32) -> Result<VsockListener> {
+        let addr = socket_address(cid, port);
+        let socket = Socket::new().map_err(map_context!())?;
+        check(unsafe {
+            libc::bind(
+                socket.0,
+                &addr as *const sockaddr_vm as *const sockaddr,
+                mem::size_of::<sockaddr_vm>() as socklen_t,
+            )
+        })?;
+        check(unsafe { libc::listen(socket.0, 128) })?;
+        Ok(VsockListener(socket))
+    }
+
+    /// Accepts a connection, see [`connect`](fn.connect.html) for the returned type.
+    pub fn accept(&self) -> Result<UnixStream> {
+        let fd = unsafe {
+            libc::accept4(
+                (self.0).0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                SOCK_CLOEXEC,
+            )
+        };
+        check(fd)?;
+        Ok(unsafe { UnixStream::from_raw_fd(fd) })
+    }
+
+    /// Returns the `vsock:cid:port` address of the listener.
+    pub fn local_address(&self) -> Result<String> {
+        let mut addr: sockaddr_vm = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<sockaddr_vm>() as socklen_t;
+        check(unsafe {
+            libc::getsockname(
+                (self.0).0,
+                &mut addr as *mut sockaddr_vm as *mut sockaddr,
+                &mut len,
+            )
+        })?;
+        if addr.svm_cid == VMADDR_CID_ANY {
+            Ok(format!("vsock:any:{}", addr.svm_port))
+        } else {
+            Ok(format!("vsock:{}:{}", addr.svm_cid, addr.svm_port))
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let mut nonblocking = nonblocking as libc::c_int;
+        check(unsafe { libc::ioctl((self.0).0, libc::FIONBIO, &mut nonblocking) })
+    }
+}
+
+#[cfg(feature = "server")]
+impl AsRawFd for VsockListener {
+    fn as_raw_fd(&self) -> RawFd {
+        (self.0).0
+    }
+}
+
+#[cfg(feature = "server")]
+impl std::fmt::Debug for VsockListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "VsockListener({})", (self.0).0)
+    }
+}
@@ -4,12 +4,33 @@
 //! # Usage
 //!
 //! ~~~norun
-//! $ varlink-rust-generator `[<varlink_file>]`
+//! $ varlink-rust-generator `[OPTIONS] [<varlink_file>]`
 //! ~~~
 //!
-//! If <varlink_file> is omitted, input is expected to come from stdin.
+//! If <varlink_file> is omitted or `-`, input is expected to come from stdin.
 //!
-//! Output is sent to stdout.
+//! Output is sent to stdout, or to the file given with `--output`. This allows build
+//! systems, which can't call the `cargo_build` functions of a `build.rs`, like Bazel or
+//! Meson, to generate the code.
+//!
+//! With `--side`, only the client or the server side of the interface is generated.
+//!
+//! `--field-naming camelCase` or `--field-naming snake_case` generates snake_case field
+//! names, which are renamed to the names of the interface by serde.
+//!
+//! `--bool-type`, `--int-type`, `--float-type` and `--string-type` replace the rust types
+//! of the varlink types, `--preamble` inserts the rust code of a file at the beginning
+//! and `--example-tests` generates tests for the `# Example:` lines of the methods.
+//!
+//! `--format` runs `$RUSTFMT` or `rustfmt` on the output file, another formatter is set
+//! with `--formatter` and `--formatter-arg`. `--builtin-formatter` formats it with the
+//! built-in pretty printer.
+//!
+//! The lint warnings of the interface are printed to stderr, `--allow` disables a rule.
+//!
+//! With `--scaffold`, a `main.rs` of a service, which implements the methods with
+//! `todo!()` and listens on `--varlink=ADDRESS`, is written to the given file in
+//! addition.
 
 extern crate varlink_generator;
 
@@ -20,13 +41,21 @@ use std::io::{Read, Write};
 use std::path::Path;
 
 use chainerror::*;
-use varlink_generator::generate;
+use varlink_generator::{
+    generate_scaffold, generate_with_options, try_format_file, GeneratorOptions, LintRule,
+};
+use varlink_parser::{lint_with, IDL};
 
 fn print_usage(program: &str, opts: &getopts::Options) {
-    let brief = format!("Usage: {} [VARLINK FILE]", program);
+    let brief = format!("Usage: {} [OPTIONS] [VARLINK FILE]", program);
     print!("{}", opts.usage(&brief));
 }
 
+/// The options are kept for the lifetime of the process.
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let args: Vec<_> = env::args().collect();
     let program = args[0].clone();
@@ -34,6 +63,69 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let mut opts = getopts::Options::new();
     opts.optflag("h", "help", "print this help menu");
     opts.optflag("", "nosource", "don't print doc header and allow");
+    opts.optopt("o", "output", "write the generated code to FILE", "FILE");
+    opts.optopt(
+        "",
+        "side",
+        "generate only the client or the server side",
+        "client|server",
+    );
+    opts.optopt(
+        "",
+        "field-naming",
+        "the naming of the fields of the generated structs",
+        "verbatim|camelCase|snake_case",
+    );
+    opts.optopt("", "bool-type", "the rust type of varlink `bool`", "TYPE");
+    opts.optopt("", "int-type", "the rust type of varlink `int`", "TYPE");
+    opts.optopt("", "float-type", "the rust type of varlink `float`", "TYPE");
+    opts.optopt(
+        "",
+        "string-type",
+        "the rust type of varlink `string`",
+        "TYPE",
+    );
+    opts.optopt(
+        "",
+        "preamble",
+        "insert the rust code of FILE at the beginning",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "example-tests",
+        "generate tests for the `# Example:` lines of the methods",
+    );
+    opts.optflag("", "format", "format the output file");
+    opts.optopt(
+        "",
+        "formatter",
+        "the formatter of --format, defaults to $RUSTFMT or rustfmt",
+        "COMMAND",
+    );
+    opts.optmulti(
+        "",
+        "formatter-arg",
+        "an argument passed to the formatter before the output file",
+        "ARG",
+    );
+    opts.optflag(
+        "",
+        "builtin-formatter",
+        "format the output file with the built-in pretty printer",
+    );
+    opts.optmulti(
+        "",
+        "allow",
+        "don't print the warnings of a lint rule",
+        "RULE",
+    );
+    opts.optopt(
+        "",
+        "scaffold",
+        "write the main.rs of a service implementing the interface to FILE",
+        "FILE",
+    );
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -50,6 +142,59 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     }
 
     let tosource = !matches.opt_present("nosource");
+    let side = match matches.opt_str("side") {
+        Some(side) => side.parse()?,
+        None => Default::default(),
+    };
+    let field_naming = match matches.opt_str("field-naming") {
+        Some(field_naming) => field_naming.parse()?,
+        None => Default::default(),
+    };
+    let preamble = match matches.opt_str("preamble") {
+        Some(path) => {
+            let mut code = String::new();
+            File::open(&path)
+                .and_then(|mut f| f.read_to_string(&mut code))
+                .map_err(mstrerr!("Failed to read '{}'", &path))?;
+            let preamble = code
+                .parse()
+                .map_err(|e| strerr!("Failed to parse '{}': {:?}", &path, e))?;
+            Some(preamble)
+        }
+        None => None,
+    };
+    let mut allow_lints = Vec::new();
+    for name in matches.opt_strs("allow") {
+        match LintRule::all().iter().find(|r| r.name() == name) {
+            Some(rule) => allow_lints.push(*rule),
+            None => return Err(strerr!("Unknown lint rule '{}'", name).into()),
+        }
+    }
+
+    let options = GeneratorOptions {
+        bool_type: matches.opt_str("bool-type").map(leak),
+        int_type: matches.opt_str("int-type").map(leak),
+        float_type: matches.opt_str("float-type").map(leak),
+        string_type: matches.opt_str("string-type").map(leak),
+        preamble,
+        example_tests: matches.opt_present("example-tests"),
+        allow_lints,
+        formatter: matches.opt_str("formatter").map(leak),
+        formatter_args: matches
+            .opt_strs("formatter-arg")
+            .into_iter()
+            .map(leak)
+            .collect(),
+        builtin_formatter: matches.opt_present("builtin-formatter"),
+        side,
+        field_naming,
+    };
+
+    let output = matches.opt_str("output");
+    let format = matches.opt_present("format") || options.builtin_formatter;
+    if format && output.is_none() {
+        return Err(strerr!("Formatting needs an output file, see --output").into());
+    }
 
     let mut reader: Box<dyn Read> = match matches.free.len() {
         0 => Box::new(io::stdin()),
@@ -64,6 +209,43 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
             }
         }
     };
-    let writer: &mut dyn Write = &mut io::stdout();
-    generate(&mut reader, writer, tosource).map_err(|e| e.into())
+
+    let mut buffer = Vec::new();
+    reader
+        .read_to_end(&mut buffer)
+        .map_err(mstrerr!("Failed to read the varlink file"))?;
+
+    // parse errors are reported by the generator
+    if let Ok(idl) = IDL::from_string(&String::from_utf8_lossy(&buffer)) {
+        let rules: Vec<LintRule> = LintRule::all()
+            .iter()
+            .filter(|r| !options.allow_lints.contains(r))
+            .cloned()
+            .collect();
+        for warning in lint_with(&idl, &rules) {
+            eprintln!("warning: {}", warning);
+        }
+    }
+
+    if let Some(path) = matches.opt_str("scaffold") {
+        let mut file = File::create(&path).map_err(mstrerr!("Failed to create '{}'", &path))?;
+        generate_scaffold(&mut buffer.as_slice(), &mut file, &options)?;
+    }
+
+    match output {
+        Some(path) => {
+            let mut file = File::create(&path).map_err(mstrerr!("Failed to create '{}'", &path))?;
+            generate_with_options(&mut buffer.as_slice(), &mut file, &options, tosource)?;
+            drop(file);
+            if format {
+                try_format_file(&path, &options)?;
+            }
+            Ok(())
+        }
+        None => {
+            let writer: &mut dyn Write = &mut io::stdout();
+            generate_with_options(&mut buffer.as_slice(), writer, &options, tosource)
+                .map_err(|e| e.into())
+        }
+    }
 }
@@ -23,7 +23,26 @@
 //!                                              /* rustfmt */ true);
 //! }
 //! ```
+//!
+//! The fields of the generated structs have plain rust types, only the optional fields
+//! marked with `?` in the interface are `Option<>`. A call missing a required field is
+//! answered with `org.varlink.service.InvalidParameter` naming the field, before the
+//! method is called.
+//!
+//! An anonymous struct or enum, also inside of arrays and maps of any depth, e.g.
+//! `points: [][](x: int, y: int)`, is generated as a type named after its fields, here
+//! `<Struct>_points` in a `Vec<Vec<<Struct>_points>>`. If two names are the same, e.g.
+//! for the fields `a_b` and `a.b`, a number is appended to the later one.
+//!
+//! A `main.rs` of a service to start from is written by [`generate_scaffold`] or
+//! `varlink-rust-generator --scaffold src/main.rs`.
+//!
+//! Build systems without a `build.rs`, like Bazel or Meson, can run
+//! `varlink-rust-generator --output org_example_ping.rs --format org.example.ping.varlink`,
+//! which takes all the `GeneratorOptions` as flags.
+//!
 //! [`varlink_generator::cargo_build_tosource`]: fn.cargo_build_tosource.html
+//! [`generate_scaffold`]: fn.generate_scaffold.html
 
 #![recursion_limit = "512"]
 #![doc(
@@ -32,6 +51,8 @@
 )]
 
 use std::borrow::Cow;
+use std::collections::HashSet;
+use std::convert::TryInto;
 use std::env;
 use std::fs::File;
 use std::io::{Read, Write};
@@ -40,10 +61,16 @@ use std::process::{exit, Command};
 use std::str::FromStr;
 
 use chainerror::*;
-use proc_macro2::{Ident, Span, TokenStream};
+use proc_macro2::{Ident, Literal, Span, TokenStream, TokenTree};
 use quote::{format_ident, quote};
 
-use varlink_parser::{Typedef, VEnum, VError, VStruct, VStructOrEnum, VType, VTypeExt, IDL};
+use varlink_parser::{
+    lint_with, Argument, Typedef, VEnum, VError, VStruct, VStructOrEnum, VType, VTypeExt, IDL,
+};
+
+pub use varlink_parser::LintRule;
+
+mod pretty;
 
 derive_str_cherr!(Error);
 pub type Result<T> = ChainResult<T, Error>;
@@ -66,13 +93,176 @@ trait ToTokenStream<'short, 'long: 'short> {
     );
 }
 
+/// Options for the code generation
+///
+/// `int_type` and `float_type` replace the default `i64` and `f64` for the whole interface.
+/// A single field can be mapped with an annotation comment in the interface definition:
+///
+/// ```varlink
+/// method Resize(
+///   # @rust-type: u64
+///   size: int
+/// ) -> ()
+/// ```
+///
+/// The client calls of a method annotated with `# @idempotent` can be retried after a
+/// connection failure with `varlink::MethodCall::retries`.
 #[derive(Default)]
 pub struct GeneratorOptions {
     pub bool_type: Option<&'static str>,
+    /// rust type for varlink `int`, e.g. `"u64"` or `"i32"`
     pub int_type: Option<&'static str>,
+    /// rust type for varlink `float`, e.g. `"f32"`
     pub float_type: Option<&'static str>,
     pub string_type: Option<&'static str>,
     pub preamble: Option<TokenStream>,
+    /// generate serde round-trip tests for the `# Example:` lines of the methods
+    pub example_tests: bool,
+    /// lint rules, which are not reported as `cargo:warning` by the `cargo_build` functions
+    pub allow_lints: Vec<LintRule>,
+    /// formatter run by the `cargo_build_tosource` functions, defaults to `$RUSTFMT` or `"rustfmt"`
+    pub formatter: Option<&'static str>,
+    /// arguments passed to the formatter before the path of the generated file
+    pub formatter_args: Vec<&'static str>,
+    /// format with the built-in pretty printer instead of running the formatter, e.g. in
+    /// build environments without rustfmt
+    pub builtin_formatter: bool,
+    /// generate only the client or the server side of the interfaces
+    pub side: Side,
+    /// the naming of the fields of the generated structs
+    pub field_naming: FieldNaming,
+}
+
+/// The side of an interface generated by the generator
+///
+/// Both sides share the types, the `Args` and `Reply` structs of the methods and the
+/// error types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Side {
+    /// client and server
+    #[default]
+    Both,
+    /// `VarlinkClientInterface` and `VarlinkClient`
+    Client,
+    /// `VarlinkInterface`, the `Call_*` and `VarlinkCallError` traits and the
+    /// `varlink::Interface` proxy returned by `new()`
+    Server,
+}
+
+impl Side {
+    fn has_client(self) -> bool {
+        self != Side::Server
+    }
+
+    fn has_server(self) -> bool {
+        self != Side::Client
+    }
+}
+
+impl FromStr for Side {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        match s {
+            "both" => Ok(Side::Both),
+            "client" => Ok(Side::Client),
+            "server" => Ok(Side::Server),
+            _ => Err(Error(format!(
+                "invalid side `{}`, expected `both`, `client` or `server`",
+                s
+            ))),
+        }
+    }
+}
+
+/// The naming of the fields of the generated structs
+///
+/// The fields of the interface definition are always serialized with their names in the
+/// definition, only the names of the rust fields and parameters differ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FieldNaming {
+    /// the names of the interface definition, e.g. `fooBar: String`
+    #[default]
+    Verbatim,
+    /// snake_case names with `#[serde(rename_all = "camelCase")]` on the structs, for
+    /// interfaces with camelCase field names, e.g. `foo_bar: String`
+    ///
+    /// Fields, whose names are not converted back by serde, e.g. `URL`, get an explicit
+    /// `#[serde(rename)]`.
+    CamelCase,
+    /// snake_case names with an explicit `#[serde(rename)]` for every field
+    SnakeCase,
+}
+
+impl FieldNaming {
+    /// Returns the attribute of a generated struct.
+    fn struct_attr(self) -> TokenStream {
+        match self {
+            FieldNaming::CamelCase => quote!(#[serde(rename_all = "camelCase")]),
+            _ => quote!(),
+        }
+    }
+
+    /// Returns the rust identifier and the serde attribute of the field `name`.
+    fn field(self, name: &str) -> (Ident, TokenStream) {
+        let rust_name = match self {
+            FieldNaming::Verbatim => return (raw_ident(name), quote!()),
+            _ => to_snake_case(name),
+        };
+        let attr = if self == FieldNaming::CamelCase && to_camel_case(&rust_name) == name {
+            quote!()
+        } else {
+            quote!(#[serde(rename = #name)])
+        };
+        (raw_ident(&rust_name), attr)
+    }
+}
+
+impl FromStr for FieldNaming {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Error> {
+        match s {
+            "verbatim" => Ok(FieldNaming::Verbatim),
+            "camelCase" => Ok(FieldNaming::CamelCase),
+            "snake_case" => Ok(FieldNaming::SnakeCase),
+            _ => Err(Error(format!(
+                "invalid field naming `{}`, expected `verbatim`, `camelCase` or `snake_case`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Returns `name` for an anonymous struct or enum, or `name` with a number appended, if
+/// `tokenstream` defines a type of that name already.
+///
+/// The name of an anonymous type joins the names of the fields leading to it with `_`,
+/// so the fields `a_b` and `a.b` of a struct would both name a type `<Struct>_a_b`.
+fn anon_type_name(name: &str, tokenstream: &TokenStream) -> String {
+    let mut defined = HashSet::new();
+    let mut tokens = tokenstream.clone().into_iter();
+    while let Some(token) = tokens.next() {
+        if let TokenTree::Ident(ref ident) = token {
+            if ident == "struct" || ident == "enum" {
+                if let Some(TokenTree::Ident(name)) = tokens.next() {
+                    defined.insert(name.to_string().trim_start_matches("r#").to_string());
+                }
+            }
+        }
+    }
+
+    let mut unique = name.to_string();
+    let mut n = 2;
+    while defined.contains(&unique) {
+        unique = format!("{}_{}", name, n);
+        n += 1;
+    }
+    unique
+}
+
+fn raw_ident(name: &str) -> Ident {
+    syn::parse_str(&(String::from("r#") + name)).unwrap()
 }
 
 impl<'short, 'long: 'short> ToRustString<'short, 'long> for VType<'long> {
@@ -90,12 +280,14 @@ impl<'short, 'long: 'short> ToRustString<'short, 'long> for VType<'long> {
             VType::Object => "serde_json::Value".into(),
             VType::Typename(v) => v.into(),
             VType::Enum(ref v) => {
-                v.to_tokenstream(name, tokenstream, options);
-                Cow::Owned(name.to_string())
+                let name = anon_type_name(name, tokenstream);
+                v.to_tokenstream(&name, tokenstream, options);
+                Cow::Owned(name)
             }
             VType::Struct(ref v) => {
-                v.to_tokenstream(name, tokenstream, options);
-                Cow::Owned(name.to_string())
+                let name = anon_type_name(name, tokenstream);
+                v.to_tokenstream(&name, tokenstream, options);
+                Cow::Owned(name)
             }
         }
     }
@@ -130,6 +322,152 @@ impl<'short, 'long: 'short> ToRustString<'short, 'long> for VTypeExt<'long> {
     }
 }
 
+const INT_TYPES: &[&str] = &[
+    "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+];
+const FLOAT_TYPES: &[&str] = &["f32", "f64"];
+
+/// Returns the value of the annotation comment `# @<key>: <value>` in `doc`.
+fn get_annotation<'a>(doc: &'a str, key: &str) -> Option<&'a str> {
+    doc.lines()
+        .map(|l| l.trim_start_matches(|c: char| c == '#' || c.is_whitespace()))
+        .filter(|l| l.starts_with('@'))
+        .find_map(|l| {
+            let mut kv = l[1..].splitn(2, ':');
+            match kv.next() {
+                Some(k) if k.trim() == key => Some(kv.next().unwrap_or("").trim()),
+                _ => None,
+            }
+        })
+}
+
+/// Maps the `int` or `float` of `vtype` to the rust type `rtype`,
+/// keeping arrays, dicts and options around it.
+fn map_primitive(vtype: &VTypeExt, rtype: &str) -> Option<String> {
+    match *vtype {
+        VTypeExt::Plain(VType::Int) if INT_TYPES.contains(&rtype) => Some(rtype.into()),
+        VTypeExt::Plain(VType::Float) if FLOAT_TYPES.contains(&rtype) => Some(rtype.into()),
+        VTypeExt::Plain(_) => None,
+        VTypeExt::Array(ref v) => map_primitive(v, rtype).map(|t| format!("Vec<{}>", t)),
+        VTypeExt::Dict(ref v) => {
+            map_primitive(v, rtype).map(|t| format!("varlink::StringHashMap<{}>", t))
+        }
+        VTypeExt::Option(ref v) => map_primitive(v, rtype).map(|t| format!("Option<{}>", t)),
+    }
+}
+
+/// Returns the `# Example: <parameters> -> <reply parameters>` lines of `doc`
+/// as pairs of normalized JSON strings.
+fn get_examples(doc: &str, context: &str) -> Result<Vec<(String, String)>> {
+    let mut examples = Vec::new();
+    for line in doc.lines() {
+        let line = line.trim_start_matches(|c: char| c == '#' || c.is_whitespace());
+        if !line.starts_with("Example:") {
+            continue;
+        }
+        let example = line["Example:".len()..].trim();
+        let mut stream =
+            serde_json::Deserializer::from_str(example).into_iter::<serde_json::Value>();
+        let request = match stream.next() {
+            Some(Ok(v)) => v,
+            _ => {
+                return Err(strerr!(
+                    Error,
+                    "{}: invalid parameters in example `{}`",
+                    context,
+                    example
+                ))
+            }
+        };
+        let rest = example[stream.byte_offset()..].trim_start();
+        if !rest.starts_with("->") {
+            return Err(strerr!(
+                Error,
+                "{}: missing `->` in example `{}`",
+                context,
+                example
+            ));
+        }
+        let reply: serde_json::Value = serde_json::from_str(&rest[2..]).map_err(mstrerr!(
+            Error,
+            "{}: invalid reply in example `{}`",
+            context,
+            example
+        ))?;
+        examples.push((request.to_string(), reply.to_string()));
+    }
+    Ok(examples)
+}
+
+/// Converts the varlink comment of a method to a rust doc comment,
+/// rendering the examples as a call transcript.
+fn method_doc(doc: &str, examples: &[(String, String)]) -> String {
+    let mut lines = Vec::new();
+    for line in doc.lines() {
+        let line = line.trim_start().trim_start_matches('#');
+        let line = line.strip_prefix(' ').unwrap_or(line);
+        if line.starts_with('@') || line.starts_with("Example:") {
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    if !examples.is_empty() {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push("# Examples".into());
+        lines.push(String::new());
+        lines.push("```text".into());
+        for (request, reply) in examples {
+            lines.push(format!("-> {}", request));
+            lines.push(format!("<- {}", reply));
+        }
+        lines.push("```".into());
+    }
+    lines.join("\n")
+}
+
+fn argument_to_rust_string<'long>(
+    arg: &'long Argument<'long>,
+    name: &str,
+    tokenstream: &mut TokenStream,
+    options: &'long GeneratorOptions,
+) -> Cow<'long, str> {
+    get_annotation(arg.doc, "rust-type")
+        .and_then(|t| map_primitive(&arg.vtype, t))
+        .map(Cow::Owned)
+        .unwrap_or_else(|| arg.vtype.to_rust_string(name, tokenstream, options))
+}
+
+fn check_annotations_vtype(vtype: &VTypeExt, context: &str) -> Result<()> {
+    match *vtype {
+        VTypeExt::Plain(VType::Struct(ref v)) => check_annotations(v, context),
+        VTypeExt::Plain(_) => Ok(()),
+        VTypeExt::Array(ref v) | VTypeExt::Dict(ref v) | VTypeExt::Option(ref v) => {
+            check_annotations_vtype(v, context)
+        }
+    }
+}
+
+fn check_annotations(vstruct: &VStruct, context: &str) -> Result<()> {
+    for e in &vstruct.elts {
+        if let Some(t) = get_annotation(e.doc, "rust-type") {
+            if map_primitive(&e.vtype, t).is_none() {
+                return Err(strerr!(
+                    Error,
+                    "{}.{}: `@rust-type: {}` does not apply to type `{}`",
+                    context,
+                    e.name,
+                    t,
+                    e.vtype
+                ));
+            }
+        }
+        check_annotations_vtype(&e.vtype, &format!("{}.{}", context, e.name))?;
+    }
+    Ok(())
+}
+
 fn to_snake_case(mut str: &str) -> String {
     let mut words = vec![];
     // Preserve leading underscores
@@ -160,6 +498,23 @@ fn to_snake_case(mut str: &str) -> String {
     words.join("_")
 }
 
+/// Converts a snake_case name like serde's `rename_all = "camelCase"`.
+fn to_camel_case(str: &str) -> String {
+    let mut buf = String::new();
+    let mut capitalize = false;
+    for ch in str.chars() {
+        if ch == '_' {
+            capitalize = !buf.is_empty();
+        } else if capitalize {
+            buf.push(ch.to_ascii_uppercase());
+            capitalize = false;
+        } else {
+            buf.push(ch);
+        }
+    }
+    buf
+}
+
 impl<'short, 'long: 'short> ToTokenStream<'short, 'long> for VStruct<'long> {
     fn to_tokenstream(
         &'long self,
@@ -171,26 +526,30 @@ impl<'short, 'long: 'short> ToTokenStream<'short, 'long> for VStruct<'long> {
 
         let mut enames = vec![];
         let mut etypes = vec![];
+        let mut eanot = vec![];
         for e in &self.elts {
-            let ename_ident: Ident = syn::parse_str(&(String::from("r#") + e.name)).unwrap();
+            let (ename_ident, rename) = options.field_naming.field(e.name);
             enames.push(ename_ident);
+            eanot.push(rename);
             etypes.push(
                 TokenStream::from_str(
-                    e.vtype
-                        .to_rust_string(
-                            format!("{}_{}", name, e.name).as_ref(),
-                            tokenstream,
-                            options,
-                        )
-                        .as_ref(),
+                    argument_to_rust_string(
+                        e,
+                        format!("{}_{}", name, e.name).as_ref(),
+                        tokenstream,
+                        options,
+                    )
+                    .as_ref(),
                 )
                 .unwrap(),
             );
         }
+        let struct_attr = options.field_naming.struct_attr();
         tokenstream.extend(quote!(
             #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+            #struct_attr
             pub struct #tname {
-                #(pub #enames: #etypes,)*
+                #(#eanot pub #enames: #etypes,)*
             }
         ));
     }
@@ -247,28 +606,31 @@ impl<'short, 'long: 'short> ToTokenStream<'short, 'long> for VError<'long> {
         let mut args_anot = vec![];
 
         for e in &self.parm.elts {
+            let (ename_ident, rename) = options.field_naming.field(e.name);
             args_anot.push(if let VTypeExt::Option(_) = e.vtype {
-                quote!(#[serde(skip_serializing_if = "Option::is_none")])
+                quote!(#rename #[serde(skip_serializing_if = "Option::is_none")])
             } else {
-                quote!()
+                rename
             });
-            let ename_ident: Ident = syn::parse_str(&(String::from("r#") + e.name)).unwrap();
             args_enames.push(ename_ident);
             args_etypes.push(
                 TokenStream::from_str(
-                    e.vtype
-                        .to_rust_string(
-                            format!("{}_Args_{}", self.name, e.name).as_ref(),
-                            tokenstream,
-                            options,
-                        )
-                        .as_ref(),
+                    argument_to_rust_string(
+                        e,
+                        format!("{}_Args_{}", self.name, e.name).as_ref(),
+                        tokenstream,
+                        options,
+                    )
+                    .as_ref(),
                 )
                 .unwrap(),
             );
         }
+        let struct_attr = options.field_naming.struct_attr();
         tokenstream.extend(quote!(
+            #[allow(dead_code)]
             #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+            #struct_attr
             pub struct #args_name {
                 #(#args_anot pub #args_enames: #args_etypes,)*
             }
@@ -276,7 +638,52 @@ impl<'short, 'long: 'short> ToTokenStream<'short, 'long> for VError<'long> {
     }
 }
 
+/// Returns `true`, if a field of `v` contains an `object` or a type not defined in `idl`.
+///
+/// Methods with such fields get `_raw` variants working with `serde_json::Value`.
+fn has_dynamic_type<'a>(idl: &'a IDL, v: &'a VStruct, seen: &mut Vec<&'a str>) -> bool {
+    v.elts.iter().any(|e| vtype_is_dynamic(idl, &e.vtype, seen))
+}
+
+fn vtype_is_dynamic<'a>(idl: &'a IDL, t: &'a VTypeExt, seen: &mut Vec<&'a str>) -> bool {
+    match t {
+        VTypeExt::Array(v) | VTypeExt::Dict(v) | VTypeExt::Option(v) => {
+            vtype_is_dynamic(idl, v, seen)
+        }
+        VTypeExt::Plain(VType::Object) => true,
+        VTypeExt::Plain(VType::Struct(v)) => has_dynamic_type(idl, v, seen),
+        VTypeExt::Plain(VType::Typename(name)) => {
+            if seen.contains(name) {
+                return false;
+            }
+            seen.push(name);
+            match idl.typedefs.get(name) {
+                None => true,
+                Some(Typedef {
+                    elt: VStructOrEnum::VStruct(v),
+                    ..
+                }) => has_dynamic_type(idl, v, seen),
+                Some(_) => false,
+            }
+        }
+        VTypeExt::Plain(_) => false,
+    }
+}
+
 fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Result<TokenStream> {
+    for t in idl.typedefs.values() {
+        if let VStructOrEnum::VStruct(ref v) = t.elt {
+            check_annotations(v, t.name)?;
+        }
+    }
+    for t in idl.errors.values() {
+        check_annotations(&t.parm, t.name)?;
+    }
+    for t in idl.methods.values() {
+        check_annotations(&t.input, t.name)?;
+        check_annotations(&t.output, t.name)?;
+    }
+
     let mut ts = TokenStream::new();
 
     if tosource {
@@ -287,18 +694,39 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
         ));
     }
 
+    let client = options.side.has_client();
+    let server = options.side.has_server();
+
     ts.extend(quote!(
         use serde_derive::{Deserialize, Serialize};
         use serde_json;
-        use std::io::BufRead;
-        use std::sync::{Arc, RwLock};
-        use varlink::{self, CallTrait};
     ));
+    if server {
+        ts.extend(quote!(
+            use std::io::BufRead;
+        ));
+    }
+    if client {
+        ts.extend(quote!(
+            use std::sync::{Arc, RwLock};
+        ));
+    }
+    if server {
+        ts.extend(quote!(
+            use varlink::{self, CallTrait};
+        ));
+    } else {
+        ts.extend(quote!(
+            use varlink;
+        ));
+    }
 
     if let Some(ref v) = options.preamble {
         ts.extend(v.clone());
     }
 
+    generate_name_constants(idl, &mut ts);
+
     generate_error_code(options, &idl, &mut ts);
 
     for t in idl.typedefs.values() {
@@ -313,8 +741,16 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
     let mut client_method_decls = TokenStream::new();
     let mut server_method_impls = TokenStream::new();
     let mut client_method_impls = TokenStream::new();
+    let mut example_tests = TokenStream::new();
     let iname = idl.name;
     let description = idl.description;
+    let mut dispatched_methods: Vec<&str> = Vec::new();
+    let table = if idl.methods.len() >= DISPATCH_TABLE_MIN_METHODS {
+        let method_names: Vec<&str> = idl.methods.values().map(|t| t.name).collect();
+        Some(dispatch_table(&method_names))
+    } else {
+        None
+    };
 
     for t in idl.methods.values() {
         let mut in_field_types = Vec::new();
@@ -330,6 +766,18 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
         let call_name = Ident::new(&format!("Call_{}", t.name), Span::call_site());
         let method_name = Ident::new(&to_snake_case(t.name), Span::call_site());
         let varlink_method_name = format!("{}.{}", idl.name, t.name);
+        let examples = get_examples(t.doc, t.name)?;
+        let doc = method_doc(t.doc, &examples);
+        let idempotent = if get_annotation(t.doc, "idempotent").is_some() {
+            quote!(.idempotent())
+        } else {
+            quote!()
+        };
+        let doc_attr = if doc.is_empty() {
+            quote!()
+        } else {
+            quote!(#[doc = #doc])
+        };
 
         generate_anon_struct(
             &format!("{}_{}", t.name, "Args"),
@@ -357,8 +805,10 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
             let in_field_names = in_field_names.iter();
             let in_field_types = in_field_types.iter();
 
+            let struct_attr = options.field_naming.struct_attr();
             ts.extend(quote!(
                 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+                #struct_attr
                 pub struct #out_struct_name {
                                 #(#out_anot pub #out_field_names: #out_field_types,)*
                 }
@@ -366,22 +816,60 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
                 impl varlink::VarlinkReply for #out_struct_name {}
 
                 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+                #struct_attr
                 pub struct #in_struct_name {
                                 #(#in_anot pub #in_field_names: #in_field_types,)*
                 }
             ));
         }
 
-        {
+        let dynamic = has_dynamic_type(idl, &t.input, &mut Vec::new())
+            || has_dynamic_type(idl, &t.output, &mut Vec::new());
+
+        let raw_call_methods = if dynamic {
+            quote!(
+                #[doc = "The untyped parameters of the call"]
+                #[allow(dead_code)]
+                fn raw_parameters(&self) -> Option<&serde_json::Value> {
+                    self.get_request().and_then(|r| r.parameters.as_ref())
+                }
+                #[doc = "Reply with untyped parameters"]
+                #[allow(dead_code)]
+                fn reply_raw(&mut self, parameters: serde_json::Value) -> varlink::Result<()> {
+                    self.reply_struct(varlink::Reply::parameters(Some(parameters)))
+                }
+            )
+        } else {
+            quote!()
+        };
+
+        if server {
             let field_names_1 = out_field_names.iter();
             let field_names_2 = out_field_names.iter();
             let field_types_1 = out_field_types.iter();
+            let field_names_3 = out_field_names.iter();
+            let field_names_4 = out_field_names.iter();
+            let field_types_3 = out_field_types.iter();
+            let field_names_5 = out_field_names.iter();
+            let field_names_6 = out_field_names.iter();
+            let field_types_5 = out_field_types.iter();
             if !t.output.elts.is_empty() {
                 ts.extend(quote!(
                 pub trait #call_name: VarlinkCallError {
                     fn reply(&mut self, #(#field_names_1: #field_types_1),*) -> varlink::Result<()> {
                         self.reply_struct(#out_struct_name { #(#field_names_2),* }.into())
                     }
+                    #[doc = "Reply of a `more` call, which is followed by more replies"]
+                    #[allow(dead_code)]
+                    fn reply_continues(&mut self, #(#field_names_3: #field_types_3),*) -> varlink::Result<()> {
+                        self.reply_struct_continues(#out_struct_name { #(#field_names_4),* }.into())
+                    }
+                    #[doc = "The last reply of the call"]
+                    #[allow(dead_code)]
+                    fn reply_final(&mut self, #(#field_names_5: #field_types_5),*) -> varlink::Result<()> {
+                        self.reply_struct_final(#out_struct_name { #(#field_names_6),* }.into())
+                    }
+                    #raw_call_methods
                 }
             ));
             } else {
@@ -390,20 +878,32 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
                         fn reply(&mut self) -> varlink::Result<()> {
                             self.reply_struct(varlink::Reply::parameters(None))
                         }
+                        #[doc = "Reply of a `more` call, which is followed by more replies"]
+                        #[allow(dead_code)]
+                        fn reply_continues(&mut self) -> varlink::Result<()> {
+                            self.reply_struct_continues(varlink::Reply::parameters(None))
+                        }
+                        #[doc = "The last reply of the call"]
+                        #[allow(dead_code)]
+                        fn reply_final(&mut self) -> varlink::Result<()> {
+                            self.reply_struct_final(varlink::Reply::parameters(None))
+                        }
+                        #raw_call_methods
                     }
                 ));
             }
-        }
 
-        ts.extend(quote!(
-            impl<'a> #call_name for varlink::Call<'a> {}
-        ));
+            ts.extend(quote!(
+                impl<'a> #call_name for varlink::Call<'a> {}
+            ));
+        }
 
         // #server_method_decls
         {
             let in_field_names = in_field_names.iter();
             let in_field_types = in_field_types.iter();
             server_method_decls.extend(quote!(
+                #doc_attr
                 fn #method_name (&self, call: &mut dyn #call_name, #(#in_field_names: #in_field_types),*) ->
                 varlink::Result<()>;
             ));
@@ -414,6 +914,7 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
             let in_field_names = in_field_names.iter();
             let in_field_types = in_field_types.iter();
             client_method_decls.extend(quote!(
+                #doc_attr
                 fn #method_name(&mut self, #(#in_field_names: #in_field_types),*) ->
                 varlink::MethodCall<#in_struct_name, #out_struct_name, Error>;
             ));
@@ -432,164 +933,382 @@ fn varlink_to_rust(idl: &IDL, options: &GeneratorOptions, tosource: bool) -> Res
                 self.connection.clone(),
                 #varlink_method_name,
                 #in_struct_name {#(#in_field_names_2),*})
+                #idempotent
+             }
+            ));
+        }
+
+        // the untyped client variant
+        if dynamic {
+            let raw_method_name = format_ident!("{}_raw", method_name);
+            client_method_decls.extend(quote!(
+                #[doc = "Like the typed variant, but with untyped parameters and reply"]
+                #[allow(dead_code)]
+                fn #raw_method_name(&mut self, parameters: serde_json::Value) ->
+                varlink::MethodCall<serde_json::Value, serde_json::Value, Error>;
+            ));
+            client_method_impls.extend(quote!(
+            fn #raw_method_name(&mut self, parameters: serde_json::Value) ->
+            varlink::MethodCall<serde_json::Value, serde_json::Value, Error> {
+             varlink::MethodCall::<serde_json::Value, serde_json::Value, Error>::new(
+                self.connection.clone(),
+                #varlink_method_name,
+                parameters)
+                #idempotent
              }
             ));
         }
 
+        // #example_tests
+        if options.example_tests {
+            for (i, (request, reply)) in examples.iter().enumerate() {
+                let test_name = format_ident!("{}_example_{}", method_name, i);
+                example_tests.extend(quote!(
+                    #[test]
+                    fn #test_name() {
+                        let args: #in_struct_name = serde_json::from_str(#request).unwrap();
+                        assert_eq!(
+                            serde_json::to_value(&args).unwrap(),
+                            serde_json::from_str::<serde_json::Value>(#request).unwrap()
+                        );
+                        let reply: #out_struct_name = serde_json::from_str(#reply).unwrap();
+                        assert_eq!(
+                            serde_json::to_value(&reply).unwrap(),
+                            serde_json::from_str::<serde_json::Value>(#reply).unwrap()
+                        );
+                    }
+                ));
+            }
+        }
+
         // #server_method_impls
         {
             let in_field_names = in_field_names.iter();
+            dispatched_methods.push(t.name);
+
+            let pattern = match table {
+                Some((_, ref slots)) => {
+                    let slot =
+                        Literal::usize_unsuffixed(slots.iter().position(|n| *n == t.name).unwrap());
+                    quote!(Some(#slot))
+                }
+                None => quote!(#varlink_method_name),
+            };
 
             if !t.input.elts.is_empty() {
                 server_method_impls.extend(quote!(
-                    #varlink_method_name => {
-                        if let Some(args) = req.parameters.clone() {
-                            let args: #in_struct_name = match serde_json::from_value(args) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    let es = format!("{}", e);
-                                    let _ = call.reply_invalid_parameter(es.clone());
-                                    return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(es)).into());
-                                }
-                            };
-                            self.inner.#method_name(call as &mut dyn #call_name, #(args.#in_field_names),*)
-                        } else {
-                            call.reply_invalid_parameter("parameters".into())
-                        }
+                    #pattern => {
+                        // missing parameters are missing fields, named in the error reply
+                        let args = req.parameters.clone().unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                        let args: #in_struct_name = match serde_json::from_value(args) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                let _ = call.reply_invalid_args(&e);
+                                return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(e.to_string())).into());
+                            }
+                        };
+                        self.inner.#method_name(call as &mut dyn #call_name, #(args.#in_field_names),*)
                     },
                 ));
             } else {
                 server_method_impls.extend(quote!(
-                    #varlink_method_name => self.inner.#method_name(call as &mut dyn #call_name),
+                    #pattern => self.inner.#method_name(call as &mut dyn #call_name),
                 ));
             }
         }
     }
 
-    ts.extend(quote!(
-        pub trait VarlinkInterface {
-            #server_method_decls
-
-            fn call_upgraded(&self, _call: &mut varlink::Call, _bufreader: &mut dyn BufRead) -> varlink::Result<Vec<u8>> {
-                Ok(Vec::new())
-            }
-        }
-        pub trait VarlinkClientInterface {
-            #client_method_decls
+    let dispatch = match table {
+        Some((displacements, slots)) => {
+            let displacements = displacements.into_iter().map(Literal::u32_unsuffixed);
+            quote!(
+                static DISPATCH: varlink::dispatch::Table = varlink::dispatch::Table {
+                    displacements: &[#(#displacements),*],
+                    names: &[#(#slots),*],
+                };
+                match DISPATCH.lookup(#iname, &req.method) {
+                    #server_method_impls
+                    _ => call.reply_method_not_found(String::from(req.method.as_ref())),
+                }
+            )
         }
+        None => quote!(
+            match req.method.as_ref() {
+                #server_method_impls
+                m => {
+                    call.reply_method_not_found(String::from(m))
+                }
+            }
+        ),
+    };
 
-        #[allow(dead_code)]
-        pub struct VarlinkClient {
-            connection: Arc<RwLock<varlink::Connection>>,
-        }
+    if server {
+        ts.extend(quote!(
+            pub trait VarlinkInterface {
+                #server_method_decls
 
-        impl VarlinkClient {
-            #[allow(dead_code)]
-            pub fn new(connection: Arc<RwLock<varlink::Connection>>) -> Self {
-                VarlinkClient {
-                    connection,
+                fn call_upgraded(&self, _call: &mut varlink::Call, _bufreader: &mut dyn BufRead) -> varlink::Result<Vec<u8>> {
+                    Ok(Vec::new())
                 }
             }
-        }
+        ));
+    }
 
-        impl VarlinkClientInterface for VarlinkClient {
-            #client_method_impls
-        }
+    if client {
+        ts.extend(quote!(
+            pub trait VarlinkClientInterface {
+                #client_method_decls
+            }
 
-        #[allow(dead_code)]
-        pub struct VarlinkInterfaceProxy {
-            inner: Box<dyn VarlinkInterface + Send + Sync>,
-        }
+            #[allow(dead_code)]
+            pub struct VarlinkClient {
+                connection: Arc<RwLock<varlink::Connection>>,
+            }
 
-        #[allow(dead_code)]
-        pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfaceProxy {
-            VarlinkInterfaceProxy { inner }
-        }
+            impl VarlinkClient {
+                #[allow(dead_code)]
+                pub fn new(connection: Arc<RwLock<varlink::Connection>>) -> Self {
+                    VarlinkClient {
+                        connection,
+                    }
+                }
+            }
 
-        impl varlink::Interface for VarlinkInterfaceProxy {
-            fn get_description(&self) -> &'static str {
-                #description
+            impl VarlinkClientInterface for VarlinkClient {
+                #client_method_impls
             }
+        ));
+    }
 
-            fn get_name(&self) -> &'static str {
-                #iname
+    if server {
+        ts.extend(quote!(
+            #[allow(dead_code)]
+            pub struct VarlinkInterfaceProxy {
+                inner: Box<dyn VarlinkInterface + Send + Sync>,
             }
 
-            fn call_upgraded(&self, call: &mut varlink::Call, bufreader: &mut dyn BufRead) -> varlink::Result<Vec<u8>> {
-                self.inner.call_upgraded(call, bufreader)
+            #[allow(dead_code)]
+            pub fn new(inner: Box<dyn VarlinkInterface + Send + Sync>) -> VarlinkInterfaceProxy {
+                VarlinkInterfaceProxy { inner }
             }
 
-            fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
-                let req = call.request.unwrap();
-                match req.method.as_ref() {
-                    #server_method_impls
-                    m => {
-                        call.reply_method_not_found(String::from(m))
-                    }
+            impl varlink::Interface for VarlinkInterfaceProxy {
+                fn get_description(&self) -> &'static str {
+                    #description
+                }
+
+                fn get_name(&self) -> &'static str {
+                    #iname
+                }
+
+                fn get_method_names(&self) -> Option<&'static [&'static str]> {
+                    Some(&[#(#dispatched_methods),*])
+                }
+
+                fn call_upgraded(&self, call: &mut varlink::Call, bufreader: &mut dyn BufRead) -> varlink::Result<Vec<u8>> {
+                    self.inner.call_upgraded(call, bufreader)
+                }
+
+                fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
+                    let req = call.require_request()?;
+                    #dispatch
                 }
             }
-        }
-    ));
+        ));
+    }
+
+    if !example_tests.is_empty() {
+        ts.extend(quote!(
+            #[cfg(test)]
+            mod example_tests {
+                use super::*;
+
+                #example_tests
+            }
+        ));
+    }
 
     Ok(ts)
 }
 
-fn generate_anon_struct(
-    name: &str,
-    vstruct: &VStruct,
-    options: &GeneratorOptions,
-    mut ts: &mut TokenStream,
-    field_types: &mut Vec<TokenStream>,
-    field_names: &mut Vec<Ident>,
-    anot: &mut Vec<TokenStream>,
-) {
-    for e in &vstruct.elts {
-        anot.push(if let VTypeExt::Option(_) = e.vtype {
-            quote!(#[serde(skip_serializing_if = "Option::is_none")])
-        } else {
-            quote!()
-        });
-        let ename_ident: Ident = syn::parse_str(&(String::from("r#") + e.name)).unwrap();
-        field_names.push(ename_ident);
-        field_types.push(
-            TokenStream::from_str(
-                e.vtype
-                    .to_rust_string(format!("{}_{}", name, e.name).as_ref(), &mut ts, options)
-                    .as_ref(),
-            )
-            .unwrap(),
-        );
+/// The number of methods from which `call()` looks up methods in a `varlink::dispatch::Table`
+///
+/// A lookup in the table takes about the same time for any number of methods, and is
+/// faster than the string `match` from about 100 methods on.
+const DISPATCH_TABLE_MIN_METHODS: usize = 100;
+
+/// The hash functions of `varlink::dispatch`, both must stay the same.
+fn dispatch_hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut h: u64 = bytes.len() as u64;
+    let mut chunks = bytes.chunks_exact(8);
+    for chunk in &mut chunks {
+        h = (h.rotate_left(5) ^ u64::from_le_bytes(chunk.try_into().unwrap()))
+            .wrapping_mul(0x517c_c1b7_2722_0a95);
     }
+    let rest = chunks
+        .remainder()
+        .iter()
+        .rev()
+        .fold(0u64, |word, &b| word << 8 | u64::from(b));
+    h = (h.rotate_left(5) ^ rest).wrapping_mul(0x517c_c1b7_2722_0a95);
+    (h ^ (h >> 32)) as u32
 }
 
-fn generate_error_code(
-    options: &GeneratorOptions,
-    idl: &varlink_parser::IDL,
-    ts: &mut TokenStream,
+fn dispatch_mix(hash: u32, seed: u32) -> u32 {
+    let mut h = hash ^ seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+/// Builds the displacements and slots of a `varlink::dispatch::Table` for `names`.
+fn dispatch_table<'a>(names: &[&'a str]) -> (Vec<u32>, Vec<&'a str>) {
+    let mut size = names.len().next_power_of_two();
+    loop {
+        if let Some(table) = try_dispatch_table(names, size) {
+            return table;
+        }
+        size *= 2;
+    }
+}
+
+/// Hash and displace: the buckets with the most names are placed first, each with
+/// the first seed, which moves all its names to free slots.
+fn try_dispatch_table<'a>(names: &[&'a str], size: usize) -> Option<(Vec<u32>, Vec<&'a str>)> {
+    let num_buckets = (names.len() / 2).max(1).next_power_of_two();
+    let mut buckets: Vec<Vec<&'a str>> = vec![Vec::new(); num_buckets];
+    for name in names {
+        buckets[dispatch_mix(dispatch_hash(name), 0) as usize & (num_buckets - 1)].push(name);
+    }
+    let mut order: Vec<usize> = (0..num_buckets).collect();
+    order.sort_by_key(|&b| std::cmp::Reverse(buckets[b].len()));
+
+    let mut displacements = vec![0; num_buckets];
+    let mut slots = vec![""; size];
+    for b in order {
+        let bucket = &buckets[b];
+        let seed = (1..=0xffff).find(|&seed| {
+            let mut taken: Vec<usize> = Vec::new();
+            bucket.iter().all(|name| {
+                let slot = dispatch_mix(dispatch_hash(name), seed) as usize & (size - 1);
+                let free = slots[slot].is_empty() && !taken.contains(&slot);
+                taken.push(slot);
+                free
+            })
+        })?;
+        for name in bucket {
+            slots[dispatch_mix(dispatch_hash(name), seed) as usize & (size - 1)] = name;
+        }
+        displacements[b] = seed;
+    }
+    Some((displacements, slots))
+}
+
+fn generate_name_constants(idl: &IDL, ts: &mut TokenStream) {
+    let iname = idl.name;
+
+    let mut method_consts = Vec::new();
+    let mut method_values = Vec::new();
+    for t in idl.methods.values() {
+        method_consts.push(Ident::new(
+            &to_snake_case(t.name).to_uppercase(),
+            Span::call_site(),
+        ));
+        method_values.push(format!("{}.{}", iname, t.name));
+    }
+
+    let mut error_consts = Vec::new();
+    let mut error_values = Vec::new();
+    for t in idl.errors.values() {
+        error_consts.push(Ident::new(
+            &to_snake_case(t.name).to_uppercase(),
+            Span::call_site(),
+        ));
+        error_values.push(format!("{}.{}", iname, t.name));
+    }
+
+    ts.extend(quote!(
+        #[allow(dead_code)]
+        pub const INTERFACE_NAME: &str = #iname;
+
+        #[allow(dead_code)]
+        pub mod method_names {
+            #(pub const #method_consts: &str = #method_values;)*
+        }
+
+        #[allow(dead_code)]
+        pub mod error_names {
+            #(pub const #error_consts: &str = #error_values;)*
+        }
+    ));
+}
+
+fn generate_anon_struct(
+    name: &str,
+    vstruct: &VStruct,
+    options: &GeneratorOptions,
+    ts: &mut TokenStream,
+    field_types: &mut Vec<TokenStream>,
+    field_names: &mut Vec<Ident>,
+    anot: &mut Vec<TokenStream>,
+) {
+    for e in &vstruct.elts {
+        let (ename_ident, rename) = options.field_naming.field(e.name);
+        anot.push(if let VTypeExt::Option(_) = e.vtype {
+            quote!(#rename #[serde(skip_serializing_if = "Option::is_none")])
+        } else {
+            rename
+        });
+        field_names.push(ename_ident);
+        field_types.push(
+            TokenStream::from_str(
+                argument_to_rust_string(e, format!("{}_{}", name, e.name).as_ref(), ts, options)
+                    .as_ref(),
+            )
+            .unwrap(),
+        );
+    }
+}
+
+fn generate_error_code(
+    options: &GeneratorOptions,
+    idl: &varlink_parser::IDL,
+    ts: &mut TokenStream,
 ) {
     // Errors traits
     {
-        let mut error_structs_and_enums = TokenStream::new();
         let mut funcs = TokenStream::new();
         {
             let mut errors = Vec::new();
             let mut errors_display = Vec::new();
             for t in idl.errors.values() {
-                errors.push(
-                    TokenStream::from_str(&format!(
-                        "{ename}(Option<{ename}_Args>)",
-                        ename = t.name,
-                    ))
-                    .unwrap(),
-                );
-                errors_display.push(
-                    TokenStream::from_str(&format!(
-                        "ErrorKind::{ename}(v) => write!(f, \"{iname}.{ename}: {{:#?}}\", v)",
-                        ename = t.name,
-                        iname = idl.name,
-                    ))
-                    .unwrap(),
-                );
+                if t.parm.elts.is_empty() {
+                    errors.push(TokenStream::from_str(t.name).unwrap());
+                    errors_display.push(
+                        TokenStream::from_str(&format!(
+                            "ErrorKind::{ename} => write!(f, \"{iname}.{ename}\")",
+                            ename = t.name,
+                            iname = idl.name,
+                        ))
+                        .unwrap(),
+                    );
+                } else {
+                    errors.push(
+                        TokenStream::from_str(&format!("{ename}({ename}_Args)", ename = t.name))
+                            .unwrap(),
+                    );
+                    errors_display.push(
+                        TokenStream::from_str(&format!(
+                            "ErrorKind::{ename}(v) => write!(f, \"{iname}.{ename}: {{:#?}}\", v)",
+                            ename = t.name,
+                            iname = idl.name,
+                        ))
+                        .unwrap(),
+                    );
+                }
             }
 
             ts.extend(quote!(
@@ -697,23 +1416,45 @@ fn generate_error_code(
     ));
         {
             let mut arms = TokenStream::new();
+            let mut conversions = TokenStream::new();
             for t in idl.errors.values() {
                 let error_name = format!("{iname}.{ename}", iname = idl.name, ename = t.name);
                 let ename = TokenStream::from_str(&format!("ErrorKind::{}", t.name)).unwrap();
-                arms.extend(quote!(
-                    varlink::Reply { error: Some(ref t), .. } if t == #error_name => {
-                        match e {
-                           varlink::Reply {
-                               parameters: Some(p),
-                               ..
-                           } => match serde_json::from_value(p.clone()) {
-                               Ok(v) => #ename(v),
-                               Err(_) => #ename(None),
-                           },
-                           _ => #ename(None),
+                if t.parm.elts.is_empty() {
+                    arms.extend(quote!(
+                        varlink::Reply { error: Some(ref t), .. } if t == #error_name => #ename,
+                    ));
+                } else {
+                    arms.extend(quote!(
+                        varlink::Reply { error: Some(ref t), .. } if t == #error_name => {
+                            match e {
+                               varlink::Reply {
+                                   parameters: Some(p),
+                                   ..
+                               } => match serde_json::from_value(p.clone()) {
+                                   Ok(v) => #ename(v),
+                                   Err(_) => ErrorKind::VarlinkReply_Error,
+                               },
+                               _ => ErrorKind::VarlinkReply_Error,
+                            }
                         }
-                    }
-                ));
+                    ));
+
+                    let args_name = Ident::new(&format!("{}_Args", t.name), Span::call_site());
+                    conversions.extend(quote!(
+                        impl From<#args_name> for ErrorKind {
+                            fn from(v: #args_name) -> Self {
+                                #ename(v)
+                            }
+                        }
+
+                        impl From<#args_name> for Error {
+                            fn from(v: #args_name) -> Self {
+                                Error(#ename(v), None, None)
+                            }
+                        }
+                    ));
+                }
             }
 
             ts.extend(quote!(
@@ -726,6 +1467,8 @@ fn generate_error_code(
                         }
                     }
                 }
+
+                #conversions
             ));
         }
         for t in idl.errors.values() {
@@ -737,18 +1480,17 @@ fn generate_error_code(
             let args_name = Ident::new(&format!("{}_Args", t.name), Span::call_site());
             if !t.parm.elts.is_empty() {
                 for e in &t.parm.elts {
-                    let ename_ident: Ident =
-                        syn::parse_str(&(String::from("r#") + e.name)).unwrap();
-                    inparms_name.push(ename_ident);
+                    inparms_name.push(options.field_naming.field(e.name).0);
+                    // the anonymous types are already generated with the `_Args` struct
                     inparms_type.push(
                         TokenStream::from_str(
-                            e.vtype
-                                .to_rust_string(
-                                    format!("{}_Args_{}", t.name, e.name).as_ref(),
-                                    &mut error_structs_and_enums,
-                                    options,
-                                )
-                                .as_ref(),
+                            argument_to_rust_string(
+                                e,
+                                format!("{}_Args_{}", t.name, e.name).as_ref(),
+                                &mut TokenStream::new(),
+                                options,
+                            )
+                            .as_ref(),
                         )
                         .unwrap(),
                     );
@@ -773,16 +1515,19 @@ fn generate_error_code(
                 }
             ));
         }
+        if options.side.has_server() {
+            ts.extend(quote!(
+                pub trait VarlinkCallError: varlink::CallTrait {
+                    #funcs
+                }
+            ));
+        }
+    }
+    if options.side.has_server() {
         ts.extend(quote!(
-            #error_structs_and_enums
-            pub trait VarlinkCallError: varlink::CallTrait {
-                #funcs
-            }
+            impl<'a> VarlinkCallError for varlink::Call<'a> {}
         ));
     }
-    ts.extend(quote!(
-        impl<'a> VarlinkCallError for varlink::Call<'a> {}
-    ));
 }
 
 pub fn compile(source: String) -> Result<TokenStream> {
@@ -832,12 +1577,284 @@ pub fn generate_with_options(
     Ok(())
 }
 
+/// `generate_scaffold` reads a varlink interface definition from `reader` and writes a
+/// `main.rs` of a service to `writer`, which is a starting point for the implementation.
+///
+/// The service implements every method of `VarlinkInterface` with `todo!()` and
+/// listens on the address passed with `--varlink=ADDRESS`. It includes the code
+/// generated with the same `options` as the module named after the interface, e.g.
+/// `org_example_ping` generated by [`cargo_build_tosource`].
+///
+/// # Examples
+///
+/// ```rust
+/// let mut main = Vec::new();
+/// varlink_generator::generate_scaffold(
+///     &mut "interface org.example.ping\nmethod Ping(ping: string) -> (pong: string)".as_bytes(),
+///     &mut main,
+///     &Default::default(),
+/// )
+/// .unwrap();
+/// let main = String::from_utf8(main).unwrap();
+/// assert!(main.contains("fn ping(&self, call: &mut dyn Call_Ping, ping: String)"));
+/// ```
+///
+/// [`cargo_build_tosource`]: fn.cargo_build_tosource.html
+pub fn generate_scaffold(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    options: &GeneratorOptions,
+) -> Result<()> {
+    let mut buffer = String::new();
+
+    reader
+        .read_to_string(&mut buffer)
+        .map_err(mstrerr!(Error, "Failed to read from buffer"))?;
+
+    let idl = IDL::from_string(&buffer).map_err(mstrerr!(Error, "Failed to parse"))?;
+
+    if !options.side.has_server() {
+        return Err(strerr!(
+            Error,
+            "The scaffold of a service needs the server side"
+        ));
+    }
+
+    writer
+        .write_all(scaffold(&idl, options).as_bytes())
+        .map_err(mstrerr!(Error, "Failed to write to buffer"))?;
+    Ok(())
+}
+
+/// Returns the name of `ident` without `r#`, unless it is a keyword.
+fn scaffold_ident(ident: &Ident) -> String {
+    let name = ident.to_string();
+    let plain = name.trim_start_matches("r#");
+    if syn::parse_str::<Ident>(plain).is_ok() {
+        plain.into()
+    } else {
+        name
+    }
+}
+
+/// Returns the `main.rs` of a service of `idl`, see [`generate_scaffold`].
+fn scaffold(idl: &IDL, options: &GeneratorOptions) -> String {
+    let module = idl.name.replace('.', "_");
+    let vendor = match idl.name.rfind('.') {
+        Some(n) => &idl.name[..n],
+        None => idl.name,
+    };
+
+    let mut methods = String::new();
+    for t in idl.methods.values() {
+        // the types of the anonymous structs are generated with the bindings
+        let mut ts = TokenStream::new();
+        let mut in_field_types = Vec::new();
+        let mut in_field_names = Vec::new();
+        let mut out_field_types = Vec::new();
+        let mut out_field_names = Vec::new();
+        generate_anon_struct(
+            &format!("{}_{}", t.name, "Args"),
+            &t.input,
+            options,
+            &mut ts,
+            &mut in_field_types,
+            &mut in_field_names,
+            &mut Vec::new(),
+        );
+        generate_anon_struct(
+            &format!("{}_{}", t.name, "Reply"),
+            &t.output,
+            options,
+            &mut ts,
+            &mut out_field_types,
+            &mut out_field_names,
+            &mut Vec::new(),
+        );
+
+        let doc = method_doc(t.doc, &[]);
+        for line in doc.lines() {
+            methods += &format!(
+                "    ///{}{}\n",
+                if line.is_empty() { "" } else { " " },
+                line
+            );
+        }
+        let mut args = vec![
+            "&self".to_string(),
+            format!("call: &mut dyn Call_{}", t.name),
+        ];
+        for (name, vtype) in in_field_names.iter().zip(in_field_types) {
+            args.push(format!(
+                "{}: {}",
+                scaffold_ident(name),
+                pretty::pretty_print(vtype).trim()
+            ));
+        }
+        let name = to_snake_case(t.name);
+        let line = format!(
+            "    fn {}({}) -> varlink::Result<()> {{",
+            name,
+            args.join(", ")
+        );
+        if line.len() <= 100 {
+            methods += &line;
+        } else {
+            methods += &format!("    fn {}(\n", name);
+            for arg in args {
+                methods += &format!("        {},\n", arg);
+            }
+            methods += "    ) -> varlink::Result<()> {";
+        }
+        let reply: Vec<String> = out_field_names.iter().map(scaffold_ident).collect();
+        methods += &format!("\n        // call.reply({})?;\n", reply.join(", "));
+        methods += "        todo!()\n    }\n\n";
+    }
+    let methods = methods.trim_end();
+
+    format!(
+        r#"//! A service of the `{iface}` interface
+//!
+//! Generated by the varlink rust generator as a starting point: implement the methods
+//! of `VarlinkInterface` and run the service with `--varlink=ADDRESS`, e.g.
+//! `--varlink=unix:/run/{iface}`.
+//!
+//! The module `{module}` is generated by `build.rs` from the interface definition:
+//!
+//! ```rust,ignore
+//! fn main() {{
+//!     varlink_generator::cargo_build_tosource("src/{iface}.varlink", true);
+//! }}
+//! ```
+
+// remove, when the methods use their arguments
+#![allow(unused_variables)]
+
+mod {module};
+
+use std::env;
+use std::process::exit;
+
+use crate::{module}::*;
+
+/// The implementation of the methods of the interface
+struct Service;
+
+impl VarlinkInterface for Service {{
+{methods}
+}}
+
+fn main() {{
+    let address = match env::args().find(|arg| arg.starts_with("--varlink=")) {{
+        Some(arg) => arg["--varlink=".len()..].to_string(),
+        None => {{
+            eprintln!("Usage: {{}} --varlink=ADDRESS", env::args().next().unwrap_or_default());
+            exit(1);
+        }}
+    }};
+
+    let service = varlink::VarlinkService::new(
+        "{vendor}",
+        "{iface}",
+        env!("CARGO_PKG_VERSION"),
+        "https://varlink.org",
+        vec![Box::new({module}::new(Box::new(Service)))],
+    );
+
+    if let Err(e) = varlink::listen(service, &address, &varlink::ListenConfig::default()) {{
+        eprintln!("{{}}", e);
+        exit(1);
+    }}
+}}
+"#,
+        iface = idl.name,
+        module = module,
+        vendor = vendor,
+        methods = methods,
+    )
+}
+
+/// The rust file generated by one of the `try_cargo_build*` functions
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedFile {
+    /// the varlink interface definition file
+    pub input_path: PathBuf,
+    /// the generated rust file
+    pub output_path: PathBuf,
+    /// `true`, if the formatter ran successfully on the generated file
+    pub formatted: bool,
+}
+
+/// Error returned by the `try_cargo_build*` functions
+///
+/// Iterate over the error chain with `iter()` for the detailed cause.
+pub type GeneratorError = ChainError<Error>;
+
+fn exit_on_error<T>(r: Result<T>) -> T {
+    r.unwrap_or_else(|e| {
+        let mut s = String::new();
+        for i in e.iter() {
+            s += &i.to_string();
+            s += "\n";
+        }
+        eprintln!("{}", s);
+        exit(1);
+    })
+}
+
+fn generate_file(
+    input_path: &Path,
+    rust_path: &Path,
+    options: &GeneratorOptions,
+    tosource: bool,
+) -> Result<GeneratedFile> {
+    let writer: &mut dyn Write = &mut File::create(rust_path).map_err(mstrerr!(
+        Error,
+        "Could not open varlink output file `{}`",
+        rust_path.display()
+    ))?;
+
+    let mut buffer = String::new();
+    File::open(input_path)
+        .and_then(|mut f| f.read_to_string(&mut buffer))
+        .map_err(mstrerr!(
+            Error,
+            "Could not read varlink input file `{}`",
+            input_path.display()
+        ))?;
+
+    // parse errors are reported by the generator
+    if let Ok(idl) = IDL::from_string(&buffer) {
+        let rules: Vec<LintRule> = LintRule::all()
+            .iter()
+            .filter(|r| !options.allow_lints.contains(r))
+            .cloned()
+            .collect();
+        for warning in lint_with(&idl, &rules) {
+            println!("cargo:warning={}: {}", input_path.display(), warning);
+        }
+    }
+
+    generate_with_options(&mut buffer.as_bytes(), writer, options, tosource).map_err(mstrerr!(
+        Error,
+        "Could not generate rust code from varlink file `{}`",
+        input_path.display()
+    ))?;
+
+    Ok(GeneratedFile {
+        input_path: input_path.into(),
+        output_path: rust_path.into(),
+        formatted: false,
+    })
+}
+
 /// cargo build helper function
 ///
 /// `cargo_build` is used in a `build.rs` program to build the rust code
 /// from a varlink interface definition.
 ///
 /// Errors are emitted to stderr and terminate the process.
+/// Use [`try_cargo_build`] to handle them yourself.
 ///
 /// # Examples
 ///
@@ -849,9 +1866,34 @@ pub fn generate_with_options(
 /// }
 /// ```
 ///
+/// [`try_cargo_build`]: fn.try_cargo_build.html
 pub fn cargo_build<T: AsRef<Path> + ?Sized>(input_path: &T) {
-    cargo_build_options_many(
-        &[input_path],
+    exit_on_error(try_cargo_build(input_path));
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build` is used in a `build.rs` program to build the rust code
+/// from a varlink interface definition.
+///
+/// The rust code is written to `$OUT_DIR` and the paths are returned.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// extern crate varlink_generator;
+///
+/// fn main() {
+///     if let Err(e) = varlink_generator::try_cargo_build("src/org.example.ping.varlink") {
+///         panic!("{:?}", e);
+///     }
+/// }
+/// ```
+pub fn try_cargo_build<T: AsRef<Path> + ?Sized>(
+    input_path: &T,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
+    try_cargo_build_options(
+        input_path,
         &GeneratorOptions {
             ..Default::default()
         },
@@ -861,7 +1903,12 @@ pub fn cargo_build<T: AsRef<Path> + ?Sized>(input_path: &T) {
 /// cargo build helper function
 ///
 /// `cargo_build_many` is used in a `build.rs` program to build the rust code
-/// from a varlink interface definition.
+/// from varlink interface definitions.
+///
+/// Like [`cargo_build_dir`](fn.cargo_build_dir.html), it also writes
+/// `varlink_interfaces.rs` with a module for every interface. Modules, which would get
+/// the same name, e.g. for files with the same name in different directories, are
+/// numbered.
 ///
 /// Errors are emitted to stderr and terminate the process.
 ///
@@ -914,13 +1961,143 @@ where
 /// }
 /// ```
 pub fn cargo_build_options<T: AsRef<Path> + ?Sized>(input_path: &T, options: &GeneratorOptions) {
-    cargo_build_options_many(&[input_path], options)
+    exit_on_error(try_cargo_build_options(input_path, options));
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build_options` is the same as [`try_cargo_build`] with additional
+/// options for the generator.
+///
+/// [`try_cargo_build`]: fn.try_cargo_build.html
+pub fn try_cargo_build_options<T: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    options: &GeneratorOptions,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
+    let input_path = input_path.as_ref();
+
+    let out_dir: PathBuf = env::var_os("OUT_DIR")
+        .ok_or_else(|| strerr!(Error, "OUT_DIR is not set"))?
+        .into();
+    let file_name = input_path.file_name().ok_or_else(|| {
+        strerr!(
+            Error,
+            "Invalid varlink input file name `{}`",
+            input_path.display()
+        )
+    })?;
+    let rust_path = out_dir.join(file_name).with_extension("rs");
+
+    try_cargo_build_to_options(input_path, &rust_path, options)
+}
+
+/// cargo build helper function
+///
+/// `cargo_build_to` is used in a `build.rs` program to build the rust code
+/// from a varlink interface definition into `output_path`, instead of
+/// `$OUT_DIR/<varlink_file>.rs`. Missing parent directories are created.
+///
+/// The code is the same as generated by [`cargo_build`], so it can be used with
+/// `include!()` or as a module with a `#[path]` attribute.
+///
+/// Errors are emitted to stderr and terminate the process.
+/// Use [`try_cargo_build_to`] to handle them yourself.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// extern crate varlink_generator;
+///
+/// fn main() {
+///     varlink_generator::cargo_build_to(
+///         "src/org.example.ping.varlink",
+///         "../generated/src/ping.rs",
+///     );
+/// }
+/// ```
+///
+/// [`cargo_build`]: fn.cargo_build.html
+/// [`try_cargo_build_to`]: fn.try_cargo_build_to.html
+pub fn cargo_build_to<T: AsRef<Path> + ?Sized, U: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    output_path: &U,
+) {
+    exit_on_error(try_cargo_build_to(input_path, output_path));
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build_to` is the same as [`cargo_build_to`], but returns the
+/// paths of the generated file or the error, instead of terminating the process.
+///
+/// [`cargo_build_to`]: fn.cargo_build_to.html
+pub fn try_cargo_build_to<T: AsRef<Path> + ?Sized, U: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    output_path: &U,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
+    try_cargo_build_to_options(
+        input_path,
+        output_path,
+        &GeneratorOptions {
+            ..Default::default()
+        },
+    )
+}
+
+/// cargo build helper function
+///
+/// `cargo_build_to_options` is the same as [`cargo_build_to`] with additional
+/// options for the generator.
+///
+/// Errors are emitted to stderr and terminate the process.
+///
+/// [`cargo_build_to`]: fn.cargo_build_to.html
+pub fn cargo_build_to_options<T: AsRef<Path> + ?Sized, U: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    output_path: &U,
+    options: &GeneratorOptions,
+) {
+    exit_on_error(try_cargo_build_to_options(input_path, output_path, options));
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build_to_options` is the same as [`try_cargo_build_to`] with
+/// additional options for the generator.
+///
+/// [`try_cargo_build_to`]: fn.try_cargo_build_to.html
+pub fn try_cargo_build_to_options<T: AsRef<Path> + ?Sized, U: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    output_path: &U,
+    options: &GeneratorOptions,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
+    let input_path = input_path.as_ref();
+    let rust_path = output_path.as_ref();
+
+    if let Some(dir) = rust_path.parent() {
+        std::fs::create_dir_all(dir).map_err(mstrerr!(
+            Error,
+            "Could not create directory `{}`",
+            dir.display()
+        ))?;
+    }
+
+    let generated = generate_file(input_path, rust_path, options, false)?;
+
+    println!("cargo:rerun-if-changed={}", input_path.display());
+
+    Ok(generated)
 }
 
 /// cargo build helper function
 ///
 /// `cargo_build_options_many` is used in a `build.rs` program to build the rust code
-/// from a varlink interface definition.
+/// from varlink interface definitions.
+///
+/// Like [`cargo_build_dir`](fn.cargo_build_dir.html), it also writes
+/// `varlink_interfaces.rs` with a module for every interface. Modules, which would get
+/// the same name, e.g. for files with the same name in different directories, are
+/// numbered.
 ///
 /// Errors are emitted to stderr and terminate the process.
 ///
@@ -947,49 +2124,234 @@ where
     T: std::marker::Sized,
     T: AsRef<Path>,
 {
-    for input_path in input_paths {
-        let input_path = input_path.as_ref();
+    exit_on_error(try_cargo_build_options_many(input_paths, options));
+}
 
-        let out_dir: PathBuf = env::var_os("OUT_DIR").unwrap().into();
-        let rust_path = out_dir
-            .join(input_path.file_name().unwrap())
-            .with_extension("rs");
+/// cargo build helper function
+///
+/// `try_cargo_build_options_many` is the same as [`cargo_build_options_many`], but
+/// returns the generated files or the error, instead of terminating the process.
+/// The last file returned is `varlink_interfaces.rs` with an empty `input_path`.
+///
+/// [`cargo_build_options_many`]: fn.cargo_build_options_many.html
+pub fn try_cargo_build_options_many<T>(
+    input_paths: &[T],
+    options: &GeneratorOptions,
+) -> std::result::Result<Vec<GeneratedFile>, GeneratorError>
+where
+    T: AsRef<Path>,
+{
+    let input_paths: Vec<PathBuf> = input_paths.iter().map(|p| p.as_ref().into()).collect();
+    build_modules(&input_paths, options)
+}
 
-        let writer: &mut dyn Write = &mut (File::create(&rust_path).unwrap_or_else(|e| {
-            eprintln!(
-                "Could not open varlink output file `{}`: {}",
-                rust_path.display(),
-                e
-            );
-            exit(1);
-        }));
+/// cargo build helper function
+///
+/// `cargo_build_dir` is used in a `build.rs` program to build the rust code
+/// for all varlink interface definition files (`*.varlink`) in `dir`.
+///
+/// Besides the rust files for the interfaces, `$OUT_DIR/varlink_interfaces.rs`
+/// is generated. It contains a module for every interface, named like the file
+/// with "." replaced by "_", and
+///
+/// * `INTERFACE_NAMES`, the names of all interfaces
+/// * `all_interfaces(inner)`, which returns the `varlink::Interface` proxies of all
+///   interfaces for an `inner` implementing all the `VarlinkInterface` traits, unless
+///   only the client side is generated
+///
+/// Errors are emitted to stderr and terminate the process.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// extern crate varlink_generator;
+///
+/// fn main() {
+///     varlink_generator::cargo_build_dir("src/interfaces");
+/// }
+/// ```
+///
+/// ```rust,ignore
+/// include!(concat!(env!("OUT_DIR"), "/varlink_interfaces.rs"));
+///
+/// let service = varlink::VarlinkService::new(
+///     "org.varlink",
+///     "test service",
+///     "0.1",
+///     "http://varlink.org",
+///     all_interfaces(MyService::default()),
+/// );
+/// ```
+pub fn cargo_build_dir<T: AsRef<Path> + ?Sized>(dir: &T) {
+    exit_on_error(try_cargo_build_dir(dir));
+}
 
-        let reader: &mut dyn Read = &mut (File::open(input_path).unwrap_or_else(|e| {
-            eprintln!(
-                "Could not read varlink input file `{}`: {}",
-                input_path.display(),
-                e
-            );
-            exit(1);
-        }));
+/// cargo build helper function
+///
+/// `try_cargo_build_dir` is the same as [`cargo_build_dir`], but returns the
+/// generated files or the error, instead of terminating the process.
+/// The last file returned is `varlink_interfaces.rs`.
+///
+/// [`cargo_build_dir`]: fn.cargo_build_dir.html
+pub fn try_cargo_build_dir<T: AsRef<Path> + ?Sized>(
+    dir: &T,
+) -> std::result::Result<Vec<GeneratedFile>, GeneratorError> {
+    try_cargo_build_dir_options(
+        dir,
+        &GeneratorOptions {
+            ..Default::default()
+        },
+    )
+}
 
-        if let Err(e) = generate_with_options(reader, writer, options, false) {
-            let mut s = String::new();
-            for i in e.iter() {
-                s += &i.to_string();
-                s += "\n";
-            }
-            eprintln!(
-                "Could not generate rust code from varlink file `{}`: {}",
-                input_path.display(),
-                s
-            );
+/// cargo build helper function
+///
+/// `try_cargo_build_dir_options` is the same as [`try_cargo_build_dir`] with
+/// additional options for the generator.
+///
+/// [`try_cargo_build_dir`]: fn.try_cargo_build_dir.html
+pub fn try_cargo_build_dir_options<T: AsRef<Path> + ?Sized>(
+    dir: &T,
+    options: &GeneratorOptions,
+) -> std::result::Result<Vec<GeneratedFile>, GeneratorError> {
+    let dir = dir.as_ref();
+
+    let mut input_paths = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(mstrerr!(
+        Error,
+        "Could not read directory `{}`",
+        dir.display()
+    ))? {
+        let path = entry
+            .map_err(mstrerr!(
+                Error,
+                "Could not read directory `{}`",
+                dir.display()
+            ))?
+            .path();
+        if path.extension() == Some(std::ffi::OsStr::new("varlink")) {
+            input_paths.push(path);
+        }
+    }
+    input_paths.sort();
 
-            exit(1);
+    let mut generated = build_modules(&input_paths, options)?;
+
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    if let Some(file) = generated.last_mut() {
+        file.input_path = dir.into();
+    }
+    Ok(generated)
+}
+
+/// Generates the rust files for `input_paths` in `$OUT_DIR`, and `varlink_interfaces.rs`
+/// with a module for every interface, see [`cargo_build_dir`](fn.cargo_build_dir.html).
+///
+/// The module of a file is named like the file with "." and "-" replaced by "_". A name,
+/// which is already taken, e.g. by a file with the same name in another directory, gets
+/// the suffix `_2`, `_3`, ..., and so does its rust file.
+fn build_modules(
+    input_paths: &[PathBuf],
+    options: &GeneratorOptions,
+) -> std::result::Result<Vec<GeneratedFile>, GeneratorError> {
+    let out_dir: PathBuf = env::var_os("OUT_DIR")
+        .ok_or_else(|| strerr!(Error, "OUT_DIR is not set"))?
+        .into();
+
+    let mut generated = Vec::new();
+    let mut modules = TokenStream::new();
+    let mut module_names = Vec::new();
+    let mut taken_names = HashSet::new();
+    let mut taken_paths = HashSet::new();
+
+    for input_path in input_paths {
+        let file_name = input_path.file_name().ok_or_else(|| {
+            strerr!(
+                Error,
+                "Invalid varlink input file name `{}`",
+                input_path.display()
+            )
+        })?;
+        let base_name = input_path
+            .file_stem()
+            .and_then(|f| f.to_str())
+            .unwrap_or_default()
+            .replace(".", "_")
+            .replace("-", "_");
+
+        let mut name = base_name.clone();
+        let mut rust_path = out_dir.join(file_name).with_extension("rs");
+        let mut n = 1;
+        while taken_names.contains(&name) || taken_paths.contains(&rust_path) {
+            n += 1;
+            name = format!("{}_{}", base_name, n);
+            rust_path = out_dir.join(format!("{}.rs", name));
         }
+        taken_names.insert(name.clone());
+        taken_paths.insert(rust_path.clone());
+
+        let file = try_cargo_build_to_options(input_path, &rust_path, options)?;
+
+        let module_name = Ident::new(&name, Span::call_site());
+        let include = format!(
+            "/{}",
+            rust_path
+                .file_name()
+                .and_then(|f| f.to_str())
+                .unwrap_or_default()
+        );
+        modules.extend(quote!(
+            #[allow(non_camel_case_types)]
+            #[allow(non_snake_case)]
+            pub mod #module_name {
+                include!(concat!(env!("OUT_DIR"), #include));
+            }
+        ));
+        module_names.push(module_name);
+        generated.push(file);
+    }
 
-        println!("cargo:rerun-if-changed={}", input_path.display());
+    let module_names_1 = module_names.iter();
+    let module_names_2 = module_names.iter();
+    let module_names_3 = module_names.iter();
+    let mut ts = quote!(
+        #modules
+
+        #[allow(dead_code)]
+        pub const INTERFACE_NAMES: &[&str] = &[#(#module_names_1::INTERFACE_NAME),*];
+    );
+    if options.side.has_server() {
+        ts.extend(quote!(
+            #[allow(dead_code)]
+            pub fn all_interfaces<T>(inner: T) -> Vec<Box<dyn varlink::Interface + Send + Sync>>
+            where
+                T: #(#module_names_2::VarlinkInterface +)* Clone + Send + Sync + 'static,
+            {
+                vec![#(
+                    Box::new(#module_names_3::new(Box::new(inner.clone())))
+                        as Box<dyn varlink::Interface + Send + Sync>
+                ),*]
+            }
+        ));
     }
+
+    let rust_path = out_dir.join("varlink_interfaces.rs");
+    File::create(&rust_path)
+        .and_then(|mut f| f.write_all(ts.to_string().as_bytes()))
+        .map_err(mstrerr!(
+            Error,
+            "Could not write varlink output file `{}`",
+            rust_path.display()
+        ))?;
+
+    generated.push(GeneratedFile {
+        input_path: PathBuf::new(),
+        output_path: rust_path,
+        formatted: false,
+    });
+
+    Ok(generated)
 }
 
 /// cargo build helper function
@@ -1004,9 +2366,11 @@ where
 ///
 /// Set `rustfmt` to `true`, if you want the generator to run rustfmt on the
 /// generated code. This might be good practice to avoid large changes after a
-/// global `cargo fmt` run.
+/// global `cargo fmt` run. If rustfmt is not installed, the code is formatted
+/// by the built-in pretty printer and a `cargo:warning` is emitted.
 ///
 /// Errors are emitted to stderr and terminate the process.
+/// Use [`try_cargo_build_tosource`] to handle them yourself.
 ///
 /// # Examples
 ///
@@ -1017,8 +2381,37 @@ where
 ///     varlink_generator::cargo_build_tosource("src/org.example.ping.varlink", true);
 /// }
 /// ```
+///
+/// [`try_cargo_build_tosource`]: fn.try_cargo_build_tosource.html
 pub fn cargo_build_tosource<T: AsRef<Path> + ?Sized>(input_path: &T, rustfmt: bool) {
-    cargo_build_tosource_options(
+    exit_on_error(try_cargo_build_tosource(input_path, rustfmt));
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build_tosource` is the same as [`cargo_build_tosource`], but
+/// returns the paths of the generated file or the error, instead of terminating
+/// the process.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// extern crate varlink_generator;
+///
+/// fn main() {
+///     let generated =
+///         varlink_generator::try_cargo_build_tosource("src/org.example.ping.varlink", true)
+///             .unwrap();
+///     assert!(generated.output_path.ends_with("org_example_ping.rs"));
+/// }
+/// ```
+///
+/// [`cargo_build_tosource`]: fn.cargo_build_tosource.html
+pub fn try_cargo_build_tosource<T: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    rustfmt: bool,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
+    try_cargo_build_tosource_options(
         input_path,
         rustfmt,
         &GeneratorOptions {
@@ -1039,7 +2432,11 @@ pub fn cargo_build_tosource<T: AsRef<Path> + ?Sized>(input_path: &T, rustfmt: bo
 ///
 /// Set `rustfmt` to `true`, if you want the generator to run rustfmt on the
 /// generated code. This might be good practice to avoid large changes after a
-/// global `cargo fmt` run.
+/// global `cargo fmt` run. Another formatter can be set with
+/// `GeneratorOptions::formatter` and `GeneratorOptions::formatter_args`.
+/// If the formatter is missing or fails, the code is formatted by the built-in
+/// pretty printer and a `cargo:warning` is emitted. Set
+/// `GeneratorOptions::builtin_formatter` to always use the pretty printer.
 ///
 /// Errors are emitted to stderr and terminate the process.
 ///
@@ -1064,64 +2461,133 @@ pub fn cargo_build_tosource_options<T: AsRef<Path> + ?Sized>(
     rustfmt: bool,
     options: &GeneratorOptions,
 ) {
+    exit_on_error(try_cargo_build_tosource_options(
+        input_path, rustfmt, options,
+    ));
+}
+
+/// Runs the formatter of `options` on `path`.
+///
+/// If the formatter is missing or fails, the built-in pretty printer formats the file.
+/// This is only reported as `cargo:warning`, because the generated code compiles anyway.
+fn format_file(path: &Path, options: &GeneratorOptions) -> bool {
+    if options.builtin_formatter {
+        return pretty_print_file(path);
+    }
+
+    if let Err(e) = run_formatter(path, options) {
+        println!("cargo:warning={}, using the built-in formatter", e);
+        return pretty_print_file(path);
+    }
+    true
+}
+
+/// Runs the formatter of `options` on `path`, which is `$RUSTFMT` or `rustfmt` by default.
+fn run_formatter(path: &Path, options: &GeneratorOptions) -> std::result::Result<(), String> {
+    let formatter = match options.formatter {
+        Some(formatter) => formatter.into(),
+        None => env::var_os("RUSTFMT").unwrap_or_else(|| "rustfmt".into()),
+    };
+
+    match Command::new(&formatter)
+        .args(&options.formatter_args)
+        .arg(path)
+        .output()
+    {
+        Ok(ref output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!(
+            "`{}` failed on `{}` with {}: {}",
+            formatter.to_string_lossy(),
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(e) => Err(format!(
+            "Could not run `{}` on `{}`: {}",
+            formatter.to_string_lossy(),
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Formats the generated code in `path` with the built-in pretty printer.
+fn pretty_print_file(path: &Path) -> bool {
+    match pretty_print_path(path) {
+        Ok(()) => true,
+        Err(e) => {
+            println!(
+                "cargo:warning=Could not format `{}`, leaving it unformatted: {}",
+                path.display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+fn pretty_print_path(path: &Path) -> std::result::Result<(), String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|code| code.parse::<TokenStream>().map_err(|e| format!("{:?}", e)))
+        .and_then(|ts| std::fs::write(path, pretty::pretty_print(ts)).map_err(|e| e.to_string()))
+}
+
+/// `try_format_file` formats the generated code in `path` with the formatter of
+/// `options`, or with the built-in pretty printer, if `options.builtin_formatter` is set.
+///
+/// Unlike the `cargo_build_tosource` functions, it returns the error of a missing or
+/// failing formatter instead of falling back to the built-in pretty printer, e.g. for
+/// build systems calling `varlink-rust-generator --output`.
+pub fn try_format_file<T: AsRef<Path> + ?Sized>(
+    path: &T,
+    options: &GeneratorOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    let formatted = if options.builtin_formatter {
+        pretty_print_path(path).map_err(|e| format!("Could not format `{}`: {}", path.display(), e))
+    } else {
+        run_formatter(path, options)
+    };
+    formatted.map_err(|e| strerr!(Error, "{}", e))
+}
+
+/// cargo build helper function
+///
+/// `try_cargo_build_tosource_options` is the same as [`try_cargo_build_tosource`]
+/// with additional options for the generator.
+///
+/// [`try_cargo_build_tosource`]: fn.try_cargo_build_tosource.html
+pub fn try_cargo_build_tosource_options<T: AsRef<Path> + ?Sized>(
+    input_path: &T,
+    rustfmt: bool,
+    options: &GeneratorOptions,
+) -> std::result::Result<GeneratedFile, GeneratorError> {
     let input_path = input_path.as_ref();
     let noextension = input_path.with_extension("");
     let newfilename = noextension
         .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| {
+            strerr!(
+                Error,
+                "Invalid varlink input file name `{}`",
+                input_path.display()
+            )
+        })?
         .replace(".", "_");
     let rust_path = input_path
         .parent()
-        .unwrap()
+        .unwrap_or_else(|| Path::new(""))
         .join(Path::new(&newfilename).with_extension("rs"));
 
-    let writer: &mut dyn Write = &mut (File::create(&rust_path).unwrap_or_else(|e| {
-        eprintln!(
-            "Could not open varlink output file `{}`: {}",
-            rust_path.display(),
-            e
-        );
-        exit(1);
-    }));
-
-    let reader: &mut dyn Read = &mut (File::open(input_path).unwrap_or_else(|e| {
-        eprintln!(
-            "Could not read varlink input file `{}`: {}",
-            input_path.display(),
-            e
-        );
-        exit(1);
-    }));
-
-    if let Err(e) = generate_with_options(reader, writer, options, true) {
-        let mut s = String::new();
-        for i in e.iter() {
-            s += &i.to_string();
-            s += "\n";
-        }
-        eprintln!(
-            "Could not generate rust code from varlink file `{}`: {}",
-            input_path.display(),
-            s
-        );
-        exit(1);
-    }
+    let mut generated = generate_file(input_path, &rust_path, options, true)?;
 
     if rustfmt {
-        if let Err(e) = Command::new("rustfmt")
-            .arg(rust_path.to_str().unwrap())
-            .output()
-        {
-            eprintln!(
-                "Could not run rustfmt on file `{}` {}",
-                rust_path.display(),
-                e
-            );
-            exit(1);
-        }
+        generated.formatted = format_file(&rust_path, options);
     }
 
     println!("cargo:rerun-if-changed={}", input_path.display());
+
+    Ok(generated)
 }
@@ -0,0 +1,340 @@
+//! A pretty printer for the generated code, which needs no external formatter
+//!
+//! It breaks the token stream into lines after statements, items, fields and match
+//! arms, and indents the blocks. Lines are not wrapped, so the result is not what
+//! rustfmt would write, but it is readable and parses to the same tokens.
+
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+
+const KEYWORDS: &[&str] = &[
+    "as", "break", "const", "continue", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl",
+    "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+    "trait", "type", "unsafe", "use", "where", "while",
+];
+
+/// The longest block, which is kept on one line, e.g. `use std::{fmt, io};`
+const INLINE_BLOCK: usize = 60;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prev {
+    /// start of the stream or of a delimited group
+    Open,
+    Ident,
+    Keyword,
+    Literal,
+    Punct {
+        ch: char,
+        joint: bool,
+        /// an unary operator or another punctuation, which binds to the next token
+        prefix: bool,
+    },
+    Close(Delimiter),
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+    line_start: bool,
+    prev: Prev,
+    /// the punctuation joined with `prev`, to tell `::` and `->` from `:` and `>`
+    prev_ch: Option<char>,
+    generics: usize,
+    closure_args: bool,
+    attribute: bool,
+    /// the nesting of the groups
+    depth: usize,
+    /// the depth of the `match`, whose arms are in the next block
+    match_body: Option<usize>,
+}
+
+/// Returns the code of `ts` with line breaks and indentation.
+pub(crate) fn pretty_print(ts: TokenStream) -> String {
+    let mut printer = Printer {
+        out: String::new(),
+        indent: 0,
+        line_start: true,
+        prev: Prev::Open,
+        prev_ch: None,
+        generics: 0,
+        closure_args: false,
+        attribute: false,
+        depth: 0,
+        match_body: None,
+    };
+    printer.stream(ts, true, false, false);
+    printer.newline();
+    printer.out
+}
+
+fn is_value(prev: Prev) -> bool {
+    matches!(
+        prev,
+        Prev::Ident | Prev::Literal | Prev::Close(_) | Prev::Punct { ch: '?', .. }
+    )
+}
+
+/// Blocks without statements, items and nested blocks are kept on one line.
+fn is_inline(ts: &TokenStream) -> bool {
+    fn simple(ts: &TokenStream) -> bool {
+        ts.clone().into_iter().all(|tt| match tt {
+            TokenTree::Group(ref g) => g.delimiter() != Delimiter::Brace && simple(&g.stream()),
+            TokenTree::Punct(ref p) => p.as_char() != ';' && p.as_char() != '#',
+            _ => true,
+        })
+    }
+    ts.to_string().len() <= INLINE_BLOCK && simple(ts)
+}
+
+impl Printer {
+    fn newline(&mut self) {
+        if !self.line_start {
+            self.out.push('\n');
+            self.line_start = true;
+        }
+    }
+
+    fn blank_line(&mut self) {
+        self.newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    fn write(&mut self, space: bool, s: &str) {
+        if self.line_start {
+            for _ in 0..self.indent {
+                self.out.push_str("    ");
+            }
+            self.line_start = false;
+        } else if space {
+            self.out.push(' ');
+        }
+        self.out.push_str(s);
+    }
+
+    /// Whether a space separates the next token from the previous one.
+    fn space_after_prev(&self) -> bool {
+        match self.prev {
+            Prev::Open => false,
+            Prev::Punct { joint: true, .. } | Prev::Punct { prefix: true, .. } => false,
+            Prev::Punct { ch: '#', .. }
+            | Prev::Punct { ch: '.', .. }
+            | Prev::Punct { ch: '<', .. } => false,
+            Prev::Punct { ch: ':', .. } => self.prev_ch != Some(':'),
+            _ => true,
+        }
+    }
+
+    /// Prints the tokens of `ts`, where `block` tells, whether they are the statements or
+    /// items of a block, `inline`, whether the block is kept on one line, and `arms`,
+    /// whether they are the arms of a `match`.
+    fn stream(&mut self, ts: TokenStream, block: bool, inline: bool, arms: bool) {
+        // in the pattern of a match arm, which ends with `=>`
+        let mut pattern = arms;
+        let multiline = block && !inline;
+
+        let mut tokens = ts.into_iter().peekable();
+        while let Some(tt) = tokens.next() {
+            match tt {
+                TokenTree::Ident(ident) => {
+                    let name = ident.to_string();
+                    let space = self.space_after_prev();
+                    self.write(space, &name);
+                    self.prev = if KEYWORDS.contains(&name.as_str()) {
+                        Prev::Keyword
+                    } else {
+                        Prev::Ident
+                    };
+                    if name == "match" {
+                        self.match_body = Some(self.depth + 1);
+                    }
+                }
+                TokenTree::Literal(literal) => {
+                    let space = self.space_after_prev();
+                    self.write(space, &literal.to_string());
+                    self.prev = Prev::Literal;
+                }
+                TokenTree::Punct(punct) => {
+                    let ch = punct.as_char();
+                    if ch == '#' && self.line_start && self.indent == 0 && multiline {
+                        let last_line = self.out.trim_end().rsplit('\n').next().unwrap_or("");
+                        if !last_line.starts_with('#') {
+                            self.blank_line();
+                        }
+                    }
+                    self.punct(ch, punct.spacing());
+                    match ch {
+                        '>' if self.prev_ch == Some('=') => pattern = false,
+                        ';' if multiline => {
+                            self.generics = 0;
+                            match tokens.peek() {
+                                Some(TokenTree::Ident(ref i))
+                                    if self.indent == 0 && *i != "use" =>
+                                {
+                                    self.blank_line()
+                                }
+                                _ => self.newline(),
+                            }
+                            self.prev = Prev::Open;
+                        }
+                        ',' if multiline && self.generics == 0 => {
+                            pattern = arms;
+                            self.newline();
+                            self.prev = Prev::Open;
+                        }
+                        _ => {}
+                    }
+                }
+                TokenTree::Group(group) => {
+                    let delimiter = group.delimiter();
+                    self.depth += 1;
+                    match delimiter {
+                        Delimiter::Brace => self.brace(group.stream()),
+                        Delimiter::Parenthesis | Delimiter::Bracket => {
+                            self.parenthesis(delimiter, group.stream(), multiline, inline)
+                        }
+                        Delimiter::None => self.stream(group.stream(), block, inline, false),
+                    }
+                    self.depth -= 1;
+
+                    if delimiter != Delimiter::Brace || !multiline || pattern {
+                        continue;
+                    }
+                    pattern = arms;
+                    match tokens.peek() {
+                        Some(TokenTree::Punct(ref p)) if p.as_char() != '#' => {}
+                        Some(TokenTree::Ident(ref i)) if *i == "else" => {}
+                        Some(_) if self.indent == 0 => self.blank_line(),
+                        Some(_) => self.newline(),
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn brace(&mut self, ts: TokenStream) {
+        // `use std::{fmt, io};`
+        let path = match self.prev {
+            Prev::Punct { ch: ':', .. } => self.prev_ch == Some(':'),
+            _ => false,
+        };
+        let space = !self.line_start && self.prev != Prev::Open && !path;
+        let arms = self.match_body == Some(self.depth);
+        self.match_body = None;
+        self.generics = 0;
+        self.closure_args = false;
+        if ts.is_empty() {
+            self.write(space, "{}");
+        } else if is_inline(&ts) {
+            self.write(space, if path { "{" } else { "{ " });
+            self.prev = Prev::Open;
+            self.stream(ts, true, true, arms);
+            self.write(!path, "}");
+        } else {
+            self.write(space, "{");
+            self.newline();
+            self.indent += 1;
+            self.prev = Prev::Open;
+            self.stream(ts, true, false, arms);
+            self.newline();
+            self.indent -= 1;
+            self.write(false, "}");
+        }
+        self.prev = Prev::Close(Delimiter::Brace);
+    }
+
+    fn parenthesis(
+        &mut self,
+        delimiter: Delimiter,
+        ts: TokenStream,
+        multiline: bool,
+        inline: bool,
+    ) {
+        let space = match self.prev {
+            Prev::Ident | Prev::Close(_) => false,
+            // `Vec::<u8>::new()`, but `-> (u8, u8)`
+            Prev::Punct { ch: '>', .. } => self.prev_ch == Some('-') || self.prev_ch == Some('='),
+            _ => self.space_after_prev(),
+        };
+        let (open, close) = if delimiter == Delimiter::Parenthesis {
+            ("(", ")")
+        } else {
+            ("[", "]")
+        };
+        let attribute = self.attribute;
+        self.attribute = false;
+        self.write(space, open);
+        self.prev = Prev::Open;
+        self.stream(ts, false, inline, false);
+        self.write(false, close);
+        self.prev = Prev::Close(delimiter);
+        if attribute && multiline {
+            self.newline();
+            self.prev = Prev::Open;
+        }
+    }
+
+    fn punct(&mut self, ch: char, spacing: Spacing) {
+        let joint = spacing == Spacing::Joint;
+        let (prev_ch, after_joint) = match self.prev {
+            Prev::Punct { ch, joint, .. } => (Some(ch), joint),
+            _ => (None, false),
+        };
+        let value = is_value(self.prev);
+        let mut prefix = false;
+
+        let space = match ch {
+            ',' | ';' | '?' => false,
+            '.' => !value && self.space_after_prev(),
+            ':' => match self.prev {
+                Prev::Ident | Prev::Close(_) | Prev::Literal => false,
+                Prev::Punct { ch: '>', .. } if self.prev_ch.is_none() => false,
+                _ => self.space_after_prev(),
+            },
+            '<' => {
+                let generic = self.prev == Prev::Ident
+                    || self.prev == Prev::Keyword
+                    || (prev_ch == Some(':') && self.prev_ch == Some(':'));
+                if generic {
+                    self.generics += 1;
+                    false
+                } else {
+                    self.space_after_prev()
+                }
+            }
+            '>' if after_joint && (prev_ch == Some('-') || prev_ch == Some('=')) => false,
+            '>' if self.generics > 0 => {
+                self.generics -= 1;
+                false
+            }
+            '!' if self.prev == Prev::Ident => {
+                prefix = true;
+                false
+            }
+            '|' if self.closure_args => {
+                self.closure_args = false;
+                false
+            }
+            '|' if !value && !after_joint => {
+                self.closure_args = !joint;
+                prefix = true;
+                self.space_after_prev()
+            }
+            '&' | '*' | '-' | '!' if !value => {
+                prefix = true;
+                self.space_after_prev()
+            }
+            '#' => {
+                self.attribute = true;
+                self.space_after_prev()
+            }
+            _ => self.space_after_prev(),
+        };
+
+        let mut s = [0; 4];
+        self.write(space, ch.encode_utf8(&mut s));
+        self.prev_ch = if after_joint { prev_ch } else { None };
+        self.prev = Prev::Punct { ch, joint, prefix };
+    }
+}
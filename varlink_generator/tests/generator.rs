@@ -39,3 +39,607 @@ fn test_generate() {
     }
     let _ = std::fs::remove_file(path);
 }
+
+#[test]
+fn test_type_annotation() {
+    let ts: String = varlink_generator::compile(
+        "
+interface org.example.types
+method Resize(
+  # @rust-type: u64
+  size: int,
+  # @rust-type: f32
+  scale: ?[]float,
+  count: int
+) -> ()
+"
+        .into(),
+    )
+    .unwrap()
+    .to_string()
+    .split_whitespace()
+    .collect();
+    assert!(ts.contains("pubr#size:u64"));
+    assert!(ts.contains("pubr#scale:Option<Vec<f32>>"));
+    assert!(ts.contains("pubr#count:i64"));
+
+    assert!(varlink_generator::compile(
+        "
+interface org.example.types
+method Resize(
+  # @rust-type: f32
+  size: int
+) -> ()
+"
+        .into(),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_idempotent_annotation() {
+    let ts: String = varlink_generator::compile(
+        "
+interface org.example.retry
+# Returns the current state
+# @idempotent
+method Get() -> (state: object)
+method Set(state: object) -> ()
+"
+        .into(),
+    )
+    .unwrap()
+    .to_string()
+    .split_whitespace()
+    .collect();
+    assert!(ts.contains("\"org.example.retry.Get\",Get_Args{}).idempotent()}"));
+    assert!(ts.contains("\"org.example.retry.Get\",parameters).idempotent()}"));
+    assert!(ts.contains("\"org.example.retry.Set\",Set_Args{r#state})}"));
+    assert_eq!(ts.matches(".idempotent()").count(), 2);
+}
+
+#[test]
+fn test_raw_variants() {
+    let ts: String = varlink_generator::compile(
+        "
+interface org.example.raw
+type Wrapper (data: ?[]object)
+method Plain(a: string) -> (b: int)
+method Nested(w: Wrapper) -> ()
+method Foreign() -> (f: Unknown)
+"
+        .into(),
+    )
+    .unwrap()
+    .to_string()
+    .split_whitespace()
+    .collect();
+    assert!(!ts.contains("fnplain_raw"));
+    assert!(ts.contains(
+        "fnnested_raw(&mutself,parameters:serde_json::Value)->varlink::MethodCall<serde_json::Value,serde_json::Value,Error>"
+    ));
+    assert!(ts.contains("fnforeign_raw("));
+    assert!(ts.contains("pubtraitCall_Nested:VarlinkCallError{fnreply(&mutself)"));
+    assert_eq!(ts.matches("fnraw_parameters(").count(), 2);
+}
+
+#[test]
+fn test_dispatch_table() {
+    fn generate(count: usize) -> String {
+        let mut idl = String::from("interface org.example.many\n");
+        for i in 0..count {
+            idl.push_str(&format!("method Method{}(a: int) -> ()\n", i));
+        }
+        varlink_generator::compile(idl)
+            .unwrap()
+            .to_string()
+            .split_whitespace()
+            .collect()
+    }
+    fn list<'a>(ts: &'a str, field: &str) -> Vec<&'a str> {
+        let start = ts.find(&format!("{}:&[", field)).unwrap() + field.len() + 3;
+        let end = start + ts[start..].find(']').unwrap();
+        ts[start..end]
+            .split(',')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    let ts = generate(99);
+    assert!(!ts.contains("DISPATCH"));
+    assert!(ts.contains("\"org.example.many.Method98\"=>{"));
+
+    let ts = generate(100);
+    let displacements: Vec<u32> = list(&ts, "displacements")
+        .iter()
+        .map(|d| d.parse().unwrap())
+        .collect();
+    let names: Vec<&'static str> = list(&ts, "names")
+        .iter()
+        .map(|n| &*Box::leak(n.trim_matches('"').to_string().into_boxed_str()))
+        .collect();
+    let table = varlink::dispatch::Table {
+        displacements: Box::leak(displacements.into_boxed_slice()),
+        names: Box::leak(names.into_boxed_slice()),
+    };
+    for i in 0..100 {
+        let slot = table
+            .lookup("org.example.many", &format!("org.example.many.Method{}", i))
+            .unwrap();
+        assert_eq!(table.names[slot], format!("Method{}", i));
+        assert!(ts.contains(&format!("Some({})=>{{", slot)));
+    }
+    assert_eq!(
+        table.lookup("org.example.many", "org.example.many.Method100"),
+        None
+    );
+}
+
+#[test]
+fn test_examples() {
+    let ts = varlink_generator::compile(
+        "
+interface org.example.examples
+# Returns the same string
+# Example: {\"ping\": \"hello\"} -> {\"pong\": \"hello\"}
+method Ping(ping: string) -> (pong: string)
+"
+        .into(),
+    )
+    .unwrap()
+    .to_string();
+    assert!(ts.contains(r#"-> {\"ping\":\"hello\"}\n<- {\"pong\":\"hello\"}"#));
+
+    assert!(varlink_generator::compile(
+        "
+interface org.example.examples
+# Example: {\"ping\": \"hello\"} {\"pong\": \"hello\"}
+method Ping(ping: string) -> (pong: string)
+"
+        .into(),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_try_cargo_build() {
+    std::env::set_var("OUT_DIR", std::env::temp_dir());
+
+    let generated =
+        varlink_generator::try_cargo_build("../examples/ping/src/org.example.ping.varlink")
+            .unwrap();
+    assert_eq!(
+        generated.output_path,
+        std::env::temp_dir().join("org.example.ping.rs")
+    );
+    let _ = std::fs::remove_file(generated.output_path);
+
+    assert!(varlink_generator::try_cargo_build("tests/org.example.missing.varlink").is_err());
+}
+
+#[test]
+fn test_try_cargo_build_dir() {
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    std::env::set_var("OUT_DIR", std::env::temp_dir());
+
+    let generated = varlink_generator::try_cargo_build_dir("../examples/more/src").unwrap();
+    assert_eq!(generated.len(), 2);
+    assert_eq!(
+        generated[0].output_path,
+        std::env::temp_dir().join("org.example.more.rs")
+    );
+
+    let mut registry = String::new();
+    File::open(&generated[1].output_path)
+        .unwrap()
+        .read_to_string(&mut registry)
+        .unwrap();
+    let registry: String = registry.split_whitespace().collect();
+    assert!(registry.contains("pubmodorg_example_more{"));
+    assert!(registry.contains("&[org_example_more::INTERFACE_NAME]"));
+
+    for file in generated {
+        let _ = std::fs::remove_file(file.output_path);
+    }
+
+    // in the same test, because both write `$OUT_DIR/varlink_interfaces.rs`
+    let dir = std::env::temp_dir().join("varlink_generator_many");
+    let _ = std::fs::create_dir_all(&dir);
+    let input_path = dir.join("org.example.more.varlink");
+    std::fs::copy("../examples/ping/src/org.example.ping.varlink", &input_path).unwrap();
+
+    let generated = varlink_generator::try_cargo_build_options_many(
+        &[
+            std::path::Path::new("../examples/more/src/org.example.more.varlink"),
+            input_path.as_path(),
+        ],
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(generated.len(), 3);
+    assert_eq!(
+        generated[0].output_path,
+        std::env::temp_dir().join("org.example.more.rs")
+    );
+    assert_eq!(
+        generated[1].output_path,
+        std::env::temp_dir().join("org_example_more_2.rs")
+    );
+
+    let mut registry = String::new();
+    File::open(&generated[2].output_path)
+        .unwrap()
+        .read_to_string(&mut registry)
+        .unwrap();
+    let registry: String = registry.split_whitespace().collect();
+    assert!(registry.contains(
+        "pubmodorg_example_more{include!(concat!(env!(\"OUT_DIR\"),\"/org.example.more.rs\"));}"
+    ));
+    assert!(registry.contains("pubmodorg_example_more_2{include!(concat!(env!(\"OUT_DIR\"),\"/org_example_more_2.rs\"));}"));
+    assert!(
+        registry.contains("&[org_example_more::INTERFACE_NAME,org_example_more_2::INTERFACE_NAME]")
+    );
+
+    for file in generated {
+        let _ = std::fs::remove_file(file.output_path);
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_try_cargo_build_to() {
+    let dir = std::env::temp_dir().join("varlink_generator_to");
+    let _ = std::fs::remove_dir_all(&dir);
+    let output_path = dir.join("generated").join("ping.rs");
+
+    let generated = varlink_generator::try_cargo_build_to(
+        "../examples/ping/src/org.example.ping.varlink",
+        &output_path,
+    )
+    .unwrap();
+    assert_eq!(generated.output_path, output_path);
+    assert!(!generated.formatted);
+    let code = std::fs::read_to_string(&output_path).unwrap();
+    assert!(code.contains("VarlinkClientInterface"));
+
+    assert!(varlink_generator::try_cargo_build_to(
+        "../examples/ping/src/org.example.missing.varlink",
+        &output_path,
+    )
+    .is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_try_cargo_build_tosource_formatter() {
+    let dir = std::env::temp_dir().join("varlink_generator_formatter");
+    let _ = std::fs::create_dir_all(&dir);
+    let input_path = dir.join("org.example.ping.varlink");
+    std::fs::copy("../examples/ping/src/org.example.ping.varlink", &input_path).unwrap();
+
+    let generated = varlink_generator::try_cargo_build_tosource_options(
+        &input_path,
+        true,
+        &varlink_generator::GeneratorOptions {
+            formatter: Some("varlink-missing-formatter"),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    // formatted by the built-in pretty printer
+    assert!(generated.formatted);
+    assert!(generated.output_path.exists());
+
+    let generated = varlink_generator::try_cargo_build_tosource_options(
+        &input_path,
+        true,
+        &varlink_generator::GeneratorOptions {
+            formatter: Some("sh"),
+            formatter_args: vec!["-c", "exit 0"],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(generated.formatted);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_try_format_file() {
+    let dir = std::env::temp_dir().join("varlink_generator_try_format_file");
+    let _ = std::fs::create_dir_all(&dir);
+    let path = dir.join("org_example_ping.rs");
+    let mut file = std::fs::File::create(&path).unwrap();
+    varlink_generator::generate(
+        &mut std::fs::File::open("../examples/ping/src/org.example.ping.varlink").unwrap(),
+        &mut file,
+        true,
+    )
+    .unwrap();
+    drop(file);
+
+    let e = varlink_generator::try_format_file(
+        &path,
+        &varlink_generator::GeneratorOptions {
+            formatter: Some("varlink-missing-formatter"),
+            ..Default::default()
+        },
+    )
+    .err()
+    .unwrap();
+    assert!(e
+        .to_string()
+        .contains("Could not run `varlink-missing-formatter`"));
+
+    let e = varlink_generator::try_format_file(
+        &path,
+        &varlink_generator::GeneratorOptions {
+            formatter: Some("sh"),
+            formatter_args: vec!["-c", "echo broken >&2; exit 3", "sh"],
+            ..Default::default()
+        },
+    )
+    .err()
+    .unwrap();
+    assert!(e.to_string().contains("failed on"));
+    assert!(e.to_string().contains(": broken"));
+
+    varlink_generator::try_format_file(
+        &path,
+        &varlink_generator::GeneratorOptions {
+            builtin_formatter: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let code = std::fs::read_to_string(&path).unwrap();
+    assert!(code.contains("\n    pub fn new("));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_builtin_formatter() {
+    use proc_macro2::{TokenStream, TokenTree};
+    use std::fs::File;
+    use std::path::Path;
+
+    // the tokens without their spacing
+    fn tokens(ts: TokenStream) -> Vec<String> {
+        let mut result = Vec::new();
+        for tt in ts {
+            match tt {
+                TokenTree::Group(g) => {
+                    result.push(format!("{:?}", g.delimiter()));
+                    result.extend(tokens(g.stream()));
+                    result.push("end".into());
+                }
+                tt => result.push(tt.to_string()),
+            }
+        }
+        result
+    }
+
+    let dir = std::env::temp_dir().join("varlink_generator_builtin_formatter");
+    let _ = std::fs::create_dir_all(&dir);
+
+    for file in &[
+        "tests/org.example.complex.varlink",
+        "../examples/more/src/org.example.more.varlink",
+        "../varlink-certification/src/org.varlink.certification.varlink",
+    ] {
+        let input_path = dir.join(Path::new(file).file_name().unwrap());
+        std::fs::copy(file, &input_path).unwrap();
+
+        let generated = varlink_generator::try_cargo_build_tosource_options(
+            &input_path,
+            true,
+            &varlink_generator::GeneratorOptions {
+                builtin_formatter: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(generated.formatted);
+
+        let code = std::fs::read_to_string(&generated.output_path).unwrap();
+        assert!(code.contains("\n    pub fn new("));
+
+        let mut expected = Vec::new();
+        varlink_generator::generate(&mut File::open(file).unwrap(), &mut expected, true).unwrap();
+        assert_eq!(
+            tokens(code.parse().unwrap()),
+            tokens(String::from_utf8(expected).unwrap().parse().unwrap())
+        );
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_side() {
+    use varlink_generator::{generate_with_options, GeneratorOptions, Side};
+
+    fn generate(side: Side) -> String {
+        let mut out = Vec::new();
+        generate_with_options(
+            &mut "
+interface org.example.side
+type State (on: bool)
+method Get() -> (state: State)
+error Failed (reason: string)
+"
+            .as_bytes(),
+            &mut out,
+            &GeneratorOptions {
+                side,
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap().split_whitespace().collect()
+    }
+
+    let shared = [
+        "pubstructr#State{",
+        "pubstructGet_Args{",
+        "pubstructGet_Reply{",
+        "pubstructFailed_Args{",
+        "pubenumErrorKind{",
+    ];
+    let client = ["pubtraitVarlinkClientInterface{", "pubstructVarlinkClient{"];
+    let server = [
+        "pubtraitVarlinkInterface{",
+        "pubtraitCall_Get:VarlinkCallError{",
+        "pubtraitVarlinkCallError:varlink::CallTrait{",
+        "pubfnnew(inner:",
+        "usestd::io::BufRead;",
+    ];
+
+    let both = generate(Side::Both);
+    assert!(shared
+        .iter()
+        .chain(&client)
+        .chain(&server)
+        .all(|s| both.contains(s)));
+
+    let ts = generate(Side::Client);
+    assert!(shared.iter().chain(&client).all(|s| ts.contains(s)));
+    assert!(!server.iter().any(|s| ts.contains(s)));
+    assert!(!ts.contains("CallTrait"));
+
+    let ts = generate(Side::Server);
+    assert!(shared.iter().chain(&server).all(|s| ts.contains(s)));
+    assert!(!client.iter().any(|s| ts.contains(s)));
+    assert!(!ts.contains("RwLock"));
+
+    assert_eq!("client".parse::<Side>().unwrap(), Side::Client);
+    assert!("clients".parse::<Side>().is_err());
+}
+
+#[test]
+fn test_field_naming() {
+    use varlink_generator::{generate_with_options, FieldNaming, GeneratorOptions};
+
+    fn generate(field_naming: FieldNaming) -> String {
+        let mut out = Vec::new();
+        generate_with_options(
+            &mut "
+interface org.example.naming
+type State (isOn: bool, URL: string)
+method Get(deviceId: string) -> (state: State)
+error Failed (errorText: ?string)
+"
+            .as_bytes(),
+            &mut out,
+            &GeneratorOptions {
+                field_naming,
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap().split_whitespace().collect()
+    }
+
+    let ts = generate(FieldNaming::Verbatim);
+    assert!(ts.contains("pubr#isOn:bool"));
+    assert!(ts.contains("pubr#deviceId:String"));
+    assert!(!ts.contains("serde(rename"));
+
+    let ts = generate(FieldNaming::CamelCase);
+    assert!(ts.contains("#[serde(rename_all=\"camelCase\")]pubstructr#State{"));
+    assert!(ts.contains("{pubr#is_on:bool,#[serde(rename=\"URL\")]pubr#url:String,}"));
+    assert!(ts.contains("pubr#device_id:String"));
+    assert!(ts.contains("fnreply_failed(&mutself,r#error_text:Option<String>)"));
+    assert!(!ts.contains("r#deviceId"));
+
+    let ts = generate(FieldNaming::SnakeCase);
+    assert!(!ts.contains("rename_all"));
+    assert!(ts.contains("#[serde(rename=\"isOn\")]pubr#is_on:bool"));
+    assert!(ts.contains("#[serde(rename=\"state\")]pubr#state:State"));
+    assert!(ts.contains("#[serde(rename=\"errorText\")]#[serde(skip_serializing_if"));
+
+    assert_eq!(
+        "camelCase".parse::<FieldNaming>().unwrap(),
+        FieldNaming::CamelCase
+    );
+    assert!("camel".parse::<FieldNaming>().is_err());
+}
+
+#[test]
+fn test_scaffold() {
+    use varlink_generator::{generate_scaffold, GeneratorOptions, Side};
+
+    fn scaffold(options: &GeneratorOptions) -> varlink_generator::Result<String> {
+        let mut out = Vec::new();
+        generate_scaffold(
+            &mut std::fs::File::open("tests/org.example.complex.varlink").unwrap(),
+            &mut out,
+            options,
+        )?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    let main = scaffold(&Default::default()).unwrap();
+    assert!(main.contains("mod org_example_complex;\n"));
+    assert!(main.contains("impl VarlinkInterface for Service {\n"));
+    assert!(main.contains("    fn bar(&self, call: &mut dyn Call_Bar) -> varlink::Result<()> {\n"));
+    // keywords stay raw identifiers, long signatures are wrapped
+    assert!(main.contains(
+        "    fn foo(
+        &self,
+        call: &mut dyn Call_Foo,
+        r#enum: Foo_Args_enum,
+        foo: TypeFoo,
+        interface: Interface,
+    ) -> varlink::Result<()> {
+        // call.reply(a, foo, interface)?;
+        todo!()
+    }"
+    ));
+    assert!(main.contains("arg.starts_with(\"--varlink=\")"));
+    assert!(main.contains("vec![Box::new(org_example_complex::new(Box::new(Service)))]"));
+    assert!(main.parse::<proc_macro2::TokenStream>().is_ok());
+
+    assert!(scaffold(&GeneratorOptions {
+        side: Side::Client,
+        ..Default::default()
+    })
+    .is_err());
+}
+
+#[test]
+fn test_nested_anonymous_types() {
+    let ts: String = varlink_generator::compile(
+        "
+interface org.example.matrix
+type Foo (a_b: (x: int), a: (b: (y: int)))
+method Get(m: [][]int, cells: [][string]?[](c: [](d: (e, f)))) -> (r: [][][](z: bool))
+"
+        .into(),
+    )
+    .unwrap()
+    .to_string()
+    .split_whitespace()
+    .collect();
+
+    assert!(ts.contains("pubr#m:Vec<Vec<i64>>"));
+    assert!(ts.contains("pubr#cells:Vec<varlink::StringHashMap<Option<Vec<Get_Args_cells>>>>"));
+    assert!(ts.contains("pubstructr#Get_Args_cells{pubr#c:Vec<Get_Args_cells_c>,}"));
+    assert!(ts.contains("pubstructr#Get_Args_cells_c{pubr#d:Get_Args_cells_c_d,}"));
+    assert!(ts.contains("pubenumr#Get_Args_cells_c_d{r#e,r#f,}"));
+    assert!(ts.contains("pubr#r:Vec<Vec<Vec<Get_Reply_r>>>"));
+
+    // the fields `a_b` and `a.b` get different types
+    assert!(ts.contains("pubstructr#Foo_a_b{pubr#x:i64,}"));
+    assert!(ts.contains("pubstructr#Foo_a_b_2{pubr#y:i64,}"));
+    assert!(ts.contains("pubstructr#Foo_a{pubr#b:Foo_a_b_2,}"));
+    assert!(ts.contains("pubstructr#Foo{pubr#a_b:Foo_a_b,pubr#a:Foo_a,}"));
+}
@@ -0,0 +1,223 @@
+//! Semantic comparison of two versions of a varlink interface
+
+use std::fmt;
+
+use crate::*;
+
+/// Whether a change keeps existing clients and services working
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible,
+    Breaking,
+}
+
+/// A single difference between two versions of an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub compatibility: Compatibility,
+    /// The changed member, e.g. `Ping.input.ping` or `MyType.field`
+    pub path: String,
+    pub description: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = match self.compatibility {
+            Compatibility::Compatible => "compatible",
+            Compatibility::Breaking => "breaking",
+        };
+        write!(f, "{}: {}: {}", c, self.path, self.description)
+    }
+}
+
+/// The result of [`diff`](fn.diff.html)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompatReport {
+    pub changes: Vec<Change>,
+}
+
+impl CompatReport {
+    /// Returns `true`, if none of the changes is breaking.
+    pub fn is_compatible(&self) -> bool {
+        !self
+            .changes
+            .iter()
+            .any(|c| c.compatibility == Compatibility::Breaking)
+    }
+
+    /// Returns the breaking changes.
+    pub fn breaking(&self) -> Vec<&Change> {
+        self.changes
+            .iter()
+            .filter(|c| c.compatibility == Compatibility::Breaking)
+            .collect()
+    }
+
+    fn add<S: Into<String>>(&mut self, compatibility: Compatibility, path: &str, description: S) {
+        self.changes.push(Change {
+            compatibility,
+            path: path.into(),
+            description: description.into(),
+        })
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.changes {
+            writeln!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares two versions of an interface and classifies the differences.
+///
+/// Added methods, types, errors, enum values and optional fields are compatible.
+/// Removed members, new mandatory fields and changed types are breaking.
+///
+/// # Examples
+///
+/// ```rust
+/// use varlink_parser::{diff, IDL};
+///
+/// let old = IDL::from_string("interface org.example.a\nmethod F(a: string) -> ()").unwrap();
+/// let new = IDL::from_string("interface org.example.a\nmethod F(a: string, b: ?int) -> ()").unwrap();
+/// assert!(diff(&old, &new).is_compatible());
+/// assert!(!diff(&new, &old).is_compatible());
+/// ```
+pub fn diff(old: &IDL, new: &IDL) -> CompatReport {
+    let mut report = CompatReport::default();
+
+    if old.name != new.name {
+        report.add(
+            Compatibility::Breaking,
+            new.name,
+            format!("interface renamed from `{}`", old.name),
+        );
+    }
+
+    for name in &old.method_keys {
+        let o = &old.methods[name];
+        match new.methods.get(name) {
+            None => report.add(Compatibility::Breaking, name, "method removed"),
+            Some(n) => {
+                diff_struct(&mut report, &format!("{}.input", name), &o.input, &n.input);
+                diff_struct(
+                    &mut report,
+                    &format!("{}.output", name),
+                    &o.output,
+                    &n.output,
+                );
+            }
+        }
+    }
+    for name in &new.method_keys {
+        if !old.methods.contains_key(name) {
+            report.add(Compatibility::Compatible, name, "method added");
+        }
+    }
+
+    for name in &old.typedef_keys {
+        let o = &old.typedefs[name];
+        match new.typedefs.get(name) {
+            None => report.add(Compatibility::Breaking, name, "type removed"),
+            Some(n) => match (&o.elt, &n.elt) {
+                (VStructOrEnum::VStruct(o), VStructOrEnum::VStruct(n)) => {
+                    diff_struct(&mut report, name, o, n)
+                }
+                (VStructOrEnum::VEnum(o), VStructOrEnum::VEnum(n)) => {
+                    diff_enum(&mut report, name, o, n)
+                }
+                _ => report.add(Compatibility::Breaking, name, "type changed"),
+            },
+        }
+    }
+    for name in &new.typedef_keys {
+        if !old.typedefs.contains_key(name) {
+            report.add(Compatibility::Compatible, name, "type added");
+        }
+    }
+
+    for name in &old.error_keys {
+        let o = &old.errors[name];
+        match new.errors.get(name) {
+            None => report.add(Compatibility::Breaking, name, "error removed"),
+            Some(n) => diff_struct(&mut report, name, &o.parm, &n.parm),
+        }
+    }
+    for name in &new.error_keys {
+        if !old.errors.contains_key(name) {
+            report.add(Compatibility::Compatible, name, "error added");
+        }
+    }
+
+    report
+}
+
+fn diff_struct(report: &mut CompatReport, path: &str, old: &VStruct, new: &VStruct) {
+    for o in &old.elts {
+        let path = format!("{}.{}", path, o.name);
+        match new.elts.iter().find(|n| n.name == o.name) {
+            None => report.add(Compatibility::Breaking, &path, "field removed"),
+            Some(n) => diff_type(report, &path, &o.vtype, &n.vtype),
+        }
+    }
+    for n in &new.elts {
+        if old.elts.iter().any(|o| o.name == n.name) {
+            continue;
+        }
+        let path = format!("{}.{}", path, n.name);
+        match n.vtype {
+            VTypeExt::Option(_) => {
+                report.add(Compatibility::Compatible, &path, "optional field added")
+            }
+            _ => report.add(Compatibility::Breaking, &path, "mandatory field added"),
+        }
+    }
+}
+
+fn diff_enum(report: &mut CompatReport, path: &str, old: &VEnum, new: &VEnum) {
+    for o in &old.elts {
+        if !new.elts.contains(o) {
+            report.add(
+                Compatibility::Breaking,
+                path,
+                format!("enum value `{}` removed", o),
+            );
+        }
+    }
+    for n in &new.elts {
+        if !old.elts.contains(n) {
+            report.add(
+                Compatibility::Compatible,
+                path,
+                format!("enum value `{}` added", n),
+            );
+        }
+    }
+}
+
+fn diff_type(report: &mut CompatReport, path: &str, old: &VTypeExt, new: &VTypeExt) {
+    match (old, new) {
+        (VTypeExt::Array(o), VTypeExt::Array(n))
+        | (VTypeExt::Dict(o), VTypeExt::Dict(n))
+        | (VTypeExt::Option(o), VTypeExt::Option(n)) => diff_type(report, path, o, n),
+        (VTypeExt::Plain(VType::Struct(o)), VTypeExt::Plain(VType::Struct(n))) => {
+            diff_struct(report, path, o, n)
+        }
+        (VTypeExt::Plain(VType::Enum(o)), VTypeExt::Plain(VType::Enum(n))) => {
+            diff_enum(report, path, o, n)
+        }
+        _ => {
+            let (o, n) = (old.get_oneline(), new.get_oneline());
+            if o != n {
+                report.add(
+                    Compatibility::Breaking,
+                    path,
+                    format!("type changed from `{}` to `{}`", o, n),
+                );
+            }
+        }
+    }
+}
@@ -43,15 +43,23 @@
     html_favicon_url = "https://varlink.org/images/varlink-small.png"
 )]
 
-use self::varlink_grammar::ParseInterface;
+use self::varlink_grammar::{ParseBlank, ParseInterface, ParseInterfaceHeader, ParseMember};
 use std::collections::BTreeMap;
 use std::collections::HashSet;
 
 use chainerror::*;
+use peg::Parse;
+use serde_derive::Serialize;
 
+mod diff;
 mod format;
+mod limits;
+mod lint;
 
+pub use crate::diff::{diff, Change, CompatReport, Compatibility};
 pub use crate::format::{Format, FormatColored};
+pub use crate::limits::Limits;
+pub use crate::lint::{lint, lint_with, LintRule, LintWarning};
 
 #[cfg(test)]
 mod test;
@@ -60,6 +68,79 @@ mod varlink_grammar;
 
 derive_str_cherr!(Error);
 
+/// Syntax error in a varlink interface definition
+///
+/// `IDL::from_string` returns it as the cause of the `Error`, use
+/// `find_cause::<ParseError>()` on the returned error to get it.
+/// The `Display` output shows the offending line with a caret at the error position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// byte offset of the error in the input
+    pub offset: usize,
+    /// line of the error, starting with 1
+    pub line: usize,
+    /// column of the error, starting with 1
+    pub column: usize,
+    /// the offending token, empty at the end of the input
+    pub token: String,
+    /// the tokens, which would have been valid at the position
+    pub expected: Vec<String>,
+    /// the text of the offending line
+    pub line_text: String,
+}
+
+impl ParseError {
+    /// Creates the error of parsing `s[start..]` with the position in `s`.
+    fn new(s: &str, start: usize, e: &peg::error::ParseError<peg::str::LineCol>) -> Self {
+        let location = s.position_repr(start + e.location.offset);
+        let rest = &s[location.offset..];
+        let token = match rest.chars().next() {
+            None => "",
+            Some(c) if c.is_alphanumeric() || c == '_' || c == '.' => rest
+                .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+                .next()
+                .unwrap_or(""),
+            Some(c) => &rest[..c.len_utf8()],
+        };
+        let mut expected: Vec<String> = e.expected.tokens().map(String::from).collect();
+        expected.sort();
+
+        ParseError {
+            offset: location.offset,
+            line: location.line,
+            column: location.column,
+            token: token.into(),
+            expected,
+            line_text: s.lines().nth(location.line - 1).unwrap_or("").into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        if self.token.is_empty() {
+            write!(f, "unexpected end of input")?;
+        } else {
+            write!(f, "unexpected `{}`", self.token)?;
+        }
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", self.expected.join(", "))?;
+        }
+        write!(
+            f,
+            "\n{}\n{marker:>col$}",
+            self.line_text,
+            marker = "^",
+            col = self.column
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum VType<'a> {
     Bool,
     Int,
@@ -71,6 +152,8 @@ pub enum VType<'a> {
     Enum(Box<VEnum<'a>>),
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum VTypeExt<'a> {
     Array(Box<VTypeExt<'a>>),
     Dict(Box<VTypeExt<'a>>),
@@ -78,36 +161,65 @@ pub enum VTypeExt<'a> {
     Plain(VType<'a>),
 }
 
+/// A field of a struct, or a parameter of a method or error
+///
+/// More members might be added, so it is created with [`new`](#method.new).
+#[derive(Serialize)]
+#[non_exhaustive]
 pub struct Argument<'a> {
     pub name: &'a str,
+    /// The comment lines before the field
+    pub doc: &'a str,
     pub vtype: VTypeExt<'a>,
 }
 
+impl<'a> Argument<'a> {
+    /// # Examples
+    ///
+    /// ```rust
+    /// use varlink_parser::{Argument, VType, VTypeExt};
+    ///
+    /// let argument = Argument::new("ping", "", VTypeExt::Plain(VType::String));
+    /// assert_eq!(argument.name, "ping");
+    /// ```
+    pub fn new(name: &'a str, doc: &'a str, vtype: VTypeExt<'a>) -> Self {
+        Argument { name, doc, vtype }
+    }
+}
+
+#[derive(Serialize)]
 pub struct VStruct<'a> {
     pub elts: Vec<Argument<'a>>,
 }
 
+#[derive(Serialize)]
 pub struct VEnum<'a> {
     pub elts: Vec<&'a str>,
 }
 
+#[derive(Serialize)]
 pub struct VError<'a> {
     pub name: &'a str,
     pub doc: &'a str,
     pub parm: VStruct<'a>,
 }
 
+#[derive(Serialize)]
 pub enum VStructOrEnum<'a> {
+    #[serde(rename = "struct")]
     VStruct(Box<VStruct<'a>>),
+    #[serde(rename = "enum")]
     VEnum(Box<VEnum<'a>>),
 }
 
+#[derive(Serialize)]
 pub struct Typedef<'a> {
     pub name: &'a str,
     pub doc: &'a str,
     pub elt: VStructOrEnum<'a>,
 }
 
+#[derive(Serialize)]
 pub struct Method<'a> {
     pub name: &'a str,
     pub doc: &'a str,
@@ -121,6 +233,7 @@ enum MethodOrTypedefOrError<'a> {
     Method(Method<'a>),
 }
 
+#[derive(Serialize)]
 pub struct IDL<'a> {
     pub description: &'a str,
     pub name: &'a str,
@@ -131,6 +244,7 @@ pub struct IDL<'a> {
     pub typedef_keys: Vec<&'a str>,
     pub errors: BTreeMap<&'a str, VError<'a>>,
     pub error_keys: Vec<&'a str>,
+    #[serde(skip)]
     pub error: HashSet<String>,
 }
 
@@ -217,17 +331,48 @@ impl<'a> IDL<'a> {
     }
 }
 
+/// Returns the offset of the next line after `offset`, which starts a member, including the
+/// comment lines right before it, or `None`, if there is none.
+fn next_member(s: &str, offset: usize) -> Option<usize> {
+    let mut line_start = match s[offset..].find('\n') {
+        Some(n) => offset + n + 1,
+        None => return None,
+    };
+    let mut doc_start = None;
+    while line_start < s.len() {
+        let line = s[line_start..].lines().next().unwrap_or("");
+        let trimmed = line.trim_start();
+        let keyword = trimmed.split_whitespace().next().unwrap_or("");
+        if keyword == "method" || keyword == "type" || keyword == "error" {
+            return Some(doc_start.unwrap_or(line_start));
+        }
+        if trimmed.starts_with('#') {
+            doc_start = doc_start.or(Some(line_start));
+        } else {
+            doc_start = None;
+        }
+        line_start += match s[line_start..].find('\n') {
+            Some(n) => n + 1,
+            None => return None,
+        };
+    }
+    None
+}
+
 impl<'a> IDL<'a> {
+    /// Returns the parsed interface as JSON, e.g. for documentation tools or diffing.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
     pub fn from_string(s: &'a str) -> ChainResult<Self, Error> {
         let interface = ParseInterface(s).map_err(|e| {
-            let line = s.split('\n').nth(e.location.line - 1).unwrap();
+            let e = ParseError::new(s, 0, &e);
             cherr!(
                 e,
                 Error(format!(
-                    "Varlink parse error\n{}\n{marker:>col$}",
-                    line,
-                    marker = "^",
-                    col = e.location.column
+                    "Varlink parse error at line {}, column {}",
+                    e.line, e.column
                 ))
             )
         })?;
@@ -249,4 +394,93 @@ impl<'a> IDL<'a> {
             Ok(interface)
         }
     }
+
+    /// Parses `s` like `from_string`, but fails if it exceeds any of the `limits`.
+    ///
+    /// The nesting depth and the names are checked before parsing, so a pathological
+    /// description does not cause a deep recursion of the parser.
+    pub fn from_string_with_limits(s: &'a str, limits: &Limits) -> ChainResult<Self, Error> {
+        limits::check_text(s, limits)
+            .map_err(|e| strerr!(Error, "Interface definition exceeds the limits: {}", e))?;
+        let interface = IDL::from_string(s)?;
+        limits::check_idl(&interface, limits)
+            .map_err(|e| strerr!(Error, "Interface definition exceeds the limits: {}", e))?;
+        Ok(interface)
+    }
+
+    /// Parses `s` like `from_string`, but continues after a syntax error.
+    ///
+    /// Returns the interface with all members, which could be parsed, and the syntax errors
+    /// in the order of the input. A member with an error is skipped up to the next line
+    /// starting with `method`, `type` or `error`. If the `interface` line has an error, the
+    /// returned interface has an empty name.
+    ///
+    /// Definition errors, like multiple definitions of a name, are not returned, but are
+    /// in the `error` field of the interface as with a successful parse.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use varlink_parser::IDL;
+    /// let (interface, errors) = IDL::from_string_with_diagnostics(
+    ///     "interface org.example.broken
+    /// method A(x: int) -> ()
+    /// method B(y: ) -> ()
+    /// method C() -> (z: strin)
+    /// error D ()
+    /// ",
+    /// );
+    /// assert_eq!(interface.method_keys, vec!["A"]);
+    /// assert_eq!(interface.error_keys, vec!["D"]);
+    /// assert_eq!(errors.len(), 2);
+    /// assert_eq!((errors[0].line, errors[1].line), (3, 4));
+    /// ```
+    pub fn from_string_with_diagnostics(s: &'a str) -> (Self, Vec<ParseError>) {
+        let full_error = match ParseInterface(s) {
+            Ok(interface) => return (interface, Vec::new()),
+            Err(e) => ParseError::new(s, 0, &e),
+        };
+
+        let mut errors = Vec::new();
+        let mut members = Vec::new();
+        let (doc, name, mut pos) = match ParseInterfaceHeader(s) {
+            Ok(header) => header,
+            Err(e) => {
+                let e = ParseError::new(s, 0, &e);
+                let next = next_member(s, e.offset);
+                errors.push(e);
+                match next {
+                    Some(next) => ("", "", next),
+                    None => ("", "", s.len()),
+                }
+            }
+        };
+
+        // an interface needs at least one member, so the first one is always parsed
+        let mut first = errors.is_empty();
+        while first || (pos < s.len() && ParseBlank(&s[pos..]).is_err()) {
+            first = false;
+            match ParseMember(&s[pos..]) {
+                Ok((member, len)) => {
+                    members.push(member);
+                    pos += len;
+                }
+                Err(e) => {
+                    let e = ParseError::new(s, pos, &e);
+                    let next = next_member(s, e.offset);
+                    errors.push(e);
+                    match next {
+                        Some(next) => pos = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            errors.push(full_error);
+        }
+
+        (IDL::from_token(s, name, members, doc), errors)
+    }
 }
@@ -0,0 +1,137 @@
+//! Limits for parsing untrusted interface definitions
+
+use crate::*;
+
+/// Limits of [`IDL::from_string_with_limits`](struct.IDL.html#method.from_string_with_limits)
+///
+/// Use them for descriptions from an untrusted source, e.g. the replies of
+/// `GetInterfaceDescription`, which could otherwise cause a deep recursion of the parser
+/// or huge allocations.
+///
+/// # Examples
+///
+/// ```rust
+/// use varlink_parser::{Limits, IDL};
+///
+/// let limits = Limits {
+///     max_depth: 1,
+///     ..Default::default()
+/// };
+/// assert!(IDL::from_string_with_limits("interface org.example.a\nmethod F(a: []int) -> ()", &limits).is_ok());
+/// assert!(IDL::from_string_with_limits("interface org.example.a\nmethod F(a: [][]int) -> ()", &limits).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum nesting depth of a type, every anonymous struct or enum and every
+    /// array, map and option counts as one level, e.g. `?[](a: int)` has a depth of 3.
+    pub max_depth: usize,
+    /// The maximum number of fields and enum values in the whole interface
+    pub max_fields: usize,
+    /// The maximum length of a name, including the dots of the interface name
+    pub max_name_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_depth: 64,
+            max_fields: 10_000,
+            max_name_length: 255,
+        }
+    }
+}
+
+/// Checks the nesting depth and the length of the names in `s`, before it is parsed.
+pub(crate) fn check_text(s: &str, limits: &Limits) -> Result<(), String> {
+    let mut line = 1;
+    // the prefixes of the types, in which each of the open parentheses is
+    let mut levels: Vec<usize> = Vec::new();
+    let mut prefixes = 0;
+    let mut name_length = 0;
+    let mut comment = false;
+
+    for c in s.chars() {
+        if c == '\n' {
+            line += 1;
+            comment = false;
+        }
+        if comment {
+            continue;
+        }
+
+        if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-' {
+            name_length += 1;
+            if name_length > limits.max_name_length {
+                return Err(format!(
+                    "name longer than {} characters in line {}",
+                    limits.max_name_length, line
+                ));
+            }
+            continue;
+        }
+        name_length = 0;
+
+        match c {
+            '#' => comment = true,
+            '[' | '?' => prefixes += 1,
+            '(' => {
+                levels.push(prefixes);
+                prefixes = 0;
+            }
+            ')' => {
+                levels.pop();
+                prefixes = 0;
+            }
+            ',' => prefixes = 0,
+            _ => {}
+        }
+
+        let depth = levels.len() + levels.iter().sum::<usize>() + prefixes;
+        // the parentheses of the method or type itself do not count
+        if depth > limits.max_depth + 1 {
+            return Err(format!(
+                "type nested deeper than {} levels in line {}",
+                limits.max_depth, line
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn count_struct(v: &VStruct) -> usize {
+    v.elts.len() + v.elts.iter().map(|e| count_type(&e.vtype)).sum::<usize>()
+}
+
+fn count_type(t: &VTypeExt) -> usize {
+    match *t {
+        VTypeExt::Array(ref v) | VTypeExt::Dict(ref v) | VTypeExt::Option(ref v) => count_type(v),
+        VTypeExt::Plain(VType::Struct(ref v)) => count_struct(v),
+        VTypeExt::Plain(VType::Enum(ref v)) => v.elts.len(),
+        _ => 0,
+    }
+}
+
+/// Checks the number of fields of the parsed `idl`.
+pub(crate) fn check_idl(idl: &IDL, limits: &Limits) -> Result<(), String> {
+    let mut fields = 0;
+    for m in idl.methods.values() {
+        fields += count_struct(&m.input) + count_struct(&m.output);
+    }
+    for t in idl.typedefs.values() {
+        fields += match t.elt {
+            VStructOrEnum::VStruct(ref v) => count_struct(v),
+            VStructOrEnum::VEnum(ref v) => v.elts.len(),
+        };
+    }
+    for e in idl.errors.values() {
+        fields += count_struct(&e.parm);
+    }
+
+    if fields > limits.max_fields {
+        return Err(format!(
+            "{} fields, more than {}",
+            fields, limits.max_fields
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Style checks for varlink interface definitions
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::*;
+
+/// A check performed by [`lint_with`](fn.lint_with.html)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Methods, types and errors are CamelCase, field names start lowercase
+    Naming,
+    /// A type is not referenced by any method, error or other type
+    UnusedType,
+    /// A struct without any fields
+    EmptyStruct,
+    /// An enum lists the same value more than once
+    DuplicateEnumValue,
+    /// A name is a rust keyword and will be mangled by the generator
+    RustKeyword,
+}
+
+impl LintRule {
+    /// All available rules
+    pub fn all() -> &'static [LintRule] {
+        &[
+            LintRule::Naming,
+            LintRule::UnusedType,
+            LintRule::EmptyStruct,
+            LintRule::DuplicateEnumValue,
+            LintRule::RustKeyword,
+        ]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LintRule::Naming => "naming",
+            LintRule::UnusedType => "unused-type",
+            LintRule::EmptyStruct => "empty-struct",
+            LintRule::DuplicateEnumValue => "duplicate-enum-value",
+            LintRule::RustKeyword => "rust-keyword",
+        }
+    }
+}
+
+impl fmt::Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A warning found by [`lint`](fn.lint.html)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LintWarning {
+    pub rule: LintRule,
+    pub message: String,
+}
+
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.rule)
+    }
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "true", "try", "type",
+    "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Checks `idl` with all rules, see [`lint_with`](fn.lint_with.html).
+pub fn lint(idl: &IDL) -> Vec<LintWarning> {
+    lint_with(idl, LintRule::all())
+}
+
+/// Checks `idl` for style issues, which are not errors of the varlink grammar.
+///
+/// Only the checks listed in `rules` are performed.
+///
+/// # Examples
+///
+/// ```rust
+/// use varlink_parser::{lint_with, LintRule, IDL};
+///
+/// let idl = IDL::from_string("interface org.example.a\nmethod Move(Type: string) -> ()").unwrap();
+/// let warnings = lint_with(&idl, &[LintRule::Naming]);
+/// assert_eq!(warnings.len(), 1);
+/// assert_eq!(
+///     warnings[0].to_string(),
+///     "field `Move.input.Type` should start with a lowercase letter [naming]"
+/// );
+/// ```
+pub fn lint_with(idl: &IDL, rules: &[LintRule]) -> Vec<LintWarning> {
+    let mut linter = Linter {
+        rules: rules.iter().cloned().collect(),
+        warnings: Vec::new(),
+        used_types: HashSet::new(),
+    };
+
+    for name in &idl.method_keys {
+        let m = &idl.methods[name];
+        linter.check_camel_case("method", name);
+        linter.check_keyword("method", name, &to_snake_case(name));
+        linter.check_struct_fields(&format!("{}.input", name), &m.input);
+        linter.check_struct_fields(&format!("{}.output", name), &m.output);
+    }
+
+    for name in &idl.typedef_keys {
+        let t = &idl.typedefs[name];
+        linter.check_camel_case("type", name);
+        match t.elt {
+            VStructOrEnum::VStruct(ref v) => {
+                if v.elts.is_empty() {
+                    linter.warn(
+                        LintRule::EmptyStruct,
+                        format!("type `{}` has no fields", name),
+                    );
+                }
+                linter.check_struct_fields(name, v);
+            }
+            VStructOrEnum::VEnum(ref v) => linter.check_enum(name, v),
+        }
+    }
+
+    for name in &idl.error_keys {
+        linter.check_camel_case("error", name);
+        linter.check_struct_fields(name, &idl.errors[name].parm);
+    }
+
+    for name in &idl.typedef_keys {
+        if !linter.used_types.contains(name) {
+            linter.warn(
+                LintRule::UnusedType,
+                format!("type `{}` is never used", name),
+            );
+        }
+    }
+
+    linter.warnings
+}
+
+struct Linter<'a> {
+    rules: HashSet<LintRule>,
+    warnings: Vec<LintWarning>,
+    used_types: HashSet<&'a str>,
+}
+
+impl<'a> Linter<'a> {
+    fn warn(&mut self, rule: LintRule, message: String) {
+        if self.rules.contains(&rule) {
+            self.warnings.push(LintWarning { rule, message });
+        }
+    }
+
+    fn check_camel_case(&mut self, kind: &str, name: &str) {
+        if name.len() > 2 && !name.chars().any(|c| c.is_ascii_lowercase()) {
+            self.warn(
+                LintRule::Naming,
+                format!("{} `{}` should be CamelCase", kind, name),
+            );
+        }
+    }
+
+    fn check_keyword(&mut self, kind: &str, path: &str, name: &str) {
+        if RUST_KEYWORDS.contains(&name) {
+            self.warn(
+                LintRule::RustKeyword,
+                format!(
+                    "{} `{}` is the rust keyword `{}` and will be mangled",
+                    kind, path, name
+                ),
+            );
+        }
+    }
+
+    fn check_struct_fields(&mut self, path: &str, v: &'a VStruct<'a>) {
+        for e in &v.elts {
+            let path = format!("{}.{}", path, e.name);
+            if e.name.starts_with(|c: char| c.is_ascii_uppercase()) {
+                self.warn(
+                    LintRule::Naming,
+                    format!("field `{}` should start with a lowercase letter", path),
+                );
+            }
+            self.check_keyword("field", &path, e.name);
+            self.check_type(&path, &e.vtype);
+        }
+    }
+
+    fn check_enum(&mut self, path: &str, v: &VEnum) {
+        let mut seen = HashSet::new();
+        for e in &v.elts {
+            if !seen.insert(e) {
+                self.warn(
+                    LintRule::DuplicateEnumValue,
+                    format!("enum `{}` has the value `{}` more than once", path, e),
+                );
+            }
+            self.check_keyword("enum value", &format!("{}.{}", path, e), e);
+        }
+    }
+
+    fn check_type(&mut self, path: &str, t: &'a VTypeExt<'a>) {
+        match *t {
+            // `[string]()` is the common way to declare a set
+            VTypeExt::Dict(ref v) => match **v {
+                VTypeExt::Plain(VType::Struct(ref s)) if s.elts.is_empty() => {}
+                _ => self.check_type(path, v),
+            },
+            VTypeExt::Array(ref v) | VTypeExt::Option(ref v) => self.check_type(path, v),
+            // a reference of a type to itself does not count
+            VTypeExt::Plain(VType::Typename(name)) if !path.starts_with(&format!("{}.", name)) => {
+                self.used_types.insert(name);
+            }
+            VTypeExt::Plain(VType::Struct(ref v)) => {
+                if v.elts.is_empty() {
+                    self.warn(
+                        LintRule::EmptyStruct,
+                        format!("field `{}` is an empty struct", path),
+                    );
+                }
+                self.check_struct_fields(path, v)
+            }
+            VTypeExt::Plain(VType::Enum(ref v)) => self.check_enum(path, v),
+            _ => {}
+        }
+    }
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut s = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                s.push('_');
+            }
+            s.push(c.to_ascii_lowercase());
+        } else {
+            s.push(c);
+        }
+    }
+    s
+}
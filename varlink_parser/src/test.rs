@@ -136,6 +136,37 @@ error ErrorFoo (a: (b: bool, c: int), foo: TypeFoo)
     );
 }
 
+#[test]
+fn test_nested_arrays() {
+    let v = IDL::from_string(
+        "interface org.example.matrix
+method Get(m: [][]int, cells: [][string]?[](c: [](d: (e, f)))) -> (r: [][][](z: bool))
+",
+    )
+    .unwrap();
+    let m = v.methods.get("Get").unwrap();
+    match m.input.elts[0].vtype {
+        VTypeExt::Array(ref a) => match **a {
+            VTypeExt::Array(ref i) => match **i {
+                VTypeExt::Plain(VType::Int) => {}
+                _ => panic!("not an int"),
+            },
+            _ => panic!("not an array of arrays"),
+        },
+        _ => panic!("not an array"),
+    }
+    assert_eq!(
+        v.to_string(),
+        "\
+interface org.example.matrix
+
+method Get(m: [][]int, cells: [][string]?[](c: [](d: (e, f)))) -> (
+  r: [][][](z: bool)
+)
+"
+    );
+}
+
 #[test]
 fn test_formatted() {
     let v = IDL::from_string(
@@ -337,3 +368,260 @@ Interface `foo.example`: multiple definitions of type `T`!'
 "
     );
 }
+
+#[test]
+fn test_field_doc() {
+    let v = IDL::from_string(
+        "
+interface foo.bar
+method F(
+  # @rust-type: u64
+  size: int,
+  name: string
+) -> ()
+",
+    )
+    .unwrap();
+    let m = v.methods.get("F").unwrap();
+    assert_eq!(m.input.elts[0].doc, "# @rust-type: u64");
+    assert_eq!(m.input.elts[1].doc, "");
+}
+
+#[test]
+fn test_parse_error() {
+    let e = IDL::from_string("interface foo.bar\ntype I (b: bool)\nmethod F(a: foo) -> ()\n")
+        .err()
+        .unwrap();
+    assert_eq!(e.to_string(), "Varlink parse error at line 3, column 13");
+    let p = e.find_cause::<ParseError>().unwrap();
+    assert_eq!(p.offset, 47);
+    assert_eq!(p.line, 3);
+    assert_eq!(p.column, 13);
+    assert_eq!(p.token, "foo");
+    assert_eq!(p.line_text, "method F(a: foo) -> ()");
+    assert_eq!(
+        p.to_string().lines().skip(1).collect::<Vec<_>>(),
+        vec!["method F(a: foo) -> ()", "            ^"]
+    );
+}
+
+#[test]
+fn test_diagnostics() {
+    let (v, errors) = IDL::from_string_with_diagnostics(
+        "interface org.example.broken
+
+method A(x: int) -> ()
+
+method B(
+  y: in t
+) -> ()
+
+# the type T
+type T (a: string)
+
+method C() -> (z: strin) method D() -> ()
+error E ()
+",
+    );
+    assert_eq!(v.name, "org.example.broken");
+    assert_eq!(v.method_keys, vec!["A"]);
+    assert_eq!(v.typedef_keys, vec!["T"]);
+    assert_eq!(v.typedefs.get("T").unwrap().doc, "# the type T");
+    assert_eq!(v.error_keys, vec!["E"]);
+    assert_eq!(
+        errors
+            .iter()
+            .map(|e| (e.line, e.column, e.token.as_str()))
+            .collect::<Vec<_>>(),
+        vec![(6, 6, "in"), (12, 19, "strin")]
+    );
+
+    let (v, errors) =
+        IDL::from_string_with_diagnostics("interface org.Example\nmethod A() -> ()\nerror B ()\n");
+    assert_eq!(v.name, "");
+    assert_eq!(v.method_keys, vec!["A"]);
+    assert_eq!(v.error_keys, vec!["B"]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        (errors[0].line, errors[0].token.as_str()),
+        (1, "org.Example")
+    );
+
+    let (v, errors) = IDL::from_string_with_diagnostics("interface org.example.empty\n");
+    assert!(v.method_keys.is_empty());
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].line, errors[0].token.as_str()), (2, ""));
+
+    let (v, errors) = IDL::from_string_with_diagnostics(
+        "interface org.example.ok\nmethod A() -> ()\nmethod A() -> ()\n",
+    );
+    assert!(errors.is_empty());
+    assert_eq!(v.method_keys, vec!["A", "A"]);
+    assert_eq!(v.error.len(), 1);
+}
+
+#[test]
+fn test_limits() {
+    let limits = Limits {
+        max_depth: 3,
+        max_fields: 6,
+        max_name_length: 16,
+    };
+    let ok = "interface org.example.ok
+# a comment with a (((((((( and a looooooooooooooooooong word
+type T (a: ?[](b: int), c: (d, e))
+method F(x: [string]T) -> ()
+";
+    assert!(IDL::from_string_with_limits(ok, &limits).is_ok());
+
+    let deep = "interface org.example.deep\nmethod F(a: ?[](b: []int)) -> ()\n";
+    assert!(IDL::from_string(deep).is_ok());
+    assert_eq!(
+        IDL::from_string_with_limits(deep, &limits)
+            .err()
+            .unwrap()
+            .to_string(),
+        "Interface definition exceeds the limits: type nested deeper than 3 levels in line 2"
+    );
+
+    let long = "interface org.example.long\nmethod F(abcdefghijklmnopq: int) -> ()\n";
+    assert_eq!(
+        IDL::from_string_with_limits(long, &limits)
+            .err()
+            .unwrap()
+            .to_string(),
+        "Interface definition exceeds the limits: name longer than 16 characters in line 2"
+    );
+
+    let many = "interface org.example.many\ntype T (a, b, c, d)\nerror E (x: (y: int, z: int))\n";
+    assert_eq!(
+        IDL::from_string_with_limits(many, &limits)
+            .err()
+            .unwrap()
+            .to_string(),
+        "Interface definition exceeds the limits: 7 fields, more than 6"
+    );
+
+    // the default limits stop a recursion, which would overflow the stack
+    let pathological = format!(
+        "interface org.example.deep\nmethod F(a: {}int) -> ()\n",
+        "[]".repeat(100_000)
+    );
+    assert!(IDL::from_string_with_limits(&pathological, &Limits::default()).is_err());
+}
+
+#[test]
+fn test_to_json() {
+    let v = IDL::from_string(
+        "interface foo.bar\ntype I (b: []bool, e: (x, y))\nmethod F(i: ?I) -> (s: [string]int)",
+    )
+    .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&v.to_json().unwrap()).unwrap();
+    assert_eq!(json["name"], "foo.bar");
+    assert_eq!(
+        json["typedefs"]["I"]["elt"],
+        serde_json::json!({ "struct": { "elts": [
+            { "name": "b", "doc": "", "vtype": { "array": { "plain": "bool" } } },
+            { "name": "e", "doc": "", "vtype": { "plain": { "enum": { "elts": ["x", "y"] } } } }
+        ]}})
+    );
+    assert_eq!(
+        json["methods"]["F"]["input"]["elts"][0]["vtype"],
+        serde_json::json!({ "option": { "plain": { "typename": "I" } } })
+    );
+    assert_eq!(
+        json["methods"]["F"]["output"]["elts"][0]["vtype"],
+        serde_json::json!({ "dict": { "plain": "int" } })
+    );
+}
+
+#[test]
+fn test_diff() {
+    let old = IDL::from_string(
+        "interface foo.bar
+type T (a: string, e: (x, y))
+method A(s: string) -> (r: T)
+method B() -> ()
+error E (reason: string)
+",
+    )
+    .unwrap();
+    let new = IDL::from_string(
+        "interface foo.bar
+type T (a: string, e: (x, y, z), n: ?int)
+type U (b: bool)
+method A(s: string, o: ?bool) -> (r: T)
+method C() -> ()
+error E (reason: int)
+error F ()
+",
+    )
+    .unwrap();
+
+    let report = diff(&old, &new);
+    assert!(!report.is_compatible());
+    assert_eq!(
+        report
+            .changes
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>(),
+        vec![
+            "compatible: A.input.o: optional field added",
+            "breaking: B: method removed",
+            "compatible: C: method added",
+            "compatible: T.e: enum value `z` added",
+            "compatible: T.n: optional field added",
+            "compatible: U: type added",
+            "breaking: E.reason: type changed from `string` to `int`",
+            "compatible: F: error added",
+        ]
+    );
+    assert_eq!(report.breaking().len(), 2);
+
+    let report = diff(&new, &old);
+    assert!(report
+        .breaking()
+        .iter()
+        .any(|c| c.to_string() == "breaking: T.n: field removed"));
+
+    let req = IDL::from_string("interface foo.bar\nmethod A(s: string, m: int) -> ()").unwrap();
+    assert_eq!(
+        diff(&old, &req).breaking()[0].to_string(),
+        "breaking: A.input.m: mandatory field added"
+    );
+    assert!(diff(&old, &old).changes.is_empty());
+}
+
+#[test]
+fn test_lint() {
+    let idl = IDL::from_string(
+        "interface foo.bar
+type Unused ()
+type Node (next: ?Node, e: (a, b, a))
+type Tree (root: Node)
+method GETALL() -> (Type: string, s: (), set: [string]())
+method Move(tree: Tree) -> ()
+error NotFound (match: string)
+",
+    )
+    .unwrap();
+
+    assert_eq!(
+        lint(&idl).iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+        vec![
+            "method `GETALL` should be CamelCase [naming]",
+            "field `GETALL.output.Type` should start with a lowercase letter [naming]",
+            "field `GETALL.output.s` is an empty struct [empty-struct]",
+            "method `Move` is the rust keyword `move` and will be mangled [rust-keyword]",
+            "type `Unused` has no fields [empty-struct]",
+            "enum `Node.e` has the value `a` more than once [duplicate-enum-value]",
+            "field `NotFound.match` is the rust keyword `match` and will be mangled [rust-keyword]",
+            "type `Unused` is never used [unused-type]",
+        ]
+    );
+
+    let warnings = lint_with(&idl, &[LintRule::UnusedType]);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].rule, LintRule::UnusedType);
+}
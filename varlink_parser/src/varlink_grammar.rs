@@ -71,12 +71,13 @@ peg::parser! {
             / o:option() a:dict() v:type_() { VTypeExt::Option(Box::new(VTypeExt::Dict(Box::new(v)))) }
 
         use crate::Argument;
+        use crate::trim_doc;
         rule object_field() -> Argument<'input>
-            = wce()* n:$(field_name()) wce()* [':'] wce()* v:type_() { Argument { name : n, vtype : v } }
+            = d:$(wce()*) n:$(field_name()) wce()* [':'] wce()* v:type_() { Argument { name : n, doc: trim_doc(d), vtype : v } }
 
         use crate::VStruct;
         rule vstruct() -> VStruct<'input>
-            = ['('] wce()* e:object_field() ** [','] wce()* [')'] { VStruct{ elts: e} }
+            = ['('] e:object_field() ** [','] wce()* [')'] { VStruct{ elts: e} }
 
         use crate::VEnum;
         rule venum() -> VEnum<'input>
@@ -84,7 +85,6 @@ peg::parser! {
 
         use crate::Typedef;
         use crate::VStructOrEnum;
-        use crate::trim_doc;
 
         rule vtypedef() -> Typedef<'input>
             = d:$(wce()*) "type" wce()+ n:$(name()) wce()* v:vstruct() {
@@ -121,5 +121,16 @@ peg::parser! {
                 IDL::from_token(__input, n, mt, trim_doc(d))
              }
 
+        /* the parts of an interface for `IDL::from_string_with_diagnostics`, which
+           return the position after the parsed part and ignore the rest of the input */
+        pub rule ParseInterfaceHeader() -> (&'input str, &'input str, usize)
+            = d:$(wce()*) "interface" wce()+ n:$interface_name() eol() p:position!() [_]* {
+                (trim_doc(d), n, p)
+             }
+
+        pub rule ParseMember() -> (MethodOrTypedefOrError<'input>, usize)
+            = m:member() p:position!() ( eol() / wce()* ![_] ) [_]* { (m, p) }
+
+        pub rule ParseBlank() = wce()*
     }
 }
@@ -339,28 +339,28 @@ impl varlink::Interface for VarlinkInterfaceProxy {
         self.inner.call_upgraded(call, bufreader)
     }
     fn call(&self, call: &mut varlink::Call) -> varlink::Result<()> {
-        let req = call.request.unwrap();
+        let req = call.require_request()?;
         match req.method.as_ref() {
             "org.varlink.service.GetInfo" => self.inner.get_info(call as &mut dyn Call_GetInfo),
             "org.varlink.service.GetInterfaceDescription" => {
-                if let Some(args) = req.parameters.clone() {
-                    let args: GetInterfaceDescription_Args = match serde_json::from_value(args) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            let es = format!("{}", e);
-                            let _ = call.reply_invalid_parameter(es.clone());
-                            return Err(
-                                varlink::context!(varlink::ErrorKind::SerdeJsonDe(es)).into()
-                            );
-                        }
-                    };
-                    self.inner.get_interface_description(
-                        call as &mut dyn Call_GetInterfaceDescription,
-                        args.r#interface,
-                    )
-                } else {
-                    call.reply_invalid_parameter("parameters".into())
-                }
+                let args = req
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+                let args: GetInterfaceDescription_Args = match serde_json::from_value(args) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        let _ = call.reply_invalid_args(&e);
+                        return Err(varlink::context!(varlink::ErrorKind::SerdeJsonDe(
+                            e.to_string()
+                        ))
+                        .into());
+                    }
+                };
+                self.inner.get_interface_description(
+                    call as &mut dyn Call_GetInterfaceDescription,
+                    args.r#interface,
+                )
             }
             m => call.reply_method_not_found(String::from(m)),
         }